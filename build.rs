@@ -5,15 +5,14 @@ fn embed_fingerprint() {
 
     let manifest_dir = env::var("CARGO_MANIFEST_DIR").unwrap();
     let env_path: PathBuf = [manifest_dir.as_str(), ".env"].iter().collect();
-    if let Ok(content) = fs::read_to_string(&env_path) {
-        if let Some(val) = content
+    if let Ok(content) = fs::read_to_string(&env_path)
+        && let Some(val) = content
             .lines()
             .find_map(|line| line.trim_start().strip_prefix(&format!("{KEY}=")))
-        {
-            println!("cargo:rustc-env={KEY}={val}");
-            println!("cargo:rerun-if-changed={}", env_path.display());
-            println!("cargo:warning=build.rs saw FINGERPRINT=\"{}\"", val);
-        }
+    {
+        println!("cargo:rustc-env={KEY}={val}");
+        println!("cargo:rerun-if-changed={}", env_path.display());
+        println!("cargo:warning=build.rs saw FINGERPRINT=\"{}\"", val);
     }
 }
 
@@ -0,0 +1,249 @@
+use std::{
+    fs,
+    io::{self, Cursor, Read},
+    path::PathBuf,
+    sync::Arc,
+    thread::{self, JoinHandle},
+};
+
+use aes_gcm::aead::{OsRng, rand_core::RngCore};
+use log::{debug, info, warn};
+use serde::{Deserialize, Serialize};
+use tiny_http::{Header, Method, Request, Response, Server};
+
+use crate::backup::BackupOptions;
+use crate::cli::load_template;
+use crate::daemon::Jobs;
+use crate::exclude::Filters;
+use crate::helpers::create_private;
+
+// a request body is a few paths and options, anything bigger is a mistake
+const MAX_BODY: u64 = 1024 * 1024;
+
+// POST /backups takes a template file or the paths themselves
+#[derive(Deserialize)]
+struct NewBackup {
+    #[serde(default)]
+    template: Option<PathBuf>,
+    #[serde(default)]
+    paths: Vec<PathBuf>,
+    out: PathBuf,
+    #[serde(default)]
+    options: BackupOptions,
+}
+
+type JsonResponse = Response<Cursor<Vec<u8>>>;
+
+#[derive(Serialize)]
+struct Started {
+    id: u64,
+}
+
+#[derive(Serialize)]
+struct Problem {
+    error: String,
+}
+
+pub struct Api {
+    server: Arc<Server>,
+    thread: JoinHandle<()>,
+}
+
+impl Api {
+    pub fn stop(self) {
+        self.server.unblock();
+        let _ = self.thread.join();
+    }
+}
+
+pub fn token_path() -> Option<PathBuf> {
+    Some(dirs::config_dir()?.join("Konserve").join("api.token"))
+}
+
+// every request carries "Authorization: Bearer <token>". the token is made
+// on first use and only readable by the user, so other users and web pages
+// can't drive the daemon
+fn token() -> Result<String, String> {
+    let path = token_path().ok_or("No config directory")?;
+    let read = |path: &PathBuf| {
+        fs::read_to_string(path)
+            .map(|text| text.trim().to_string())
+            .map_err(|e| format!("{}: {e}", path.display()))
+    };
+    if path.exists() {
+        return read(&path);
+    }
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+    }
+    let mut bytes = [0u8; 32];
+    OsRng.fill_bytes(&mut bytes);
+    let token = hex::encode(bytes);
+    match create_private(&path, token.as_bytes()) {
+        Ok(()) => Ok(token),
+        Err(e) if e.kind() == io::ErrorKind::AlreadyExists => read(&path),
+        Err(e) => Err(format!("{}: {e}", path.display())),
+    }
+}
+
+// only ever on loopback
+pub fn start(port: u16, jobs: Arc<Jobs>) -> Result<Api, String> {
+    let token = token()?;
+    let server = Server::http(("127.0.0.1", port))
+        .map_err(|e| format!("Couldn't listen on 127.0.0.1:{port}: {e}"))?;
+    let server = Arc::new(server);
+    info!("API listening on http://127.0.0.1:{port}");
+
+    let thread = thread::spawn({
+        let server = server.clone();
+        move || {
+            for request in server.incoming_requests() {
+                handle(request, &token, &jobs);
+            }
+            debug!("API stopped");
+        }
+    });
+    Ok(Api { server, thread })
+}
+
+fn json<T: Serialize>(status: u16, body: &T) -> JsonResponse {
+    let body = serde_json::to_string(body).unwrap_or_else(|_| "{}".into());
+    let header = Header::from_bytes("Content-Type", "application/json").expect("static header");
+    Response::from_string(body)
+        .with_status_code(status)
+        .with_header(header)
+}
+
+fn problem(status: u16, error: impl Into<String>) -> JsonResponse {
+    json(
+        status,
+        &Problem {
+            error: error.into(),
+        },
+    )
+}
+
+fn header<'a>(request: &'a Request, name: &'static str) -> Option<&'a str> {
+    request
+        .headers()
+        .iter()
+        .find(|h| h.field.equiv(name))
+        .map(|h| h.value.as_str())
+}
+
+// compares every byte so the time taken doesn't give the token away
+fn same(a: &str, b: &str) -> bool {
+    a.len() == b.len()
+        && a.bytes()
+            .zip(b.bytes())
+            .fold(0, |diff, (x, y)| diff | (x ^ y))
+            == 0
+}
+
+// browsers send an Origin with anything a page makes them fetch, and a
+// page can't set Authorization on a request it doesn't get to read
+fn refuse(request: &Request, token: &str) -> Option<JsonResponse> {
+    if header(request, "Origin").is_some() {
+        return Some(problem(403, "Requests from web pages aren't accepted."));
+    }
+    let given = header(request, "Authorization")
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .map(str::trim);
+    if !given.is_some_and(|given| same(given, token)) {
+        return Some(problem(
+            401,
+            "Missing or wrong token, see api.token in the config folder.",
+        ));
+    }
+    if *request.method() == Method::Post
+        && !header(request, "Content-Type").is_some_and(|value| {
+            value
+                .split(';')
+                .next()
+                .is_some_and(|mime| mime.trim().eq_ignore_ascii_case("application/json"))
+        })
+    {
+        return Some(problem(415, "Send the body as application/json."));
+    }
+    None
+}
+
+fn handle(mut request: Request, token: &str, jobs: &Arc<Jobs>) {
+    debug!("API: {} {}", request.method(), request.url());
+    if let Some(response) = refuse(&request, token) {
+        if let Err(e) = request.respond(response) {
+            warn!("API: couldn't answer: {e}");
+        }
+        return;
+    }
+    let parts: Vec<&str> = request
+        .url()
+        .split('?')
+        .next()
+        .unwrap_or_default()
+        .trim_matches('/')
+        .split('/')
+        .collect();
+    let id = parts.get(1).and_then(|id| id.parse::<u64>().ok());
+
+    let response = match (request.method(), parts.as_slice(), id) {
+        (Method::Post, ["backups"], _) => {
+            let mut body = String::new();
+            match request.as_reader().take(MAX_BODY).read_to_string(&mut body) {
+                Ok(_) => new_backup(&body, jobs),
+                Err(e) => problem(400, e.to_string()),
+            }
+        }
+        (Method::Get, ["backups"], _) => json(200, &jobs.all()),
+        (Method::Get, ["backups", _, "progress"], Some(id)) => match jobs.status(id) {
+            Some(status) => json(200, &status),
+            None => problem(404, format!("No job {id}.")),
+        },
+        (Method::Post, ["backups", _, "cancel"], Some(id)) => match jobs.cancel(id) {
+            Ok(()) => json(200, &jobs.status(id)),
+            Err(e) if jobs.status(id).is_some() => problem(409, e),
+            Err(e) => problem(404, e),
+        },
+        (Method::Post, ["backups", _, action @ ("pause" | "resume")], Some(id)) => {
+            match jobs.pause(id, *action == "pause") {
+                Ok(()) => json(200, &jobs.status(id)),
+                Err(e) if jobs.status(id).is_some() => problem(409, e),
+                Err(e) => problem(404, e),
+            }
+        }
+        _ => problem(404, "Not found."),
+    };
+
+    if let Err(e) = request.respond(response) {
+        warn!("API: couldn't answer: {e}");
+    }
+}
+
+fn new_backup(body: &str, jobs: &Arc<Jobs>) -> JsonResponse {
+    let mut new: NewBackup = match serde_json::from_str(body) {
+        Ok(new) => new,
+        Err(e) => return problem(400, format!("Bad request: {e}")),
+    };
+    if let Some(template) = &new.template {
+        match load_template(template) {
+            Ok(template) => {
+                new.paths.extend(template.paths);
+                if new.options.recipients.is_empty() {
+                    new.options.recipients = template.recipients;
+                }
+                if new.options.filters == Filters::default() {
+                    new.options.filters = template.filters;
+                }
+                if new.options.on_error.is_default() {
+                    new.options.on_error = template.on_error;
+                }
+            }
+            Err(e) => return problem(422, format!("Couldn't read the template {e}")),
+        }
+    }
+    if new.paths.is_empty() {
+        return problem(422, "Nothing to back up, give a template or paths.");
+    }
+    let id = jobs.start(new.paths, new.out, new.options);
+    json(201, &Started { id })
+}
@@ -0,0 +1,930 @@
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, Cursor, Read, Seek, SeekFrom, Write},
+    ops::ControlFlow,
+    path::{Path, PathBuf},
+    sync::{
+        Arc,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use chrono::{DateTime, Datelike, Local, NaiveDate, Timelike};
+use flate2::{Compression, read::GzDecoder, write::GzEncoder};
+use log::debug;
+use serde::{Deserialize, Serialize};
+use sevenz_rust2::{ArchiveEntry, NtTime, Password};
+use tar::{Builder, EntryType, Header};
+use xz2::{read::XzDecoder, write::XzEncoder};
+use zip::{CompressionMethod, ZipArchive, ZipWriter, write::SimpleFileOptions};
+
+use crate::crypto::{self, EncryptedFile, Key};
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ArchiveFormat {
+    Tar,
+    TarGz,
+    TarZst,
+    TarXz,
+    Zip,
+    SevenZ,
+}
+
+pub const ZSTD_LEVELS: std::ops::RangeInclusive<i32> = 1..=19;
+pub const DEFAULT_ZSTD_LEVEL: i32 = 3;
+// xz is picked for long-term storage, so squeeze as hard as it goes
+const XZ_PRESET: u32 = 9;
+
+impl ArchiveFormat {
+    pub const ALL: [ArchiveFormat; 6] = [
+        Self::Tar,
+        Self::TarGz,
+        Self::TarZst,
+        Self::TarXz,
+        Self::Zip,
+        Self::SevenZ,
+    ];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            Self::Tar => "tar",
+            Self::TarGz => "tar.gz",
+            Self::TarZst => "tar.zst",
+            Self::TarXz => "tar.xz",
+            Self::Zip => "zip",
+            Self::SevenZ => "7z",
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            Self::Tar => "tar",
+            Self::TarGz => "tar.gz",
+            Self::TarZst => "tar.zst",
+            Self::TarXz => "tar.xz",
+            Self::Zip => "zip",
+            Self::SevenZ => "7z",
+        }
+    }
+
+    // the whole tar is compressed as one stream, so entries have no size of their own
+    pub fn compresses_whole_stream(self) -> bool {
+        matches!(self, Self::TarGz | Self::TarZst | Self::TarXz)
+    }
+
+    // sniffed from the first bytes so renamed archives still open
+    pub fn detect(path: &Path, key: Option<&Key>) -> Result<Self, String> {
+        Self::sniff(&mut open_input(path, key)?)
+    }
+
+    fn sniff(input: &mut (impl Read + Seek)) -> Result<Self, String> {
+        let mut magic = Vec::with_capacity(6);
+        input
+            .take(6)
+            .read_to_end(&mut magic)
+            .and_then(|_| input.rewind())
+            .map_err(|e| e.to_string())?;
+
+        Ok(match magic.as_slice() {
+            [b'P', b'K', 3, 4, ..] | [b'P', b'K', 5, 6, ..] => Self::Zip,
+            [0x1f, 0x8b, ..] => Self::TarGz,
+            [0x28, 0xb5, 0x2f, 0xfd, ..] => Self::TarZst,
+            [0xfd, b'7', b'z', b'X', b'Z', 0] => Self::TarXz,
+            [b'7', b'z', 0xbc, 0xaf, 0x27, 0x1c] => Self::SevenZ,
+            _ => Self::Tar,
+        })
+    }
+}
+
+// "holiday.tar.gz" -> "holiday"
+pub fn stem(path: &Path) -> String {
+    let name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let name = name
+        .strip_suffix(".gpg")
+        .or_else(|| name.strip_suffix(".pgp"))
+        .unwrap_or(&name);
+    ArchiveFormat::ALL
+        .iter()
+        .map(|format| format.extension())
+        .chain(["tgz", "tzst", "txz"])
+        .find_map(|ext| name.strip_suffix(&format!(".{ext}")))
+        .unwrap_or(name)
+        .to_string()
+}
+
+// every extension the open dialogs should offer, gpg wrapped ones included
+pub const EXTENSIONS: [&str; 11] = [
+    "tar", "gz", "tgz", "zst", "tzst", "xz", "txz", "zip", "7z", "gpg", "pgp",
+];
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum EntryKind {
+    File,
+    Dir,
+    // a symlink stored as one, `link` says where it points
+    Link,
+    // fifos, devices, nothing we restore
+    Other,
+}
+
+pub struct Entry<'a> {
+    // `/` separated, without a trailing slash for directories
+    pub name: String,
+    pub kind: EntryKind,
+    pub size: u64,
+    // bytes the entry takes up inside the archive
+    pub stored_size: u64,
+    pub mtime: u64,
+    pub mode: Option<u32>,
+    // the target of a link, as it was on disk
+    pub link: Option<PathBuf>,
+    pub data: &'a mut dyn Read,
+}
+
+trait ReadSeek: Read + Seek {}
+impl<T: Read + Seek> ReadSeek for T {}
+
+// the archive bytes, decrypted on the fly when the backup has a password
+fn open_input(path: &Path, key: Option<&Key>) -> Result<Box<dyn ReadSeek>, String> {
+    let file = File::open(path).map_err(|e| e.to_string())?;
+    if crypto::protection(path)?.is_none() {
+        return Ok(Box::new(file));
+    }
+    let key = key.ok_or("This backup is encrypted.")?;
+    Ok(Box::new(EncryptedFile::open(file, key)?))
+}
+
+// calls `visit` for every entry in archive order until it breaks
+pub fn visit_entries(
+    path: &Path,
+    key: Option<&Key>,
+    mut visit: impl FnMut(Entry) -> Result<ControlFlow<()>, String>,
+) -> Result<(), String> {
+    let mut file = open_input(path, key)?;
+    let format = ArchiveFormat::sniff(&mut file)?;
+    debug!("visit_entries: {} as {}", path.display(), format.label());
+
+    match format {
+        ArchiveFormat::Tar => visit_tar(file, &mut visit),
+        ArchiveFormat::TarGz => visit_tar(GzDecoder::new(file), &mut visit),
+        ArchiveFormat::TarZst => visit_tar(
+            zstd::Decoder::new(file).map_err(|e| e.to_string())?,
+            &mut visit,
+        ),
+        ArchiveFormat::TarXz => visit_tar(XzDecoder::new(file), &mut visit),
+        ArchiveFormat::Zip => visit_zip(file, &mut visit),
+        ArchiveFormat::SevenZ => visit_7z(file, &mut visit),
+    }
+}
+
+// tar stores each entry as a 512 byte header plus data padded to 512 bytes
+fn tar_stored_size(size: u64) -> u64 {
+    512 + size.div_ceil(512) * 512
+}
+
+fn visit_tar(
+    reader: impl Read,
+    visit: &mut impl FnMut(Entry) -> Result<ControlFlow<()>, String>,
+) -> Result<(), String> {
+    let mut archive = tar::Archive::new(reader);
+
+    for entry in archive.entries().map_err(|e| e.to_string())? {
+        let mut entry = entry.map_err(|e| e.to_string())?;
+        let header = entry.header();
+        let ty = header.entry_type();
+        // a hard link reads as the empty file the other formats store for
+        // one, the fingerprint says where its data is
+        let kind = if ty.is_dir() {
+            EntryKind::Dir
+        } else if ty.is_file() || ty.is_hard_link() {
+            EntryKind::File
+        } else if ty.is_symlink() {
+            EntryKind::Link
+        } else {
+            EntryKind::Other
+        };
+        let link = match kind {
+            EntryKind::Link => entry
+                .link_name()
+                .map_err(|e| e.to_string())?
+                .map(|target| target.into_owned()),
+            _ => None,
+        };
+        let mtime = header.mtime().unwrap_or(0);
+        let mode = header.mode().ok().map(|m| m & 0o7777);
+        // tars made with `tar -C dir .` name everything "./…"
+        let name = entry
+            .path()
+            .map_err(|e| e.to_string())?
+            .to_string_lossy()
+            .trim_start_matches("./")
+            .trim_end_matches('/')
+            .to_string();
+        let size = entry.size();
+
+        let flow = visit(Entry {
+            name,
+            kind,
+            size,
+            stored_size: tar_stored_size(size),
+            mtime,
+            mode,
+            link,
+            data: &mut entry,
+        })?;
+        if flow.is_break() {
+            break;
+        }
+    }
+    Ok(())
+}
+
+// zip keeps local time without a zone, same as the tools that wrote it
+fn zip_to_unix(time: zip::DateTime) -> Option<u64> {
+    let local =
+        NaiveDate::from_ymd_opt(time.year() as i32, time.month() as u32, time.day() as u32)?
+            .and_hms_opt(
+                time.hour() as u32,
+                time.minute() as u32,
+                time.second() as u32,
+            )?
+            .and_local_timezone(Local)
+            .earliest()?;
+    u64::try_from(local.timestamp()).ok()
+}
+
+fn visit_zip<R: Read + Seek>(
+    reader: R,
+    visit: &mut impl FnMut(Entry) -> Result<ControlFlow<()>, String>,
+) -> Result<(), String> {
+    let mut archive = ZipArchive::new(reader).map_err(|e| e.to_string())?;
+
+    for i in 0..archive.len() {
+        let mut file = archive.by_index(i).map_err(|e| e.to_string())?;
+        let kind = if file.is_dir() {
+            EntryKind::Dir
+        } else if file.is_symlink() {
+            EntryKind::Link
+        } else if file.is_file() {
+            EntryKind::File
+        } else {
+            EntryKind::Other
+        };
+        let name = file
+            .name()
+            .map_err(|e| e.to_string())?
+            .trim_end_matches('/')
+            .to_string();
+        let size = file.size();
+        let stored_size = file.compressed_size();
+        let mtime = file.last_modified().and_then(zip_to_unix).unwrap_or(0);
+        let mode = file.unix_mode().map(|m| m & 0o7777);
+        // zip and 7z keep the target as the link's data
+        let link = match kind {
+            EntryKind::Link => Some(read_link_target(&mut file)?),
+            _ => None,
+        };
+
+        let flow = visit(Entry {
+            name,
+            kind,
+            size,
+            stored_size,
+            mtime,
+            mode,
+            link,
+            data: &mut file,
+        })?;
+        if flow.is_break() {
+            break;
+        }
+    }
+    Ok(())
+}
+
+// 7-Zip keeps unix permissions in the high half of the windows attributes
+const ATTR_READONLY: u32 = 0x1;
+const ATTR_DIRECTORY: u32 = 0x10;
+const ATTR_UNIX_EXTENSION: u32 = 0x8000;
+const S_IFMT: u32 = 0o170000;
+const S_IFDIR: u32 = 0o040000;
+const S_IFREG: u32 = 0o100000;
+const S_IFLNK: u32 = 0o120000;
+
+fn read_link_target(data: &mut dyn Read) -> Result<PathBuf, String> {
+    let mut target = String::new();
+    data.read_to_string(&mut target)
+        .map_err(|e| e.to_string())?;
+    Ok(PathBuf::from(target))
+}
+
+// p7zip's convention, 7-Zip itself only knows windows reparse points
+fn sevenz_is_link(entry: &ArchiveEntry) -> bool {
+    let attrs = entry.windows_attributes();
+    entry.has_windows_attributes
+        && attrs & ATTR_UNIX_EXTENSION != 0
+        && (attrs >> 16) & S_IFMT == S_IFLNK
+}
+
+fn sevenz_mode(entry: &ArchiveEntry) -> Option<u32> {
+    if !entry.has_windows_attributes {
+        return None;
+    }
+    let attrs = entry.windows_attributes();
+    Some(
+        match (attrs & ATTR_UNIX_EXTENSION != 0, attrs & ATTR_READONLY != 0) {
+            (true, _) => (attrs >> 16) & 0o7777,
+            (false, true) => 0o444,
+            (false, false) if entry.is_directory() => 0o755,
+            (false, false) => 0o644,
+        },
+    )
+}
+
+fn visit_7z<R: Read + Seek>(
+    file: R,
+    visit: &mut impl FnMut(Entry) -> Result<ControlFlow<()>, String>,
+) -> Result<(), String> {
+    let mut archive =
+        sevenz_rust2::ArchiveReader::new(file, Password::empty()).map_err(|e| e.to_string())?;
+
+    // the callback has to return the crate's error type, so ours waits here
+    let mut failed = None;
+    archive
+        .for_each_entries(|entry, data| {
+            let kind = match (entry.is_anti_item(), entry.is_directory()) {
+                (true, _) => EntryKind::Other,
+                (false, true) => EntryKind::Dir,
+                (false, false) if sevenz_is_link(entry) => EntryKind::Link,
+                (false, false) => EntryKind::File,
+            };
+            let link = match kind {
+                EntryKind::Link => match read_link_target(data) {
+                    Ok(target) => Some(target),
+                    Err(e) => {
+                        failed = Some(e);
+                        return Ok(false);
+                    }
+                },
+                _ => None,
+            };
+            let mtime = SystemTime::from(entry.last_modified_date())
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or(0);
+
+            let flow = visit(Entry {
+                name: entry.name().trim_end_matches('/').to_string(),
+                kind,
+                size: entry.size(),
+                stored_size: entry.compressed_size,
+                mtime,
+                mode: sevenz_mode(entry),
+                link,
+                data,
+            });
+            match flow {
+                Ok(flow) => Ok(flow.is_continue()),
+                Err(e) => {
+                    failed = Some(e);
+                    Ok(false)
+                }
+            }
+        })
+        .map_err(|e| e.to_string())?;
+
+    match failed {
+        Some(e) => Err(e),
+        None => Ok(()),
+    }
+}
+
+pub fn read_fingerprint(path: &Path, key: Option<&Key>) -> Result<Option<String>, String> {
+    let mut txt = None;
+    visit_entries(path, key, |entry| {
+        if entry.name != "fingerprint.txt" {
+            return Ok(ControlFlow::Continue(()));
+        }
+        let mut s = String::new();
+        entry
+            .data
+            .read_to_string(&mut s)
+            .map_err(|e| e.to_string())?;
+        txt = Some(s);
+        Ok(ControlFlow::Break(()))
+    })?;
+    Ok(txt)
+}
+
+pub trait ArchiveWriter {
+    // `size` is what `data` holds, less than the file for a sparse one
+    fn add_file(
+        &mut self,
+        name: &Path,
+        meta: &fs::Metadata,
+        size: u64,
+        data: &mut dyn Read,
+    ) -> Result<(), String>;
+    fn add_dir(&mut self, name: &Path, meta: &fs::Metadata) -> Result<(), String>;
+    // `meta` is the link's own, not its target's
+    fn add_symlink(
+        &mut self,
+        name: &Path,
+        target: &Path,
+        meta: &fs::Metadata,
+    ) -> Result<(), String>;
+    // an empty entry for a file that shares the data of the entry `stored`
+    fn add_hard_link(
+        &mut self,
+        name: &Path,
+        stored: &Path,
+        meta: &fs::Metadata,
+    ) -> Result<(), String>;
+    fn add_bytes(&mut self, name: &str, data: &[u8]) -> Result<(), String>;
+    fn finish(self: Box<Self>) -> Result<(), String>;
+    // syncs what was written so far and says how far the archive is good,
+    // for formats that can be continued after a crash
+    fn checkpoint(&mut self) -> Result<Option<u64>, String> {
+        Ok(None)
+    }
+}
+
+// `zstd_level` and `workers` only matter for tar.zst
+pub fn create(
+    format: ArchiveFormat,
+    zstd_level: i32,
+    workers: usize,
+    path: &Path,
+    key: Option<&Key>,
+) -> Result<Box<dyn ArchiveWriter>, String> {
+    match key {
+        Some(key) => create_on(
+            format,
+            zstd_level,
+            workers,
+            EncryptedFile::create(path, key)?,
+        ),
+        None => create_on(
+            format,
+            zstd_level,
+            workers,
+            File::create(path).map_err(|e| e.to_string())?,
+        ),
+    }
+}
+
+// an archive kept in memory only to see how large it gets, the size is
+// there once it's finished
+pub fn measure(
+    format: ArchiveFormat,
+    zstd_level: i32,
+    workers: usize,
+) -> Result<(Box<dyn ArchiveWriter>, Arc<AtomicU64>), String> {
+    let size = Arc::new(AtomicU64::new(0));
+    let out = Measured {
+        out: Cursor::new(Vec::new()),
+        size: size.clone(),
+    };
+    Ok((create_on(format, zstd_level, workers, out)?, size))
+}
+
+// continues a plain tar that stopped at `offset`, cutting off whatever came after
+pub fn append_tar(path: &Path, offset: u64) -> Result<Box<dyn ArchiveWriter>, String> {
+    let mut file = OpenOptions::new()
+        .write(true)
+        .open(path)
+        .map_err(|e| e.to_string())?;
+    file.set_len(offset).map_err(|e| e.to_string())?;
+    file.seek(SeekFrom::Start(offset))
+        .map_err(|e| e.to_string())?;
+    Ok(Box::new(TarOut(Builder::new(file))))
+}
+
+fn create_on<W: Sink + Seek + 'static>(
+    format: ArchiveFormat,
+    zstd_level: i32,
+    workers: usize,
+    out: W,
+) -> Result<Box<dyn ArchiveWriter>, String> {
+    Ok(match format {
+        ArchiveFormat::Tar => Box::new(TarOut(Builder::new(out))),
+        ArchiveFormat::TarGz => Box::new(TarOut(Builder::new(GzEncoder::new(
+            out,
+            Compression::default(),
+        )))),
+        ArchiveFormat::TarZst => {
+            let mut encoder = zstd::Encoder::new(out, zstd_level).map_err(|e| e.to_string())?;
+            // compresses on its own threads while the writer keeps feeding it.
+            // xz could too, but at preset 9 every thread wants hundreds of MB
+            encoder
+                .multithread(workers as u32)
+                .map_err(|e| e.to_string())?;
+            Box::new(TarOut(Builder::new(encoder)))
+        }
+        ArchiveFormat::TarXz => Box::new(TarOut(Builder::new(XzEncoder::new(out, XZ_PRESET)))),
+        ArchiveFormat::Zip => Box::new(ZipOut(ZipWriter::new(out))),
+        ArchiveFormat::SevenZ => Box::new(SevenZOut(
+            sevenz_rust2::ArchiveWriter::new(out).map_err(|e| e.to_string())?,
+        )),
+    })
+}
+
+// where archive bytes end up, compressors and encryption need their trailer written
+trait Sink: Write {
+    fn close(self) -> io::Result<()>;
+    // only a plain file knows where its bytes end up
+    fn sync(&mut self) -> io::Result<Option<u64>> {
+        Ok(None)
+    }
+}
+
+impl Sink for File {
+    fn close(mut self) -> io::Result<()> {
+        self.flush()
+    }
+
+    fn sync(&mut self) -> io::Result<Option<u64>> {
+        self.sync_data()?;
+        self.stream_position().map(Some)
+    }
+}
+
+struct Measured {
+    out: Cursor<Vec<u8>>,
+    size: Arc<AtomicU64>,
+}
+
+impl Write for Measured {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.out.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Seek for Measured {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.out.seek(pos)
+    }
+}
+
+impl Sink for Measured {
+    fn close(self) -> io::Result<()> {
+        self.size
+            .store(self.out.get_ref().len() as u64, Ordering::Relaxed);
+        Ok(())
+    }
+}
+
+impl Sink for EncryptedFile {
+    fn close(self) -> io::Result<()> {
+        EncryptedFile::close(self)
+    }
+}
+
+impl<W: Sink> Sink for GzEncoder<W> {
+    fn close(self) -> io::Result<()> {
+        self.finish()?.close()
+    }
+}
+
+impl<W: Sink> Sink for zstd::Encoder<'static, W> {
+    fn close(self) -> io::Result<()> {
+        self.finish()?.close()
+    }
+}
+
+impl<W: Sink> Sink for XzEncoder<W> {
+    fn close(self) -> io::Result<()> {
+        self.finish()?.close()
+    }
+}
+
+struct TarOut<W: Sink>(Builder<W>);
+
+impl<W: Sink> ArchiveWriter for TarOut<W> {
+    fn add_file(
+        &mut self,
+        name: &Path,
+        meta: &fs::Metadata,
+        size: u64,
+        data: &mut dyn Read,
+    ) -> Result<(), String> {
+        let mut header = Header::new_gnu();
+        header.set_metadata(meta);
+        header.set_size(size);
+        header.set_cksum();
+        self.0
+            .append_data(&mut header, name, data)
+            .map_err(|e| e.to_string())
+    }
+
+    fn add_dir(&mut self, name: &Path, meta: &fs::Metadata) -> Result<(), String> {
+        let mut header = Header::new_gnu();
+        header.set_metadata(meta);
+        header.set_cksum();
+        self.0
+            .append_data(&mut header, name, io::empty())
+            .map_err(|e| e.to_string())
+    }
+
+    fn add_symlink(
+        &mut self,
+        name: &Path,
+        target: &Path,
+        meta: &fs::Metadata,
+    ) -> Result<(), String> {
+        let mut header = Header::new_gnu();
+        header.set_metadata(meta);
+        header.set_size(0);
+        self.0
+            .append_link(&mut header, name, target)
+            .map_err(|e| e.to_string())
+    }
+
+    fn add_hard_link(
+        &mut self,
+        name: &Path,
+        stored: &Path,
+        meta: &fs::Metadata,
+    ) -> Result<(), String> {
+        let mut header = Header::new_gnu();
+        header.set_metadata(meta);
+        header.set_entry_type(EntryType::Link);
+        header.set_size(0);
+        self.0
+            .append_link(&mut header, name, stored)
+            .map_err(|e| e.to_string())
+    }
+
+    fn add_bytes(&mut self, name: &str, data: &[u8]) -> Result<(), String> {
+        let mut header = Header::new_gnu();
+        header.set_size(data.len() as u64);
+        header.set_mode(0o644);
+        header.set_mtime(Local::now().timestamp() as u64);
+        header.set_cksum();
+        self.0
+            .append_data(&mut header, name, data)
+            .map_err(|e| e.to_string())
+    }
+
+    fn finish(self: Box<Self>) -> Result<(), String> {
+        let sink = self.0.into_inner().map_err(|e| e.to_string())?;
+        sink.close().map_err(|e| e.to_string())
+    }
+
+    fn checkpoint(&mut self) -> Result<Option<u64>, String> {
+        self.0.get_mut().sync().map_err(|e| e.to_string())
+    }
+}
+
+struct ZipOut<W: Sink + Seek>(ZipWriter<W>);
+
+// zip and 7z names always use `/`, whatever the platform separator is
+fn slash_name(path: &Path) -> String {
+    path.components()
+        .map(|c| c.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+fn zip_time(time: DateTime<Local>) -> Option<zip::DateTime> {
+    zip::DateTime::from_date_and_time(
+        time.year().try_into().ok()?,
+        time.month() as u8,
+        time.day() as u8,
+        time.hour() as u8,
+        time.minute() as u8,
+        time.second() as u8,
+    )
+    .ok()
+}
+
+pub fn file_mode(meta: &fs::Metadata) -> u32 {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        meta.permissions().mode() & 0o7777
+    }
+    // windows only knows the read-only attribute
+    #[cfg(not(unix))]
+    match (meta.is_dir(), meta.permissions().readonly()) {
+        (true, _) => 0o755,
+        (false, true) => 0o444,
+        (false, false) => 0o644,
+    }
+}
+
+// "H" and "S" for what windows marks hidden or system, nothing elsewhere
+pub fn file_flags(meta: &fs::Metadata) -> String {
+    #[cfg(windows)]
+    {
+        use std::os::windows::fs::MetadataExt;
+        use windows_sys::Win32::Storage::FileSystem::{
+            FILE_ATTRIBUTE_HIDDEN, FILE_ATTRIBUTE_SYSTEM,
+        };
+        let attributes = meta.file_attributes();
+        let mut flags = String::new();
+        if attributes & FILE_ATTRIBUTE_HIDDEN != 0 {
+            flags.push('H');
+        }
+        if attributes & FILE_ATTRIBUTE_SYSTEM != 0 {
+            flags.push('S');
+        }
+        flags
+    }
+    #[cfg(not(windows))]
+    {
+        let _ = meta;
+        String::new()
+    }
+}
+
+fn zip_options(meta: &fs::Metadata) -> SimpleFileOptions {
+    let mut options = SimpleFileOptions::default()
+        .compression_method(CompressionMethod::Deflated)
+        .unix_permissions(file_mode(meta))
+        .large_file(meta.len() >= u32::MAX as u64);
+    if let Some(time) = meta
+        .modified()
+        .ok()
+        .and_then(|t| zip_time(DateTime::<Local>::from(t)))
+    {
+        options = options.last_modified_time(time);
+    }
+    options
+}
+
+impl<W: Sink + Seek> ArchiveWriter for ZipOut<W> {
+    fn add_file(
+        &mut self,
+        name: &Path,
+        meta: &fs::Metadata,
+        _size: u64,
+        data: &mut dyn Read,
+    ) -> Result<(), String> {
+        self.0
+            .start_file(slash_name(name), zip_options(meta))
+            .map_err(|e| e.to_string())?;
+        io::copy(data, &mut self.0).map_err(|e| e.to_string())?;
+        Ok(())
+    }
+
+    fn add_dir(&mut self, name: &Path, meta: &fs::Metadata) -> Result<(), String> {
+        self.0
+            .add_directory(slash_name(name), zip_options(meta))
+            .map_err(|e| e.to_string())
+    }
+
+    fn add_symlink(
+        &mut self,
+        name: &Path,
+        target: &Path,
+        meta: &fs::Metadata,
+    ) -> Result<(), String> {
+        self.0
+            .add_symlink(
+                slash_name(name),
+                target.to_string_lossy(),
+                zip_options(meta),
+            )
+            .map_err(|e| e.to_string())
+    }
+
+    fn add_hard_link(
+        &mut self,
+        name: &Path,
+        _stored: &Path,
+        meta: &fs::Metadata,
+    ) -> Result<(), String> {
+        self.0
+            .start_file(slash_name(name), zip_options(meta))
+            .map_err(|e| e.to_string())
+    }
+
+    fn add_bytes(&mut self, name: &str, data: &[u8]) -> Result<(), String> {
+        let mut options =
+            SimpleFileOptions::default().compression_method(CompressionMethod::Deflated);
+        if let Some(time) = zip_time(Local::now()) {
+            options = options.last_modified_time(time);
+        }
+        self.0
+            .start_file(name, options)
+            .map_err(|e| e.to_string())?;
+        self.0.write_all(data).map_err(|e| e.to_string())
+    }
+
+    fn finish(self: Box<Self>) -> Result<(), String> {
+        let sink = self.0.finish().map_err(|e| e.to_string())?;
+        sink.close().map_err(|e| e.to_string())
+    }
+}
+
+struct SevenZOut<W: Sink + Seek>(sevenz_rust2::ArchiveWriter<W>);
+
+fn sevenz_entry(name: &Path, meta: &fs::Metadata) -> ArchiveEntry {
+    let name = slash_name(name);
+    let mut entry = if meta.is_dir() {
+        ArchiveEntry::new_directory(&name)
+    } else {
+        ArchiveEntry::new_file(&name)
+    };
+
+    // every entry gets a time and attributes, the writer mangles the headers
+    // of archives where only some of them have one
+    entry.last_modified_date = meta
+        .modified()
+        .ok()
+        .and_then(|t| NtTime::try_from(t).ok())
+        .unwrap_or(NtTime::UNIX_EPOCH);
+    entry.has_last_modified_date = true;
+
+    // full st_mode with the type bits, other readers reject a directory without S_IFDIR
+    let mode = file_mode(meta);
+    let mut attrs = ATTR_UNIX_EXTENSION;
+    if meta.is_dir() {
+        attrs |= ATTR_DIRECTORY | ((S_IFDIR | mode) << 16);
+    } else if meta.is_symlink() {
+        attrs |= (S_IFLNK | mode) << 16;
+    } else {
+        attrs |= (S_IFREG | mode) << 16;
+    }
+    if mode & 0o200 == 0 {
+        attrs |= ATTR_READONLY;
+    }
+    entry.windows_attributes = attrs;
+    entry.has_windows_attributes = true;
+    entry
+}
+
+impl<W: Sink + Seek> ArchiveWriter for SevenZOut<W> {
+    fn add_file(
+        &mut self,
+        name: &Path,
+        meta: &fs::Metadata,
+        size: u64,
+        data: &mut dyn Read,
+    ) -> Result<(), String> {
+        // 7z marks empty files by giving them no stream at all
+        let data = (size > 0).then_some(data);
+        self.0
+            .push_archive_entry(sevenz_entry(name, meta), data)
+            .map_err(|e| e.to_string())?;
+        Ok(())
+    }
+
+    fn add_dir(&mut self, name: &Path, meta: &fs::Metadata) -> Result<(), String> {
+        self.0
+            .push_archive_entry::<&[u8]>(sevenz_entry(name, meta), None)
+            .map_err(|e| e.to_string())?;
+        Ok(())
+    }
+
+    fn add_symlink(
+        &mut self,
+        name: &Path,
+        target: &Path,
+        meta: &fs::Metadata,
+    ) -> Result<(), String> {
+        let target = target.to_string_lossy();
+        self.0
+            .push_archive_entry(sevenz_entry(name, meta), Some(target.as_bytes()))
+            .map_err(|e| e.to_string())?;
+        Ok(())
+    }
+
+    fn add_hard_link(
+        &mut self,
+        name: &Path,
+        _stored: &Path,
+        meta: &fs::Metadata,
+    ) -> Result<(), String> {
+        self.0
+            .push_archive_entry::<&[u8]>(sevenz_entry(name, meta), None)
+            .map_err(|e| e.to_string())?;
+        Ok(())
+    }
+
+    fn add_bytes(&mut self, name: &str, data: &[u8]) -> Result<(), String> {
+        let mut entry = ArchiveEntry::new_file(name);
+        entry.last_modified_date = NtTime::now();
+        entry.has_last_modified_date = true;
+        entry.windows_attributes = ATTR_UNIX_EXTENSION | ((S_IFREG | 0o644) << 16);
+        entry.has_windows_attributes = true;
+        self.0
+            .push_archive_entry(entry, Some(data))
+            .map_err(|e| e.to_string())?;
+        Ok(())
+    }
+
+    fn finish(self: Box<Self>) -> Result<(), String> {
+        let sink = self.0.finish().map_err(|e| e.to_string())?;
+        sink.close().map_err(|e| e.to_string())
+    }
+}
@@ -1,199 +1,1262 @@
-use crate::helpers::{Progress, get_fingered};
+use crate::archive::{self, ArchiveFormat};
+use crate::catalog::Catalog;
+use crate::crypto::{self, Key, Protection};
+use crate::exclude::{Excludes, Filters, SymlinkPolicy};
+use crate::gpg;
+use crate::helpers::{Progress, ProgressEvent, format_bytes, get_fingered};
+use crate::incremental::{self, Attributes, FileState, HardLinks, Manifest, Stored};
+use crate::journal::{self, Journal};
+use crate::names;
+use crate::priority;
+use crate::remote;
+use crate::report::{self, ErrorLog, LeftOut, Problem};
+use crate::restore::default_workers;
+use crate::signing;
+use crate::sparse::{self, Layout, Sparse};
+use crate::streams;
+use crate::vss::Shadows;
 use std::{
-    fs::File,
-    io,
+    collections::{BTreeMap, HashMap, HashSet},
+    fs::{self, File},
+    io::{self, Cursor, Read},
     path::{Path, PathBuf},
+    sync::{
+        Arc, Condvar, Mutex,
+        atomic::{AtomicUsize, Ordering},
+        mpsc,
+    },
+    thread,
+    time::{Duration, Instant, UNIX_EPOCH},
 };
 
 use chrono::Local;
+use log::{debug, info, warn};
 use serde::{Deserialize, Serialize};
-use tar::{Builder, Header};
 use uuid::Uuid;
-use walkdir::WalkDir;
 
-#[derive(Serialize, Deserialize)]
-struct BackupTemplate {
-    paths: Vec<PathBuf>,
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum BackupMode {
+    Full,
+    // changes since the previous backup of any kind
+    Incremental,
+    // changes since a full backup, so restoring needs just the two
+    Differential,
+}
+
+impl BackupMode {
+    pub const ALL: [BackupMode; 3] = [Self::Full, Self::Incremental, Self::Differential];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            Self::Full => "Full",
+            Self::Incremental => "Incremental",
+            Self::Differential => "Differential",
+        }
+    }
+}
+
+// what happens to a file that can't be read
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorPolicy {
+    // files other programs keep busy are left out, anything else stops the
+    // backup unless it runs headless
+    #[default]
+    Auto,
+    // the first file that can't be read ends the backup
+    Abort,
+    // left out and listed in the report
+    Skip,
+}
+
+impl ErrorPolicy {
+    pub const ALL: [ErrorPolicy; 3] = [Self::Auto, Self::Abort, Self::Skip];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            Self::Auto => "Skip files in use",
+            Self::Abort => "Stop the backup",
+            Self::Skip => "Skip and report",
+        }
+    }
+
+    pub fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+// also what the daemon is handed for a job
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct BackupOptions {
+    pub format: ArchiveFormat,
+    pub zstd_level: i32,
+    // encrypts the archive when set
+    pub password: Option<String>,
+    // age public keys, used instead of the password when present
+    pub recipients: Vec<String>,
+    // gpg key the finished archive is encrypted to
+    pub gpg_recipient: Option<String>,
+    // writes "<archive>.sig" once the archive is final
+    pub sign: bool,
+    // the finished archive is also copied into this folder
+    pub remote: Option<PathBuf>,
+    pub mode: BackupMode,
+    // the previous backup, or the full base of a differential
+    pub parent: Option<PathBuf>,
+    // threads hashing, reading ahead and compressing
+    pub workers: usize,
+    // caps how fast files are read, in MB/s. the archive is never written
+    // faster than that either
+    pub max_mb_per_sec: Option<u32>,
+    // the backup's threads get the cpu and the disk only when nothing else
+    // wants them
+    pub background: bool,
+    // alternate data streams and extended attributes go in too
+    pub streams: bool,
+    // windows only, files are read from a volume shadow copy taken at the
+    // start so ones other programs have locked can be read
+    pub shadow_copy: bool,
+    // what to leave out below the backed up folders
+    #[serde(flatten)]
+    pub filters: Filters,
+    // whether a file that can't be read stops the backup
+    pub on_error: ErrorPolicy,
+    // false for command line runs: nothing may wait on a person, and with
+    // the auto error policy a file that can't be read is left out instead of
+    // failing the whole backup
+    pub interactive: bool,
+}
+
+impl Default for BackupOptions {
+    fn default() -> Self {
+        Self {
+            format: ArchiveFormat::Tar,
+            zstd_level: archive::DEFAULT_ZSTD_LEVEL,
+            password: None,
+            recipients: Vec::new(),
+            gpg_recipient: None,
+            sign: false,
+            remote: None,
+            mode: BackupMode::Full,
+            parent: None,
+            workers: default_workers(),
+            max_mb_per_sec: None,
+            background: false,
+            streams: false,
+            shadow_copy: false,
+            filters: Filters::default(),
+            on_error: ErrorPolicy::Auto,
+            interactive: true,
+        }
+    }
+}
+
+pub struct BackupSummary {
+    pub archive: PathBuf,
+    pub original_bytes: u64,
+    pub archive_bytes: u64,
+    pub elapsed: Duration,
+    // special files, and links with the skip policy
+    pub skipped: LeftOut,
+    // left out of an incremental because the parent has them
+    pub unchanged: u32,
+    // couldn't be read, with the reason. only files other programs kept
+    // busy unless the run was headless
+    pub failed: ErrorLog,
+    // names stored once for files with several
+    pub hard_links: u32,
+    // continued where a crashed run stopped
+    pub resumed: bool,
+    // what was skipped or failed and why, next to the archive
+    pub report_file: Option<PathBuf>,
+    // where the copy in the remote folder went, or why it didn't
+    pub uploaded: Option<Result<PathBuf, String>>,
+}
+
+impl BackupSummary {
+    // archive size as a fraction of the original data, 1.0 == no savings
+    pub fn ratio(&self) -> f64 {
+        if self.original_bytes == 0 {
+            return 1.0;
+        }
+        self.archive_bytes as f64 / self.original_bytes as f64
+    }
+
+    pub fn report(&self) -> String {
+        let mut report = format!(
+            "{} → {} ({:.1}% of original) in {:.1}s",
+            format_bytes(self.original_bytes),
+            format_bytes(self.archive_bytes),
+            self.ratio() * 100.0,
+            self.elapsed.as_secs_f64()
+        );
+        if self.unchanged > 0 {
+            report.push_str(&format!(
+                "\n{} unchanged files left to the parent",
+                self.unchanged
+            ));
+        }
+        if !self.skipped.is_empty() {
+            report.push_str(&format!(
+                "\n{} special files and links skipped",
+                self.skipped.len()
+            ));
+        }
+        if self.hard_links > 0 {
+            report.push_str(&format!(
+                "\n{} hard links stored without their data",
+                self.hard_links
+            ));
+        }
+        if !self.failed.is_empty() {
+            report.push_str(&format!(
+                "\n{} files couldn't be read and were left out",
+                self.failed.len()
+            ));
+        }
+        if self.resumed {
+            report.push_str("\nContinued an interrupted backup");
+        }
+        if let Some(file) = &self.report_file {
+            report.push_str(&format!("\nDetails in {}", file.display()));
+        }
+        match &self.uploaded {
+            Some(Ok(copy)) => report.push_str(&format!("\nCopied to {}", copy.display())),
+            Some(Err(e)) => report.push_str(&format!(
+                "\nCouldn't copy it to the remote folder, the next backup tries again: {e}"
+            )),
+            None => {}
+        }
+        report
+    }
+}
+
+#[cfg(unix)]
+fn special_kind(file_type: &fs::FileType) -> Option<&'static str> {
+    use std::os::unix::fs::FileTypeExt;
+
+    if file_type.is_fifo() {
+        Some("fifo")
+    } else if file_type.is_socket() {
+        Some("socket")
+    } else if file_type.is_block_device() {
+        Some("block device")
+    } else if file_type.is_char_device() {
+        Some("character device")
+    } else {
+        None
+    }
+}
+
+#[cfg(not(unix))]
+fn special_kind(_file_type: &fs::FileType) -> Option<&'static str> {
+    None
+}
+
+// (device, file id) of a file with more than one name
+#[cfg(unix)]
+fn hard_link_id(_path: &Path, metadata: &fs::Metadata) -> Option<(u64, u64)> {
+    use std::os::unix::fs::MetadataExt;
+
+    (metadata.nlink() > 1).then(|| (metadata.dev(), metadata.ino()))
+}
+
+// std only has the link count on nightly, windows gives it for an open file
+#[cfg(windows)]
+fn hard_link_id(path: &Path, _metadata: &fs::Metadata) -> Option<(u64, u64)> {
+    use std::os::windows::io::AsRawHandle;
+    use windows_sys::Win32::Storage::FileSystem::{
+        BY_HANDLE_FILE_INFORMATION, GetFileInformationByHandle,
+    };
+
+    let file = File::open(path).ok()?;
+    let mut info: BY_HANDLE_FILE_INFORMATION = unsafe { std::mem::zeroed() };
+    if unsafe { GetFileInformationByHandle(file.as_raw_handle() as _, &mut info) } == 0 {
+        return None;
+    }
+    (info.nNumberOfLinks > 1).then(|| {
+        let index = ((info.nFileIndexHigh as u64) << 32) | info.nFileIndexLow as u64;
+        (info.dwVolumeSerialNumber as u64, index)
+    })
+}
+
+#[cfg(not(any(unix, windows)))]
+fn hard_link_id(_path: &Path, _metadata: &fs::Metadata) -> Option<(u64, u64)> {
+    None
+}
+
+pub fn mtime_secs(metadata: &fs::Metadata) -> u64 {
+    metadata
+        .modified()
+        .ok()
+        .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+        .map_or(0, |d| d.as_secs())
+}
+
+// fails up front instead of after an hour of packing, and before anything
+// is written to a drive that has been pulled out again
+pub fn check_destination(dir: &Path) -> Result<(), String> {
+    let probe = dir.join(format!(".konserve-{}", std::process::id()));
+    fs::create_dir_all(dir)
+        .and_then(|()| fs::write(&probe, b""))
+        .and_then(|()| fs::remove_file(&probe))
+        .map_err(|e| {
+            format!(
+                "Can't write to {}: {e}. Pick another folder or check its permissions.",
+                dir.display()
+            )
+        })
+}
+
+// a single file backed up on its own is "<uuid>.<ext>", anything in a
+// folder "<uuid>/<path below the folder>"
+fn entry_name(uuid: &Uuid, root: &Path, path: &Path) -> String {
+    if path == root {
+        return match root.extension().and_then(|e| e.to_str()) {
+            Some(ext) => format!("{uuid}.{ext}"),
+            None => uuid.to_string(),
+        };
+    }
+    let relative = path.strip_prefix(root).unwrap_or(path);
+    Path::new(&uuid.to_string())
+        .join(names::escape(relative))
+        .to_string_lossy()
+        .into_owned()
+}
+
+// how long to wait before each new try at a file another program has open
+const BUSY_RETRIES: [Duration; 4] = [
+    Duration::from_millis(250),
+    Duration::from_millis(500),
+    Duration::from_secs(1),
+    Duration::from_secs(2),
+];
+
+// open without sharing by another program, which usually passes soon
+fn is_busy(e: &io::Error) -> bool {
+    // ERROR_SHARING_VIOLATION and ERROR_LOCK_VIOLATION
+    if cfg!(windows) && matches!(e.raw_os_error(), Some(32 | 33)) {
+        return true;
+    }
+    matches!(
+        e.kind(),
+        io::ErrorKind::ResourceBusy | io::ErrorKind::ExecutableFileBusy | io::ErrorKind::WouldBlock
+    )
+}
+
+fn open_retrying(path: &Path) -> io::Result<File> {
+    let mut waits = BUSY_RETRIES.iter();
+    loop {
+        match File::open(path) {
+            Err(e) if is_busy(&e) => {
+                let Some(wait) = waits.next() else {
+                    return Err(e);
+                };
+                debug!("{} is in use, trying again in {wait:?}", path.display());
+                thread::sleep(*wait);
+            }
+            opened => return opened,
+        }
+    }
+}
+
+// by default a file still in use after the retries is left out even when
+// someone is watching, one busy file shouldn't cost the whole backup.
+// anything else only on headless runs
+fn leave_out(
+    failed: &mut ErrorLog,
+    options: &BackupOptions,
+    path: &Path,
+    e: &io::Error,
+) -> Result<(), String> {
+    let busy = is_busy(e);
+    let error = if busy {
+        format!("{}: in use by another program ({e})", path.display())
+    } else {
+        format!("{}: {e}", path.display())
+    };
+    let skip = match options.on_error {
+        ErrorPolicy::Auto => busy || !options.interactive,
+        ErrorPolicy::Abort => false,
+        ErrorPolicy::Skip => true,
+    };
+    if !skip {
+        return Err(error);
+    }
+    warn!("Leaving out {error}");
+    failed.push(Problem::io(path, "read", e, error));
+    Ok(())
+}
+
+// hashes every file that has no hash yet, spread over `workers` threads
+fn hash_missing(
+    files: &[(PathBuf, FileState)],
+    workers: usize,
+    throttle: &Throttle,
+    progress: &Progress,
+    background: bool,
+    shadows: &Shadows,
+) -> Vec<Option<io::Result<String>>> {
+    let next = AtomicUsize::new(0);
+    let results: Vec<Mutex<Option<io::Result<String>>>> =
+        files.iter().map(|_| Mutex::new(None)).collect();
+    thread::scope(|scope| {
+        for _ in 0..workers.max(1) {
+            scope.spawn(|| {
+                if background {
+                    priority::background();
+                }
+                loop {
+                    let i = next.fetch_add(1, Ordering::Relaxed);
+                    let Some((path, state)) = files.get(i) else {
+                        return;
+                    };
+                    if state.hash.is_none() {
+                        let hash = open_retrying(&shadows.source(path)).and_then(|f| {
+                            incremental::hash_reader(&mut progress.reading(throttle.reading(f)))
+                        });
+                        *results[i].lock().unwrap() = Some(hash);
+                    }
+                }
+            });
+        }
+    });
+    results
+        .into_iter()
+        .map(|r| r.into_inner().unwrap())
+        .collect()
+}
+
+// the parent's file list and hard links, plus its key when it has a
+// password so the whole chain opens with one
+fn read_parent(
+    parent: &Path,
+    options: &BackupOptions,
+) -> Result<(Manifest, HardLinks, Option<Key>), String> {
+    if gpg::is_gpg(parent) {
+        return Err("Incremental backups can't build on a gpg encrypted backup.".into());
+    }
+    let key = match crypto::protection(parent)? {
+        None => None,
+        Some(Protection::Password) => {
+            let password = options
+                .password
+                .as_deref()
+                .ok_or("The previous backup is password protected, enter its password.")?;
+            Some(crypto::unlock(parent, password)?)
+        }
+        Some(Protection::PublicKey) => {
+            return Err("Incremental backups can't build on a public key encrypted backup.".into());
+        }
+    };
+
+    let txt = archive::read_fingerprint(parent, key.as_ref())?
+        .ok_or("The previous backup has no fingerprint.")?;
+    if options.mode == BackupMode::Differential && incremental::parent_name(&txt).is_some() {
+        return Err("A differential backup needs a full backup as its base.".into());
+    }
+    let manifest = incremental::parse_manifest(&txt)
+        .ok_or("The previous backup has no file list, make a full backup first.")?;
+    Ok((manifest, incremental::parse_hard_links(&txt), key))
 }
 
 pub fn backup_gui(
     folders: &[PathBuf],
     output_dir: &Path,
     progress: &Progress,
-) -> Result<PathBuf, String> {
-    println!("[DEBUG] backup_gui: Started");
-    println!("[DEBUG] Output directory: {}", output_dir.display());
+    options: &BackupOptions,
+) -> Result<BackupSummary, String> {
+    debug!("backup_gui: Started");
+    let started = Instant::now();
+    debug!("Output directory: {}", output_dir.display());
+    check_destination(output_dir)?;
+    let excludes = Excludes::new(&options.filters)?;
+    // the compressing threads zstd starts inherit it on linux
+    if options.background {
+        priority::background();
+    }
 
-    let timestamp = Local::now().format("%Y-%m-%d_%H-%M-%S");
-    let zip_name = format!("backup_{}.tar", timestamp);
-    let zip_path = output_dir.join(&zip_name);
-    println!("[DEBUG] Creating backup archive: {}", zip_path.display());
+    let parent = options
+        .parent
+        .as_ref()
+        .filter(|_| options.mode != BackupMode::Full);
+    if options.mode != BackupMode::Full && parent.is_none() {
+        return Err(format!(
+            "No base backup chosen for the {} backup.",
+            options.mode.label().to_lowercase()
+        ));
+    }
+    let (parent_manifest, parent_links, parent_key) = match parent {
+        Some(parent) => {
+            debug!("{} on top of {}", options.mode.label(), parent.display());
+            let (manifest, links, key) = read_parent(parent, options)?;
+            (Some(manifest), links, key)
+        }
+        None => (None, HardLinks::new(), None),
+    };
+
+    let key = match &options.password {
+        _ if !options.recipients.is_empty() => {
+            debug!("Encrypting to {} recipients", options.recipients.len());
+            Some(Key::for_recipients(&options.recipients)?)
+        }
+        Some(_) if parent_key.is_some() => parent_key,
+        Some(password) => {
+            debug!("Deriving encryption key");
+            Some(Key::new(password)?)
+        }
+        None => None,
+    };
 
-    let tar_file = File::create(&zip_path).map_err(|e| e.to_string())?;
-    let mut tar_builder = Builder::new(tar_file);
+    // only an unencrypted plain tar can be cut after an entry and continued
+    let resumable = options.format == ArchiveFormat::Tar && key.is_none();
+    let unfinished = resumable
+        .then(|| journal::find(output_dir, folders, options.mode, parent))
+        .flatten();
+    // the file list is in the unfinished archive already, whatever changed
+    // since is stored the way it is now
+    let (listed, mut hard_links, mut attributes, mut extra, mut holes) = match &unfinished {
+        Some(unfinished) => {
+            let txt = archive::read_fingerprint(&unfinished.archive, None)?
+                .ok_or("The unfinished backup has no fingerprint.")?;
+            let manifest = incremental::parse_manifest(&txt)
+                .ok_or("The unfinished backup has no file list.")?;
+            (
+                Some(manifest.into_iter().collect::<Vec<_>>()),
+                incremental::parse_hard_links(&txt),
+                incremental::parse_attributes(&txt),
+                streams::parse_streams(&txt),
+                sparse::parse_sparse(&txt),
+            )
+        }
+        None => (
+            None,
+            HardLinks::new(),
+            Attributes::new(),
+            Vec::new(),
+            Sparse::new(),
+        ),
+    };
+
+    let zip_path = match &unfinished {
+        Some(unfinished) => unfinished.archive.clone(),
+        None => {
+            let timestamp = Local::now().format("%Y-%m-%d_%H-%M-%S");
+            output_dir.join(format!(
+                "backup_{}.{}",
+                timestamp,
+                options.format.extension()
+            ))
+        }
+    };
+    let mut writer = match &unfinished {
+        Some(unfinished) => {
+            info!(
+                "Continuing {} from byte {}",
+                zip_path.display(),
+                unfinished.offset
+            );
+            archive::append_tar(&zip_path, unfinished.offset)?
+        }
+        None => {
+            debug!("Creating backup archive: {}", zip_path.display());
+            archive::create(
+                options.format,
+                options.zstd_level,
+                options.workers,
+                &zip_path,
+                key.as_ref(),
+            )?
+        }
+    };
 
     let mut fingerprint_content = format!("{}\n[Backup Info]\n", get_fingered());
 
-    // folders to uuid
-    let folder_uuid: Vec<(Uuid, &PathBuf)> = folders
+    // folders to uuid, the same ones again when continuing
+    let folder_uuid: Vec<(Uuid, &PathBuf)> = match &unfinished {
+        Some(unfinished) => unfinished.uuids.iter().copied().zip(folders).collect(),
+        None => folders
+            .iter()
+            .map(|folder| {
+                let uuid = Uuid::new_v4();
+                debug!("Assigned UUID {} to {}", uuid, folder.display());
+                (uuid, folder)
+            })
+            .collect(),
+    };
+
+    let mut journal = match &unfinished {
+        Some(unfinished) => Some(Journal::reopen(unfinished)?),
+        None if resumable => Journal::create(&zip_path, &folder_uuid, options.mode, parent)
+            .inspect_err(|e| warn!("No journal, this backup can't be continued: {e}"))
+            .ok(),
+        None => None,
+    };
+    let resumed = unfinished.is_some();
+    let done_before = unfinished.map(|u| u.done).unwrap_or_default();
+
+    let mut failed = ErrorLog::new();
+
+    // taken before the scan, anything it finds is in the snapshot already
+    let shadows = Arc::new(if options.shadow_copy {
+        Shadows::create(folders)
+    } else {
+        Shadows::default()
+    });
+
+    // listed up front, the fingerprint carrying the file list goes in first
+    let scan = listed.is_none();
+    let mut found: Vec<(PathBuf, FileState)> = Vec::new();
+    // the first name of every file with several, in walk order
+    let mut first_names: HashMap<(u64, u64), PathBuf> = HashMap::new();
+    for entry in folders
         .iter()
-        .map(|folder| {
-            let uuid = Uuid::new_v4();
-            println!("[DEBUG] Assigned UUID {} to {}", uuid, folder.display());
-            (uuid, folder)
-        })
+        .filter(|_| scan)
+        .flat_map(|root| excludes.walk(root))
+    {
+        let Ok(metadata) = entry.metadata() else {
+            continue;
+        };
+        let flags = archive::file_flags(&metadata);
+        if !flags.is_empty() {
+            attributes.insert(entry.path().to_path_buf(), flags);
+        }
+        if options.streams {
+            extra.extend(streams::list(entry.path(), extra.len()));
+        }
+        if !metadata.is_file() {
+            continue;
+        }
+        let (mtime, size) = (mtime_secs(&metadata), metadata.len());
+        // a file picked on its own is always stored whole
+        if entry.depth() > 0
+            && let Some(id) = hard_link_id(entry.path(), &metadata)
+        {
+            match first_names.get(&id) {
+                Some(first) => {
+                    debug!(
+                        "Hard link: {} -> {}",
+                        entry.path().display(),
+                        first.display()
+                    );
+                    hard_links.insert(entry.path().to_path_buf(), first.clone());
+                }
+                None => {
+                    first_names.insert(id, entry.path().to_path_buf());
+                }
+            }
+        }
+        // the parent has no data for its hard links
+        let previous = parent_manifest
+            .as_ref()
+            .filter(|_| !parent_links.contains_key(entry.path()))
+            .and_then(|m| m.get(entry.path()))
+            .filter(|p| p.mtime == mtime && p.size == size);
+        let stored = if previous.is_some() {
+            Stored::Parent
+        } else {
+            Stored::Here
+        };
+        if stored == Stored::Here
+            && !hard_links.contains_key(entry.path())
+            && let Some(layout) = sparse::detect(&shadows.source(entry.path()), &metadata)
+        {
+            debug!(
+                "Sparse: {}, {} of {} allocated",
+                entry.path().display(),
+                format_bytes(layout.packed_len()),
+                format_bytes(layout.size)
+            );
+            holes.insert(entry.path().to_path_buf(), layout);
+        }
+        found.push((
+            entry.into_path(),
+            FileState {
+                stored,
+                mtime,
+                size,
+                // unchanged files keep the hash the parent took
+                hash: previous.and_then(|p| p.hash.clone()),
+            },
+        ));
+    }
+    // the whole job is known before any of it starts: hashing reads what
+    // has no hash yet, then everything not in the parent is stored
+    let stored_up_front: Vec<&FileState> = listed
+        .iter()
+        .flatten()
+        .chain(&found)
+        .map(|(_, state)| state)
+        .filter(|state| state.stored == Stored::Here)
         .collect();
+    let to_hash: u64 = found
+        .iter()
+        .filter(|(_, state)| state.hash.is_none())
+        .map(|(_, state)| state.size)
+        .sum();
+    let to_store: u64 = stored_up_front.iter().map(|state| state.size).sum();
+    progress.set_total_bytes(to_hash + to_store);
+    progress.set_total_files(stored_up_front.len() as u64);
+    debug!(
+        "Scanned: {} files to store, {} to hash, {} to store",
+        stored_up_front.len(),
+        format_bytes(to_hash),
+        format_bytes(to_store)
+    );
 
-    let total_files: u32 = folders
+    let throttle = Throttle::new(options.max_mb_per_sec);
+    let hashed = hash_missing(
+        &found,
+        options.workers,
+        &throttle,
+        progress,
+        options.background,
+        &shadows,
+    );
+    let mut files: Vec<(PathBuf, FileState)> = listed.unwrap_or_default();
+    for ((path, mut state), hash) in found.into_iter().zip(hashed) {
+        match hash {
+            Some(Ok(hash)) => state.hash = Some(hash),
+            Some(Err(e)) => {
+                leave_out(&mut failed, options, &path, &e)?;
+                continue;
+            }
+            None => {}
+        }
+        files.push((path, state));
+    }
+    let left_out: HashSet<PathBuf> = failed.iter().map(|problem| problem.path.clone()).collect();
+    // with include patterns only the folders leading to a file go in
+    let holding_files: HashSet<&Path> = files
         .iter()
-        .flat_map(|p| WalkDir::new(p).into_iter().filter_map(Result::ok))
-        .filter(|e| e.file_type().is_file())
-        .count()
-        .max(1) as u32;
+        .filter(|_| excludes.has_includes())
+        .flat_map(|(path, _)| path.ancestors().skip(1))
+        .collect();
+    let unchanged: HashSet<&Path> = files
+        .iter()
+        .filter(|(_, state)| state.stored == Stored::Parent)
+        .map(|(path, _)| path.as_path())
+        .collect();
+
+    let total_bytes: u64 = files
+        .iter()
+        .filter(|(_, state)| state.stored == Stored::Here)
+        .map(|(_, state)| state.size)
+        .sum();
+    let stored_bytes = |done: u64| {
+        progress.event(ProgressEvent::Bytes {
+            done,
+            total: total_bytes,
+            percent: (done * 100).checked_div(total_bytes).unwrap_or(100) as u32,
+        })
+    };
 
-    let mut done = 0u32;
+    let mut original_bytes = 0u64;
+    let mut skipped = LeftOut::new();
 
     // generate fingerprint content
     for (uuid, original_path) in &folder_uuid {
-        fingerprint_content.push_str(&format!("{}: {}\n", uuid, original_path.display()));
+        fingerprint_content.push_str(&format!("{uuid}: {}\n", names::escape(original_path)));
+    }
+    if let Some(parent) = parent.and_then(|p| p.file_name()) {
+        fingerprint_content.push_str(&format!("[Parent]\n{}\n", parent.to_string_lossy()));
     }
+    fingerprint_content.push_str(names::format_names());
+    fingerprint_content.push_str(&incremental::format_manifest(&files));
+    fingerprint_content.push_str(&incremental::format_hard_links(&hard_links));
+    fingerprint_content.push_str(&incremental::format_attributes(&attributes));
+    fingerprint_content.push_str(&streams::format_streams(&extra));
+    fingerprint_content.push_str(&sparse::format_sparse(&holes));
 
     // write fingerprint.txt
-    let mut fingerprint_header = Header::new_gnu();
-    fingerprint_header.set_size(fingerprint_content.len() as u64);
-    fingerprint_header.set_mode(0o644);
-    fingerprint_header.set_mtime(Local::now().timestamp() as u64);
-    fingerprint_header.set_cksum();
-
-    tar_builder
-        .append_data(
-            &mut fingerprint_header,
-            "fingerprint.txt",
-            fingerprint_content.as_bytes(),
-        )
-        .map_err(|e| e.to_string())?;
-    println!("[DEBUG] fingerprint.txt added to archive");
+    if !done_before.contains("fingerprint.txt") {
+        writer.add_bytes("fingerprint.txt", fingerprint_content.as_bytes())?;
+        debug!("fingerprint.txt added to archive");
+        if let Some(journal) = &mut journal {
+            journal.entry_done("fingerprint.txt", writer.as_mut());
+        }
+    }
+
+    // a cancelled backup leaves nothing half written behind
+    let cancelled = |writer: Box<dyn archive::ArchiveWriter>, journal: Option<Journal>| {
+        drop(writer);
+        let _ = fs::remove_file(&zip_path);
+        if let Some(journal) = journal {
+            journal.remove();
+        }
+        warn!("Backup cancelled, removed {}", zip_path.display());
+        Err("Backup cancelled.".to_string())
+    };
+
+    // what the loop below will store, in the order it gets there: a walk
+    // sorted by name visits paths in their sort order
+    let mut ahead: Vec<(PathBuf, u64)> = Vec::new();
+    for (uuid, root) in &folder_uuid {
+        let mut under: Vec<&(PathBuf, FileState)> = files
+            .iter()
+            .filter(|(path, state)| path.starts_with(root) && state.stored == Stored::Here)
+            .filter(|(path, _)| !hard_links.contains_key(path) && !holes.contains_key(path))
+            .filter(|(path, _)| !done_before.contains(&entry_name(uuid, root, path)))
+            .collect();
+        under.sort_by(|a, b| a.0.cmp(&b.0));
+        ahead.extend(
+            under
+                .into_iter()
+                .map(|(path, state)| (path.clone(), state.size)),
+        );
+    }
+    // the entries tar hard links point at
+    let link_targets: HashMap<&Path, String> = hard_links
+        .values()
+        .filter_map(|first| {
+            let (uuid, root) = folder_uuid
+                .iter()
+                .find(|(_, root)| first.starts_with(root))?;
+            Some((first.as_path(), entry_name(uuid, root, first)))
+        })
+        .collect();
+    let mut read_ahead = ReadAhead::start(
+        ahead,
+        options.workers,
+        throttle,
+        options.background,
+        shadows.clone(),
+    );
 
     for (uuid, original_path) in folder_uuid {
+        progress.wait_if_paused();
+        if progress.is_cancelled() {
+            return cancelled(writer, journal.take());
+        }
         if original_path.is_file() {
-            println!("[DEBUG] Adding single file: {}", original_path.display());
-
+            if unchanged.contains(original_path.as_path()) {
+                debug!("Unchanged: {}", original_path.display());
+                continue;
+            }
+            if left_out.contains(original_path) {
+                continue;
+            }
             let metadata = original_path.metadata().map_err(|e| e.to_string())?;
-            let mut header = Header::new_gnu();
-            header.set_metadata(&metadata);
-            header.set_cksum();
+            let entry_name = entry_name(&uuid, original_path, original_path);
 
-            let mut f = File::open(original_path).map_err(|e| e.to_string())?;
+            if done_before.contains(&entry_name) {
+                debug!(
+                    "Stored before the interruption: {}",
+                    original_path.display()
+                );
+                progress.add_bytes(metadata.len());
+                progress.count_file();
+            } else {
+                debug!("Adding single file: {}", original_path.display());
+                progress.file_started(original_path);
+                let data = match read_ahead.open(original_path, holes.get(original_path)) {
+                    Ok(data) => data,
+                    Err(e) => {
+                        leave_out(&mut failed, options, original_path, &e)?;
+                        continue;
+                    }
+                };
+                let mut data = progress.reading(data);
+                debug!("-> Entry name in archive: {}", entry_name);
 
-            let entry_name = match original_path.extension().and_then(|e| e.to_str()) {
-                Some(ext) => format!("{}.{}", uuid, ext),
-                None => uuid.to_string(),
-            };
-            println!("[DEBUG] -> Entry name in tar: {}", entry_name);
-
-            tar_builder
-                .append_data(&mut header, entry_name, &mut f)
-                .map_err(|e| e.to_string())?;
+                let size = holes
+                    .get(original_path)
+                    .map_or(metadata.len(), Layout::packed_len);
+                writer.add_file(Path::new(&entry_name), &metadata, size, &mut data)?;
+                if let Some(journal) = &mut journal {
+                    journal.entry_done(&entry_name, writer.as_mut());
+                }
+            }
 
-            done += 1;
-            progress.set(done * 100 / total_files);
+            original_bytes += metadata.len();
+            stored_bytes(original_bytes);
 
             continue;
         }
 
-        println!("[DEBUG] Walking folder: {}", original_path.display());
+        debug!("Walking folder: {}", original_path.display());
 
-        for entry in WalkDir::new(original_path)
-            .into_iter()
-            .filter_map(Result::ok)
-        {
+        // sorted so every run writes the same entry order, parents first
+        for entry in excludes.walk(original_path) {
+            progress.wait_if_paused();
+            if progress.is_cancelled() {
+                return cancelled(writer, journal.take());
+            }
             let entry_path = entry.path();
             let metadata = entry.metadata().map_err(|e| e.to_string())?;
+
+            // reading a fifo blocks forever and devices/sockets have no data to store
+            if let Some(kind) = special_kind(&metadata.file_type()) {
+                debug!("Skipping {kind}: {}", entry_path.display());
+                skipped.push((
+                    entry_path.to_path_buf(),
+                    format!("{}: {kind}", entry_path.display()),
+                ));
+                continue;
+            }
+
             let relative_path = entry_path.strip_prefix(original_path).unwrap();
-            let tar_entry_path = Path::new(&uuid.to_string()).join(relative_path);
-
-            let mut header = Header::new_gnu();
-            header.set_metadata(&metadata);
-            header.set_cksum();
-
-            if metadata.is_file() {
-                println!("[DEBUG] Adding file: {}", entry_path.display());
-                let mut file = File::open(entry_path).map_err(|e| e.to_string())?;
-                tar_builder
-                    .append_data(&mut header, tar_entry_path, &mut file)
-                    .map_err(|e| e.to_string())?;
-
-                done += 1;
-                progress.set(done * 100 / total_files);
-            } else if metadata.is_dir() {
-                println!("[DEBUG] Adding directory: {}", entry_path.display());
-                tar_builder
-                    .append_data(&mut header, tar_entry_path, io::empty())
-                    .map_err(|e| e.to_string())?;
+            let archive_path = Path::new(&uuid.to_string()).join(names::escape(relative_path));
+            let entry_name = archive_path.to_string_lossy();
+
+            if metadata.is_file() && unchanged.contains(entry_path) {
+                debug!("Unchanged: {}", entry_path.display());
+            } else if metadata.is_file() && left_out.contains(entry_path) {
+                debug!("Left out: {}", entry_path.display());
+            } else if metadata.is_file() && done_before.contains(entry_name.as_ref()) {
+                debug!("Stored before the interruption: {}", entry_path.display());
+                original_bytes += metadata.len();
+                progress.add_bytes(metadata.len());
+                progress.count_file();
+            } else if metadata.is_file()
+                && let Some(first) = hard_links.get(entry_path)
+            {
+                debug!("Adding hard link: {}", entry_path.display());
+                let stored = link_targets.get(first.as_path()).map_or("", String::as_str);
+                writer.add_hard_link(&archive_path, Path::new(stored), &metadata)?;
+                if let Some(journal) = &mut journal {
+                    journal.entry_done(&entry_name, writer.as_mut());
+                }
+                progress.add_bytes(metadata.len());
+                progress.count_file();
+            } else if metadata.is_file() {
+                debug!("Adding file: {}", entry_path.display());
+                progress.file_started(entry_path);
+                let data = match read_ahead.open(entry_path, holes.get(entry_path)) {
+                    Ok(data) => data,
+                    Err(e) => {
+                        leave_out(&mut failed, options, entry_path, &e)?;
+                        continue;
+                    }
+                };
+                let mut data = progress.reading(data);
+                let size = holes
+                    .get(entry_path)
+                    .map_or(metadata.len(), Layout::packed_len);
+                writer.add_file(&archive_path, &metadata, size, &mut data)?;
+                if let Some(journal) = &mut journal {
+                    journal.entry_done(&entry_name, writer.as_mut());
+                }
+
+                original_bytes += metadata.len();
+                stored_bytes(original_bytes);
+            } else if metadata.is_symlink() {
+                // only seen when links aren't followed
+                if options.filters.symlinks == SymlinkPolicy::Skip {
+                    warn!("Skipping symlink: {}", entry_path.display());
+                    skipped.push((
+                        entry_path.to_path_buf(),
+                        format!("{}: symlink", entry_path.display()),
+                    ));
+                } else if !done_before.contains(entry_name.as_ref()) {
+                    let target = fs::read_link(entry_path)
+                        .map_err(|e| format!("{}: {e}", entry_path.display()))?;
+                    debug!(
+                        "Adding symlink: {} -> {}",
+                        entry_path.display(),
+                        target.display()
+                    );
+                    writer.add_symlink(&archive_path, &target, &metadata)?;
+                    if let Some(journal) = &mut journal {
+                        journal.entry_done(&entry_name, writer.as_mut());
+                    }
+                }
+            } else if metadata.is_dir()
+                && excludes.has_includes()
+                && !holding_files.contains(entry_path)
+            {
+                debug!("No included files in: {}", entry_path.display());
+            } else if metadata.is_dir() && !done_before.contains(entry_name.as_ref()) {
+                debug!("Adding directory: {}", entry_path.display());
+                writer.add_dir(&archive_path, &metadata)?;
+                if let Some(journal) = &mut journal {
+                    journal.entry_done(&entry_name, writer.as_mut());
+                }
+            }
+        }
+    }
+
+    // after everything else, a restore has the files they belong to by then
+    for stream in &extra {
+        progress.wait_if_paused();
+        if progress.is_cancelled() {
+            return cancelled(writer, journal.take());
+        }
+        if done_before.contains(&stream.entry) || left_out.contains(&stream.path) {
+            continue;
+        }
+        match streams::read(stream) {
+            Ok(data) => {
+                debug!("Adding stream: {}", stream.entry);
+                writer.add_bytes(&stream.entry, &data)?;
+                if let Some(journal) = &mut journal {
+                    journal.entry_done(&stream.entry, writer.as_mut());
+                }
             }
+            Err(e) => warn!("Stream left out: {e}"),
         }
     }
 
-    tar_builder.finish().map_err(|e| e.to_string())?;
-    println!("[DEBUG] Archive finished: {}", zip_path.display());
+    writer.finish()?;
+    debug!("Archive finished: {}", zip_path.display());
+    if let Some(journal) = journal.take() {
+        journal.remove();
+    }
+
+    let zip_path = match &options.gpg_recipient {
+        // a failed gpg run shouldn't leave an unencrypted copy behind
+        Some(recipient) => gpg::encrypt(&zip_path, recipient).inspect_err(|_| {
+            let _ = fs::remove_file(&zip_path);
+        })?,
+        None => zip_path,
+    };
+
+    if options.sign {
+        signing::sign(&zip_path)?;
+    }
+
+    // the local archive is complete either way, a failed copy is reported
+    // rather than failing the backup
+    let uploaded = options.remote.as_deref().map(|remote| {
+        remote::send(&zip_path, remote, progress)
+            .inspect_err(|e| warn!("Copy to {} failed: {e}", remote.display()))
+    });
+
+    let summary = BackupSummary {
+        archive_bytes: fs::metadata(&zip_path).map_err(|e| e.to_string())?.len(),
+        report_file: report::write_sidecar(&zip_path, "backup", &skipped, &failed),
+        archive: zip_path,
+        original_bytes,
+        elapsed: started.elapsed(),
+        skipped,
+        unchanged: unchanged.len() as u32,
+        failed,
+        hard_links: hard_links.len() as u32,
+        resumed,
+        uploaded,
+    };
+    debug!("Backup summary: {}", summary.report());
+
+    // the backup itself is done, a catalog that can't be written only loses history
+    if let Err(e) = Catalog::open().and_then(|mut catalog| {
+        catalog.record(
+            &summary,
+            options.mode.label(),
+            parent.map(PathBuf::as_path),
+            &files,
+        )
+    }) {
+        warn!(
+            "Couldn't add {} to the catalog: {e}",
+            summary.archive.display()
+        );
+    }
 
     progress.done();
 
-    Ok(zip_path)
-}
-
-// let file = File::create(&zip_path).map_err(|e| e.to_string())?;
-// let mut zip = ZipWriter::new(file);
-// let options: FileOptions<'_, ()> = FileOptions::default().compression_method(
-//     CompressionMethod::Deflated
-// );
-
-// zip.start_file("fingerprint.txt", options).unwrap();
-// let mut fingerprint = format!("{}\n[Backup Info]\n", get_fingered());
-// for folder in folders {
-//     if let Some(name) = folder.file_name() {
-//         fingerprint.push_str(&format!(
-//             "{}: {}\n",
-//             name.to_string_lossy(),
-//             folder.display()
-//         ));
-//     }
-// }
-
-// zip.write_all(fingerprint.as_bytes()).unwrap();
-
-// for path in folders {
-//     if path.is_file() {
-//         let filename = path.file_name().unwrap().to_string_lossy();
-//         zip.start_file(filename, options).unwrap();
-//         let mut f = File::open(path).unwrap();
-//         io::copy(&mut f, &mut zip).unwrap();
-//     } else if path.is_dir() {
-//         for entry in WalkDir::new(path).into_iter().filter_map(Result::ok) {
-//             let entry_path = entry.path();
-//             let relative = match entry_path.strip_prefix(path) {
-//                 Ok(r) => r,
-//                 Err(_) => {
-//                     continue;
-//                 }
-//             };
-
-//             let zip_folder = path.file_name().unwrap();
-//             let final_path = Path::new(zip_folder).join(relative);
-
-//             if entry_path.is_file() {
-//                 zip.start_file(final_path.to_string_lossy(), options)
-//                     .unwrap();
-//                 let mut f = File::open(entry_path).unwrap();
-//                 io::copy(&mut f, &mut zip).unwrap();
-//             } else if !relative.as_os_str().is_empty() {
-//                 zip.add_directory(final_path.to_string_lossy(), options)
-//                     .unwrap();
-//             }
-//         }
-//     }
-//     }
-
-//     zip.finish().unwrap();
-//     Ok(zip_path)
-// }
+    Ok(summary)
+}
+
+// small files are read on worker threads ahead of the writer, so the disk
+// stays busy while the archive compresses. big ones the writer streams itself
+const READ_AHEAD_MAX_FILE: u64 = 8 * 1024 * 1024;
+// read but not written yet
+const READ_AHEAD_BUDGET: u64 = 64 * 1024 * 1024;
+
+#[derive(Default)]
+struct Window {
+    claimed: usize,
+    in_flight: u64,
+    stop: bool,
+}
+
+struct ReadAhead {
+    files: Arc<Vec<(PathBuf, u64)>>,
+    index: HashMap<PathBuf, usize>,
+    next: usize,
+    // read out of order, waiting for their turn
+    ready: BTreeMap<usize, io::Result<Vec<u8>>>,
+    rx: mpsc::Receiver<(usize, io::Result<Vec<u8>>)>,
+    window: Arc<(Mutex<Window>, Condvar)>,
+    throttle: Throttle,
+    shadows: Arc<Shadows>,
+}
+
+impl ReadAhead {
+    fn start(
+        files: Vec<(PathBuf, u64)>,
+        workers: usize,
+        throttle: Throttle,
+        background: bool,
+        shadows: Arc<Shadows>,
+    ) -> Self {
+        let files: Vec<(PathBuf, u64)> = files
+            .into_iter()
+            .filter(|(_, size)| *size <= READ_AHEAD_MAX_FILE)
+            .collect();
+        let files = Arc::new(files);
+        let window: Arc<(Mutex<Window>, Condvar)> = Arc::default();
+        let (tx, rx) = mpsc::channel();
+        for _ in 0..workers.max(1) {
+            let (files, window, tx) = (files.clone(), window.clone(), tx.clone());
+            let throttle = throttle.clone();
+            let shadows = shadows.clone();
+            thread::spawn(move || {
+                if background {
+                    priority::background();
+                }
+                let (lock, wake) = &*window;
+                loop {
+                    let i = {
+                        let mut window = lock.lock().unwrap();
+                        loop {
+                            let Some((_, size)) = files.get(window.claimed) else {
+                                return;
+                            };
+                            if window.stop {
+                                return;
+                            }
+                            // one file always fits, however big
+                            if window.in_flight == 0 || window.in_flight + size <= READ_AHEAD_BUDGET
+                            {
+                                window.in_flight += size;
+                                window.claimed += 1;
+                                break window.claimed - 1;
+                            }
+                            window = wake.wait(window).unwrap();
+                        }
+                    };
+                    let (path, size) = &files[i];
+                    let data = open_retrying(&shadows.source(path)).and_then(|f| {
+                        let mut data = Vec::with_capacity(*size as usize);
+                        throttle.reading(f).read_to_end(&mut data)?;
+                        Ok(data)
+                    });
+                    if tx.send((i, data)).is_err() {
+                        return;
+                    }
+                }
+            });
+        }
+
+        let index = files
+            .iter()
+            .enumerate()
+            .map(|(i, (path, _))| (path.clone(), i))
+            .collect();
+        Self {
+            files,
+            index,
+            next: 0,
+            ready: BTreeMap::new(),
+            rx,
+            window,
+            throttle,
+            shadows,
+        }
+    }
+
+    // what was read for `path`, None when it wasn't read ahead. files the
+    // writer never asked for are dropped on the way
+    fn take(&mut self, path: &Path) -> Option<io::Result<Vec<u8>>> {
+        let &wanted = self.index.get(path)?;
+        while self.next <= wanted {
+            let data = match self.ready.remove(&self.next) {
+                Some(data) => data,
+                None => {
+                    let (i, data) = self.rx.recv().ok()?;
+                    if i != self.next {
+                        self.ready.insert(i, data);
+                        continue;
+                    }
+                    data
+                }
+            };
+            let i = self.next;
+            self.next += 1;
+            let (lock, wake) = &*self.window;
+            lock.lock().unwrap().in_flight -= self.files[i].1;
+            wake.notify_all();
+            if i == wanted {
+                return Some(data);
+            }
+        }
+        None
+    }
+
+    // a sparse file only gives the ranges in its `layout`
+    fn open(&mut self, path: &Path, layout: Option<&Layout>) -> io::Result<Box<dyn Read>> {
+        match self.take(path) {
+            Some(data) => Ok(Box::new(Cursor::new(data?))),
+            None => {
+                let file = open_retrying(&self.shadows.source(path))?;
+                match layout {
+                    Some(layout) => Ok(Box::new(
+                        self.throttle.reading(sparse::Packed::new(file, layout)),
+                    )),
+                    None => Ok(Box::new(self.throttle.reading(file))),
+                }
+            }
+        }
+    }
+}
+
+impl Drop for ReadAhead {
+    fn drop(&mut self) {
+        let (lock, wake) = &*self.window;
+        lock.lock().unwrap().stop = true;
+        wake.notify_all();
+    }
+}
+
+// holds reads to a rate, shared by every thread reading for one backup
+#[derive(Clone)]
+struct Throttle {
+    // bytes per second, none reads as fast as the disk goes
+    rate: Option<f64>,
+    // when everything read so far would have been read at the rate
+    until: Arc<Mutex<Instant>>,
+}
+
+impl Throttle {
+    fn new(mb_per_sec: Option<u32>) -> Self {
+        Self {
+            rate: mb_per_sec.map(|mb| mb.max(1) as f64 * 1024.0 * 1024.0),
+            until: Arc::new(Mutex::new(Instant::now())),
+        }
+    }
+
+    fn take(&self, bytes: usize) {
+        let Some(rate) = self.rate else {
+            return;
+        };
+        let until = {
+            let mut until = self.until.lock().unwrap();
+            // time spent idle isn't saved up for a burst later
+            *until = (*until).max(Instant::now()) + Duration::from_secs_f64(bytes as f64 / rate);
+            *until
+        };
+        thread::sleep(until.saturating_duration_since(Instant::now()));
+    }
+
+    fn reading<R: Read>(&self, inner: R) -> Throttled<R> {
+        Throttled {
+            inner,
+            throttle: self.clone(),
+        }
+    }
+}
+
+struct Throttled<R> {
+    inner: R,
+    throttle: Throttle,
+}
+
+impl<R: Read> Read for Throttled<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.throttle.take(n);
+        Ok(n)
+    }
+}
@@ -0,0 +1,179 @@
+use std::{
+    fs::{self, File},
+    io::Read,
+    path::{Path, PathBuf},
+    sync::atomic::Ordering,
+    time::{Duration, Instant},
+};
+
+use log::{debug, info};
+
+use crate::archive::{self, ArchiveFormat, DEFAULT_ZSTD_LEVEL};
+use crate::exclude::{Excludes, Filters};
+use crate::helpers::Progress;
+
+// how much of the selection every format compresses. at most PER_FILE of a
+// file goes in so a few large ones don't stand for everything
+const SAMPLE_BYTES: u64 = 64 * 1024 * 1024;
+const PER_FILE: u64 = 4 * 1024 * 1024;
+
+// zstd is tried at these levels, the other formats have no setting
+const ZSTD_TRIALS: [i32; 4] = [1, DEFAULT_ZSTD_LEVEL, 9, 19];
+
+// a trial may take this many times as long as the fastest compressing one
+// and still win
+const SLOWEST_WINNER: u32 = 4;
+
+pub struct Trial {
+    pub format: ArchiveFormat,
+    pub zstd_level: i32,
+    pub size: u64,
+    pub elapsed: Duration,
+}
+
+impl Trial {
+    pub fn label(&self) -> String {
+        match self.format {
+            ArchiveFormat::TarZst => format!("{} {}", self.format.label(), self.zstd_level),
+            format => format.label().to_string(),
+        }
+    }
+}
+
+pub struct Benchmark {
+    pub files: usize,
+    // bytes of the sample, what every trial compressed
+    pub sample: u64,
+    pub trials: Vec<Trial>,
+}
+
+impl Benchmark {
+    pub fn ratio(&self, trial: &Trial) -> f64 {
+        trial.size as f64 / self.sample.max(1) as f64
+    }
+
+    // bytes of the sample per second
+    pub fn speed(&self, trial: &Trial) -> f64 {
+        self.sample as f64 / trial.elapsed.as_secs_f64().max(0.001)
+    }
+
+    // the smallest archive among the compressing trials that weren't much
+    // slower than the fastest of them. plain tar only copies, it would set a
+    // pace no codec keeps up with and is never the pick
+    pub fn winner(&self) -> Option<&Trial> {
+        let compressing = || {
+            self.trials
+                .iter()
+                .filter(|t| t.format != ArchiveFormat::Tar)
+        };
+        let fastest = compressing().map(|t| t.elapsed).min()?;
+        compressing()
+            .filter(|t| t.elapsed <= fastest * SLOWEST_WINNER)
+            .min_by_key(|t| t.size)
+    }
+}
+
+// files spread over the whole selection, found the way a backup finds them
+fn sample(paths: &[PathBuf], filters: &Filters) -> Result<Vec<(PathBuf, u64)>, String> {
+    let excludes = Excludes::new(filters)?;
+    let found: Vec<(PathBuf, u64)> = paths
+        .iter()
+        .flat_map(|root| excludes.walk(root))
+        .filter(|entry| entry.file_type().is_file())
+        .filter_map(|entry| {
+            let size = entry.metadata().ok()?.len().min(PER_FILE);
+            Some((entry.into_path(), size))
+        })
+        .collect();
+
+    // every nth file when all of them would be too much
+    let total: u64 = found.iter().map(|(_, size)| size).sum();
+    let step = total.div_ceil(SAMPLE_BYTES).max(1) as usize;
+    let mut taken = 0;
+    Ok(found
+        .into_iter()
+        .step_by(step)
+        .take_while(|(_, size)| {
+            let under = taken < SAMPLE_BYTES;
+            taken += size;
+            under
+        })
+        .collect())
+}
+
+fn read_head(path: &Path) -> Option<(fs::Metadata, Vec<u8>)> {
+    let file = File::open(path).ok()?;
+    let meta = file.metadata().ok()?;
+    let mut data = Vec::new();
+    file.take(PER_FILE).read_to_end(&mut data).ok()?;
+    Some((meta, data))
+}
+
+// compresses a sample of `paths` with every format and the zstd levels in
+// memory, so only the compression is timed and nothing is written to disk
+pub fn run(
+    paths: &[PathBuf],
+    filters: &Filters,
+    workers: usize,
+    progress: &Progress,
+) -> Result<Benchmark, String> {
+    let files: Vec<(PathBuf, fs::Metadata, Vec<u8>)> = sample(paths, filters)?
+        .into_iter()
+        .filter_map(|(path, _)| {
+            let (meta, data) = read_head(&path)?;
+            Some((path, meta, data))
+        })
+        .collect();
+    if files.is_empty() {
+        return Err("Nothing to compress in the selection.".into());
+    }
+    let sample: u64 = files.iter().map(|(_, _, data)| data.len() as u64).sum();
+    info!("bench: {} files, {} bytes sampled", files.len(), sample);
+
+    let candidates: Vec<(ArchiveFormat, i32)> = ArchiveFormat::ALL
+        .into_iter()
+        .flat_map(|format| match format {
+            ArchiveFormat::TarZst => ZSTD_TRIALS.map(|level| (format, level)).to_vec(),
+            format => vec![(format, DEFAULT_ZSTD_LEVEL)],
+        })
+        .collect();
+
+    let mut trials = Vec::new();
+    for (done, (format, zstd_level)) in candidates.iter().copied().enumerate() {
+        if progress.is_cancelled() {
+            return Err("Cancelled.".into());
+        }
+        let started = Instant::now();
+        let (mut writer, size) = archive::measure(format, zstd_level, workers)?;
+        for (index, (_, meta, data)) in files.iter().enumerate() {
+            writer.add_file(
+                Path::new(&index.to_string()),
+                meta,
+                data.len() as u64,
+                &mut data.as_slice(),
+            )?;
+        }
+        writer.finish()?;
+        let trial = Trial {
+            format,
+            zstd_level,
+            size: size.load(Ordering::Relaxed),
+            elapsed: started.elapsed(),
+        };
+        debug!(
+            "bench: {} {} bytes in {:?}",
+            trial.label(),
+            trial.size,
+            trial.elapsed
+        );
+        trials.push(trial);
+        progress.set(((done + 1) * 100 / candidates.len()) as u32);
+    }
+
+    trials.sort_by_key(|t| t.size);
+    Ok(Benchmark {
+        files: files.len(),
+        sample,
+        trials,
+    })
+}
@@ -0,0 +1,385 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use chrono::Local;
+use log::debug;
+use rusqlite::{Connection, TransactionBehavior, params};
+
+use crate::backup::BackupSummary;
+use crate::incremental::{FileState, Stored};
+
+// every backup made on this machine and the files in it, so old archives can
+// be found by what they hold instead of by browsing for them
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS backups (
+        id INTEGER PRIMARY KEY,
+        path TEXT NOT NULL UNIQUE,
+        created TEXT NOT NULL,
+        size INTEGER NOT NULL,
+        mode TEXT NOT NULL,
+        parent TEXT
+    );
+    CREATE TABLE IF NOT EXISTS files (
+        backup_id INTEGER NOT NULL REFERENCES backups(id) ON DELETE CASCADE,
+        path TEXT NOT NULL,
+        size INTEGER NOT NULL,
+        mtime INTEGER NOT NULL,
+        hash TEXT,
+        stored_here INTEGER NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS files_backup ON files(backup_id);
+    CREATE INDEX IF NOT EXISTS files_hash ON files(hash);
+";
+
+// applied in order to catalogs made before them, user_version counts how
+// many have run. backups recorded earlier have NULL in the new columns
+const MIGRATIONS: [&str; 2] = [
+    "
+    ALTER TABLE backups ADD COLUMN original_size INTEGER;
+    ALTER TABLE backups ADD COLUMN ratio REAL;
+    ALTER TABLE backups ADD COLUMN elapsed_ms INTEGER;
+    ",
+    // the file name lowercased the unicode way for search, filled in by
+    // fill_names. the index is narrower than the table, so a search scans it
+    // instead
+    "
+    ALTER TABLE files ADD COLUMN name TEXT;
+    CREATE INDEX files_name ON files(name);
+    ",
+];
+
+pub struct CatalogEntry {
+    pub id: i64,
+    pub path: PathBuf,
+    pub created: String,
+    pub size: u64,
+    pub mode: String,
+    pub files: u64,
+    // unknown for backups recorded by older versions
+    pub original_size: Option<u64>,
+    pub ratio: Option<f64>,
+    pub elapsed: Option<Duration>,
+}
+
+impl CatalogEntry {
+    // moved or deleted since it was made
+    pub fn exists(&self) -> bool {
+        self.path.exists()
+    }
+}
+
+pub struct SearchHit {
+    pub archive: PathBuf,
+    pub created: String,
+    // where the file was backed up from
+    pub path: PathBuf,
+    pub size: u64,
+    pub mtime: u64,
+}
+
+const SEARCH_LIMIT: usize = 500;
+
+pub struct Catalog {
+    conn: Connection,
+}
+
+fn user_version(conn: &Connection) -> Result<usize, String> {
+    conn.query_row("PRAGMA user_version", [], |r| r.get(0))
+        .map_err(|e| e.to_string())
+}
+
+fn migrate(conn: &mut Connection) -> Result<(), String> {
+    if user_version(conn)? >= MIGRATIONS.len() {
+        return Ok(());
+    }
+    // immediate, so of two programs opening an old catalog only one migrates it
+    let tx = conn
+        .transaction_with_behavior(TransactionBehavior::Immediate)
+        .map_err(|e| e.to_string())?;
+    let version = user_version(&tx)?;
+    for (done, migration) in MIGRATIONS.iter().enumerate().skip(version) {
+        debug!("Catalog: migrating to version {}", done + 1);
+        tx.execute_batch(migration).map_err(|e| e.to_string())?;
+    }
+    if version < 2 {
+        fill_names(&tx)?;
+    }
+    tx.pragma_update(None, "user_version", MIGRATIONS.len())
+        .map_err(|e| e.to_string())?;
+    tx.commit().map_err(|e| e.to_string())
+}
+
+// what search compares against, sqlite's lower() only knows ascii
+fn search_name(path: &Path) -> Option<String> {
+    Some(path.file_name()?.to_string_lossy().to_lowercase())
+}
+
+fn fill_names(conn: &Connection) -> Result<(), String> {
+    let mut select = conn
+        .prepare("SELECT rowid, path FROM files WHERE name IS NULL")
+        .map_err(|e| e.to_string())?;
+    let mut update = conn
+        .prepare("UPDATE files SET name = ?1 WHERE rowid = ?2")
+        .map_err(|e| e.to_string())?;
+    let rows = select
+        .query_map([], |r| Ok((r.get::<_, i64>(0)?, r.get::<_, String>(1)?)))
+        .map_err(|e| e.to_string())?;
+    for row in rows {
+        let (rowid, path) = row.map_err(|e| e.to_string())?;
+        update
+            .execute(params![search_name(Path::new(&path)), rowid])
+            .map_err(|e| e.to_string())?;
+    }
+    Ok(())
+}
+
+fn catalog_path() -> Option<PathBuf> {
+    Some(dirs::config_dir()?.join("Konserve").join("catalog.db"))
+}
+
+impl Catalog {
+    pub fn open() -> Result<Self, String> {
+        Self::open_at(&catalog_path().ok_or("No config directory")?)
+    }
+
+    pub fn open_at(path: &Path) -> Result<Self, String> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+        }
+        let mut conn = Connection::open(path).map_err(|e| e.to_string())?;
+        // two backups finishing together take turns instead of failing
+        conn.busy_timeout(Duration::from_secs(5))
+            .map_err(|e| e.to_string())?;
+        conn.execute_batch("PRAGMA foreign_keys = ON;")
+            .map_err(|e| e.to_string())?;
+        conn.execute_batch(SCHEMA).map_err(|e| e.to_string())?;
+        migrate(&mut conn)?;
+        Ok(Self { conn })
+    }
+
+    // a backup written again to the same path replaces the old record
+    pub fn record(
+        &mut self,
+        summary: &BackupSummary,
+        mode: &str,
+        parent: Option<&Path>,
+        files: &[(PathBuf, FileState)],
+    ) -> Result<i64, String> {
+        let archive = &summary.archive;
+        debug!("Catalog: recording {}", archive.display());
+        let tx = self.conn.transaction().map_err(|e| e.to_string())?;
+        tx.execute(
+            "DELETE FROM backups WHERE path = ?1",
+            params![archive.to_string_lossy()],
+        )
+        .map_err(|e| e.to_string())?;
+        tx.execute(
+            "INSERT INTO backups (path, created, size, mode, parent, original_size, ratio, elapsed_ms)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+            params![
+                archive.to_string_lossy(),
+                Local::now().format("%Y-%m-%d %H:%M:%S").to_string(),
+                summary.archive_bytes as i64,
+                mode,
+                parent.map(|p| p.to_string_lossy().into_owned()),
+                summary.original_bytes as i64,
+                summary.ratio(),
+                summary.elapsed.as_millis() as i64,
+            ],
+        )
+        .map_err(|e| e.to_string())?;
+        let id = tx.last_insert_rowid();
+        {
+            let mut insert = tx
+                .prepare(
+                    "INSERT INTO files (backup_id, path, size, mtime, hash, stored_here, name)
+                     VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+                )
+                .map_err(|e| e.to_string())?;
+            for (path, state) in files {
+                insert
+                    .execute(params![
+                        id,
+                        path.to_string_lossy(),
+                        state.size as i64,
+                        state.mtime as i64,
+                        state.hash,
+                        state.stored == Stored::Here,
+                        search_name(path),
+                    ])
+                    .map_err(|e| e.to_string())?;
+            }
+        }
+        tx.commit().map_err(|e| e.to_string())?;
+        Ok(id)
+    }
+
+    fn query(&self, filter: &str, arg: Option<&str>) -> Result<Vec<CatalogEntry>, String> {
+        let sql = format!(
+            "SELECT b.id, b.path, b.created, b.size, b.mode,
+                    (SELECT COUNT(*) FROM files f WHERE f.backup_id = b.id),
+                    b.original_size, b.ratio, b.elapsed_ms
+             FROM backups b {filter} ORDER BY b.created DESC, b.id DESC"
+        );
+        let mut stmt = self.conn.prepare(&sql).map_err(|e| e.to_string())?;
+        let row = |r: &rusqlite::Row| {
+            Ok(CatalogEntry {
+                id: r.get(0)?,
+                path: PathBuf::from(r.get::<_, String>(1)?),
+                created: r.get(2)?,
+                size: r.get::<_, i64>(3)? as u64,
+                mode: r.get(4)?,
+                files: r.get::<_, i64>(5)? as u64,
+                original_size: r.get::<_, Option<i64>>(6)?.map(|s| s as u64),
+                ratio: r.get(7)?,
+                elapsed: r
+                    .get::<_, Option<i64>>(8)?
+                    .map(|ms| Duration::from_millis(ms as u64)),
+            })
+        };
+        let rows = match arg {
+            Some(arg) => stmt.query_map(params![arg], row),
+            None => stmt.query_map([], row),
+        }
+        .map_err(|e| e.to_string())?;
+        rows.collect::<Result<_, _>>().map_err(|e| e.to_string())
+    }
+
+    // newest first
+    pub fn backups(&self) -> Result<Vec<CatalogEntry>, String> {
+        self.query("", None)
+    }
+
+    // backups holding a file with exactly these contents
+    pub fn containing(&self, hash: &str) -> Result<Vec<CatalogEntry>, String> {
+        self.query(
+            "WHERE b.id IN (SELECT backup_id FROM files WHERE hash = ?1)",
+            Some(hash),
+        )
+    }
+
+    pub fn forget(&self, id: i64) -> Result<(), String> {
+        self.conn
+            .execute("DELETE FROM backups WHERE id = ?1", params![id])
+            .map_err(|e| e.to_string())?;
+        Ok(())
+    }
+
+    // files whose name contains `name`, ignoring case, newest backups first
+    pub fn search(&self, name: &str) -> Result<Vec<SearchHit>, String> {
+        let needle = name.trim().to_lowercase();
+        if needle.is_empty() {
+            return Ok(Vec::new());
+        }
+        let mut stmt = self
+            .conn
+            .prepare(
+                "SELECT b.path, b.created, f.path, f.size, f.mtime
+                 FROM files f JOIN backups b ON b.id = f.backup_id
+                 WHERE instr(f.name, ?1) > 0
+                 ORDER BY b.created DESC, b.id DESC, f.path
+                 LIMIT ?2",
+            )
+            .map_err(|e| e.to_string())?;
+        let rows = stmt
+            .query_map(params![needle, SEARCH_LIMIT as i64], |r| {
+                Ok(SearchHit {
+                    archive: PathBuf::from(r.get::<_, String>(0)?),
+                    created: r.get(1)?,
+                    path: PathBuf::from(r.get::<_, String>(2)?),
+                    size: r.get::<_, i64>(3)? as u64,
+                    mtime: r.get::<_, i64>(4)? as u64,
+                })
+            })
+            .map_err(|e| e.to_string())?;
+        rows.collect::<Result<_, _>>().map_err(|e| e.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::helpers::test_dir;
+
+    fn catalog_with(test: &str, files: &[&str]) -> (PathBuf, Catalog) {
+        let base = test_dir(test);
+        let mut catalog = Catalog::open_at(&base.join("catalog.db")).unwrap();
+        let summary = BackupSummary {
+            archive: base.join("backup.tar"),
+            original_bytes: 10,
+            archive_bytes: 10,
+            elapsed: Duration::ZERO,
+            skipped: Vec::new(),
+            unchanged: 0,
+            failed: Vec::new(),
+            hard_links: 0,
+            resumed: false,
+            report_file: None,
+            uploaded: None,
+        };
+        let files: Vec<(PathBuf, FileState)> = files
+            .iter()
+            .map(|name| {
+                let state = FileState {
+                    stored: Stored::Here,
+                    mtime: 0,
+                    size: 1,
+                    hash: None,
+                };
+                (PathBuf::from("/home/me").join(name), state)
+            })
+            .collect();
+        catalog.record(&summary, "Full", None, &files).unwrap();
+        (base, catalog)
+    }
+
+    fn found(catalog: &Catalog, name: &str) -> Vec<String> {
+        catalog
+            .search(name)
+            .unwrap()
+            .iter()
+            .map(|hit| hit.path.file_name().unwrap().to_string_lossy().into_owned())
+            .collect()
+    }
+
+    #[test]
+    fn search_folds_non_ascii_case() {
+        let (base, catalog) =
+            catalog_with("search_case", &["Über.txt", "ÅRSRAPPORT.pdf", "plain.txt"]);
+        assert_eq!(found(&catalog, "über"), ["Über.txt"]);
+        assert_eq!(found(&catalog, "ÜBER"), ["Über.txt"]);
+        assert_eq!(found(&catalog, "årsrapport"), ["ÅRSRAPPORT.pdf"]);
+        assert_eq!(found(&catalog, "PLAIN"), ["plain.txt"]);
+        // only the name counts, not the folders above it
+        assert!(found(&catalog, "home").is_empty());
+        let _ = fs::remove_dir_all(&base);
+    }
+
+    #[test]
+    fn older_catalogs_are_searchable() {
+        let (base, _) = catalog_with("search_migrated", &[]);
+        let path = base.join("old.db");
+        let conn = Connection::open(&path).unwrap();
+        conn.execute_batch(SCHEMA).unwrap();
+        conn.execute_batch(
+            "INSERT INTO backups (id, path, created, size, mode) VALUES (1, 'a.tar', '', 0, 'Full');
+             INSERT INTO files (backup_id, path, size, mtime, stored_here)
+             VALUES (1, '/home/me/Über.txt', 1, 0, 1);",
+        )
+        .unwrap();
+        drop(conn);
+        let catalog = Catalog::open_at(&path).unwrap();
+        assert_eq!(found(&catalog, "über"), ["Über.txt"]);
+        let _ = fs::remove_dir_all(&base);
+    }
+
+    #[test]
+    fn search_takes_patterns_literally() {
+        let (base, catalog) = catalog_with("search_literal", &["50%_off.txt", "500 off.txt"]);
+        assert_eq!(found(&catalog, "50%_"), ["50%_off.txt"]);
+        let _ = fs::remove_dir_all(&base);
+    }
+}
@@ -0,0 +1,777 @@
+use std::{
+    fs,
+    ops::ControlFlow,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use log::{info, warn};
+
+use crate::BackupTemplate;
+use crate::archive::{self, ArchiveFormat, EntryKind};
+use crate::backup::{BackupMode, BackupOptions, backup_gui, check_destination};
+use crate::crypto::{self, Key, Protection};
+use crate::daemon::{self, Reply, Request};
+use crate::gpg;
+use crate::helpers::{self, Progress, ProgressEvent, fix_skip, format_bytes, original_path};
+use crate::incremental;
+use crate::logger;
+use crate::report::Problem;
+use crate::restore::{Existing, RestoreOptions, missing_drives, other_homes, restore_backup};
+use crate::secrets;
+use crate::settings::Settings;
+
+const USAGE: &str = "\
+Usage:
+  konserve --template <file.json> --out <folder> [backup options]
+  konserve list <archive> [--with <archive>]... [--identity <file>]
+  konserve extract <archive> [--with <archive>]... [--only <glob>]... [--to <folder>] [--remap <old>=<new>]... [--to-my-home] [--identity <file>] [--no-hard-links] [--no-permissions] [--existing <choice>] [--quarantine <folder>] [--staged]
+  konserve daemon [--http <port>]
+  konserve submit --template <file.json> --out <folder> [backup options]
+  konserve jobs
+  konserve cancel <job>
+  konserve pause <job>
+  konserve resume <job>
+  konserve stop
+
+Backs up a template, lists what an archive restores, or restores it
+without opening the window.
+
+`daemon` stays running and takes backups from `submit` and from the app's
+\"In background\" option, so they outlive the window. `jobs` shows their
+progress, `cancel` stops one, `pause` holds it before the next file until
+`resume`, and `stop` ends an idle daemon.
+
+With --http the daemon also answers on http://127.0.0.1:<port>. Requests need
+\"Authorization: Bearer <token>\" with the token from api.token in the config
+folder, and POST bodies \"Content-Type: application/json\":
+  POST /backups                 {\"template\": file, \"out\": folder} or {\"paths\": [...], \"out\": folder}
+  GET  /backups                 every job
+  GET  /backups/<id>/progress   state, percent and the file being stored
+  POST /backups/<id>/cancel
+  POST /backups/<id>/pause
+  POST /backups/<id>/resume
+
+Backup options:
+  --format <tar|tar.gz|tar.zst|tar.xz|zip|7z>   archive format (default tar)
+  --zstd-level <1-19>                           tar.zst compression level
+  --workers <n>                                 threads reading and compressing (default from settings)
+  --limit <MB/s>                                read files no faster than this (default from settings)
+  --background                                  low cpu and disk priority (default from settings)
+  --remote <folder>                             also copy the archive there and check the copy (default from settings)
+  --incremental <archive>                       only store changes since <archive>
+  --differential <archive>                      only store changes since the full <archive>
+  --json                                        progress, warnings and the result as json lines
+  --sleep-after                                 put the computer to sleep once the backup is over
+
+List and extract options:
+  --with <archive>   more archives read together with <archive>, e.g. a full
+                     backup kept elsewhere. the newest copy of a file wins
+
+Extract options:
+  --only <glob>      only files whose original path matches, e.g. \"*.xlsx\"
+  --to <folder>      recreate the original layout below <folder> instead of in place
+                     required for tar and zip files Konserve didn't make
+  --remap <old>=<new>
+                     restore what was below <old> below <new>, before --to
+  --to-my-home       restore what was in other users' home folders into yours
+  --identity <file>  age identity for backups encrypted to public keys
+  --no-hard-links    write a copy for every hard link instead of linking it
+  --no-permissions   leave out the saved permissions and attributes
+  --existing <overwrite|newer|skip|keep-both>
+                     what to do with files already there (default overwrite),
+                     newer only replaces files modified before the archived
+                     copy, keep-both restores a numbered copy next to them
+  --quarantine <folder>
+                     move files about to be replaced below <folder> first
+  --staged           unpack everything first and only move it into place
+                     when nothing failed, a failed extract changes nothing
+
+A password is taken from KONSERVE_PASSWORD, or for backups from the keyring
+when the template's password was remembered in the app. Nothing is ever
+prompted for, gpg archives need their key unlocked in gpg-agent beforehand.
+
+Exit codes:
+  0  done
+  1  failed
+  2  bad arguments
+  3  the template can't be read or has no paths
+  4  the destination folder can't be written to
+  5  the archive can't be opened or unlocked
+  6  finished, but some files couldn't be read or written, or the archive
+     couldn't be copied to the remote folder";
+
+// the password for a headless run never comes from a prompt
+const PASSWORD_ENV: &str = "KONSERVE_PASSWORD";
+
+const EXIT_FAILED: i32 = 1;
+const EXIT_USAGE: i32 = 2;
+const EXIT_TEMPLATE: i32 = 3;
+const EXIT_DESTINATION: i32 = 4;
+const EXIT_ARCHIVE: i32 = 5;
+const EXIT_PARTIAL: i32 = 6;
+
+// an error and the exit code that tells scripts what kind it was
+struct Failure {
+    code: i32,
+    message: String,
+}
+
+impl Failure {
+    fn new(code: i32, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+        }
+    }
+
+    fn usage(message: String) -> Self {
+        Self::new(EXIT_USAGE, message)
+    }
+}
+
+impl From<String> for Failure {
+    fn from(message: String) -> Self {
+        Self::new(EXIT_FAILED, message)
+    }
+}
+
+#[derive(Default)]
+struct Args {
+    template: Option<PathBuf>,
+    out: Option<PathBuf>,
+    format: Option<ArchiveFormat>,
+    zstd_level: Option<i32>,
+    workers: Option<usize>,
+    limit: Option<u32>,
+    background: bool,
+    remote: Option<PathBuf>,
+    mode: Option<(BackupMode, PathBuf)>,
+    json: bool,
+    sleep_after: bool,
+}
+
+fn parse(args: &[String]) -> Result<Args, String> {
+    let mut parsed = Args::default();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .cloned()
+                .ok_or_else(|| format!("{arg} needs a value"))
+        };
+        match arg.as_str() {
+            "--template" => parsed.template = Some(value()?.into()),
+            "--out" => parsed.out = Some(value()?.into()),
+            "--format" => {
+                let name = value()?;
+                let format = ArchiveFormat::ALL
+                    .into_iter()
+                    .find(|f| f.extension() == name.trim_start_matches('.'))
+                    .ok_or_else(|| format!("Unknown format {name}"))?;
+                parsed.format = Some(format);
+            }
+            "--zstd-level" => {
+                let level: i32 = value()?
+                    .parse()
+                    .map_err(|_| "--zstd-level needs a number".to_string())?;
+                if !archive::ZSTD_LEVELS.contains(&level) {
+                    return Err(format!(
+                        "--zstd-level has to be between 1 and 19, not {level}"
+                    ));
+                }
+                parsed.zstd_level = Some(level);
+            }
+            "--workers" => {
+                let workers: usize = value()?
+                    .parse()
+                    .map_err(|_| "--workers needs a number".to_string())?;
+                if workers == 0 {
+                    return Err("--workers needs at least 1".into());
+                }
+                parsed.workers = Some(workers);
+            }
+            "--limit" => {
+                let limit: u32 = value()?
+                    .parse()
+                    .map_err(|_| "--limit needs a number of MB/s".to_string())?;
+                if limit == 0 {
+                    return Err("--limit needs at least 1 MB/s".into());
+                }
+                parsed.limit = Some(limit);
+            }
+            "--incremental" => parsed.mode = Some((BackupMode::Incremental, value()?.into())),
+            "--differential" => parsed.mode = Some((BackupMode::Differential, value()?.into())),
+            "--background" => parsed.background = true,
+            "--remote" => parsed.remote = Some(value()?.into()),
+            "--json" => parsed.json = true,
+            "--sleep-after" => parsed.sleep_after = true,
+            other => return Err(format!("Unknown argument {other}")),
+        }
+    }
+    Ok(parsed)
+}
+
+pub fn load_template(path: &Path) -> Result<BackupTemplate, String> {
+    let data = fs::read_to_string(path).map_err(|e| format!("{}: {e}", path.display()))?;
+    let mut template: BackupTemplate =
+        serde_json::from_str(&data).map_err(|e| format!("{}: {e}", path.display()))?;
+    template.paths = template
+        .paths
+        .into_iter()
+        .map(|p| fix_skip(&p).unwrap_or(p))
+        .collect();
+    Ok(template)
+}
+
+fn writable(dir: &Path) -> Result<(), Failure> {
+    check_destination(dir).map_err(|e| Failure::new(EXIT_DESTINATION, e))
+}
+
+// encryption, signing and exclusions for an unattended backup of a
+// template, the way the app would do it
+pub fn headless_options(template_path: &Path, template: &BackupTemplate) -> BackupOptions {
+    let settings = Settings::load();
+    let password = std::env::var(PASSWORD_ENV)
+        .ok()
+        .filter(|p| !p.is_empty())
+        .or_else(|| {
+            settings
+                .remember_passwords
+                .then(|| secrets::load(template_path))
+                .flatten()
+        });
+    BackupOptions {
+        password,
+        recipients: template.recipients.clone(),
+        filters: template.filters.clone(),
+        on_error: template.on_error,
+        gpg_recipient: Some(settings.gpg_recipient.trim().to_string()).filter(|r| !r.is_empty()),
+        sign: settings.sign_backups,
+        remote: Some(settings.remote_folder.trim())
+            .filter(|folder| !folder.is_empty())
+            .map(PathBuf::from),
+        workers: settings.backup_workers,
+        max_mb_per_sec: Some(settings.backup_limit).filter(|limit| *limit > 0),
+        background: settings.background_priority,
+        streams: settings.keep_streams,
+        shadow_copy: settings.shadow_copy,
+        interactive: false,
+        ..BackupOptions::default()
+    }
+}
+
+// the template's folders, where the archive goes and how it's made
+fn prepare(args: Args) -> Result<(Vec<PathBuf>, PathBuf, BackupOptions), Failure> {
+    let template_path = args
+        .template
+        .ok_or_else(|| Failure::usage("--template is required".into()))?;
+    let out = args
+        .out
+        .ok_or_else(|| Failure::usage("--out is required".into()))?;
+    let template = load_template(&template_path).map_err(|e| {
+        Failure::new(
+            EXIT_TEMPLATE,
+            format!("Couldn't read the template {e}. Save it again from the app."),
+        )
+    })?;
+    if template.paths.is_empty() {
+        return Err(Failure::new(
+            EXIT_TEMPLATE,
+            format!(
+                "{} has no paths, add folders to it in the app.",
+                template_path.display()
+            ),
+        ));
+    }
+    writable(&out)?;
+
+    let (mode, parent) = match args.mode {
+        Some((mode, parent)) => (mode, Some(parent)),
+        None => (BackupMode::Full, None),
+    };
+    let options = BackupOptions {
+        format: args.format.unwrap_or(ArchiveFormat::Tar),
+        zstd_level: args.zstd_level.unwrap_or(archive::DEFAULT_ZSTD_LEVEL),
+        mode,
+        parent,
+        ..headless_options(&template_path, &template)
+    };
+    let options = BackupOptions {
+        workers: args.workers.unwrap_or(options.workers),
+        max_mb_per_sec: args.limit.or(options.max_mb_per_sec),
+        background: args.background || options.background,
+        remote: args.remote.or(options.remote),
+        ..options
+    };
+    info!(
+        "Headless backup of {} into {}",
+        template_path.display(),
+        out.display()
+    );
+    Ok((template.paths, out, options))
+}
+
+fn backup(args: Args) -> Result<(), Failure> {
+    let json = args.json;
+    let (paths, out, options) = prepare(args)?;
+    let progress = if json {
+        Progress::with_events(Arc::new(emit))
+    } else {
+        Progress::default()
+    };
+    let summary = backup_gui(&paths, &out, &progress, &options)?;
+    if json {
+        emit(&ProgressEvent::Summary {
+            archive: summary.archive.clone(),
+            original_bytes: summary.original_bytes,
+            archive_bytes: summary.archive_bytes,
+            unchanged: summary.unchanged,
+            skipped: summary.skipped.len() as u32,
+            failed: summary.failed.len() as u32,
+            elapsed_secs: summary.elapsed.as_secs_f64(),
+        });
+    } else {
+        println!("{}", summary.archive.display());
+        println!("{}", summary.report());
+    }
+    if let Some(Err(e)) = &summary.uploaded {
+        if json {
+            emit(&ProgressEvent::Warning {
+                message: format!("Couldn't copy the backup to the remote folder: {e}"),
+            });
+        }
+        return Err(Failure::new(
+            EXIT_PARTIAL,
+            format!("The backup was made but couldn't be copied: {e}"),
+        ));
+    }
+    partial(&summary.failed, "read and were left out of the backup")
+}
+
+// the run finished, but not with everything
+fn partial(failed: &[Problem], what: &str) -> Result<(), Failure> {
+    if failed.is_empty() {
+        return Ok(());
+    }
+    for problem in failed {
+        eprintln!("  {}", problem.error);
+    }
+    Err(Failure::new(
+        EXIT_PARTIAL,
+        format!("{} files couldn't be {what}.", failed.len()),
+    ))
+}
+
+// one event per line on stdout, for scripts following a long backup
+fn emit(event: &ProgressEvent) {
+    match serde_json::to_string(event) {
+        Ok(json) => println!("{json}"),
+        Err(e) => warn!("Couldn't serialize progress: {e}"),
+    }
+}
+
+#[derive(Default)]
+struct ArchiveArgs {
+    archive: PathBuf,
+    // read together with `archive`, same key
+    with: Vec<PathBuf>,
+    identity: Option<PathBuf>,
+    only: Vec<String>,
+    to: Option<PathBuf>,
+    remap: Vec<(PathBuf, PathBuf)>,
+    to_my_home: bool,
+    no_hard_links: bool,
+    no_permissions: bool,
+    existing: Existing,
+    quarantine: Option<PathBuf>,
+    staged: bool,
+}
+
+fn parse_archive_args(args: &[String], extract: bool) -> Result<ArchiveArgs, String> {
+    let mut parsed = ArchiveArgs::default();
+    let mut archive = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .cloned()
+                .ok_or_else(|| format!("{arg} needs a value"))
+        };
+        match arg.as_str() {
+            "--identity" => parsed.identity = Some(value()?.into()),
+            "--with" => parsed.with.push(value()?.into()),
+            "--only" if extract => parsed.only.push(value()?),
+            "--to" if extract => parsed.to = Some(value()?.into()),
+            "--to-my-home" if extract => parsed.to_my_home = true,
+            "--remap" if extract => {
+                let rule = value()?;
+                let (from, to) = rule
+                    .split_once('=')
+                    .filter(|(from, _)| !from.is_empty())
+                    .ok_or_else(|| format!("--remap needs <old>=<new>, got {rule}"))?;
+                parsed.remap.push((from.into(), to.into()));
+            }
+            "--no-hard-links" if extract => parsed.no_hard_links = true,
+            "--no-permissions" if extract => parsed.no_permissions = true,
+            "--staged" if extract => parsed.staged = true,
+            "--quarantine" if extract => parsed.quarantine = Some(value()?.into()),
+            "--existing" if extract => {
+                parsed.existing = match value()?.as_str() {
+                    "overwrite" => Existing::Overwrite,
+                    "newer" => Existing::Newer,
+                    "skip" => Existing::Skip,
+                    "keep-both" => Existing::KeepBoth,
+                    other => return Err(format!("Unknown --existing choice {other}")),
+                }
+            }
+            other if other.starts_with("--") => return Err(format!("Unknown argument {other}")),
+            other if archive.is_none() => archive = Some(PathBuf::from(other)),
+            other => return Err(format!("Unexpected argument {other}")),
+        }
+    }
+    parsed.archive = archive.ok_or("No archive given")?;
+    Ok(parsed)
+}
+
+// the archive to read, decrypted by gpg into the temp dir when needed
+struct Opened {
+    path: PathBuf,
+    key: Option<Key>,
+    gpg_plain: bool,
+}
+
+impl Drop for Opened {
+    fn drop(&mut self) {
+        if self.gpg_plain {
+            gpg::discard(&self.path);
+        }
+    }
+}
+
+fn open(args: &ArchiveArgs) -> Result<Opened, Failure> {
+    unlock(args).map_err(|e| Failure::new(EXIT_ARCHIVE, e))
+}
+
+fn unlock(args: &ArchiveArgs) -> Result<Opened, String> {
+    let mut opened = Opened {
+        path: args.archive.clone(),
+        key: None,
+        gpg_plain: false,
+    };
+    if gpg::is_gpg(&args.archive) {
+        opened.path = gpg::decrypt(&args.archive, false)?;
+        opened.gpg_plain = true;
+    }
+    opened.key = match crypto::protection(&opened.path)? {
+        None => None,
+        Some(Protection::Password) => {
+            let password = std::env::var(PASSWORD_ENV)
+                .map_err(|_| format!("The backup is password protected, set {PASSWORD_ENV}."))?;
+            Some(crypto::unlock(&opened.path, &password)?)
+        }
+        Some(Protection::PublicKey) => {
+            let identity = args
+                .identity
+                .as_ref()
+                .ok_or("The backup is encrypted to public keys, pass --identity.")?;
+            Some(crypto::unlock_with_identity(&opened.path, identity)?)
+        }
+    };
+    Ok(opened)
+}
+
+// original path, size and whether it's a folder, for everything the backup
+// restores, through the chain of an incremental
+fn contents(
+    path: &Path,
+    also: &[PathBuf],
+    key: Option<&Key>,
+) -> Result<Vec<(PathBuf, u64, bool)>, String> {
+    let chain = incremental::session(path, also, key)?;
+    let owners = incremental::owners(&chain);
+    let mut contents = Vec::new();
+    for (index, link) in chain.iter().enumerate() {
+        archive::visit_entries(&link.path, key, |entry| {
+            if entry.name != "fingerprint.txt"
+                && entry.kind != EntryKind::Other
+                && incremental::keeps(owners.as_ref(), &chain, index, &entry.name, entry.kind)
+                && let Some(original) = original_path(&link.path_map, &entry.name, link.escaped)
+            {
+                let size = link.sparse.get(&original).map_or(entry.size, |l| l.size);
+                contents.push((original, size, entry.kind == EntryKind::Dir));
+            }
+            Ok(ControlFlow::Continue(()))
+        })?;
+    }
+    contents.sort();
+    Ok(contents)
+}
+
+fn list(args: ArchiveArgs) -> Result<(), Failure> {
+    let opened = open(&args)?;
+    for (path, size, dir) in contents(&opened.path, &args.with, opened.key.as_ref())? {
+        if dir {
+            println!("{:>10}  {}/", "", path.display());
+        } else {
+            println!("{:>10}  {}", format_bytes(size), path.display());
+        }
+    }
+    Ok(())
+}
+
+fn globs(patterns: &[String]) -> Result<GlobSet, String> {
+    let mut builder = GlobSetBuilder::new();
+    for pattern in patterns {
+        builder.add(Glob::new(pattern).map_err(|e| e.to_string())?);
+    }
+    builder.build().map_err(|e| e.to_string())
+}
+
+fn extract(args: ArchiveArgs) -> Result<(), Failure> {
+    let only = globs(&args.only).map_err(Failure::usage)?;
+    if let Some(to) = &args.to {
+        writable(to)?;
+    }
+    let opened = open(&args)?;
+
+    // restore picks files by original path, the same way ticking them in the tree does
+    let selected = if args.only.is_empty() {
+        None
+    } else {
+        let matching: Vec<String> = contents(&opened.path, &args.with, opened.key.as_ref())?
+            .into_iter()
+            .filter(|(path, _, dir)| {
+                !dir && only.is_match(path.to_string_lossy().replace('\\', "/"))
+            })
+            .map(|(path, _, _)| path.display().to_string())
+            .collect();
+        if matching.is_empty() {
+            return Err(Failure::new(
+                EXIT_FAILED,
+                "No files match --only, check the paths `konserve list` shows.",
+            ));
+        }
+        Some(matching)
+    };
+
+    let chain = incremental::session(&opened.path, &args.with, opened.key.as_ref())?;
+    let originals = || chain.iter().flat_map(|link| link.path_map.values());
+    let mut remap = args.remap.clone();
+    if args.to_my_home
+        && let Some(home) = dirs::home_dir()
+    {
+        for old in other_homes(originals(), &home) {
+            println!("{} → {}", old.display(), home.display());
+            remap.push((old, home.clone()));
+        }
+    }
+    // with --to every path lands below it anyway
+    if args.to.is_none()
+        && let Some(drive) = missing_drives(originals(), &remap).first()
+    {
+        return Err(Failure::new(
+            EXIT_FAILED,
+            format!(
+                "{} isn't on this computer, restore it elsewhere with --remap \"{}=<folder>\" or --to",
+                drive.display(),
+                drive.display()
+            ),
+        ));
+    }
+
+    let options = RestoreOptions {
+        key: opened.key.clone(),
+        also: args.with.clone(),
+        into: args.to.clone(),
+        remap,
+        hard_links: !args.no_hard_links,
+        permissions: !args.no_permissions,
+        existing: args.existing,
+        quarantine: args.quarantine.clone(),
+        staged: args.staged,
+        interactive: false,
+        ..RestoreOptions::default()
+    };
+    let status = Arc::new(Mutex::new(String::new()));
+    let report = restore_backup(
+        &opened.path,
+        selected,
+        status,
+        &Progress::default(),
+        &options,
+    )?;
+    println!("Restored {} files", report.restored);
+    for (from, to) in &report.renamed {
+        println!("renamed {} -> {}", from.display(), to.display());
+    }
+    for (_, reason) in &report.skipped {
+        println!("skipped {reason}");
+    }
+    if let Some(file) = &report.report_file {
+        println!("Details in {}", file.display());
+    }
+    partial(&report.failed, "written")
+}
+
+fn submit(args: Args) -> Result<(), Failure> {
+    let (paths, out, options) = prepare(args)?;
+    daemon::ensure_running()?;
+    match daemon::request(&Request::Run {
+        paths,
+        out,
+        options: Box::new(options),
+    })? {
+        Reply::Started { id } => println!("Started job {id}"),
+        _ => return Err("Unexpected reply from the daemon.".to_string().into()),
+    }
+    Ok(())
+}
+
+fn jobs() -> Result<(), Failure> {
+    let Reply::Jobs { jobs } = daemon::request(&Request::Jobs)? else {
+        return Err("Unexpected reply from the daemon.".to_string().into());
+    };
+    for job in jobs {
+        println!(
+            "{:>4}  {:<8} {:>3}%  {}",
+            job.id,
+            if job.paused {
+                "Paused"
+            } else {
+                job.state.label()
+            },
+            job.percent,
+            job.out.display()
+        );
+        for line in job.message.lines() {
+            println!("      {line}");
+        }
+    }
+    Ok(())
+}
+
+// commands that take nothing after their name
+fn bare(args: &[String]) -> Result<(), Failure> {
+    match args.get(1) {
+        Some(extra) => Err(Failure::usage(format!("Unexpected argument {extra}"))),
+        None => Ok(()),
+    }
+}
+
+// the exit code of a command line run
+pub fn run(args: &[String]) -> i32 {
+    if args.iter().any(|a| a == "--help" || a == "-h") {
+        println!("{USAGE}");
+        return 0;
+    }
+    logger::headless(false);
+    let (command, result) = match args.first().map(String::as_str) {
+        Some("list") => (
+            "List",
+            parse_archive_args(&args[1..], false)
+                .map_err(Failure::usage)
+                .and_then(list),
+        ),
+        Some("extract") => (
+            "Extract",
+            parse_archive_args(&args[1..], true)
+                .map_err(Failure::usage)
+                .and_then(extract),
+        ),
+        Some("daemon") => (
+            "Daemon",
+            match &args[1..] {
+                [] => Ok(None),
+                [flag, port] if flag == "--http" => port
+                    .parse()
+                    .map(Some)
+                    .map_err(|_| Failure::usage(format!("--http needs a port, not {port}"))),
+                [extra, ..] => Err(Failure::usage(format!("Unexpected argument {extra}"))),
+            }
+            .and_then(|http| Ok(daemon::serve(http)?)),
+        ),
+        Some("submit") => (
+            "Submit",
+            parse(&args[1..]).map_err(Failure::usage).and_then(submit),
+        ),
+        Some("jobs") => ("Jobs", bare(args).and_then(|()| jobs())),
+        Some("cancel") => (
+            "Cancel",
+            match args.get(1).and_then(|id| id.parse().ok()) {
+                Some(id) if args.len() == 2 => daemon::request(&Request::Cancel { id })
+                    .map(|_| ())
+                    .map_err(Failure::from),
+                _ => Err(Failure::usage("cancel needs a job number".into())),
+            },
+        ),
+        Some(action @ ("pause" | "resume")) => (
+            if action == "pause" { "Pause" } else { "Resume" },
+            match args.get(1).and_then(|id| id.parse().ok()) {
+                Some(id) if args.len() == 2 => daemon::request(&Request::Pause {
+                    id,
+                    paused: action == "pause",
+                })
+                .map(|_| ())
+                .map_err(Failure::from),
+                _ => Err(Failure::usage(format!("{action} needs a job number"))),
+            },
+        ),
+        Some("stop") => (
+            "Stop",
+            bare(args).and_then(|()| Ok(daemon::request(&Request::Shutdown).map(|_| ())?)),
+        ),
+        _ => (
+            "Backup",
+            parse(args).map_err(Failure::usage).and_then(|args| {
+                let json = args.json;
+                let sleep_after = args.sleep_after;
+                if json {
+                    logger::headless(true);
+                }
+                let result = backup(args).inspect_err(|failure| {
+                    if json {
+                        emit(&ProgressEvent::Error {
+                            code: failure.code,
+                            message: failure.message.clone(),
+                        });
+                    }
+                });
+                // whether it worked or not, the computer was only woken for it
+                if sleep_after && let Err(e) = helpers::suspend() {
+                    warn!("{e}");
+                }
+                result
+            }),
+        ),
+    };
+    let Err(failure) = result else {
+        return 0;
+    };
+    match failure.code {
+        EXIT_USAGE => eprintln!("{}\n\n{USAGE}", failure.message),
+        EXIT_PARTIAL => {
+            warn!("{command} incomplete: {}", failure.message);
+            eprintln!("{command} incomplete: {}", failure.message);
+        }
+        _ => {
+            warn!("{command} failed: {}", failure.message);
+            eprintln!("{command} failed: {}", failure.message);
+        }
+    }
+    failure.code
+}
+
+// the exe is built for the windows subsystem, so it has no console of its
+// own and borrows the one it was started from
+#[cfg(windows)]
+pub fn attach_console() {
+    use windows_sys::Win32::System::Console::{ATTACH_PARENT_PROCESS, AttachConsole};
+    unsafe {
+        AttachConsole(ATTACH_PARENT_PROCESS);
+    }
+}
+
+#[cfg(not(windows))]
+pub fn attach_console() {}
@@ -0,0 +1,209 @@
+use std::{
+    collections::{BTreeMap, HashSet},
+    fs::File,
+    ops::ControlFlow,
+    path::{Path, PathBuf},
+};
+
+use log::{debug, info};
+use walkdir::WalkDir;
+
+use crate::archive::{self, EntryKind};
+use crate::backup::mtime_secs;
+use crate::crypto::Key;
+use crate::helpers::{adjust_path, original_path};
+use crate::incremental::{self, FileState, Manifest, Stored, hash_reader};
+
+// seen from the older side: "added" is only on disk, or only in the newer
+// of two archives
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Change {
+    Added,
+    Removed,
+    Modified,
+}
+
+impl Change {
+    pub fn label(self) -> &'static str {
+        match self {
+            Self::Added => "Added",
+            Self::Removed => "Removed",
+            Self::Modified => "Modified",
+        }
+    }
+
+    pub fn symbol(self) -> &'static str {
+        match self {
+            Self::Added => "+",
+            Self::Removed => "−",
+            Self::Modified => "~",
+        }
+    }
+}
+
+pub struct Difference {
+    pub path: PathBuf,
+    pub change: Change,
+}
+
+// differences grouped by folder, with per-kind counts of everything below
+#[derive(Default)]
+pub struct DiffTree {
+    pub children: BTreeMap<String, DiffTree>,
+    // set on files
+    pub change: Option<Change>,
+    pub counts: [u32; 3],
+}
+
+impl DiffTree {
+    pub fn build(differences: &[Difference]) -> Self {
+        let mut root = DiffTree::default();
+        for difference in differences {
+            let slot = difference.change as usize;
+            root.counts[slot] += 1;
+            let mut node = &mut root;
+            for part in difference.path.components() {
+                node = node
+                    .children
+                    .entry(part.as_os_str().to_string_lossy().into_owned())
+                    .or_default();
+                node.counts[slot] += 1;
+            }
+            node.change = Some(difference.change);
+        }
+        root
+    }
+
+    pub fn count(&self, change: Change) -> u32 {
+        self.counts[change as usize]
+    }
+}
+
+// every file the backup restores, by original path. archives from before file
+// lists existed get one built from their entries, without hashes
+fn listing(zip_path: &Path, key: Option<&Key>) -> Result<(Manifest, Vec<PathBuf>), String> {
+    let chain = incremental::chain(zip_path, key)?;
+    let newest = chain.last().ok_or("Empty backup chain.")?;
+    let roots: Vec<PathBuf> = newest.path_map.values().cloned().collect();
+    if let Some(manifest) = &newest.manifest {
+        return Ok((manifest.clone(), roots));
+    }
+
+    debug!("{} has no file list, reading entries", zip_path.display());
+    let mut manifest = Manifest::new();
+    archive::visit_entries(zip_path, key, |entry| {
+        if entry.kind == EntryKind::File
+            && entry.name != "fingerprint.txt"
+            && let Some(original) = original_path(&newest.path_map, &entry.name, newest.escaped)
+        {
+            manifest.insert(
+                original,
+                FileState {
+                    stored: Stored::Here,
+                    mtime: entry.mtime,
+                    size: entry.size,
+                    hash: None,
+                },
+            );
+        }
+        Ok(ControlFlow::Continue(()))
+    })?;
+    Ok((manifest, roots))
+}
+
+fn sorted(changes: BTreeMap<PathBuf, Change>) -> Vec<Difference> {
+    changes
+        .into_iter()
+        .map(|(path, change)| Difference { path, change })
+        .collect()
+}
+
+// what restoring would bring back (removed) or overwrite (modified), and what
+// appeared since the backup (added)
+pub fn compare_with_disk(zip_path: &Path, key: Option<&Key>) -> Result<Vec<Difference>, String> {
+    info!("Comparing {} with the disk", zip_path.display());
+    let (manifest, roots) = listing(zip_path, key)?;
+    let current_home = dirs::home_dir().unwrap_or_else(|| PathBuf::from("C:\\"));
+    let mut changes = BTreeMap::new();
+
+    for (original, state) in &manifest {
+        let on_disk = adjust_path(original, &current_home);
+        let Some(meta) = on_disk.metadata().ok().filter(|m| m.is_file()) else {
+            changes.insert(original.clone(), Change::Removed);
+            continue;
+        };
+        let same = if meta.len() != state.size {
+            false
+        } else if let Some(hash) = &state.hash {
+            File::open(&on_disk)
+                .and_then(|mut f| hash_reader(&mut f))
+                .is_ok_and(|actual| &actual == hash)
+        } else {
+            mtime_secs(&meta) == state.mtime
+        };
+        if !same {
+            changes.insert(original.clone(), Change::Modified);
+        }
+    }
+
+    for root in roots {
+        let on_disk_root = adjust_path(&root, &current_home);
+        for entry in WalkDir::new(&on_disk_root)
+            .into_iter()
+            .filter_map(Result::ok)
+            .filter(|e| e.file_type().is_file())
+        {
+            let Ok(rel) = entry.path().strip_prefix(&on_disk_root) else {
+                continue;
+            };
+            let original = if rel.as_os_str().is_empty() {
+                root.clone()
+            } else {
+                root.join(rel)
+            };
+            if !manifest.contains_key(&original) {
+                changes.insert(original, Change::Added);
+            }
+        }
+    }
+
+    info!("{} differences", changes.len());
+    Ok(sorted(changes))
+}
+
+// `older` against `newer`, both with their whole chains
+pub fn diff_archives(
+    older: &Path,
+    older_key: Option<&Key>,
+    newer: &Path,
+    newer_key: Option<&Key>,
+) -> Result<Vec<Difference>, String> {
+    info!("Diffing {} against {}", older.display(), newer.display());
+    let (before, _) = listing(older, older_key)?;
+    let (after, _) = listing(newer, newer_key)?;
+    let mut changes = BTreeMap::new();
+
+    let paths: HashSet<&PathBuf> = before.keys().chain(after.keys()).collect();
+    for path in paths {
+        let change = match (before.get(path), after.get(path)) {
+            (None, Some(_)) => Change::Added,
+            (Some(_), None) => Change::Removed,
+            (Some(a), Some(b)) => {
+                // hashes when both have them, size and mtime for older archives
+                let same = match (&a.hash, &b.hash) {
+                    (Some(x), Some(y)) => x == y,
+                    _ => a.size == b.size && a.mtime == b.mtime,
+                };
+                if same {
+                    continue;
+                }
+                Change::Modified
+            }
+            (None, None) => continue,
+        };
+        changes.insert(path.clone(), change);
+    }
+
+    info!("{} differences", changes.len());
+    Ok(sorted(changes))
+}
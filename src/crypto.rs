@@ -0,0 +1,549 @@
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, Read, Seek, SeekFrom, Write},
+    path::Path,
+};
+
+use aes_gcm::{
+    Aes256Gcm, Key as AesKey, KeyInit, Nonce,
+    aead::{Aead, OsRng, Payload, rand_core::RngCore},
+};
+use age::{Decryptor, Encryptor, IdentityFile, x25519};
+use argon2::{Algorithm, Argon2, Params, Version};
+use log::debug;
+
+// encrypted archives are the plain archive cut into chunks, each sealed with
+// AES-256-GCM under its own random nonce. the chunk index goes in as
+// associated data so chunks can't be reordered, and a sealed footer holding
+// the plain length catches truncation. fixed size chunks keep the file
+// seekable, which zip and 7z need on both ends.
+//
+// the header says how to get the key back, from a password or from an age
+// identity the key was wrapped for:
+// header: MAGIC | m_cost | t_cost | p_cost (u32 LE each) | salt
+//     or: AGE_MAGIC | u32 LE length | age file holding the key
+// chunk:  nonce | ciphertext | tag
+// footer: nonce | sealed u64 LE plain length | tag
+const MAGIC: &[u8; 8] = b"KNSVENC1";
+const AGE_MAGIC: &[u8; 8] = b"KNSVAGE1";
+const SALT_LEN: usize = 16;
+const PASSWORD_HEADER_LEN: usize = 8 + 12 + SALT_LEN;
+const NONCE_LEN: usize = 12;
+const TAG_LEN: usize = 16;
+const CHUNK: u64 = 64 * 1024;
+const SEALED_CHUNK: u64 = CHUNK + (NONCE_LEN + TAG_LEN) as u64;
+const FOOTER_LEN: u64 = (NONCE_LEN + 8 + TAG_LEN) as u64;
+const FOOTER_AAD: &[u8] = b"footer";
+// the header comes from the file, so a damaged or hostile one could ask for
+// terabytes of memory or years of hashing. ours are far below these
+const MAX_M_COST: u32 = 1024 * 1024;
+const MAX_T_COST: u32 = 64;
+const MAX_P_COST: u32 = 16;
+// the age file holding the key, a few hundred bytes per recipient
+const MAX_WRAPPED: usize = 64 * 1024;
+
+#[derive(Clone)]
+pub struct Key {
+    key: [u8; 32],
+    // written in front of the chunks
+    header: Vec<u8>,
+}
+
+// how an encrypted archive gets unlocked
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Protection {
+    Password,
+    PublicKey,
+}
+
+impl Key {
+    fn derive(password: &str, salt: [u8; SALT_LEN], params: [u32; 3]) -> Result<Self, String> {
+        let [m, t, p] = params;
+        let argon = Argon2::new(
+            Algorithm::Argon2id,
+            Version::V0x13,
+            Params::new(m, t, p, Some(32)).map_err(|e| e.to_string())?,
+        );
+        let mut key = [0u8; 32];
+        argon
+            .hash_password_into(password.as_bytes(), &salt, &mut key)
+            .map_err(|e| e.to_string())?;
+
+        let mut header = MAGIC.to_vec();
+        for p in params {
+            header.extend_from_slice(&p.to_le_bytes());
+        }
+        header.extend_from_slice(&salt);
+        Ok(Self { key, header })
+    }
+
+    // fresh salt for a new archive
+    pub fn new(password: &str) -> Result<Self, String> {
+        let mut salt = [0u8; SALT_LEN];
+        OsRng.fill_bytes(&mut salt);
+        Self::derive(
+            password,
+            salt,
+            [
+                Params::DEFAULT_M_COST,
+                Params::DEFAULT_T_COST,
+                Params::DEFAULT_P_COST,
+            ],
+        )
+    }
+
+    // random key wrapped for every "age1..." recipient, no password involved
+    pub fn for_recipients(recipients: &[String]) -> Result<Self, String> {
+        let recipients = recipients
+            .iter()
+            .map(|r| parse_recipient(r))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let mut key = [0u8; 32];
+        OsRng.fill_bytes(&mut key);
+
+        let encryptor =
+            Encryptor::with_recipients(recipients.iter().map(|r| r as &dyn age::Recipient))
+                .map_err(|e| e.to_string())?;
+        let mut wrapped = Vec::new();
+        let mut writer = encryptor
+            .wrap_output(&mut wrapped)
+            .map_err(|e| e.to_string())?;
+        writer
+            .write_all(&key)
+            .and_then(|_| writer.finish())
+            .map_err(|e| e.to_string())?;
+
+        if wrapped.len() > MAX_WRAPPED {
+            return Err("Too many recipients for one backup.".into());
+        }
+        let mut header = AGE_MAGIC.to_vec();
+        header.extend_from_slice(&(wrapped.len() as u32).to_le_bytes());
+        header.extend_from_slice(&wrapped);
+        Ok(Self { key, header })
+    }
+
+    fn cipher(&self) -> Aes256Gcm {
+        Aes256Gcm::new(AesKey::<Aes256Gcm>::from_slice(&self.key))
+    }
+}
+
+pub fn parse_recipient(recipient: &str) -> Result<x25519::Recipient, String> {
+    recipient
+        .trim()
+        .parse()
+        .map_err(|_| format!("Not an age public key: {recipient}"))
+}
+
+// None for plain archives
+pub fn protection(path: &Path) -> Result<Option<Protection>, String> {
+    let mut magic = [0u8; 8];
+    let mut file = File::open(path).map_err(|e| e.to_string())?;
+    match file.read_exact(&mut magic) {
+        Ok(()) if &magic == MAGIC => Ok(Some(Protection::Password)),
+        Ok(()) if &magic == AGE_MAGIC => Ok(Some(Protection::PublicKey)),
+        Ok(()) => Ok(None),
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => Ok(None),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+// derives the key from the archive's own salt and checks it against the footer
+pub fn unlock(path: &Path, password: &str) -> Result<Key, String> {
+    debug!("unlock: Deriving key for {}", path.display());
+    let mut file = File::open(path).map_err(|e| e.to_string())?;
+    let mut header = [0u8; PASSWORD_HEADER_LEN];
+    file.read_exact(&mut header)
+        .map_err(|_| "Not a password protected backup.".to_string())?;
+    if &header[..8] != MAGIC {
+        return Err("Not a password protected backup.".into());
+    }
+
+    let word = |at: usize| u32::from_le_bytes(header[at..at + 4].try_into().unwrap());
+    let params = [word(8), word(12), word(16)];
+    let [m, t, p] = params;
+    if m > MAX_M_COST || t > MAX_T_COST || p > MAX_P_COST {
+        return Err(format!(
+            "The backup asks for more key stretching than Konserve allows (m={m}, t={t}, p={p}), it's damaged or wasn't made by Konserve."
+        ));
+    }
+    let salt = header[20..].try_into().unwrap();
+
+    let key = Key::derive(password, salt, params)?;
+    EncryptedFile::open(file, &key)?;
+    Ok(key)
+}
+
+// unwraps the key with any identity in an age identity file
+pub fn unlock_with_identity(path: &Path, identity_file: &Path) -> Result<Key, String> {
+    debug!(
+        "unlock_with_identity: {} with {}",
+        path.display(),
+        identity_file.display()
+    );
+    let mut file = File::open(path).map_err(|e| e.to_string())?;
+    let mut start = [0u8; 12];
+    file.read_exact(&mut start)
+        .map_err(|_| "Not a public key encrypted backup.".to_string())?;
+    if &start[..8] != AGE_MAGIC {
+        return Err("Not a public key encrypted backup.".into());
+    }
+    let wrapped_len = u32::from_le_bytes(start[8..].try_into().unwrap()) as usize;
+    if wrapped_len > MAX_WRAPPED {
+        return Err(format!(
+            "The backup's wrapped key is {wrapped_len} bytes, it's damaged or wasn't made by Konserve."
+        ));
+    }
+    let mut wrapped = vec![0u8; wrapped_len];
+    file.read_exact(&mut wrapped)
+        .map_err(|_| "Encrypted backup is truncated.".to_string())?;
+
+    let identities = IdentityFile::from_file(identity_file.display().to_string())
+        .map_err(|e| format!("Couldn't read identity file: {e}"))?
+        .into_identities()
+        .map_err(|e| format!("Couldn't read identity file: {e}"))?;
+
+    let mut key = [0u8; 32];
+    Decryptor::new_buffered(wrapped.as_slice())
+        .and_then(|d| d.decrypt(identities.iter().map(|i| i.as_ref())))
+        .map_err(|_| "None of these identities can open the backup.".to_string())?
+        .read_exact(&mut key)
+        .map_err(|_| "Encrypted backup is damaged.".to_string())?;
+
+    let key = Key {
+        key,
+        header: [&start[..], &wrapped].concat(),
+    };
+    EncryptedFile::open(file, &key)?;
+    Ok(key)
+}
+
+fn seal(cipher: &Aes256Gcm, plain: &[u8], aad: &[u8]) -> Result<Vec<u8>, String> {
+    let mut nonce = [0u8; NONCE_LEN];
+    OsRng.fill_bytes(&mut nonce);
+    let sealed = cipher
+        .encrypt(Nonce::from_slice(&nonce), Payload { msg: plain, aad })
+        .map_err(|_| "Encryption failed.".to_string())?;
+    Ok([&nonce[..], &sealed].concat())
+}
+
+fn open_sealed(cipher: &Aes256Gcm, data: &[u8], aad: &[u8]) -> Option<Vec<u8>> {
+    let (nonce, sealed) = data.split_at_checked(NONCE_LEN)?;
+    cipher
+        .decrypt(Nonce::from_slice(nonce), Payload { msg: sealed, aad })
+        .ok()
+}
+
+fn sealed_len(plain_len: u64) -> u64 {
+    let rest = plain_len % CHUNK;
+    plain_len / CHUNK * SEALED_CHUNK
+        + if rest > 0 {
+            rest + (NONCE_LEN + TAG_LEN) as u64
+        } else {
+            0
+        }
+}
+
+fn io_err(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+// reads and writes plain bytes, encrypting a chunk at a time underneath
+pub struct EncryptedFile {
+    file: File,
+    cipher: Aes256Gcm,
+    header_len: u64,
+    len: u64,
+    pos: u64,
+    chunk: Option<u64>,
+    buf: Vec<u8>,
+    dirty: bool,
+}
+
+impl EncryptedFile {
+    pub fn create(path: &Path, key: &Key) -> Result<Self, String> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)
+            .map_err(|e| e.to_string())?;
+
+        file.write_all(&key.header).map_err(|e| e.to_string())?;
+
+        Ok(Self {
+            file,
+            cipher: key.cipher(),
+            header_len: key.header.len() as u64,
+            len: 0,
+            pos: 0,
+            chunk: None,
+            buf: Vec::new(),
+            dirty: false,
+        })
+    }
+
+    pub fn open(mut file: File, key: &Key) -> Result<Self, String> {
+        let cipher = key.cipher();
+        let header_len = key.header.len() as u64;
+        let total = file.metadata().map_err(|e| e.to_string())?.len();
+        if total < header_len + FOOTER_LEN {
+            return Err("Encrypted backup is truncated.".into());
+        }
+
+        let mut footer = [0u8; FOOTER_LEN as usize];
+        file.seek(SeekFrom::Start(total - FOOTER_LEN))
+            .and_then(|_| file.read_exact(&mut footer))
+            .map_err(|e| e.to_string())?;
+        let len = open_sealed(&cipher, &footer, FOOTER_AAD)
+            .and_then(|plain| Some(u64::from_le_bytes(plain.try_into().ok()?)))
+            .ok_or("Wrong password, or the backup is damaged.")?;
+
+        if header_len + sealed_len(len) + FOOTER_LEN != total {
+            return Err("Encrypted backup is truncated.".into());
+        }
+
+        Ok(Self {
+            file,
+            cipher,
+            header_len,
+            len,
+            pos: 0,
+            chunk: None,
+            buf: Vec::new(),
+            dirty: false,
+        })
+    }
+
+    fn flush_chunk(&mut self) -> io::Result<()> {
+        let Some(index) = self.chunk.filter(|_| self.dirty) else {
+            return Ok(());
+        };
+        let sealed = seal(&self.cipher, &self.buf, &index.to_le_bytes()).map_err(|e| io_err(&e))?;
+        self.file
+            .seek(SeekFrom::Start(self.header_len + index * SEALED_CHUNK))?;
+        self.file.write_all(&sealed)?;
+        self.dirty = false;
+        Ok(())
+    }
+
+    fn load(&mut self, index: u64) -> io::Result<()> {
+        if self.chunk == Some(index) {
+            return Ok(());
+        }
+        self.flush_chunk()?;
+        self.buf.clear();
+        self.chunk = Some(index);
+
+        let start = index * CHUNK;
+        if start >= self.len {
+            return Ok(());
+        }
+        let plain_len = (self.len - start).min(CHUNK) as usize;
+        let mut sealed = vec![0u8; plain_len + NONCE_LEN + TAG_LEN];
+        self.file
+            .seek(SeekFrom::Start(self.header_len + index * SEALED_CHUNK))?;
+        self.file.read_exact(&mut sealed)?;
+        self.buf = open_sealed(&self.cipher, &sealed, &index.to_le_bytes())
+            .ok_or_else(|| io_err("encrypted backup is damaged"))?;
+        Ok(())
+    }
+
+    // seals the last chunk and writes the footer, the file is unreadable without it
+    pub fn close(mut self) -> io::Result<()> {
+        self.flush_chunk()?;
+        let footer =
+            seal(&self.cipher, &self.len.to_le_bytes(), FOOTER_AAD).map_err(|e| io_err(&e))?;
+        let end = self.header_len + sealed_len(self.len);
+        self.file.seek(SeekFrom::Start(end))?;
+        self.file.write_all(&footer)?;
+        self.file.set_len(end + FOOTER_LEN)?;
+        self.file.flush()
+    }
+}
+
+impl Read for EncryptedFile {
+    fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+        if self.pos >= self.len || out.is_empty() {
+            return Ok(0);
+        }
+        self.load(self.pos / CHUNK)?;
+        let offset = (self.pos % CHUNK) as usize;
+        let n = out.len().min(self.buf.len() - offset);
+        out[..n].copy_from_slice(&self.buf[offset..offset + n]);
+        self.pos += n as u64;
+        Ok(n)
+    }
+}
+
+impl Write for EncryptedFile {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        if data.is_empty() {
+            return Ok(0);
+        }
+        // a seek past the end leaves a hole, fill it so every chunk before pos exists
+        while self.pos > self.len {
+            let target = self.pos;
+            self.pos = self.len;
+            let gap = (target - self.len).min(CHUNK - self.len % CHUNK) as usize;
+            self.write_all(&vec![0u8; gap])?;
+            self.pos = target;
+        }
+
+        self.load(self.pos / CHUNK)?;
+        let offset = (self.pos % CHUNK) as usize;
+        let n = data.len().min(CHUNK as usize - offset);
+        if self.buf.len() < offset + n {
+            self.buf.resize(offset + n, 0);
+        }
+        self.buf[offset..offset + n].copy_from_slice(&data[..n]);
+        self.dirty = true;
+        self.pos += n as u64;
+        self.len = self.len.max(self.pos);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.flush_chunk()?;
+        self.file.flush()
+    }
+}
+
+impl Seek for EncryptedFile {
+    fn seek(&mut self, to: SeekFrom) -> io::Result<u64> {
+        let pos = match to {
+            SeekFrom::Start(p) => Some(p),
+            SeekFrom::End(d) => self.len.checked_add_signed(d),
+            SeekFrom::Current(d) => self.pos.checked_add_signed(d),
+        };
+        self.pos = pos.ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, "seek before start of file")
+        })?;
+        Ok(self.pos)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::helpers::test_dir;
+    use std::fs;
+
+    // a bit over three chunks, so the last one is partial
+    fn sealed(base: &Path, key: &Key) -> (std::path::PathBuf, Vec<u8>) {
+        let plain: Vec<u8> = (0..3 * CHUNK + 123).map(|i| (i % 251) as u8).collect();
+        let path = base.join("backup.tar.enc");
+        let mut file = EncryptedFile::create(&path, key).unwrap();
+        file.write_all(&plain).unwrap();
+        file.close().unwrap();
+        (path, plain)
+    }
+
+    fn open(path: &Path, key: &Key) -> Result<Vec<u8>, String> {
+        let mut file = EncryptedFile::open(File::open(path).unwrap(), key)?;
+        let mut plain = Vec::new();
+        file.read_to_end(&mut plain).map_err(|e| e.to_string())?;
+        Ok(plain)
+    }
+
+    #[test]
+    fn round_trip() {
+        let base = test_dir("crypto_round_trip");
+        let key = Key::new("hunter2").unwrap();
+        let (path, plain) = sealed(&base, &key);
+        assert_eq!(
+            fs::metadata(&path).unwrap().len(),
+            key.header.len() as u64 + sealed_len(plain.len() as u64) + FOOTER_LEN
+        );
+        assert_eq!(protection(&path).unwrap(), Some(Protection::Password));
+
+        let key = unlock(&path, "hunter2").unwrap();
+        assert_eq!(open(&path, &key).unwrap(), plain);
+        assert!(unlock(&path, "hunter3").is_err());
+
+        // reads from the middle cross into the next chunk
+        let mut file = EncryptedFile::open(File::open(&path).unwrap(), &key).unwrap();
+        let mut buf = vec![0u8; 100];
+        file.seek(SeekFrom::Start(CHUNK - 50)).unwrap();
+        file.read_exact(&mut buf).unwrap();
+        assert_eq!(buf, plain[CHUNK as usize - 50..CHUNK as usize + 50]);
+        let _ = fs::remove_dir_all(&base);
+    }
+
+    #[test]
+    fn truncated_files_are_refused() {
+        let base = test_dir("crypto_truncated");
+        let key = Key::new("hunter2").unwrap();
+        let (path, _) = sealed(&base, &key);
+        let whole = fs::read(&path).unwrap();
+
+        // the footer cut short
+        fs::write(&path, &whole[..whole.len() - 1]).unwrap();
+        assert!(open(&path, &key).is_err());
+
+        // a whole chunk gone but the footer intact, it no longer adds up
+        let footer_at = whole.len() - FOOTER_LEN as usize;
+        let last_chunk_at = key.header.len() + 3 * SEALED_CHUNK as usize;
+        fs::write(
+            &path,
+            [&whole[..last_chunk_at], &whole[footer_at..]].concat(),
+        )
+        .unwrap();
+        assert_eq!(
+            open(&path, &key).unwrap_err(),
+            "Encrypted backup is truncated."
+        );
+
+        // nothing but the header
+        fs::write(&path, &key.header).unwrap();
+        assert_eq!(
+            open(&path, &key).unwrap_err(),
+            "Encrypted backup is truncated."
+        );
+        let _ = fs::remove_dir_all(&base);
+    }
+
+    #[test]
+    fn tampering_is_caught() {
+        let base = test_dir("crypto_tampered");
+        let key = Key::new("hunter2").unwrap();
+        let (path, _) = sealed(&base, &key);
+        let whole = fs::read(&path).unwrap();
+
+        // one bit in the sealed length
+        let mut bad = whole.clone();
+        bad[whole.len() - TAG_LEN - 1] ^= 1;
+        fs::write(&path, &bad).unwrap();
+        assert!(open(&path, &key).is_err());
+
+        // the footer of another archive under the same key
+        let other = base.join("other");
+        fs::create_dir_all(&other).unwrap();
+        let mut shorter = EncryptedFile::create(&other.join("b"), &key).unwrap();
+        shorter.write_all(b"short").unwrap();
+        shorter.close().unwrap();
+        let short = fs::read(other.join("b")).unwrap();
+        let footer_at = whole.len() - FOOTER_LEN as usize;
+        let mut bad = whole.clone();
+        bad[footer_at..].copy_from_slice(&short[short.len() - FOOTER_LEN as usize..]);
+        fs::write(&path, &bad).unwrap();
+        assert!(open(&path, &key).is_err());
+
+        // a chunk changed, the footer still opens but reading fails
+        let mut bad = whole.clone();
+        bad[key.header.len() + SEALED_CHUNK as usize + 100] ^= 1;
+        fs::write(&path, &bad).unwrap();
+        assert!(open(&path, &key).unwrap_err().contains("damaged"));
+
+        // two chunks swapped
+        let mut bad = whole.clone();
+        let first = key.header.len();
+        let second = first + SEALED_CHUNK as usize;
+        let (a, b) = bad[first..second + SEALED_CHUNK as usize].split_at_mut(SEALED_CHUNK as usize);
+        a.swap_with_slice(b);
+        fs::write(&path, &bad).unwrap();
+        assert!(open(&path, &key).unwrap_err().contains("damaged"));
+        let _ = fs::remove_dir_all(&base);
+    }
+}
@@ -0,0 +1,477 @@
+use std::{
+    collections::BTreeMap,
+    io::{self, BufRead, BufReader, Write},
+    path::PathBuf,
+    process::{Command, Stdio},
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, AtomicU64, Ordering},
+    },
+    thread,
+    time::Duration,
+};
+
+#[cfg(unix)]
+use interprocess::local_socket::GenericFilePath;
+#[cfg(not(unix))]
+use interprocess::local_socket::GenericNamespaced;
+use interprocess::local_socket::{ListenerOptions, Name, Stream, prelude::*};
+use log::{debug, info, warn};
+use serde::{Deserialize, Serialize};
+
+use crate::api;
+use crate::backup::{BackupOptions, backup_gui};
+use crate::helpers::Progress;
+use crate::schedule;
+
+// a named pipe on windows, elsewhere a unix socket in a folder only the user
+// can enter
+const SOCKET_NAME: &str = "konserve.sock";
+
+// a client that connects and never sends its line gives up its thread after this
+const READ_TIMEOUT: Duration = Duration::from_secs(10);
+
+// one json line each way per connection
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "cmd", rename_all = "snake_case")]
+pub enum Request {
+    Run {
+        paths: Vec<PathBuf>,
+        out: PathBuf,
+        options: Box<BackupOptions>,
+    },
+    Jobs,
+    Cancel {
+        id: u64,
+    },
+    Pause {
+        id: u64,
+        paused: bool,
+    },
+    Shutdown,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "reply", rename_all = "snake_case")]
+pub enum Reply {
+    Started { id: u64 },
+    Jobs { jobs: Vec<JobStatus> },
+    Ok,
+    Error { message: String },
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum JobState {
+    Running,
+    Done,
+    Failed,
+}
+
+impl JobState {
+    pub fn label(self) -> &'static str {
+        match self {
+            Self::Running => "Running",
+            Self::Done => "Done",
+            Self::Failed => "Failed",
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct JobStatus {
+    pub id: u64,
+    pub state: JobState,
+    pub percent: u32,
+    pub out: PathBuf,
+    // while running
+    #[serde(default)]
+    pub current_file: Option<PathBuf>,
+    #[serde(default)]
+    pub paused: bool,
+    #[serde(default)]
+    pub bytes_done: u64,
+    #[serde(default)]
+    pub bytes_total: u64,
+    // the archive and its report once done, the error once failed
+    pub message: String,
+}
+
+struct Job {
+    status: JobStatus,
+    progress: Progress,
+}
+
+// every job since the daemon started, shared by the socket and the http api
+#[derive(Default)]
+pub struct Jobs {
+    table: Mutex<BTreeMap<u64, Job>>,
+    next_id: AtomicU64,
+}
+
+impl Jobs {
+    pub fn start(
+        self: &Arc<Self>,
+        paths: Vec<PathBuf>,
+        out: PathBuf,
+        options: BackupOptions,
+    ) -> u64 {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed) + 1;
+        info!("Daemon: job {id} backs up {} paths", paths.len());
+        let progress = Progress::default();
+        self.table.lock().unwrap().insert(
+            id,
+            Job {
+                status: JobStatus {
+                    id,
+                    state: JobState::Running,
+                    percent: 0,
+                    out: out.clone(),
+                    current_file: None,
+                    paused: false,
+                    bytes_done: 0,
+                    bytes_total: 0,
+                    message: String::new(),
+                },
+                progress: progress.clone(),
+            },
+        );
+
+        let jobs = self.clone();
+        thread::spawn(move || {
+            // nobody is watching to answer a prompt
+            let options = BackupOptions {
+                interactive: false,
+                ..options
+            };
+            let result = backup_gui(&paths, &out, &progress, &options);
+            let mut table = jobs.table.lock().unwrap();
+            let Some(job) = table.get_mut(&id) else {
+                return;
+            };
+            match result {
+                Ok(summary) => {
+                    info!("Daemon: job {id} done");
+                    job.status.state = JobState::Done;
+                    job.status.message =
+                        format!("{}\n{}", summary.archive.display(), summary.report());
+                }
+                Err(e) => {
+                    warn!("Daemon: job {id} failed: {e}");
+                    job.status.state = JobState::Failed;
+                    job.status.message = e;
+                }
+            }
+        });
+        id
+    }
+
+    pub fn status(&self, id: u64) -> Option<JobStatus> {
+        self.table.lock().unwrap().get(&id).map(Job::status)
+    }
+
+    pub fn all(&self) -> Vec<JobStatus> {
+        self.table
+            .lock()
+            .unwrap()
+            .values()
+            .map(Job::status)
+            .collect()
+    }
+
+    pub fn cancel(&self, id: u64) -> Result<(), String> {
+        match self.table.lock().unwrap().get(&id) {
+            Some(job) if job.status.state == JobState::Running => {
+                info!("Daemon: cancelling job {id}");
+                job.progress.cancel();
+                Ok(())
+            }
+            Some(_) => Err(format!("Job {id} already finished.")),
+            None => Err(format!("No job {id}.")),
+        }
+    }
+
+    pub fn pause(&self, id: u64, paused: bool) -> Result<(), String> {
+        match self.table.lock().unwrap().get(&id) {
+            Some(job) if job.status.state == JobState::Running => {
+                info!(
+                    "Daemon: {} job {id}",
+                    if paused { "pausing" } else { "resuming" }
+                );
+                if paused {
+                    job.progress.pause();
+                } else {
+                    job.progress.resume();
+                }
+                Ok(())
+            }
+            Some(_) => Err(format!("Job {id} already finished.")),
+            None => Err(format!("No job {id}.")),
+        }
+    }
+
+    fn running(&self) -> usize {
+        self.all()
+            .iter()
+            .filter(|job| job.state == JobState::Running)
+            .count()
+    }
+}
+
+impl Job {
+    // the stored status with live progress filled in
+    fn status(&self) -> JobStatus {
+        let running = self.status.state == JobState::Running;
+        let (bytes_done, bytes_total) = self.progress.bytes();
+        JobStatus {
+            percent: self.progress.get().min(100),
+            current_file: self.progress.current_file().filter(|_| running),
+            paused: running && self.progress.is_paused(),
+            bytes_done,
+            bytes_total,
+            ..self.status.clone()
+        }
+    }
+}
+
+// the runtime dir on linux, the per-user cache dir where there is none
+#[cfg(unix)]
+fn socket_path() -> io::Result<PathBuf> {
+    use std::os::unix::fs::{DirBuilderExt, PermissionsExt};
+
+    let dir = dirs::runtime_dir()
+        .or_else(dirs::cache_dir)
+        .ok_or_else(|| io::Error::other("No runtime or cache directory"))?
+        .join("Konserve");
+    std::fs::DirBuilder::new()
+        .recursive(true)
+        .mode(0o700)
+        .create(&dir)?;
+    std::fs::set_permissions(&dir, std::fs::Permissions::from_mode(0o700))?;
+    Ok(dir.join(SOCKET_NAME))
+}
+
+#[cfg(unix)]
+fn name() -> io::Result<Name<'static>> {
+    socket_path()?.to_fs_name::<GenericFilePath>()
+}
+
+#[cfg(not(unix))]
+fn name() -> io::Result<Name<'static>> {
+    SOCKET_NAME.to_ns_name::<GenericNamespaced>()
+}
+
+fn listen(overwrite: bool) -> io::Result<interprocess::local_socket::Listener> {
+    let options = ListenerOptions::new()
+        .name(name()?)
+        .try_overwrite(overwrite);
+    #[cfg(unix)]
+    let options = {
+        use interprocess::os::unix::local_socket::ListenerOptionsExt;
+        options.mode(0o600)
+    };
+    options.create_sync()
+}
+
+// stays in the foreground until a shutdown request, backups run on their
+// own threads so closing the window that started them doesn't end them.
+// with `http` the same jobs are also served on that loopback port
+pub fn serve(http: Option<u16>) -> Result<(), String> {
+    let listener = match listen(false) {
+        Err(e) if e.kind() == io::ErrorKind::AddrInUse => {
+            // a socket file left behind by a daemon that didn't exit cleanly
+            // refuses connections and can be replaced
+            if Stream::connect(name().map_err(|e| e.to_string())?).is_ok() {
+                return Err("A Konserve daemon is already running.".into());
+            }
+            listen(true).map_err(|e| e.to_string())?
+        }
+        listener => listener.map_err(|e| e.to_string())?,
+    };
+    info!("Daemon listening on {SOCKET_NAME}");
+
+    let jobs: Arc<Jobs> = Arc::default();
+    schedule::start(jobs.clone());
+    let api = match http {
+        Some(port) => Some(api::start(port, jobs.clone())?),
+        None => None,
+    };
+
+    // each connection gets its own thread so a slow client can't hold up
+    // the rest. a shutdown sets `stop` and connects once more to wake the
+    // accept loop
+    let stop = Arc::new(AtomicBool::new(false));
+    for conn in listener.incoming() {
+        if stop.load(Ordering::SeqCst) {
+            break;
+        }
+        let conn = match conn {
+            Ok(conn) => conn,
+            Err(e) => {
+                warn!("Daemon: connection failed: {e}");
+                continue;
+            }
+        };
+        let jobs = jobs.clone();
+        let stop = stop.clone();
+        thread::spawn(move || {
+            if answer(conn, &jobs) {
+                stop.store(true, Ordering::SeqCst);
+                if let Err(e) = name().and_then(Stream::connect) {
+                    warn!("Daemon: couldn't wake the listener: {e}");
+                }
+            }
+        });
+    }
+    info!("Daemon shutting down");
+    if let Some(api) = api {
+        api.stop();
+    }
+    Ok(())
+}
+
+// reads one request and answers it, true when the daemon should stop
+fn answer(conn: Stream, jobs: &Arc<Jobs>) -> bool {
+    // named pipes have no read timeout, there the thread waits for the client
+    if let Err(e) = conn.set_recv_timeout(Some(READ_TIMEOUT)) {
+        debug!("Daemon: no read timeout: {e}");
+    }
+    let mut conn = BufReader::new(conn);
+    let mut line = String::new();
+    if let Err(e) = conn.read_line(&mut line) {
+        warn!("Daemon: couldn't read request: {e}");
+        return false;
+    }
+
+    let mut stop = false;
+    let reply = match serde_json::from_str::<Request>(&line) {
+        Ok(Request::Run {
+            paths,
+            out,
+            options,
+        }) => Reply::Started {
+            id: jobs.start(paths, out, *options),
+        },
+        Ok(Request::Jobs) => Reply::Jobs { jobs: jobs.all() },
+        Ok(Request::Cancel { id }) => match jobs.cancel(id) {
+            Ok(()) => Reply::Ok,
+            Err(message) => Reply::Error { message },
+        },
+        Ok(Request::Pause { id, paused }) => match jobs.pause(id, paused) {
+            Ok(()) => Reply::Ok,
+            Err(message) => Reply::Error { message },
+        },
+        Ok(Request::Shutdown) => match jobs.running() {
+            0 => {
+                stop = true;
+                Reply::Ok
+            }
+            running => Reply::Error {
+                message: format!("{running} backups still running, cancel them first."),
+            },
+        },
+        Err(e) => Reply::Error {
+            message: format!("Bad request: {e}"),
+        },
+    };
+
+    let sent = serde_json::to_string(&reply)
+        .map_err(|e| io::Error::other(e.to_string()))
+        .and_then(|json| writeln!(conn.get_mut(), "{json}"));
+    if let Err(e) = sent {
+        warn!("Daemon: couldn't answer: {e}");
+    }
+    stop
+}
+
+pub fn request(request: &Request) -> Result<Reply, String> {
+    let stream = Stream::connect(name().map_err(|e| e.to_string())?)
+        .map_err(|e| format!("The daemon isn't running ({e})."))?;
+    let mut conn = BufReader::new(stream);
+    let json = serde_json::to_string(request).map_err(|e| e.to_string())?;
+    writeln!(conn.get_mut(), "{json}").map_err(|e| e.to_string())?;
+
+    let mut line = String::new();
+    conn.read_line(&mut line).map_err(|e| e.to_string())?;
+    match serde_json::from_str(&line).map_err(|e| format!("Bad reply from the daemon: {e}"))? {
+        Reply::Error { message } => Err(message),
+        reply => Ok(reply),
+    }
+}
+
+// starts `konserve daemon` next to us unless one answers already
+pub fn ensure_running() -> Result<(), String> {
+    if request(&Request::Jobs).is_ok() {
+        return Ok(());
+    }
+    let exe = std::env::current_exe().map_err(|e| e.to_string())?;
+    info!("Starting the daemon");
+    Command::new(exe)
+        .arg("daemon")
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .map_err(|e| format!("Couldn't start the daemon: {e}"))?;
+
+    for _ in 0..50 {
+        thread::sleep(Duration::from_millis(100));
+        if request(&Request::Jobs).is_ok() {
+            return Ok(());
+        }
+    }
+    Err("The daemon didn't start.".into())
+}
+
+pub fn job(id: u64) -> Result<JobStatus, String> {
+    match request(&Request::Jobs)? {
+        Reply::Jobs { jobs } => jobs
+            .into_iter()
+            .find(|job| job.id == id)
+            .ok_or_else(|| format!("The daemon lost job {id}.")),
+        _ => Err("Unexpected reply from the daemon.".into()),
+    }
+}
+
+// hands a backup to the daemon, starting it if needed, and mirrors the job's
+// percentage and bytes into `progress` until it finishes. pausing or
+// cancelling `progress` is passed on to the job
+pub fn run_and_follow(
+    paths: Vec<PathBuf>,
+    out: PathBuf,
+    options: BackupOptions,
+    progress: &Progress,
+) -> Result<JobStatus, String> {
+    ensure_running()?;
+    let Reply::Started { id } = request(&Request::Run {
+        paths,
+        out,
+        options: Box::new(options),
+    })?
+    else {
+        return Err("Unexpected reply from the daemon.".into());
+    };
+    let mut cancel_sent = false;
+    loop {
+        let status = job(id)?;
+        if status.state != JobState::Running {
+            progress.done();
+            return Ok(status);
+        }
+        // the bytes too, so the window can tell how fast it goes
+        if progress.bytes().1 != status.bytes_total {
+            progress.set_total_bytes(status.bytes_total);
+        }
+        progress.add_bytes(status.bytes_done.saturating_sub(progress.bytes().0));
+        progress.set(status.percent);
+        if progress.is_cancelled() && !cancel_sent {
+            cancel_sent = request(&Request::Cancel { id }).is_ok();
+        } else if progress.is_paused() != status.paused {
+            let paused = progress.is_paused();
+            if let Err(e) = request(&Request::Pause { id, paused }) {
+                warn!("Daemon: couldn't pause job {id}: {e}");
+            }
+        }
+        thread::sleep(Duration::from_millis(300));
+    }
+}
@@ -0,0 +1,152 @@
+use std::{fs::File, io::Write, path::Path, thread};
+
+use chrono::Local;
+use log::debug;
+use serde_json::Value;
+use zip::{ZipWriter, write::SimpleFileOptions};
+
+use crate::catalog::Catalog;
+use crate::helpers::format_bytes;
+use crate::{gpg, logger, settings::Settings};
+
+// backups listed one by one in catalog.txt, the rest only counted
+const RECENT_BACKUPS: usize = 20;
+
+// swaps the home folder for ~ so user names don't end up in bug reports
+fn sanitize(text: &str) -> String {
+    match dirs::home_dir().and_then(|h| h.to_str().map(str::to_string)) {
+        Some(home) if !home.is_empty() => text.replace(&home, "~"),
+        _ => text.to_string(),
+    }
+}
+
+// where a path ends is enough to tell templates and archives apart
+fn hide_folders(path: &str) -> String {
+    let name = Path::new(path).file_name().unwrap_or_default();
+    format!("<redacted>/{}", name.to_string_lossy())
+}
+
+// key ids and fingerprints say who the user is, folders what their disk holds
+fn redact(value: &mut Value) {
+    match value {
+        Value::String(text) if Path::new(text.as_str()).is_absolute() => {
+            *text = hide_folders(text);
+        }
+        Value::Array(items) => items.iter_mut().for_each(redact),
+        Value::Object(fields) => {
+            for (key, field) in fields.iter_mut() {
+                match (key.as_str(), &mut *field) {
+                    ("gpg_recipient", Value::String(text)) if !text.is_empty() => {
+                        *text = "<redacted>".into();
+                    }
+                    ("trusted_keys", Value::Array(keys)) => {
+                        keys.iter_mut().for_each(|k| *k = "<redacted>".into());
+                    }
+                    _ => redact(field),
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+fn catalog_summary() -> String {
+    let backups = match Catalog::open().and_then(|catalog| catalog.backups()) {
+        Ok(backups) => backups,
+        Err(e) => return format!("Couldn't read the catalog: {e}\n"),
+    };
+    let missing = backups.iter().filter(|b| !b.exists()).count();
+    let mut summary = format!(
+        "Backups: {} ({missing} missing)\nFiles recorded: {}\nTotal size: {}\n",
+        backups.len(),
+        backups.iter().map(|b| b.files).sum::<u64>(),
+        format_bytes(backups.iter().map(|b| b.size).sum()),
+    );
+    if let (Some(newest), Some(oldest)) = (backups.first(), backups.last()) {
+        summary.push_str(&format!(
+            "Oldest: {}\nNewest: {}\n",
+            oldest.created, newest.created
+        ));
+    }
+    if !backups.is_empty() {
+        summary.push_str("\nRecent:\n");
+    }
+    for backup in backups.iter().take(RECENT_BACKUPS) {
+        summary.push_str(&format!(
+            "{}  {}  {} files  {}",
+            backup.created,
+            backup.mode,
+            backup.files,
+            format_bytes(backup.size)
+        ));
+        if let (Some(ratio), Some(elapsed)) = (backup.ratio, backup.elapsed) {
+            summary.push_str(&format!(
+                "  ({:.1}% of original, {:.1}s)",
+                ratio * 100.0,
+                elapsed.as_secs_f64()
+            ));
+        }
+        if !backup.exists() {
+            summary.push_str("  missing");
+        }
+        summary.push_str(&format!(
+            "  {}\n",
+            hide_folders(&backup.path.to_string_lossy())
+        ));
+    }
+    summary
+}
+
+fn system_info() -> String {
+    let threads = thread::available_parallelism()
+        .map(|n| n.get().to_string())
+        .unwrap_or_else(|_| "unknown".into());
+
+    format!(
+        "Konserve {}\nOS: {} ({})\nCPU threads: {}\nGPG: {}\nGenerated: {}\n",
+        env!("CARGO_PKG_VERSION"),
+        std::env::consts::OS,
+        std::env::consts::ARCH,
+        threads,
+        gpg::version().unwrap_or_else(|| "not found".into()),
+        Local::now().format("%Y-%m-%d %H:%M:%S %z"),
+    )
+}
+
+pub fn default_file_name() -> String {
+    format!(
+        "konserve-diagnostics-{}.zip",
+        Local::now().format("%Y%m%d-%H%M%S")
+    )
+}
+
+pub fn export_bundle(out: &Path, settings: &Settings) -> Result<(), String> {
+    debug!("export_bundle: Writing {}", out.display());
+
+    let file = File::create(out).map_err(|e| e.to_string())?;
+    let mut zip = ZipWriter::new(file);
+    let options = SimpleFileOptions::default();
+
+    let log: String = logger::snapshot()
+        .iter()
+        .map(|l| l.render() + "\n")
+        .collect();
+    let mut config = serde_json::to_value(settings).map_err(|e| e.to_string())?;
+    redact(&mut config);
+    let config = serde_json::to_string_pretty(&config).map_err(|e| e.to_string())?;
+
+    for (name, contents) in [
+        ("system.txt", system_info()),
+        ("settings.json", sanitize(&config)),
+        ("catalog.txt", catalog_summary()),
+        ("konserve.log", sanitize(&log)),
+    ] {
+        zip.start_file(name, options).map_err(|e| e.to_string())?;
+        zip.write_all(contents.as_bytes())
+            .map_err(|e| e.to_string())?;
+    }
+
+    zip.finish().map_err(|e| e.to_string())?;
+    debug!("export_bundle: Done");
+    Ok(())
+}
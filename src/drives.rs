@@ -0,0 +1,242 @@
+use std::{collections::HashSet, path::PathBuf, sync::mpsc::Sender, thread, time::Duration};
+
+use log::{debug, info};
+use serde::{Deserialize, Serialize};
+
+use crate::archive::ArchiveFormat;
+
+// plugging a drive in shows up within this
+const POLL: Duration = Duration::from_secs(3);
+
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+pub struct Drive {
+    pub label: String,
+    // the volume serial, "1A2B-3C4D" on fat and ntfs alike; empty where the
+    // platform doesn't tell
+    pub serial: String,
+    pub root: PathBuf,
+}
+
+impl Drive {
+    pub fn describe(&self) -> String {
+        if self.serial.is_empty() {
+            format!("{} ({})", self.label, self.root.display())
+        } else {
+            format!("{} [{}] ({})", self.label, self.serial, self.root.display())
+        }
+    }
+}
+
+// a template backed up to a drive whenever it is plugged in
+#[derive(Serialize, Deserialize, Clone)]
+pub struct DriveBinding {
+    pub template: PathBuf,
+    pub label: String,
+    pub serial: String,
+    // inside the drive, so the backups don't litter its root
+    pub folder: String,
+    pub format: ArchiveFormat,
+}
+
+impl DriveBinding {
+    pub fn new(template: PathBuf, drive: &Drive, format: ArchiveFormat) -> Self {
+        Self {
+            template,
+            label: drive.label.clone(),
+            serial: drive.serial.clone(),
+            folder: "Konserve".into(),
+            format,
+        }
+    }
+
+    // the serial tells two sticks with the same label apart, the label is
+    // all there is without one
+    pub fn matches(&self, drive: &Drive) -> bool {
+        if self.serial.is_empty() || drive.serial.is_empty() {
+            self.label == drive.label
+        } else {
+            self.serial.eq_ignore_ascii_case(&drive.serial)
+        }
+    }
+
+    pub fn out(&self, drive: &Drive) -> PathBuf {
+        drive.root.join(&self.folder)
+    }
+}
+
+// sends every drive that shows up after it started, the ones already
+// plugged in don't count
+pub fn monitor(tx: Sender<Drive>) {
+    thread::spawn(move || {
+        let mut known: HashSet<Drive> = removable().into_iter().collect();
+        debug!("Drives: {} present at start", known.len());
+        loop {
+            thread::sleep(POLL);
+            let now: HashSet<Drive> = removable().into_iter().collect();
+            for drive in now.difference(&known) {
+                info!("Drive arrived: {}", drive.describe());
+                if tx.send(drive.clone()).is_err() {
+                    return;
+                }
+            }
+            known = now;
+        }
+    });
+}
+
+#[cfg(windows)]
+pub fn removable() -> Vec<Drive> {
+    use windows_sys::Win32::Storage::FileSystem::{
+        GetDriveTypeW, GetLogicalDrives, GetVolumeInformationW,
+    };
+    use windows_sys::Win32::System::WindowsProgramming::{DRIVE_FIXED, DRIVE_REMOVABLE};
+
+    // usb disks often call themselves fixed, only the system drive is
+    // certainly not one of them
+    let system = std::env::var("SystemDrive").unwrap_or_else(|_| "C:".into());
+    let mask = unsafe { GetLogicalDrives() };
+    let mut drives = Vec::new();
+    for letter in (0..26u8).filter(|i| mask & (1 << i) != 0) {
+        let letter = (b'A' + letter) as char;
+        if system.eq_ignore_ascii_case(&format!("{letter}:")) {
+            continue;
+        }
+        let root = format!("{letter}:\\");
+        let wide: Vec<u16> = root.encode_utf16().chain([0]).collect();
+        let kind = unsafe { GetDriveTypeW(wide.as_ptr()) };
+        if kind != DRIVE_REMOVABLE && kind != DRIVE_FIXED {
+            continue;
+        }
+        let mut label = [0u16; 261];
+        let mut serial = 0u32;
+        let ok = unsafe {
+            GetVolumeInformationW(
+                wide.as_ptr(),
+                label.as_mut_ptr(),
+                label.len() as u32,
+                &mut serial,
+                std::ptr::null_mut(),
+                std::ptr::null_mut(),
+                std::ptr::null_mut(),
+                0,
+            )
+        };
+        // an empty card reader slot
+        if ok == 0 {
+            continue;
+        }
+        let len = label.iter().position(|c| *c == 0).unwrap_or(label.len());
+        drives.push(Drive {
+            label: String::from_utf16_lossy(&label[..len]),
+            serial: format!("{:04X}-{:04X}", serial >> 16, serial & 0xFFFF),
+            root: root.into(),
+        });
+    }
+    drives
+}
+
+// usb partitions that are mounted, named by udev's /dev/disk links
+#[cfg(target_os = "linux")]
+pub fn removable() -> Vec<Drive> {
+    use std::{collections::HashMap, fs};
+
+    // (the device, the link's name), a device has several links in by-id
+    let links = |dir: &str| -> Vec<(PathBuf, String)> {
+        let Ok(entries) = fs::read_dir(dir) else {
+            return Vec::new();
+        };
+        entries
+            .filter_map(Result::ok)
+            .filter_map(|entry| {
+                let device = fs::canonicalize(entry.path()).ok()?;
+                Some((device, unescape(&entry.file_name().to_string_lossy())))
+            })
+            .collect()
+    };
+    let usb: HashSet<PathBuf> = links("/dev/disk/by-id")
+        .into_iter()
+        .filter(|(_, id)| id.starts_with("usb-"))
+        .map(|(device, _)| device)
+        .collect();
+    let labels: HashMap<_, _> = links("/dev/disk/by-label").into_iter().collect();
+    let uuids: HashMap<_, _> = links("/dev/disk/by-uuid").into_iter().collect();
+
+    let mounts = fs::read_to_string("/proc/self/mounts").unwrap_or_default();
+    let mut drives = Vec::new();
+    for line in mounts.lines() {
+        let mut fields = line.split(' ');
+        let (Some(device), Some(root)) = (fields.next(), fields.next()) else {
+            continue;
+        };
+        let Ok(device) = fs::canonicalize(device) else {
+            continue;
+        };
+        if !usb.contains(&device) {
+            continue;
+        }
+        let root = PathBuf::from(unescape(root));
+        let label = labels.get(&device).cloned().unwrap_or_else(|| {
+            root.file_name()
+                .map(|n| n.to_string_lossy().into_owned())
+                .unwrap_or_default()
+        });
+        drives.push(Drive {
+            label,
+            serial: uuids.get(&device).cloned().unwrap_or_default(),
+            root,
+        });
+    }
+    drives
+}
+
+// everything under /Volumes but the startup disk, macOS has no cheap serial
+#[cfg(target_os = "macos")]
+pub fn removable() -> Vec<Drive> {
+    let Ok(entries) = std::fs::read_dir("/Volumes") else {
+        return Vec::new();
+    };
+    entries
+        .filter_map(Result::ok)
+        .filter(|entry| !entry.path().is_symlink())
+        .map(|entry| Drive {
+            label: entry.file_name().to_string_lossy().into_owned(),
+            serial: String::new(),
+            root: entry.path(),
+        })
+        .collect()
+}
+
+#[cfg(not(any(windows, target_os = "linux", target_os = "macos")))]
+pub fn removable() -> Vec<Drive> {
+    Vec::new()
+}
+
+// udev writes "\x20" and /proc/mounts "\040" for a space
+#[cfg(target_os = "linux")]
+fn unescape(text: &str) -> String {
+    let bytes = text.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes.get(i + 1) == Some(&b'x');
+        let (digits, radix) = if hex {
+            (i + 2..i + 4, 16)
+        } else {
+            (i + 1..i + 4, 8)
+        };
+        let code = text
+            .get(digits.clone())
+            .and_then(|d| u8::from_str_radix(d, radix).ok());
+        match code {
+            Some(code) if bytes[i] == b'\\' => {
+                out.push(code);
+                i = digits.end;
+            }
+            _ => {
+                out.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
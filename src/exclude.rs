@@ -0,0 +1,460 @@
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+    time::UNIX_EPOCH,
+};
+
+use chrono::{Local, NaiveDate, TimeDelta};
+use globset::{GlobBuilder, GlobMatcher, GlobSet, GlobSetBuilder};
+use log::{debug, warn};
+use serde::{Deserialize, Serialize};
+use walkdir::{DirEntry, WalkDir};
+
+use crate::helpers::fix_skip;
+use crate::restore::CASE_INSENSITIVE_FS;
+
+// read in every folder that is walked when ignore files are honored
+pub const IGNORE_FILES: [&str; 2] = [".gitignore", ".konserveignore"];
+
+// left out with hidden files, whatever the disk marks them as
+const SYSTEM_FILES: [&str; 5] = [
+    "Thumbs.db",
+    "ehthumbs.db",
+    "desktop.ini",
+    ".DS_Store",
+    "$RECYCLE.BIN",
+];
+
+// what a backup does with the symlinks it walks into
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum SymlinkPolicy {
+    // as a link, restored as one pointing to the same place
+    #[default]
+    Store,
+    // what it points to, as if it were there
+    Follow,
+    // left out with a warning
+    Skip,
+}
+
+impl SymlinkPolicy {
+    pub const ALL: [SymlinkPolicy; 3] = [Self::Store, Self::Follow, Self::Skip];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            Self::Store => "Store as links",
+            Self::Follow => "Follow",
+            Self::Skip => "Skip",
+        }
+    }
+
+    fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+// what a template leaves out and how it walks, handed on to its backups as
+// a whole
+#[derive(Serialize, Deserialize, Clone, Default, PartialEq, Debug)]
+#[serde(default)]
+pub struct Filters {
+    // globs of what to leave out below the backed up folders
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub excludes: Vec<String>,
+    // globs of the only files to store, all of them when empty
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub includes: Vec<String>,
+    // leave out what .gitignore and .konserveignore files say
+    pub ignore_files: bool,
+    // leave out dotfiles, what windows marks hidden or system and the
+    // thumbnail caches and folder settings explorer and finder leave around
+    pub skip_hidden: bool,
+    // files over this many MB are left out
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_size_mb: Option<u64>,
+    // and files under this many KB
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub min_size_kb: Option<u64>,
+    // files last changed before this are left out, a date like "2025-01-31"
+    // or a number of days back like "7d". empty is any time
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub modified_after: String,
+    // how many levels below a backed up folder are walked, by the folder as
+    // the template lists it. 1 is only the files right in it
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub max_depth: BTreeMap<PathBuf, usize>,
+    #[serde(skip_serializing_if = "SymlinkPolicy::is_default")]
+    pub symlinks: SymlinkPolicy,
+    // don't go into other drives mounted below the backed up folders, or on
+    // windows into reparse points like the OneDrive folder
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub stay_on_volume: bool,
+}
+
+impl Filters {
+    // "over 4096 MB, under 1 KB"
+    pub fn describe_sizes(&self) -> Option<String> {
+        let sizes: Vec<String> = [
+            self.max_size_mb.map(|mb| format!("over {mb} MB")),
+            self.min_size_kb.map(|kb| format!("under {kb} KB")),
+        ]
+        .into_iter()
+        .flatten()
+        .collect();
+        (!sizes.is_empty()).then(|| sizes.join(", "))
+    }
+
+    // the depth limits by the folders as `fix_skip` finds them here
+    pub fn fixed_depths(&self) -> BTreeMap<PathBuf, usize> {
+        self.max_depth
+            .iter()
+            .map(|(root, depth)| (fix_skip(root).unwrap_or(root.clone()), *depth))
+            .collect()
+    }
+}
+
+// the `modified_after` cutoff in unix seconds, days are counted back from now
+pub fn parse_cutoff(text: &str) -> Result<Option<u64>, String> {
+    let text = text.trim();
+    if text.is_empty() {
+        return Ok(None);
+    }
+    let cutoff = match text.strip_suffix('d').map(str::parse::<u32>) {
+        Some(Ok(days)) => Some(Local::now() - TimeDelta::days(days.into())),
+        _ => NaiveDate::parse_from_str(text, "%Y-%m-%d")
+            .ok()
+            .and_then(|date| {
+                date.and_hms_opt(0, 0, 0)?
+                    .and_local_timezone(Local)
+                    .earliest()
+            }),
+    };
+    match cutoff {
+        Some(cutoff) => Ok(Some(cutoff.timestamp().max(0) as u64)),
+        None => Err(format!(
+            "\"{text}\" isn't a date like 2025-01-31 or a number of days like 7d"
+        )),
+    }
+}
+
+// globs as a template lists them. one without a "/" is matched against
+// names at any depth ("*.tmp", "Thumbs.db"), any other against the path
+// below the backed up folder ("Cache/*", "**/node_modules/**"). an entry
+// may hold several split by ";", like "*.docx;*.xlsx"
+#[derive(Clone)]
+struct Patterns {
+    names: GlobSet,
+    paths: GlobSet,
+}
+
+impl Patterns {
+    fn new(patterns: &[String]) -> Result<Self, String> {
+        let (mut names, mut paths) = (GlobSetBuilder::new(), GlobSetBuilder::new());
+        let patterns = patterns
+            .iter()
+            .flat_map(|p| p.split(';'))
+            .map(str::trim)
+            .filter(|p| !p.is_empty());
+        for pattern in patterns {
+            let glob = |pattern: &str| {
+                GlobBuilder::new(pattern)
+                    .literal_separator(true)
+                    // "*.docx" takes "Report.DOCX" where the disk doesn't care
+                    .case_insensitive(CASE_INSENSITIVE_FS)
+                    .build()
+                    .map_err(|e| format!("Pattern {e}"))
+            };
+            if !pattern.contains('/') {
+                names.add(glob(pattern)?);
+                continue;
+            }
+            let pattern = pattern.trim_start_matches('/');
+            paths.add(glob(pattern)?);
+            // "dir/**" means the folder too, so it isn't walked at all
+            if let Some(dir) = pattern.strip_suffix("/**") {
+                paths.add(glob(dir)?);
+            }
+        }
+        Ok(Self {
+            names: names.build().map_err(|e| e.to_string())?,
+            paths: paths.build().map_err(|e| e.to_string())?,
+        })
+    }
+
+    fn is_empty(&self) -> bool {
+        self.names.is_empty() && self.paths.is_empty()
+    }
+
+    fn matches(&self, entry: &DirEntry, relative: &str) -> bool {
+        self.names.is_match(entry.file_name()) || self.paths.is_match(relative)
+    }
+}
+
+// what a backup leaves out below each folder it was given
+#[derive(Clone)]
+pub struct Excludes {
+    excludes: Patterns,
+    // when there are any, files that match none of them are left out
+    includes: Patterns,
+    ignore_files: bool,
+    skip_hidden: bool,
+    // in bytes
+    max_size: Option<u64>,
+    min_size: Option<u64>,
+    // unix seconds
+    modified_after: Option<u64>,
+    // by the folder as found on this machine
+    max_depth: BTreeMap<PathBuf, usize>,
+    follow_links: bool,
+    stay_on_volume: bool,
+}
+
+impl Excludes {
+    pub fn new(filters: &Filters) -> Result<Self, String> {
+        Ok(Self {
+            excludes: Patterns::new(&filters.excludes)?,
+            includes: Patterns::new(&filters.includes)?,
+            ignore_files: filters.ignore_files,
+            skip_hidden: filters.skip_hidden,
+            max_size: filters.max_size_mb.map(|mb| mb * 1024 * 1024),
+            min_size: filters.min_size_kb.map(|kb| kb * 1024),
+            modified_after: parse_cutoff(&filters.modified_after)?,
+            max_depth: filters.fixed_depths(),
+            follow_links: filters.symlinks == SymlinkPolicy::Follow,
+            stay_on_volume: filters.stay_on_volume,
+        })
+    }
+
+    // for the template editor, whether a pattern entry can be used
+    pub fn check(pattern: &str) -> Result<(), String> {
+        Patterns::new(&[pattern.to_string()]).map(|_| ())
+    }
+
+    // only some files go in, folders without any are left out
+    pub fn has_includes(&self) -> bool {
+        !self.includes.is_empty()
+    }
+
+    // everything below `root` that isn't left out, a folder that is isn't
+    // walked. sorted so every run visits the same paths in the same order.
+    // links that point nowhere or back up the tree are left out when followed.
+    // junctions and mounted folders count as links on windows
+    pub fn walk<'a>(&'a self, root: &'a Path) -> impl Iterator<Item = DirEntry> + 'a {
+        let mut ignored = self.ignore_files.then(Ignored::default);
+        // a mount point is kept as an empty folder, like tar --one-file-system
+        let mut walk = WalkDir::new(root)
+            .follow_links(self.follow_links)
+            .same_file_system(self.stay_on_volume);
+        if let Some(&depth) = self.max_depth.get(root) {
+            debug!("Walking {} {depth} levels deep", root.display());
+            walk = walk.max_depth(depth);
+        }
+        walk.sort_by_file_name()
+            .into_iter()
+            .filter_entry(move |entry| {
+                !self.skips(root, entry)
+                    && !ignored.as_mut().is_some_and(|ignored| ignored.skips(entry))
+            })
+            .filter_map(|entry| entry.inspect_err(|e| warn!("Left out: {e}")).ok())
+    }
+
+    // `root` itself was picked by hand and always goes in
+    fn skips(&self, root: &Path, entry: &DirEntry) -> bool {
+        if entry.depth() == 0 {
+            return false;
+        }
+        let Ok(relative) = entry.path().strip_prefix(root) else {
+            return false;
+        };
+        if self.stay_on_volume && is_reparse_point(entry) {
+            debug!("Left out reparse point: {}", entry.path().display());
+            return true;
+        }
+        if self.skip_hidden && is_hidden(entry) {
+            debug!("Left out hidden: {}", entry.path().display());
+            return true;
+        }
+        let relative = relative.to_string_lossy().replace('\\', "/");
+        let excluded = self.excludes.matches(entry, &relative);
+        // folders are walked for the files in them
+        let is_dir = entry.file_type().is_dir();
+        let not_included =
+            self.has_includes() && !is_dir && !self.includes.matches(entry, &relative);
+        if excluded || not_included {
+            debug!("Left out: {}", entry.path().display());
+            return true;
+        }
+        if is_dir
+            || (self.max_size.is_none() && self.min_size.is_none() && self.modified_after.is_none())
+        {
+            return false;
+        }
+        let Ok(metadata) = entry.metadata() else {
+            return false;
+        };
+        let size = metadata.len();
+        if self.max_size.is_some_and(|max| size > max)
+            || self.min_size.is_some_and(|min| size < min)
+        {
+            debug!(
+                "Left out by size ({size} bytes): {}",
+                entry.path().display()
+            );
+            return true;
+        }
+        let changed = metadata
+            .modified()
+            .ok()
+            .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+            .map_or(0, |d| d.as_secs());
+        if self.modified_after.is_some_and(|cutoff| changed < cutoff) {
+            debug!("Left out, not changed since: {}", entry.path().display());
+            return true;
+        }
+        false
+    }
+}
+
+fn is_hidden(entry: &DirEntry) -> bool {
+    let name = entry.file_name().to_string_lossy();
+    if name.starts_with('.')
+        || SYSTEM_FILES
+            .iter()
+            .any(|system| name.eq_ignore_ascii_case(system))
+    {
+        return true;
+    }
+    #[cfg(windows)]
+    {
+        use std::os::windows::fs::MetadataExt;
+        use windows_sys::Win32::Storage::FileSystem::{
+            FILE_ATTRIBUTE_HIDDEN, FILE_ATTRIBUTE_SYSTEM,
+        };
+        if let Ok(metadata) = entry.metadata() {
+            return metadata.file_attributes() & (FILE_ATTRIBUTE_HIDDEN | FILE_ATTRIBUTE_SYSTEM)
+                != 0;
+        }
+    }
+    false
+}
+
+// folders windows hands to a filter driver, cloud sync roots and the like.
+// junctions and symlinks are reparse points too but are walked as links
+#[cfg(windows)]
+fn is_reparse_point(entry: &DirEntry) -> bool {
+    use std::os::windows::fs::MetadataExt;
+    use windows_sys::Win32::Storage::FileSystem::FILE_ATTRIBUTE_REPARSE_POINT;
+
+    entry.file_type().is_dir()
+        && entry
+            .metadata()
+            .is_ok_and(|meta| meta.file_attributes() & FILE_ATTRIBUTE_REPARSE_POINT != 0)
+}
+
+#[cfg(not(windows))]
+fn is_reparse_point(_entry: &DirEntry) -> bool {
+    false
+}
+
+// one line of an ignore file, the way git reads it
+struct Rule {
+    glob: GlobMatcher,
+    negate: bool,
+    dir_only: bool,
+    // with a "/" before the end it is matched against the path below the
+    // ignore file's folder, without one against names at any depth
+    anchored: bool,
+}
+
+fn parse_rule(line: &str) -> Option<Result<Rule, String>> {
+    let line = line.trim_end();
+    if line.is_empty() || line.starts_with('#') {
+        return None;
+    }
+    let (negate, line) = match line.strip_prefix('!') {
+        Some(rest) => (true, rest),
+        None => (false, line.strip_prefix('\\').unwrap_or(line)),
+    };
+    let (dir_only, line) = match line.strip_suffix('/') {
+        Some(rest) => (true, rest),
+        None => (false, line),
+    };
+    let anchored = line.contains('/');
+    let pattern = line.trim_start_matches('/');
+    let glob = GlobBuilder::new(pattern)
+        .literal_separator(true)
+        .build()
+        .map_err(|e| e.to_string());
+    Some(glob.map(|glob| Rule {
+        glob: glob.compile_matcher(),
+        negate,
+        dir_only,
+        anchored,
+    }))
+}
+
+fn read_rules(dir: &Path) -> Vec<Rule> {
+    let mut rules = Vec::new();
+    for name in IGNORE_FILES {
+        let path = dir.join(name);
+        let Ok(text) = fs::read_to_string(&path) else {
+            continue;
+        };
+        for line in text.lines() {
+            match parse_rule(line) {
+                Some(Ok(rule)) => rules.push(rule),
+                Some(Err(e)) => warn!("{}: ignoring {line:?}: {e}", path.display()),
+                None => {}
+            }
+        }
+    }
+    rules
+}
+
+// the rules of the folders above the entry being walked, outermost first
+#[derive(Default)]
+struct Ignored {
+    levels: Vec<(usize, PathBuf, Vec<Rule>)>,
+}
+
+impl Ignored {
+    // entries come depth first, a folder's rules are read on the way in
+    fn skips(&mut self, entry: &DirEntry) -> bool {
+        let depth = entry.depth();
+        while self.levels.last().is_some_and(|(d, _, _)| *d >= depth) {
+            self.levels.pop();
+        }
+        let is_dir = entry.file_type().is_dir();
+        // the last rule that matches decides, deeper files come later
+        let mut ignored = false;
+        for (_, dir, rules) in &self.levels {
+            let Ok(relative) = entry.path().strip_prefix(dir) else {
+                continue;
+            };
+            let relative = relative.to_string_lossy().replace('\\', "/");
+            for rule in rules.iter().filter(|rule| is_dir || !rule.dir_only) {
+                let hit = if rule.anchored {
+                    rule.glob.is_match(&relative)
+                } else {
+                    rule.glob.is_match(entry.file_name())
+                };
+                if hit {
+                    ignored = !rule.negate;
+                }
+            }
+        }
+        if ignored {
+            debug!("Ignored: {}", entry.path().display());
+            return true;
+        }
+        if is_dir {
+            let rules = read_rules(entry.path());
+            if !rules.is_empty() {
+                self.levels.push((depth, entry.path().to_path_buf(), rules));
+            }
+        }
+        false
+    }
+}
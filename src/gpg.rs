@@ -0,0 +1,123 @@
+use std::{
+    env,
+    ffi::OsStr,
+    fs,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use aes_gcm::aead::{OsRng, rand_core::RngCore};
+use log::debug;
+
+// finished archives can be handed to an installed gpg, which leaves
+// "backup_x.tar.gpg" next to where the plain archive was
+pub const EXTENSIONS: [&str; 2] = ["gpg", "pgp"];
+
+pub fn is_gpg(path: &Path) -> bool {
+    path.extension()
+        .and_then(|e| e.to_str())
+        .is_some_and(|e| EXTENSIONS.contains(&e.to_lowercase().as_str()))
+}
+
+fn run<S: AsRef<OsStr>>(args: &[S]) -> Result<(), String> {
+    let output = Command::new("gpg")
+        .args(args)
+        .output()
+        .map_err(|e| format!("Couldn't run gpg: {e}"))?;
+    if output.status.success() {
+        return Ok(());
+    }
+
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    debug!("gpg failed: {stderr}");
+    Err(stderr
+        .lines()
+        .last()
+        .unwrap_or("gpg failed")
+        .trim_start_matches("gpg: ")
+        .to_string())
+}
+
+// first line of `gpg --version`, None when gpg isn't installed
+pub fn version() -> Option<String> {
+    let output = Command::new("gpg").arg("--version").output().ok()?;
+    let text = String::from_utf8_lossy(&output.stdout);
+    text.lines().next().map(str::to_string)
+}
+
+// encrypts to `recipient` (key id, fingerprint or email), the plain archive is removed
+pub fn encrypt(archive: &Path, recipient: &str) -> Result<PathBuf, String> {
+    let mut out = archive.as_os_str().to_owned();
+    out.push(".gpg");
+    let out = PathBuf::from(out);
+    debug!("gpg: Encrypting {} for {recipient}", archive.display());
+
+    run(&[
+        OsStr::new("--batch"),
+        OsStr::new("--yes"),
+        // the user picked the key, gpg's web of trust doesn't get a say
+        OsStr::new("--trust-model"),
+        OsStr::new("always"),
+        OsStr::new("--recipient"),
+        OsStr::new(recipient),
+        OsStr::new("--output"),
+        out.as_os_str(),
+        OsStr::new("--encrypt"),
+        archive.as_os_str(),
+    ])?;
+
+    fs::remove_file(archive).map_err(|e| e.to_string())?;
+    Ok(out)
+}
+
+// a new folder under the temp dir with a random name that only the user can
+// enter. creating it fails rather than reusing one someone else made
+fn private_dir() -> Result<PathBuf, String> {
+    let mut bytes = [0u8; 8];
+    OsRng.fill_bytes(&mut bytes);
+    let dir = env::temp_dir().join(format!("konserve-{}", hex::encode(bytes)));
+    let mut builder = fs::DirBuilder::new();
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::DirBuilderExt;
+        builder.mode(0o700);
+    }
+    builder
+        .create(&dir)
+        .map_err(|e| format!("{}: {e}", dir.display()))?;
+    Ok(dir)
+}
+
+// decrypts into a private folder in the temp dir, gpg-agent asks for the
+// key's passphrase itself. headless runs only get keys the agent already has
+// unlocked. hand the result to `discard` when done
+pub fn decrypt(path: &Path, interactive: bool) -> Result<PathBuf, String> {
+    let name = path
+        .file_stem()
+        .map(|s| s.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "backup".into());
+    let out = private_dir()?.join(name);
+    debug!("gpg: Decrypting {} to {}", path.display(), out.display());
+
+    let mut args = vec![OsStr::new("--batch")];
+    if !interactive {
+        args.extend([OsStr::new("--pinentry-mode"), OsStr::new("error")]);
+    }
+    args.extend([
+        OsStr::new("--output"),
+        out.as_os_str(),
+        OsStr::new("--decrypt"),
+        path.as_os_str(),
+    ]);
+    run(&args).inspect_err(|_| discard(&out))?;
+    Ok(out)
+}
+
+// removes a decrypted copy and the folder `decrypt` made for it
+pub fn discard(plain: &Path) {
+    debug!("Removing decrypted copy {}", plain.display());
+    let _ = fs::remove_file(plain);
+    if let Some(dir) = plain.parent() {
+        let _ = fs::remove_dir(dir);
+    }
+}
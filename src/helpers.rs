@@ -1,29 +1,107 @@
 use eframe::egui;
 use eframe::egui::IconData;
 use egui::CollapsingHeader;
+use globset::{Glob, GlobMatcher};
+use log::{debug, info, warn};
+use serde::Serialize;
 use std::{
     collections::HashMap,
-    fs::File,
-    io::Read,
-    path::{Path, PathBuf},
+    io::{self, Read},
+    ops::ControlFlow,
+    path::{Component, Path, PathBuf},
+    process::Command,
     sync::{
-        Arc,
-        atomic::{AtomicU32, Ordering},
+        Arc, Mutex,
+        atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering},
     },
+    thread,
+    time::{Duration, Instant},
 };
-use tar::Archive;
+use walkdir::WalkDir;
 
 use crate::FolderTreeNode;
+use crate::archive::{self, EntryKind};
+use crate::compare::{Change, DiffTree};
+use crate::crypto::Key;
+use crate::exclude::Excludes;
+use crate::incremental;
+use crate::names;
+use crate::streams;
+
+// what a headless run reports as it goes, one json line each
+#[derive(Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum ProgressEvent {
+    FileStarted {
+        path: PathBuf,
+    },
+    Bytes {
+        done: u64,
+        total: u64,
+        percent: u32,
+    },
+    Warning {
+        message: String,
+    },
+    Summary {
+        archive: PathBuf,
+        original_bytes: u64,
+        archive_bytes: u64,
+        unchanged: u32,
+        skipped: u32,
+        failed: u32,
+        elapsed_secs: f64,
+    },
+    Error {
+        code: i32,
+        message: String,
+    },
+}
+
+pub type EventSink = Arc<dyn Fn(&ProgressEvent) + Send + Sync>;
 
 #[derive(Clone)]
 pub struct Progress {
     inner: Arc<AtomicU32>,
+    bytes_done: Arc<AtomicU64>,
+    bytes_total: Arc<AtomicU64>,
+    files_done: Arc<AtomicU64>,
+    files_total: Arc<AtomicU64>,
+    // when counting bytes began, moved later by the time spent paused
+    started: Arc<Mutex<Option<Instant>>>,
+    cancelled: Arc<AtomicBool>,
+    paused: Arc<AtomicBool>,
+    current: Arc<Mutex<Option<PathBuf>>>,
+    events: Option<EventSink>,
 }
 
 impl Progress {
     pub fn new() -> Self {
         Self {
             inner: Arc::new(AtomicU32::new(0)),
+            bytes_done: Arc::default(),
+            bytes_total: Arc::default(),
+            files_done: Arc::default(),
+            files_total: Arc::default(),
+            started: Arc::default(),
+            cancelled: Arc::new(AtomicBool::new(false)),
+            paused: Arc::new(AtomicBool::new(false)),
+            current: Arc::default(),
+            events: None,
+        }
+    }
+
+    // the gui only polls the percentage, the cli also wants each step
+    pub fn with_events(events: EventSink) -> Self {
+        Self {
+            events: Some(events),
+            ..Self::new()
+        }
+    }
+
+    pub fn event(&self, event: ProgressEvent) {
+        if let Some(events) = &self.events {
+            events(&event);
         }
     }
 
@@ -36,6 +114,110 @@ impl Progress {
     pub fn done(&self) {
         self.set(101);
     }
+
+    // backup and restore count bytes instead of files, so one huge file
+    // still moves the bar
+    pub fn set_total_bytes(&self, total: u64) {
+        self.bytes_total.store(total, Ordering::Relaxed);
+        self.bytes_done.store(0, Ordering::Relaxed);
+        *self.started.lock().unwrap() = Some(Instant::now());
+    }
+    pub fn add_bytes(&self, n: u64) {
+        let done = self.bytes_done.fetch_add(n, Ordering::Relaxed) + n;
+        let total = self.bytes_total.load(Ordering::Relaxed);
+        if let Some(pct) = (done.min(total) * 100).checked_div(total) {
+            self.set(pct as u32);
+        }
+    }
+    // (done, total)
+    pub fn bytes(&self) -> (u64, u64) {
+        (
+            self.bytes_done.load(Ordering::Relaxed),
+            self.bytes_total.load(Ordering::Relaxed),
+        )
+    }
+    // bytes per second so far, nothing until there is a second to go by
+    pub fn rate(&self) -> Option<f64> {
+        let elapsed = (*self.started.lock().unwrap())?.elapsed().as_secs_f64();
+        let (done, _) = self.bytes();
+        (elapsed >= 1.0 && done > 0).then(|| done as f64 / elapsed)
+    }
+    pub fn eta(&self) -> Option<Duration> {
+        let (done, total) = self.bytes();
+        let rate = self.rate()?;
+        Some(Duration::from_secs_f64(
+            total.saturating_sub(done) as f64 / rate,
+        ))
+    }
+    // counts what passes through as it is read
+    pub fn reading<R: Read>(&self, inner: R) -> ProgressReader<R> {
+        ProgressReader {
+            inner,
+            progress: self.clone(),
+        }
+    }
+
+    // known before the first file is stored, so the count doesn't grow
+    // while the work goes on
+    pub fn set_total_files(&self, total: u64) {
+        self.files_total.store(total, Ordering::Relaxed);
+        self.files_done.store(0, Ordering::Relaxed);
+    }
+    // (done, total)
+    pub fn files(&self) -> (u64, u64) {
+        (
+            self.files_done.load(Ordering::Relaxed),
+            self.files_total.load(Ordering::Relaxed),
+        )
+    }
+    // a file that was done before this run, a resumed one
+    pub fn count_file(&self) {
+        self.files_done.fetch_add(1, Ordering::Relaxed);
+    }
+    pub fn file_started(&self, path: &Path) {
+        self.count_file();
+        *self.current.lock().unwrap() = Some(path.to_path_buf());
+        self.event(ProgressEvent::FileStarted {
+            path: path.to_path_buf(),
+        });
+    }
+    // what is being worked on right now, for anyone watching from outside
+    pub fn current_file(&self) -> Option<PathBuf> {
+        self.current.lock().unwrap().clone()
+    }
+
+    // asks the work to stop at the next file
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+
+    // holds the work at the next file until resumed
+    pub fn pause(&self) {
+        self.paused.store(true, Ordering::Relaxed);
+    }
+    pub fn resume(&self) {
+        self.paused.store(false, Ordering::Relaxed);
+    }
+    pub fn is_paused(&self) -> bool {
+        self.paused.load(Ordering::Relaxed)
+    }
+    // called between files by the worker, a cancel still gets through
+    pub fn wait_if_paused(&self) {
+        if !self.is_paused() {
+            return;
+        }
+        let paused_at = Instant::now();
+        while self.is_paused() && !self.is_cancelled() {
+            thread::sleep(Duration::from_millis(100));
+        }
+        // time on hold doesn't count against the rate
+        if let Some(started) = self.started.lock().unwrap().as_mut() {
+            *started += paused_at.elapsed();
+        }
+    }
 }
 
 impl Default for Progress {
@@ -44,19 +226,37 @@ impl Default for Progress {
     }
 }
 
+pub struct ProgressReader<R> {
+    inner: R,
+    progress: Progress,
+}
+
+impl<R: Read> Read for ProgressReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.progress.add_bytes(n as u64);
+        Ok(n)
+    }
+}
+
 // if !icon then fuck you
-pub fn load_icon_image() -> Arc<IconData> {
-    println!("[DEBUG] load_icon_image: Start");
+// icon.ico carries 16/24/32/48/256 px frames, pick the smallest one that is at
+// least `size` px so Windows doesn't have to scale the 256 px one down
+pub fn load_icon_image(size: u32) -> Arc<IconData> {
+    debug!("load_icon_image: Start, want {size}px");
 
-    let image_bytes = include_bytes!("../assets/icon.png");
-    println!("[DEBUG] Icon bytes loaded: {} bytes", image_bytes.len());
+    let ico_bytes = include_bytes!("../assets/icon.ico");
+    debug!("Icon bytes loaded: {} bytes", ico_bytes.len());
 
-    let image = image::load_from_memory(image_bytes)
-        .expect("Icon image couldn't be loaded")
-        .into_rgba8();
+    let image = image::load_from_memory_with_format(
+        &single_frame_ico(ico_bytes, size),
+        image::ImageFormat::Ico,
+    )
+    .expect("Icon image couldn't be loaded")
+    .into_rgba8();
 
     let (w, h) = image.dimensions();
-    println!("[DEBUG] Icon dimensions: {}x{}", w, h);
+    debug!("Icon dimensions: {}x{}", w, h);
 
     let icon_data = Arc::new(IconData {
         rgba: image.into_raw(),
@@ -64,29 +264,188 @@ pub fn load_icon_image() -> Arc<IconData> {
         height: h,
     });
 
-    println!("[DEBUG] load_icon_image: Done");
+    debug!("load_icon_image: Done");
     icon_data
 }
 
-fn set_all_checked(node: &mut FolderTreeNode, checked: bool) {
-    println!(
-        "[DEBUG] set_all_checked: Setting node (is_file: {}) to checked = {}",
+// the ico decoder always picks the largest frame, so hand it a copy of the
+// file that only lists the frame we want
+fn single_frame_ico(ico: &[u8], size: u32) -> Vec<u8> {
+    let read_u16 = |at: usize| u16::from_le_bytes([ico[at], ico[at + 1]]) as usize;
+    let read_u32 =
+        |at: usize| u32::from_le_bytes([ico[at], ico[at + 1], ico[at + 2], ico[at + 3]]) as usize;
+
+    // a width byte of 0 means 256
+    let frames: Vec<(u32, usize)> = (0..read_u16(4))
+        .map(|i| {
+            let entry = 6 + i * 16;
+            let width = match ico[entry] {
+                0 => 256,
+                w => w as u32,
+            };
+            (width, entry)
+        })
+        .collect();
+
+    let (_, entry) = frames
+        .iter()
+        .filter(|(w, _)| *w >= size)
+        .min_by_key(|(w, _)| *w)
+        .or_else(|| frames.iter().max_by_key(|(w, _)| *w))
+        .copied()
+        .expect("icon.ico has no frames");
+
+    let data_len = read_u32(entry + 8);
+    let data_offset = read_u32(entry + 12);
+
+    let mut out = Vec::with_capacity(22 + data_len);
+    out.extend_from_slice(&[0, 0, 1, 0, 1, 0]);
+    out.extend_from_slice(&ico[entry..entry + 12]);
+    out.extend_from_slice(&22u32.to_le_bytes());
+    out.extend_from_slice(&ico[data_offset..data_offset + data_len]);
+    out
+}
+
+// 32 px at 100%, 48 px at 150% and so on
+pub fn icon_size_for(pixels_per_point: f32) -> u32 {
+    (32.0 * pixels_per_point).round() as u32
+}
+
+pub fn set_all_open(node: &mut FolderTreeNode, open: bool) {
+    node.open = open;
+    for child in node.children.values_mut() {
+        set_all_open(child, open);
+    }
+}
+
+pub fn set_all_checked(node: &mut FolderTreeNode, checked: bool) {
+    debug!(
+        "set_all_checked: Setting node (is_file: {}) to checked = {}",
         node.is_file, checked
     );
 
     node.checked = checked;
     for (name, child) in node.children.iter_mut() {
-        println!("[DEBUG]   -> Descending into child: \"{name}\"");
+        debug!("  -> Descending into child: \"{name}\"");
         set_all_checked(child, checked);
     }
 }
 
-pub fn render_tree(ui: &mut egui::Ui, path: &mut Vec<String>, node: &mut FolderTreeNode) {
+// flips every file and empty folder, folders end up ticked when anything
+// below them is
+pub fn invert_checked(node: &mut FolderTreeNode) {
+    if node.is_file || node.children.is_empty() {
+        node.checked = !node.checked;
+        return;
+    }
+    for child in node.children.values_mut() {
+        invert_checked(child);
+    }
+    node.checked = node.children.values().any(|c| c.checked);
+}
+
+// "*.jpg" and "Documents/**" match anywhere in the tree, patterns starting
+// at a root like "/home/me/**" or "C:/Users/**" only there
+fn tree_glob(pattern: &str) -> Result<GlobMatcher, String> {
+    let pattern = pattern.trim().replace('\\', "/");
+    if pattern.is_empty() {
+        return Err("No pattern given".into());
+    }
+    let rooted = pattern.starts_with('/')
+        || pattern.starts_with("**")
+        || pattern.chars().nth(1) == Some(':');
+    let pattern = if rooted {
+        pattern
+    } else {
+        format!("**/{pattern}")
+    };
+    Ok(Glob::new(&pattern)
+        .map_err(|e| e.to_string())?
+        .compile_matcher())
+}
+
+// ticks or unticks everything whose path matches `pattern`, with what's
+// below it. gives how many files and empty folders that touched
+pub fn check_matching(
+    root: &mut FolderTreeNode,
+    pattern: &str,
+    checked: bool,
+) -> Result<usize, String> {
+    fn walk(
+        node: &mut FolderTreeNode,
+        path: &mut Vec<String>,
+        glob: &GlobMatcher,
+        checked: bool,
+    ) -> usize {
+        let mut touched = 0;
+        for (name, child) in node.children.iter_mut() {
+            path.push(name.clone());
+            let full_path = path.join("/").replace('\\', "/");
+            if glob.is_match(&full_path) {
+                touched += count_items(child);
+                set_all_checked(child, checked);
+            } else if !child.children.is_empty() {
+                touched += walk(child, path, glob, checked);
+                child.checked = child.children.values().any(|c| c.checked);
+            }
+            path.pop();
+        }
+        touched
+    }
+    let glob = tree_glob(pattern)?;
+    let touched = walk(root, &mut Vec::new(), &glob, checked);
+    debug!("check_matching: \"{pattern}\" set {touched} items to {checked}");
+    Ok(touched)
+}
+
+// files and empty folders, what collect_paths picks up
+fn count_items(node: &FolderTreeNode) -> usize {
+    if node.is_file || node.children.is_empty() {
+        return 1;
+    }
+    node.children.values().map(count_items).sum()
+}
+
+// picked from the menu of a file in the restore tree, with its original path
+pub enum TreeAction {
+    Preview(PathBuf),
+    Extract(PathBuf),
+}
+
+// whether a name below `node` contains `filter`, which is lowercase
+fn matches_below(node: &FolderTreeNode, filter: &str) -> bool {
+    node.children
+        .iter()
+        .any(|(name, child)| name.to_lowercase().contains(filter) || matches_below(child, filter))
+}
+
+// with a `filter` only names containing it are shown, with the folders
+// leading to them opened and everything below them
+pub fn render_tree(
+    ui: &mut egui::Ui,
+    path: &mut Vec<String>,
+    node: &mut FolderTreeNode,
+    filter: &str,
+    action: &mut Option<TreeAction>,
+) {
     for (name, child) in node.children.iter_mut() {
-        let mut label = name.clone();
-        if !child.is_file {
-            label.push('/');
+        let matched = filter.is_empty() || name.to_lowercase().contains(filter);
+        if !matched && !matches_below(child, filter) {
+            continue;
         }
+        // a match shows all of what's below it
+        let below = if matched { "" } else { filter };
+
+        let label = match (child.is_file, child.files) {
+            (true, _) => format!("{name}  ({})", format_bytes(child.size)),
+            (false, 0) => format!("{name}/"),
+            (false, 1) => format!("{name}/  (1 file, {})", format_bytes(child.size)),
+            (false, files) => format!(
+                "{name}/  ({} files, {})",
+                format_count(files),
+                format_bytes(child.size)
+            ),
+        };
 
         path.push(name.clone());
         let current_path = path.join("/");
@@ -94,22 +453,39 @@ pub fn render_tree(ui: &mut egui::Ui, path: &mut Vec<String>, node: &mut FolderT
         if child.children.is_empty() {
             ui.horizontal(|ui| {
                 ui.checkbox(&mut child.checked, "");
-                ui.label(label);
+                let response = ui.add(egui::Label::new(label).sense(egui::Sense::click()));
+                if child.is_file {
+                    response.context_menu(|ui| {
+                        if ui.button("Preview").clicked() {
+                            *action = Some(TreeAction::Preview(path.iter().collect()));
+                            ui.close_menu();
+                        }
+                        if ui.button("Extract this file to…").clicked() {
+                            *action = Some(TreeAction::Extract(path.iter().collect()));
+                            ui.close_menu();
+                        }
+                    });
+                }
             });
         } else {
             ui.horizontal(|ui| {
                 if ui.checkbox(&mut child.checked, "").changed() {
-                    println!(
-                        "[DEBUG] Checkbox changed: setting all children of \"{}\" to {}",
+                    debug!(
+                        "Checkbox changed: setting all children of \"{}\" to {}",
                         current_path, child.checked
                     );
                     set_all_checked(child, child.checked);
                 }
-                CollapsingHeader::new(label)
-                    .default_open(false)
+                // the node keeps whether it's open so the whole tree can be
+                // opened or closed at once
+                let header = CollapsingHeader::new(label)
+                    .open(Some(child.open || !below.is_empty()))
                     .show(ui, |ui| {
-                        render_tree(ui, path, child);
+                        render_tree(ui, path, child, below, action);
                     });
+                if header.header_response.clicked() {
+                    child.open = !child.open;
+                }
             });
             child.checked = child.children.values().any(|c| c.checked);
         }
@@ -118,15 +494,55 @@ pub fn render_tree(ui: &mut egui::Ui, path: &mut Vec<String>, node: &mut FolderT
     }
 }
 
+fn change_color(change: Change) -> egui::Color32 {
+    match change {
+        Change::Added => egui::Color32::from_rgb(80, 190, 100),
+        Change::Removed => egui::Color32::from_rgb(230, 70, 70),
+        Change::Modified => egui::Color32::from_rgb(230, 180, 60),
+    }
+}
+
+pub fn render_diff_tree(ui: &mut egui::Ui, node: &DiffTree) {
+    for (name, child) in &node.children {
+        if let Some(change) = child.change {
+            ui.colored_label(change_color(change), format!("{} {name}", change.symbol()))
+                .on_hover_text(change.label());
+            continue;
+        }
+
+        // "/" > "home" > "me" reads better as one "/home/me" row
+        let mut label = name.clone();
+        let mut folder = child;
+        while folder.children.len() == 1
+            && let Some((next, only)) = folder.children.iter().next()
+            && only.change.is_none()
+        {
+            label = Path::new(&label).join(next).to_string_lossy().into_owned();
+            folder = only;
+        }
+
+        let counts = [Change::Added, Change::Removed, Change::Modified]
+            .into_iter()
+            .filter(|c| folder.count(*c) > 0)
+            .map(|c| format!("{}{}", c.symbol(), folder.count(c)))
+            .collect::<Vec<_>>()
+            .join(" ");
+        CollapsingHeader::new(format!("{label}/  {counts}"))
+            .default_open(false)
+            .show(ui, |ui| render_diff_tree(ui, folder));
+    }
+}
+
+// entries come with the size of what they restore
 pub fn build_human_tree(
-    entries: Vec<String>,
+    entries: Vec<(String, u64)>,
     path_map: HashMap<String, PathBuf>,
 ) -> FolderTreeNode {
-    println!("[DEBUG] build_human_tree: Start");
+    debug!("build_human_tree: Start");
     let mut root = FolderTreeNode::default();
 
     for (uuid, original_path) in path_map {
-        println!("[DEBUG] Processing UUID: {uuid}, Path: {:?}", original_path);
+        debug!("Processing UUID: {uuid}, Path: {:?}", original_path);
 
         let parent_label = original_path
             .parent()
@@ -139,7 +555,7 @@ pub fn build_human_tree(
             .to_string_lossy()
             .to_string();
 
-        println!("[DEBUG] parent_label = \"{parent_label}\", item_name = \"{item_name}\"");
+        debug!("parent_label = \"{parent_label}\", item_name = \"{item_name}\"");
 
         let parent_node = root
             .children
@@ -152,53 +568,86 @@ pub fn build_human_tree(
             .or_insert_with(FolderTreeNode::default);
 
         let dir_prefix = format!("{uuid}/");
-        let is_dir_backup = entries.iter().any(|e| e.starts_with(&dir_prefix));
+        let is_dir_backup = entries.iter().any(|(e, _)| e.starts_with(&dir_prefix));
 
         if is_dir_backup {
-            println!("[DEBUG] Detected directory backup for UUID: {uuid}");
+            debug!("Detected directory backup for UUID: {uuid}");
             parent_node.children.get_mut(&item_name).unwrap().is_file = false;
 
-            for tar_path in entries.iter().filter(|e| e.starts_with(&dir_prefix)) {
-                println!("[DEBUG]   tar_path = \"{tar_path}\"");
+            for (tar_path, size) in entries.iter().filter(|(e, _)| e.starts_with(&dir_prefix)) {
+                debug!("  tar_path = \"{tar_path}\"");
 
                 let rest = tar_path[dir_prefix.len()..].trim_end_matches('/');
                 if rest.is_empty() {
-                    println!("[DEBUG]   Skipping empty rest after trim");
+                    debug!("  Skipping empty rest after trim");
                     continue;
                 }
 
-                println!("[DEBUG]   Rest path: \"{rest}\"");
+                debug!("  Rest path: \"{rest}\"");
 
                 let mut cursor = parent_node.children.get_mut(&item_name).unwrap();
 
                 for part in rest.split('/') {
-                    println!("[DEBUG]     Descending into part: \"{part}\"");
+                    debug!("    Descending into part: \"{part}\"");
                     cursor = cursor
                         .children
                         .entry(part.to_string())
                         .or_insert_with(FolderTreeNode::default);
                 }
-                cursor.is_file = true;
+                cursor.is_file = !tar_path.ends_with('/');
+                cursor.size = *size;
             }
         } else {
-            println!("[DEBUG] Detected file (not dir) for UUID: {uuid}");
-            parent_node.children.get_mut(&item_name).unwrap().is_file = true;
+            debug!("Detected file (not dir) for UUID: {uuid}");
+            let node = parent_node.children.get_mut(&item_name).unwrap();
+            node.is_file = true;
+            // a single file is stored as "uuid" or "uuid.ext"
+            node.size = entries
+                .iter()
+                .find(|(e, _)| e.split_once('.').map_or(e.as_str(), |(u, _)| u) == uuid)
+                .map_or(0, |(_, size)| *size);
         }
     }
 
-    println!("[DEBUG] build_human_tree: Finished building tree");
+    add_up(&mut root);
+    debug!("build_human_tree: Finished building tree");
     root
 }
 
+// folders get the files and bytes below them
+fn add_up(node: &mut FolderTreeNode) {
+    if node.is_file {
+        node.files = 1;
+        return;
+    }
+    node.files = 0;
+    node.size = 0;
+    for child in node.children.values_mut() {
+        add_up(child);
+        node.files += child.files;
+        node.size += child.size;
+    }
+}
+
+// files and bytes of what's ticked
+pub fn selected_total(node: &FolderTreeNode) -> (usize, u64) {
+    if node.is_file {
+        return if node.checked { (1, node.size) } else { (0, 0) };
+    }
+    node.children
+        .values()
+        .filter(|child| child.checked)
+        .map(selected_total)
+        .fold((0, 0), |(files, bytes), (f, b)| (files + f, bytes + b))
+}
+
 pub fn collect_recursive(node: &FolderTreeNode, path: &mut Vec<String>, output: &mut Vec<String>) {
     for (name, child) in &node.children {
         path.push(name.clone());
-        if child.is_file && child.checked {
+        // empty folders have nothing below them to select, so they count as items
+        if child.checked && (child.is_file || child.children.is_empty()) {
             let full_path = path.join("/");
-            println!(
-                "[DEBUG] collect_recursive: Adding checked file {}",
-                full_path
-            );
+            debug!("collect_recursive: Adding checked file {}", full_path);
             output.push(full_path);
         }
 
@@ -208,72 +657,143 @@ pub fn collect_recursive(node: &FolderTreeNode, path: &mut Vec<String>, output:
 }
 
 pub fn collect_paths(root: &FolderTreeNode) -> Vec<String> {
-    println!("[DEBUG] collect_paths: Start");
+    debug!("collect_paths: Start");
     let mut result = Vec::new();
     let mut path = Vec::new();
     collect_recursive(root, &mut path, &mut result);
-    println!(
-        "[DEBUG] collect_paths: Done, collected {} paths",
-        result.len()
-    );
+    debug!("collect_paths: Done, collected {} paths", result.len());
     result
 }
 
-pub fn parse_fingerprint(
-    zip_path: &PathBuf,
-) -> Result<(Vec<String>, HashMap<String, PathBuf>), String> {
-    println!(
-        "[DEBUG] parse_fingerprint: Opening archive at {}",
-        zip_path.display()
-    );
+// lines of a "[Name]" section of fingerprint.txt, up to the next section
+pub fn fingerprint_section<'a>(txt: &'a str, name: &str) -> impl Iterator<Item = &'a str> {
+    let header = format!("[{name}]");
+    txt.lines()
+        .skip_while(move |l| l.trim() != header)
+        .skip(1)
+        .take_while(|l| !l.starts_with('['))
+}
 
-    let file = File::open(zip_path).map_err(|e| e.to_string())?;
-    let mut archive = Archive::new(file);
+// "uuid: original path" lines below the fingerprint header
+pub fn fingerprint_map(txt: &str) -> HashMap<String, PathBuf> {
+    let escaped = names::escaped(txt);
     let mut path_map = HashMap::new();
+    for line in fingerprint_section(txt, "Backup Info").filter(|l| l.contains(": ")) {
+        let (uuid, p) = line.split_once(": ").unwrap();
+        debug!("  Parsed fingerprint: {} → {}", uuid, p.trim());
+        // escaped paths keep spaces at the ends
+        let p = if escaped { p } else { p.trim() };
+        path_map.insert(uuid.to_string(), names::read(p, escaped));
+    }
+    path_map
+}
 
-    println!("[DEBUG] Scanning for fingerprint.txt…");
-    for entry in archive.entries().map_err(|e| e.to_string())? {
-        let mut entry = entry.map_err(|e| e.to_string())?;
-        let header_path = entry.path().map_err(|e| e.to_string())?;
-        let name = header_path.to_string_lossy();
-
-        if name == "fingerprint.txt" {
-            println!("[DEBUG] Found fingerprint.txt");
-            let mut txt = String::new();
-            entry.read_to_string(&mut txt).map_err(|e| e.to_string())?;
-
-            for line in txt.lines().filter(|l| l.contains(": ")) {
-                let (uuid, p) = line.split_once(": ").unwrap();
-                println!("[DEBUG]   Parsed fingerprint: {} → {}", uuid, p.trim());
-                path_map.insert(uuid.to_string(), PathBuf::from(p.trim()));
+// "uuid/a/b.txt" or "uuid.txt" -> the path it was backed up from. None for
+// a name that would leave its folder, "uuid/../x" or a part that decodes to
+// an absolute path, which only a crafted archive has
+pub fn original_path(
+    path_map: &HashMap<String, PathBuf>,
+    name: &str,
+    escaped: bool,
+) -> Option<PathBuf> {
+    let (root, rest) = name.split_once('/').unwrap_or((name, ""));
+    if let Some(base) = path_map.get(root) {
+        let mut path = base.clone();
+        for part in rest.split('/').filter(|c| !c.is_empty()) {
+            let part = names::read(part, escaped);
+            let mut components = part.components();
+            match (components.next(), components.next()) {
+                (Some(Component::Normal(_)), None) => path.push(part),
+                (Some(Component::CurDir), None) => {}
+                _ => {
+                    debug!("{name} leads outside {}", base.display());
+                    return None;
+                }
             }
-            break;
         }
+        return Some(path);
     }
+    let (uuid, _ext) = root.split_once('.')?;
+    path_map.get(uuid).cloned()
+}
+
+// original path -> sha256 of every file the backup restores
+pub type FileHashes = HashMap<PathBuf, String>;
 
-    println!("[DEBUG] Re-opening archive to collect entries");
-    let file = File::open(zip_path).map_err(|e| e.to_string())?;
-    let mut archive = Archive::new(file);
+pub type Fingerprint = (Vec<(String, u64)>, HashMap<String, PathBuf>, FileHashes);
+
+// entries and uuids of the archive and, for incrementals, of the parents
+// holding the unchanged files. hashes come from the newest file list, which
+// also names the files kept in the parents, and are empty for old archives.
+// `also` are more archives opened with it, see incremental::session
+pub fn parse_fingerprint(
+    zip_path: &Path,
+    also: &[PathBuf],
+    key: Option<&Key>,
+) -> Result<Fingerprint, String> {
+    debug!(
+        "parse_fingerprint: Opening archive at {}",
+        zip_path.display()
+    );
+
+    let chain = incremental::session(zip_path, also, key)?;
+    let owners = incremental::owners(&chain);
+    let mut path_map = HashMap::new();
     let mut entries = Vec::new();
 
-    for entry in archive.entries().map_err(|e| e.to_string())? {
-        let entry = entry.map_err(|e| e.to_string())?;
-        let entry_path = entry.path().map_err(|e| e.to_string())?;
-        let entry_name = entry_path.to_string_lossy().into_owned();
+    for (index, link) in chain.iter().enumerate() {
+        debug!("Collecting entries of {}", link.path.display());
+        archive::visit_entries(&link.path, key, |entry| {
+            if entry.name == "fingerprint.txt"
+                || streams::is_stream_entry(&entry.name)
+                || !incremental::keeps(owners.as_ref(), &chain, index, &entry.name, entry.kind)
+            {
+                return Ok(ControlFlow::Continue(()));
+            }
+
+            // sparse files are stored without their holes
+            let size = if link.sparse.is_empty() {
+                entry.size
+            } else {
+                original_path(&link.path_map, &entry.name, link.escaped)
+                    .and_then(|original| link.sparse.get(&original))
+                    .map_or(entry.size, |layout| layout.size)
+            };
+            let mut entry_name = entry.name;
+            // directories keep a trailing slash so the tree can tell them apart
+            if entry.kind == EntryKind::Dir {
+                entry_name.push('/');
+            }
+            debug!("  Found entry: {}", entry_name);
+            entries.push((entry_name, size));
+            Ok(ControlFlow::Continue(()))
+        })?;
+        // uuids are fresh for every backup, so the maps never clash
+        path_map.extend(link.path_map.clone());
+    }
 
-        if entry_name != "fingerprint.txt" {
-            entries.push(entry_name.clone());
-            println!("[DEBUG]   Found entry: {}", entry_name);
+    let mut hashes = FileHashes::new();
+    for manifest in chain
+        .iter()
+        .filter(|link| link.head)
+        .filter_map(|link| link.manifest.as_ref())
+    {
+        for (path, state) in manifest {
+            match &state.hash {
+                Some(hash) => hashes.insert(path.clone(), hash.clone()),
+                None => hashes.remove(path),
+            };
         }
     }
 
-    println!(
-        "[DEBUG] parse_fingerprint: Done. {} entries, {} fingerprinted",
+    debug!(
+        "parse_fingerprint: Done. {} entries, {} fingerprinted, {} hashed",
         entries.len(),
-        path_map.len()
+        path_map.len(),
+        hashes.len()
     );
 
-    Ok((entries, path_map))
+    Ok((entries, path_map, hashes))
 }
 
 pub fn get_fingered() -> &'static str {
@@ -281,11 +801,11 @@ pub fn get_fingered() -> &'static str {
 
     match option_env!("FINGERPRINT") {
         Some(val) => {
-            println!("get_fingered: using embedded fingerprint = \"{}\"", val);
+            debug!("get_fingered: using embedded fingerprint = \"{}\"", val);
             val
         }
         None => {
-            println!(
+            debug!(
                 "get_fingered: no embedded fingerprint found, fallback \"{}\"",
                 DEFAULT
             );
@@ -294,38 +814,185 @@ pub fn get_fingered() -> &'static str {
     }
 }
 
+pub fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KB", "MB", "GB", "TB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{bytes} B")
+    } else {
+        format!("{value:.1} {}", UNITS[unit])
+    }
+}
+
+// 4322 -> "4,322"
+pub fn format_count(count: usize) -> String {
+    let digits = count.to_string();
+    let mut out = String::new();
+    for (i, digit) in digits.chars().enumerate() {
+        if i > 0 && (digits.len() - i).is_multiple_of(3) {
+            out.push(',');
+        }
+        out.push(digit);
+    }
+    out
+}
+
+// "about 4 minutes left"
+pub fn format_eta(left: Duration) -> String {
+    let mins = (left.as_secs() + 30) / 60;
+    match mins {
+        0 => "less than a minute left".into(),
+        1 => "about a minute left".into(),
+        2..90 => format!("about {mins} minutes left"),
+        _ => {
+            let hours = (mins + 30) / 60;
+            format!("about {hours} hours left")
+        }
+    }
+}
+
+pub fn path_size(path: &Path) -> u64 {
+    WalkDir::new(path)
+        .into_iter()
+        .filter_map(Result::ok)
+        .filter_map(|e| e.metadata().ok())
+        .filter(|m| m.is_file())
+        .map(|m| m.len())
+        .sum()
+}
+
+// a new file only its owner can read, private from the first byte. fails
+// when `path` already exists
+pub fn create_private(path: &Path, contents: &[u8]) -> io::Result<()> {
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    let mut file = options.open(path)?;
+    io::Write::write_all(&mut file, contents)
+}
+
+pub fn open_in_file_manager(path: &Path) {
+    debug!("open_in_file_manager: {}", path.display());
+
+    #[cfg(windows)]
+    let result = if path.is_dir() {
+        Command::new("explorer").arg(path).spawn()
+    } else {
+        Command::new("explorer")
+            .arg(format!("/select,{}", path.display()))
+            .spawn()
+    };
+    #[cfg(target_os = "macos")]
+    let result = Command::new("open").arg("-R").arg(path).spawn();
+    #[cfg(not(any(windows, target_os = "macos")))]
+    let result = Command::new("xdg-open")
+        .arg(if path.is_dir() {
+            path
+        } else {
+            path.parent().unwrap_or(path)
+        })
+        .spawn();
+
+    if let Err(e) = result {
+        warn!("Couldn't open file manager: {e}");
+    }
+}
+
+// puts the computer to sleep, for a backup that woke it up
+pub fn suspend() -> Result<(), String> {
+    info!("Going back to sleep");
+    #[cfg(windows)]
+    {
+        use windows_sys::Win32::System::Power::SetSuspendState;
+        // sleep rather than hibernate, and let wake timers work next time
+        if unsafe { SetSuspendState(false, false, false) } {
+            return Ok(());
+        }
+        Err(format!(
+            "Couldn't go to sleep: {}",
+            io::Error::last_os_error()
+        ))
+    }
+    #[cfg(not(windows))]
+    {
+        #[cfg(target_os = "macos")]
+        let status = Command::new("pmset").arg("sleepnow").status();
+        #[cfg(not(target_os = "macos"))]
+        let status = Command::new("systemctl").arg("suspend").status();
+        match status {
+            Ok(status) if status.success() => Ok(()),
+            Ok(status) => Err(format!("Couldn't go to sleep: {status}")),
+            Err(e) => Err(format!("Couldn't go to sleep: {e}")),
+        }
+    }
+}
+
+// `path` for the win32 calls std doesn't make for us, nul terminated and
+// with the \\?\ prefix that lifts MAX_PATH. std::fs adds it on its own
+#[cfg(windows)]
+pub fn wide_path(path: &Path) -> Vec<u16> {
+    use std::os::windows::ffi::OsStrExt;
+
+    // the prefix turns off slash and ".." handling, so that happens first
+    let full = std::path::absolute(path).unwrap_or_else(|_| path.to_path_buf());
+    let wide: Vec<u16> = full.as_os_str().encode_wide().collect();
+    let starts = |prefix: &str| wide.starts_with(&prefix.encode_utf16().collect::<Vec<_>>());
+    let (prefix, rest) = if starts(r"\\?\") || starts(r"\\.\") {
+        ("", &wide[..])
+    } else if starts(r"\\") {
+        // \\server\share becomes \\?\UNC\server\share
+        (r"\\?\UNC", &wide[1..])
+    } else {
+        (r"\\?\", &wide[..])
+    };
+    prefix
+        .encode_utf16()
+        .chain(rest.iter().copied())
+        .chain(Some(0))
+        .collect()
+}
+
 pub fn adjust_path(original: &Path, current_home: &Path) -> PathBuf {
     let og_str = original.to_string_lossy();
     let current_str = current_home.to_string_lossy();
 
-    println!("[DEBUG] adjust_path: original = {}", og_str);
-    println!("[DEBUG] adjust_path: current_home = {}", current_str);
+    debug!("adjust_path: original = {}", og_str);
+    debug!("adjust_path: current_home = {}", current_str);
 
     if og_str.to_lowercase().starts_with("c:\\users\\") {
         let parts: Vec<&str> = og_str.split('\\').collect();
         if parts.len() > 2 {
             let old_username = parts[2];
             let expected_prefix = format!("C:\\Users\\{}", old_username);
-            println!("[DEBUG] Detected old user prefix: {}", expected_prefix);
+            debug!("Detected old user prefix: {}", expected_prefix);
 
             if og_str.starts_with(&expected_prefix) {
                 let rel_path = og_str.strip_prefix(&expected_prefix).unwrap_or("");
                 let adjusted = format!("{}{}", current_str, rel_path);
-                println!("[DEBUG] Path adjusted: {} → {}", og_str, adjusted);
+                debug!("Path adjusted: {} → {}", og_str, adjusted);
                 return PathBuf::from(adjusted);
             }
         }
     }
 
-    println!("[DEBUG] No adjustment needed");
+    debug!("No adjustment needed");
     original.to_path_buf()
 }
 
 pub fn fix_skip(p: &Path) -> Option<PathBuf> {
-    println!("[DEBUG] fix_skip: Checking path {}", p.display());
+    debug!("fix_skip: Checking path {}", p.display());
 
     if p.exists() {
-        println!("[DEBUG] -> Path exists, using as-is");
+        debug!("-> Path exists, using as-is");
         return Some(p.to_path_buf());
     }
 
@@ -333,17 +1000,74 @@ pub fn fix_skip(p: &Path) -> Option<PathBuf> {
     let adjusted = adjust_path(p, &current_home);
 
     if adjusted.exists() {
-        println!(
-            "[DEBUG] -> Adjusted path exists: using {}",
-            adjusted.display()
-        );
+        debug!("-> Adjusted path exists: using {}", adjusted.display());
         Some(adjusted)
     } else {
-        println!(
-            "[DEBUG] -> Neither original nor adjusted path exists ({} -> {})",
+        debug!(
+            "-> Neither original nor adjusted path exists ({} -> {})",
             p.display(),
             adjusted.display()
         );
         None
     }
 }
+
+// a list of glob entries with a mark for the ones that don't parse
+pub fn edit_patterns(ui: &mut egui::Ui, patterns: &mut Vec<String>, hint: &str) {
+    let mut to_remove = None;
+    for (i, pattern) in patterns.iter_mut().enumerate() {
+        ui.horizontal(|ui| {
+            ui.add_sized(
+                [240.0, 20.0],
+                egui::TextEdit::singleline(pattern).hint_text(hint),
+            );
+
+            match Excludes::check(pattern) {
+                Ok(()) => ui.label("✅").on_hover_text("Valid pattern"),
+                Err(e) => ui.label("❌").on_hover_text(e),
+            };
+
+            if ui.button("Remove").clicked() {
+                to_remove = Some(i);
+            }
+        });
+    }
+    if let Some(i) = to_remove {
+        patterns.remove(i);
+    }
+    if ui.button("Add Pattern").clicked() {
+        patterns.push(String::new());
+    }
+}
+
+// an optional number, off until the box is ticked
+pub fn edit_limit(
+    ui: &mut egui::Ui,
+    limit: &mut Option<u64>,
+    label: &str,
+    suffix: &str,
+    default: u64,
+) {
+    ui.horizontal(|ui| {
+        let mut on = limit.is_some();
+        ui.checkbox(&mut on, label);
+        let mut value = limit.unwrap_or(default);
+        ui.add_enabled(
+            on,
+            egui::DragValue::new(&mut value)
+                .range(1..=u32::MAX as u64)
+                .suffix(suffix),
+        );
+        *limit = on.then_some(value);
+    });
+}
+
+// an empty folder for one test, named after it and the process so parallel
+// runs don't trip over each other. the test removes it when it's done
+#[cfg(test)]
+pub fn test_dir(test: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("konserve_{test}_{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
@@ -0,0 +1,512 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs,
+    io::{self, Read},
+    ops::ControlFlow,
+    path::{Path, PathBuf},
+};
+
+use log::debug;
+use sha2::{Digest, Sha256};
+
+use crate::archive::{self, EntryKind};
+use crate::crypto::Key;
+use crate::helpers::{fingerprint_map, fingerprint_section, original_path};
+use crate::names;
+use crate::sparse::{self, Sparse};
+
+// every backup lists all the files it covers in the [Files] section of its
+// fingerprint. "+" means the data is in this archive, "=" that the file was
+// unchanged and lives in the parent, which sits in the same folder and is
+// named in the [Parent] section. a restore walks that chain back to the full
+// backup and takes every file from the newest archive that has it.
+// lines also carry the sha256 of the file, archives from before that don't.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Stored {
+    Here,
+    Parent,
+}
+
+#[derive(Clone, Debug)]
+pub struct FileState {
+    pub stored: Stored,
+    pub mtime: u64,
+    pub size: u64,
+    // lowercase hex sha256 of the contents
+    pub hash: Option<String>,
+}
+
+pub type Manifest = HashMap<PathBuf, FileState>;
+
+// files that are hard links to another one the backup has, the [Hardlinks]
+// section lists them as "<stored path>\t<link path>". the archive only
+// has an empty entry for the link, a tar hard link in tar formats
+pub type HardLinks = BTreeMap<PathBuf, PathBuf>;
+
+// windows attributes the mode bits don't carry, "H" for hidden and "S" for
+// system. the [Attributes] section lists them as "<letters>\t<path>"
+pub type Attributes = BTreeMap<PathBuf, String>;
+
+pub fn hash_reader(data: &mut dyn Read) -> io::Result<String> {
+    let mut hasher = Sha256::new();
+    io::copy(data, &mut hasher)?;
+    Ok(hex::encode(hasher.finalize()))
+}
+
+fn is_hash(field: &str) -> bool {
+    field.len() == 64 && field.bytes().all(|b| b.is_ascii_hexdigit())
+}
+
+pub fn format_manifest(files: &[(PathBuf, FileState)]) -> String {
+    let mut out = String::from("[Files]\n");
+    for (path, state) in files {
+        let flag = match state.stored {
+            Stored::Here => '+',
+            Stored::Parent => '=',
+        };
+        match &state.hash {
+            Some(hash) => out.push_str(&format!(
+                "{flag}\t{}\t{}\t{hash}\t{}\n",
+                state.mtime,
+                state.size,
+                names::escape(path)
+            )),
+            None => out.push_str(&format!(
+                "{flag}\t{}\t{}\t{}\n",
+                state.mtime,
+                state.size,
+                names::escape(path)
+            )),
+        }
+    }
+    out
+}
+
+pub fn format_hard_links(links: &HardLinks) -> String {
+    if links.is_empty() {
+        return String::new();
+    }
+    let mut out = String::from("[Hardlinks]\n");
+    for (link, stored) in links {
+        out.push_str(&format!(
+            "{}\t{}\n",
+            names::escape(stored),
+            names::escape(link)
+        ));
+    }
+    out
+}
+
+// link path -> the path whose data it shares
+pub fn parse_hard_links(txt: &str) -> HardLinks {
+    let escaped = names::escaped(txt);
+    fingerprint_section(txt, "Hardlinks")
+        .filter_map(|line| {
+            let (stored, link) = line.split_once('\t')?;
+            Some((names::read(link, escaped), names::read(stored, escaped)))
+        })
+        .collect()
+}
+
+pub fn format_attributes(attributes: &Attributes) -> String {
+    if attributes.is_empty() {
+        return String::new();
+    }
+    let mut out = String::from("[Attributes]\n");
+    for (path, flags) in attributes {
+        out.push_str(&format!("{flags}\t{}\n", names::escape(path)));
+    }
+    out
+}
+
+pub fn parse_attributes(txt: &str) -> Attributes {
+    let escaped = names::escaped(txt);
+    fingerprint_section(txt, "Attributes")
+        .filter_map(|line| {
+            let (flags, path) = line.split_once('\t')?;
+            Some((names::read(path, escaped), flags.to_string()))
+        })
+        .collect()
+}
+
+// None for archives made before file lists existed
+pub fn parse_manifest(txt: &str) -> Option<Manifest> {
+    if !txt.lines().any(|l| l.trim() == "[Files]") {
+        return None;
+    }
+
+    let escaped = names::escaped(txt);
+    let mut manifest = Manifest::new();
+    for line in fingerprint_section(txt, "Files") {
+        let mut fields = line.splitn(4, '\t');
+        let (Some(flag), Some(mtime), Some(size), Some(path)) =
+            (fields.next(), fields.next(), fields.next(), fields.next())
+        else {
+            continue;
+        };
+        let stored = match flag {
+            "+" => Stored::Here,
+            "=" => Stored::Parent,
+            _ => continue,
+        };
+        let (Ok(mtime), Ok(size)) = (mtime.parse(), size.parse()) else {
+            continue;
+        };
+        let (hash, path) = match path.split_once('\t') {
+            Some((hash, path)) if is_hash(hash) => (Some(hash.to_string()), path),
+            _ => (None, path),
+        };
+        manifest.insert(
+            names::read(path, escaped),
+            FileState {
+                stored,
+                mtime,
+                size,
+                hash,
+            },
+        );
+    }
+    Some(manifest)
+}
+
+pub fn parent_name(txt: &str) -> Option<String> {
+    fingerprint_section(txt, "Parent")
+        .map(str::trim)
+        .find(|l| !l.is_empty())
+        .map(str::to_string)
+}
+
+pub struct Link {
+    pub path: PathBuf,
+    // empty when the archive has no fingerprint.txt
+    pub fingerprint: String,
+    // a tar or zip Konserve didn't make, its top level names stand in for
+    // the uuids and lead to a folder named after the archive
+    pub foreign: bool,
+    pub path_map: HashMap<String, PathBuf>,
+    // the archive named in [Parent]
+    pub parent: Option<PathBuf>,
+    // no other link builds on this one, its file list is the state to restore
+    pub head: bool,
+    // entry names and fingerprint paths are escaped
+    pub escaped: bool,
+    pub manifest: Option<Manifest>,
+    pub hard_links: HardLinks,
+    pub attributes: Attributes,
+    // files this archive holds just the data ranges of
+    pub sparse: Sparse,
+}
+
+// the full backup first, `path` last
+pub fn chain(path: &Path, key: Option<&Key>) -> Result<Vec<Link>, String> {
+    chain_among(path, key, &[])
+}
+
+// parents that aren't next to their child are looked for among `others`
+fn chain_among(path: &Path, key: Option<&Key>, others: &[PathBuf]) -> Result<Vec<Link>, String> {
+    let mut links: Vec<Link> = Vec::new();
+    let mut next = Some(path.to_path_buf());
+
+    while let Some(path) = next {
+        if links.iter().any(|l| l.path == path) {
+            return Err(format!("{} is its own ancestor.", path.display()));
+        }
+
+        let fingerprint = archive::read_fingerprint(&path, key)?;
+        let foreign = fingerprint.is_none();
+        let fingerprint = fingerprint.unwrap_or_default();
+        next = match parent_name(&fingerprint) {
+            Some(name) => {
+                let beside = path.with_file_name(&name);
+                let parent = if beside.exists() {
+                    beside
+                } else {
+                    others
+                        .iter()
+                        .find(|other| other.file_name() == Some(name.as_ref()))
+                        .cloned()
+                        .ok_or_else(|| {
+                            format!(
+                                "Missing parent backup {name}, it has to be next to {}.",
+                                path.display()
+                            )
+                        })?
+                };
+                debug!("chain: {} -> {}", path.display(), parent.display());
+                Some(parent)
+            }
+            None => None,
+        };
+
+        links.push(Link {
+            parent: next.clone(),
+            head: links.is_empty(),
+            path_map: if foreign {
+                foreign_map(&path, key)?
+            } else {
+                fingerprint_map(&fingerprint)
+            },
+            foreign,
+            escaped: names::escaped(&fingerprint),
+            manifest: parse_manifest(&fingerprint),
+            hard_links: parse_hard_links(&fingerprint),
+            attributes: parse_attributes(&fingerprint),
+            sparse: sparse::parse_sparse(&fingerprint),
+            fingerprint,
+            path,
+        });
+    }
+
+    links.reverse();
+    Ok(links)
+}
+
+// several archives restored as one, oldest first. each brings its parents
+// along and a file comes from the newest archive that has it, going by when
+// the archives were written
+pub fn session(path: &Path, also: &[PathBuf], key: Option<&Key>) -> Result<Vec<Link>, String> {
+    if also.is_empty() {
+        return chain(path, key);
+    }
+    let mut archives = vec![path.to_path_buf()];
+    for other in also {
+        if !archives.contains(other) {
+            archives.push(other.clone());
+        }
+    }
+    archives.sort_by_key(|archive| fs::metadata(archive).and_then(|m| m.modified()).ok());
+
+    let mut links: Vec<Link> = Vec::new();
+    for archive in &archives {
+        for link in chain_among(archive, key, &archives)? {
+            if !links.iter().any(|l| l.path == link.path) {
+                links.push(link);
+            }
+        }
+    }
+    // a full backup added next to its incrementals isn't a head of its own
+    let parents: Vec<PathBuf> = links.iter().filter_map(|l| l.parent.clone()).collect();
+    for link in &mut links {
+        link.head = !parents.contains(&link.path);
+        // archives without a file list hold the files they have
+        if link.head && link.manifest.is_none() {
+            link.manifest = Some(entry_manifest(link, key)?);
+        }
+    }
+    Ok(links)
+}
+
+fn entry_manifest(link: &Link, key: Option<&Key>) -> Result<Manifest, String> {
+    let mut manifest = Manifest::new();
+    archive::visit_entries(&link.path, key, |entry| {
+        if entry.kind == EntryKind::File
+            && let Some(original) = original_path(&link.path_map, &entry.name, link.escaped)
+        {
+            manifest.insert(
+                original,
+                FileState {
+                    stored: Stored::Here,
+                    mtime: entry.mtime,
+                    size: entry.size,
+                    hash: None,
+                },
+            );
+        }
+        Ok(ControlFlow::Continue(()))
+    })?;
+    Ok(manifest)
+}
+
+// "photos/a.jpg" of holiday.tar.gz -> "holiday/photos/a.jpg"
+fn foreign_map(path: &Path, key: Option<&Key>) -> Result<HashMap<String, PathBuf>, String> {
+    let folder = archive::stem(path);
+    let mut path_map = HashMap::new();
+    archive::visit_entries(path, key, |entry| {
+        let top = entry.name.split('/').next().unwrap_or_default();
+        if !matches!(top, "" | "." | "..") && top != "fingerprint.txt" {
+            path_map
+                .entry(top.to_string())
+                .or_insert_with(|| Path::new(&folder).join(top));
+        }
+        Ok(ControlFlow::Continue(()))
+    })?;
+    Ok(path_map)
+}
+
+// which link holds the data of each file the heads still list, a later head
+// wins. None for a lone archive where everything inside counts
+pub fn owners(chain: &[Link]) -> Option<HashMap<PathBuf, usize>> {
+    if chain.len() < 2 {
+        return None;
+    }
+
+    let mut owners = HashMap::new();
+    for (head, link) in chain.iter().enumerate().filter(|(_, link)| link.head) {
+        for path in link.manifest.as_ref()?.keys() {
+            let mut index = head;
+            while chain[index]
+                .manifest
+                .as_ref()
+                .and_then(|m| m.get(path))
+                .is_some_and(|f| f.stored == Stored::Parent)
+                && let Some(parent) = chain[index]
+                    .parent
+                    .as_ref()
+                    .and_then(|parent| chain.iter().position(|l| &l.path == parent))
+            {
+                index = parent;
+            }
+            owners.insert(path.clone(), index);
+        }
+    }
+    Some(owners)
+}
+
+// hard links of all heads, a later head wins
+pub fn head_hard_links(chain: &[Link]) -> HardLinks {
+    chain
+        .iter()
+        .filter(|link| link.head)
+        .flat_map(|link| link.hard_links.clone())
+        .collect()
+}
+
+pub fn head_attributes(chain: &[Link]) -> Attributes {
+    chain
+        .iter()
+        .filter(|link| link.head)
+        .flat_map(|link| link.attributes.clone())
+        .collect()
+}
+
+// whether an entry of chain[index] belongs to the state the chain restores,
+// folders and links come from the heads which have all of them
+pub fn keeps(
+    owners: Option<&HashMap<PathBuf, usize>>,
+    chain: &[Link],
+    index: usize,
+    name: &str,
+    kind: EntryKind,
+) -> bool {
+    let Some(owners) = owners else {
+        return true;
+    };
+    if matches!(kind, EntryKind::Dir | EntryKind::Link) {
+        return chain[index].head;
+    }
+    original_path(&chain[index].path_map, name, chain[index].escaped)
+        .is_some_and(|original| owners.get(&original) == Some(&index))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backup::{BackupMode, BackupOptions, backup_gui};
+    use crate::helpers::{Progress, test_dir};
+
+    fn write(path: &Path, data: &[u8], mtime: i64) {
+        fs::write(path, data).unwrap();
+        filetime::set_file_mtime(path, filetime::FileTime::from_unix_time(mtime, 0)).unwrap();
+    }
+
+    // backups are named by the second they start in, renaming each one keeps
+    // the next from landing on it
+    fn backup(src: &Path, parent: Option<&Path>, name: &str) -> PathBuf {
+        let out = src.with_file_name("out");
+        fs::create_dir_all(&out).unwrap();
+        let options = BackupOptions {
+            mode: if parent.is_some() {
+                BackupMode::Incremental
+            } else {
+                BackupMode::Full
+            },
+            parent: parent.map(Path::to_path_buf),
+            ..Default::default()
+        };
+        let summary = backup_gui(
+            std::slice::from_ref(&src.to_path_buf()),
+            &out,
+            &Progress::default(),
+            &options,
+        )
+        .unwrap();
+        let named = out.join(name);
+        fs::rename(&summary.archive, &named).unwrap();
+        named
+    }
+
+    #[test]
+    fn incrementals_chain_back_to_the_full_backup() {
+        let base = test_dir("incremental_chain");
+        let src = base.join("src");
+        fs::create_dir_all(&src).unwrap();
+        write(&src.join("keep.txt"), b"keep", 1_500_000_000);
+        write(&src.join("change.txt"), b"v1", 1_500_000_000);
+        write(&src.join("gone.txt"), b"gone", 1_500_000_000);
+        let full = backup(&src, None, "full.tar");
+
+        write(&src.join("change.txt"), b"version 2", 1_600_000_000);
+        fs::remove_file(src.join("gone.txt")).unwrap();
+        let first = backup(&src, Some(&full), "first.tar");
+
+        write(&src.join("new.txt"), b"new", 1_600_000_000);
+        let second = backup(&src, Some(&first), "second.tar");
+
+        let links = chain(&second, None).unwrap();
+        let paths: Vec<&Path> = links.iter().map(|l| l.path.as_path()).collect();
+        assert_eq!(paths, [&full, &first, &second]);
+        assert_eq!(links[0].parent, None);
+        assert_eq!(links[1].parent.as_deref(), Some(full.as_path()));
+        assert_eq!(links[2].parent.as_deref(), Some(first.as_path()));
+        assert_eq!(
+            links.iter().map(|l| l.head).collect::<Vec<_>>(),
+            [false, false, true]
+        );
+
+        let manifest = links[2].manifest.as_ref().unwrap();
+        assert_eq!(manifest.len(), 3);
+        assert_eq!(manifest[&src.join("keep.txt")].stored, Stored::Parent);
+        assert_eq!(manifest[&src.join("change.txt")].stored, Stored::Parent);
+        assert_eq!(manifest[&src.join("new.txt")].stored, Stored::Here);
+
+        // each file still listed comes from the newest archive with its data,
+        // the deleted one from none
+        let owners = owners(&links).unwrap();
+        assert_eq!(owners.len(), 3);
+        assert_eq!(owners[&src.join("keep.txt")], 0);
+        assert_eq!(owners[&src.join("change.txt")], 1);
+        assert_eq!(owners[&src.join("new.txt")], 2);
+        assert!(!owners.contains_key(&src.join("gone.txt")));
+        let _ = fs::remove_dir_all(&base);
+    }
+
+    #[test]
+    fn a_lone_backup_owns_everything() {
+        let base = test_dir("incremental_lone");
+        let src = base.join("src");
+        fs::create_dir_all(&src).unwrap();
+        write(&src.join("a.txt"), b"a", 1_500_000_000);
+        let full = backup(&src, None, "full.tar");
+
+        let links = chain(&full, None).unwrap();
+        assert_eq!(links.len(), 1);
+        assert!(links[0].head);
+        assert!(owners(&links).is_none());
+        let _ = fs::remove_dir_all(&base);
+    }
+
+    #[test]
+    fn a_missing_parent_breaks_the_chain() {
+        let base = test_dir("incremental_missing");
+        let src = base.join("src");
+        fs::create_dir_all(&src).unwrap();
+        write(&src.join("a.txt"), b"a", 1_500_000_000);
+        let full = backup(&src, None, "full.tar");
+        let first = backup(&src, Some(&full), "first.tar");
+
+        fs::remove_file(&full).unwrap();
+        let err = chain(&first, None).err().unwrap();
+        assert!(err.starts_with("Missing parent backup full.tar"), "{err}");
+        let _ = fs::remove_dir_all(&base);
+    }
+}
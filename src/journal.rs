@@ -0,0 +1,212 @@
+use std::{
+    collections::HashSet,
+    fs::{self, File, OpenOptions},
+    io::{BufRead, BufReader, Write},
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+use log::{debug, info, warn};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::archive::ArchiveWriter;
+use crate::backup::BackupMode;
+
+// syncing the archive costs a flush to disk, a crash loses at most this much work
+const CHECKPOINT: Duration = Duration::from_secs(5);
+
+// a plain tar backup notes its entries next to the archive as they reach
+// the disk, so a run cut short by a crash or reboot can continue instead of
+// starting over. the first line says what is being backed up, then come
+// entry names, each batch closed by "@<offset>" once the archive is synced
+// up to there. names after the last offset never made it
+#[derive(Serialize, Deserialize, PartialEq)]
+struct Header {
+    folders: Vec<(Uuid, PathBuf)>,
+    mode: BackupMode,
+    parent: Option<PathBuf>,
+}
+
+pub struct Journal {
+    file: File,
+    path: PathBuf,
+    pending: Vec<String>,
+    synced: Instant,
+}
+
+// a backup that stopped before it finished
+pub struct Unfinished {
+    pub archive: PathBuf,
+    pub uuids: Vec<Uuid>,
+    // the archive is good up to here
+    pub offset: u64,
+    pub done: HashSet<String>,
+    // the journal up to its last offset, what comes after is dropped
+    journal_len: u64,
+}
+
+pub fn journal_path(archive: &Path) -> PathBuf {
+    let mut name = archive.as_os_str().to_owned();
+    name.push(".journal");
+    PathBuf::from(name)
+}
+
+// the newest unfinished tar in `out_dir` of the same folders, mode and parent
+pub fn find(
+    out_dir: &Path,
+    folders: &[PathBuf],
+    mode: BackupMode,
+    parent: Option<&PathBuf>,
+) -> Option<Unfinished> {
+    let mut journals: Vec<PathBuf> = fs::read_dir(out_dir)
+        .ok()?
+        .filter_map(Result::ok)
+        .map(|entry| entry.path())
+        .filter(|path| path.to_string_lossy().ends_with(".tar.journal"))
+        .collect();
+    // named by timestamp
+    journals.sort();
+    journals.into_iter().rev().find_map(|path| {
+        let unfinished = read(&path, folders, mode, parent)?;
+        info!(
+            "Found an unfinished backup {}, {} entries done",
+            unfinished.archive.display(),
+            unfinished.done.len()
+        );
+        Some(unfinished)
+    })
+}
+
+fn read(
+    path: &Path,
+    folders: &[PathBuf],
+    mode: BackupMode,
+    parent: Option<&PathBuf>,
+) -> Option<Unfinished> {
+    let mut reader = BufReader::new(File::open(path).ok()?);
+    let mut line = String::new();
+    let mut read_at = reader.read_line(&mut line).ok()? as u64;
+    let header: Header = serde_json::from_str(&line).ok()?;
+    let paths: Vec<&PathBuf> = header.folders.iter().map(|(_, path)| path).collect();
+    if paths != folders.iter().collect::<Vec<_>>()
+        || header.mode != mode
+        || header.parent.as_ref() != parent
+    {
+        debug!("{} is for another backup", path.display());
+        return None;
+    }
+    let archive = PathBuf::from(path.to_string_lossy().strip_suffix(".journal")?);
+    if !archive.exists() {
+        return None;
+    }
+
+    let (mut offset, mut journal_len) = (None, read_at);
+    let (mut done, mut batch) = (HashSet::new(), Vec::new());
+    loop {
+        line.clear();
+        match reader.read_line(&mut line) {
+            Ok(0) | Err(_) => break,
+            Ok(n) => read_at += n as u64,
+        }
+        // a line the crash cut short
+        let Some(text) = line.strip_suffix('\n') else {
+            break;
+        };
+        match text.strip_prefix('@').map(str::parse::<u64>) {
+            Some(Ok(at)) => {
+                offset = Some(at);
+                journal_len = read_at;
+                done.extend(batch.drain(..));
+            }
+            Some(Err(_)) => break,
+            None => batch.push(text.to_string()),
+        }
+    }
+    Some(Unfinished {
+        archive,
+        uuids: header.folders.into_iter().map(|(uuid, _)| uuid).collect(),
+        offset: offset?,
+        done,
+        journal_len,
+    })
+}
+
+impl Journal {
+    pub fn create(
+        archive: &Path,
+        folders: &[(Uuid, &PathBuf)],
+        mode: BackupMode,
+        parent: Option<&PathBuf>,
+    ) -> Result<Self, String> {
+        let header = Header {
+            folders: folders
+                .iter()
+                .map(|(uuid, path)| (*uuid, (*path).clone()))
+                .collect(),
+            mode,
+            parent: parent.cloned(),
+        };
+        let path = journal_path(archive);
+        let mut file = File::create(&path).map_err(|e| e.to_string())?;
+        let header = serde_json::to_string(&header).map_err(|e| e.to_string())?;
+        writeln!(file, "{header}").map_err(|e| e.to_string())?;
+        Ok(Self::on(file, path))
+    }
+
+    pub fn reopen(unfinished: &Unfinished) -> Result<Self, String> {
+        let path = journal_path(&unfinished.archive);
+        let file = OpenOptions::new()
+            .append(true)
+            .open(&path)
+            .map_err(|e| e.to_string())?;
+        file.set_len(unfinished.journal_len)
+            .map_err(|e| e.to_string())?;
+        Ok(Self::on(file, path))
+    }
+
+    fn on(file: File, path: PathBuf) -> Self {
+        Self {
+            file,
+            path,
+            pending: Vec::new(),
+            synced: Instant::now(),
+        }
+    }
+
+    // after each entry; only every few seconds the archive is synced and the
+    // batch written down
+    pub fn entry_done(&mut self, name: &str, writer: &mut dyn ArchiveWriter) {
+        self.pending.push(name.to_string());
+        if self.synced.elapsed() < CHECKPOINT {
+            return;
+        }
+        self.synced = Instant::now();
+        let offset = match writer.checkpoint() {
+            Ok(Some(offset)) => offset,
+            Ok(None) => return,
+            Err(e) => {
+                warn!("Couldn't sync the archive: {e}");
+                return;
+            }
+        };
+        let mut batch = self.pending.join("\n");
+        batch.push_str(&format!("\n@{offset}\n"));
+        match self
+            .file
+            .write_all(batch.as_bytes())
+            .and_then(|()| self.file.sync_data())
+        {
+            Ok(()) => self.pending.clear(),
+            Err(e) => warn!("Couldn't write {}: {e}", self.path.display()),
+        }
+    }
+
+    // the archive is complete, or was thrown away
+    pub fn remove(self) {
+        drop(self.file);
+        if let Err(e) = fs::remove_file(&self.path) {
+            warn!("Couldn't remove {}: {e}", self.path.display());
+        }
+    }
+}
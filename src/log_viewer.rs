@@ -0,0 +1,139 @@
+use std::fs;
+
+use eframe::egui;
+use log::{Level, LevelFilter};
+use rfd::FileDialog;
+
+use crate::logger::{self, LogLine};
+
+const LEVELS: [LevelFilter; 5] = [
+    LevelFilter::Error,
+    LevelFilter::Warn,
+    LevelFilter::Info,
+    LevelFilter::Debug,
+    LevelFilter::Trace,
+];
+
+pub struct LogViewer {
+    pub open: bool,
+    min_level: LevelFilter,
+    search: String,
+    follow: bool,
+    export_status: Option<String>,
+}
+
+impl Default for LogViewer {
+    fn default() -> Self {
+        Self {
+            open: false,
+            min_level: LevelFilter::Debug,
+            search: String::new(),
+            follow: true,
+            export_status: None,
+        }
+    }
+}
+
+fn level_text(line: &LogLine) -> egui::RichText {
+    let text = egui::RichText::new(line.render()).monospace();
+    match line.level {
+        Level::Error => text.color(egui::Color32::from_rgb(230, 80, 80)),
+        Level::Warn => text.color(egui::Color32::from_rgb(230, 180, 60)),
+        Level::Info => text,
+        Level::Debug | Level::Trace => text.weak(),
+    }
+}
+
+impl LogViewer {
+    fn visible_lines(&self) -> Vec<LogLine> {
+        let needle = self.search.to_lowercase();
+        logger::snapshot()
+            .into_iter()
+            .filter(|l| l.level <= self.min_level)
+            .filter(|l| needle.is_empty() || l.render().to_lowercase().contains(&needle))
+            .collect()
+    }
+
+    fn export(&mut self, lines: &[LogLine]) {
+        let Some(path) = FileDialog::new()
+            .set_title("Export log")
+            .set_file_name("konserve.log")
+            .save_file()
+        else {
+            return;
+        };
+
+        let text: String = lines.iter().map(|l| l.render() + "\n").collect();
+        self.export_status = Some(match fs::write(&path, text) {
+            Ok(()) => format!("✅ Saved {} lines to {}", lines.len(), path.display()),
+            Err(e) => format!("❌ Export failed: {e}"),
+        });
+    }
+
+    pub fn show(&mut self, ctx: &egui::Context) {
+        if !self.open {
+            return;
+        }
+
+        ctx.show_viewport_immediate(
+            egui::ViewportId::from_hash_of("log_viewer"),
+            egui::ViewportBuilder::default()
+                .with_title("Konserve Log")
+                .with_inner_size([700.0, 450.0]),
+            |ctx, _class| {
+                if ctx.input(|i| i.viewport().close_requested()) {
+                    self.open = false;
+                    return;
+                }
+
+                let lines = self.visible_lines();
+
+                egui::TopBottomPanel::top("log_controls").show(ctx, |ui| {
+                    ui.add_space(4.0);
+                    ui.horizontal(|ui| {
+                        egui::ComboBox::from_id_salt("log_level")
+                            .selected_text(self.min_level.as_str())
+                            .show_ui(ui, |ui| {
+                                for level in LEVELS {
+                                    ui.selectable_value(&mut self.min_level, level, level.as_str());
+                                }
+                            });
+                        ui.add(
+                            egui::TextEdit::singleline(&mut self.search)
+                                .hint_text("Search…")
+                                .desired_width(200.0),
+                        );
+                        ui.checkbox(&mut self.follow, "Follow")
+                            .on_hover_text("Keep scrolled to the newest line");
+                        if ui.button("Export…").clicked() {
+                            self.export(&lines);
+                        }
+                    });
+                    ui.horizontal(|ui| {
+                        ui.label(format!("{} of {} lines", lines.len(), logger::line_count()));
+                        if let Some(msg) = &self.export_status {
+                            ui.label(msg);
+                        }
+                    });
+                    ui.add_space(4.0);
+                });
+
+                egui::CentralPanel::default().show(ctx, |ui| {
+                    let row_height = ui.text_style_height(&egui::TextStyle::Monospace);
+                    egui::ScrollArea::both()
+                        .auto_shrink(false)
+                        .stick_to_bottom(self.follow)
+                        .show_rows(ui, row_height, lines.len(), |ui, range| {
+                            for line in &lines[range] {
+                                // one line per row so show_rows can skip what's off screen
+                                ui.add(egui::Label::new(level_text(line)).extend());
+                            }
+                        });
+                });
+
+                // new lines arrive from worker threads without any input events
+                ctx.request_repaint_after(std::time::Duration::from_millis(250));
+            },
+        );
+    }
+}
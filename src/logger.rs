@@ -0,0 +1,114 @@
+use std::{
+    collections::VecDeque,
+    sync::{
+        Mutex, OnceLock,
+        atomic::{AtomicU8, Ordering},
+    },
+};
+
+use chrono::Local;
+use log::{Level, LevelFilter, Log, Metadata, Record};
+
+use crate::helpers::ProgressEvent;
+
+// oldest lines are dropped past this so a long session can't eat memory
+const MAX_LINES: usize = 20_000;
+
+#[derive(Clone)]
+pub struct LogLine {
+    pub time: String,
+    pub level: Level,
+    pub target: String,
+    pub message: String,
+}
+
+impl LogLine {
+    pub fn render(&self) -> String {
+        format!(
+            "{} [{}] {}: {}",
+            self.time, self.level, self.target, self.message
+        )
+    }
+}
+
+// where lines are echoed: stdout for the app, stderr when stdout carries a
+// command's output, and json warnings on top of that for --json runs
+const CONSOLE_STDOUT: u8 = 0;
+const CONSOLE_STDERR: u8 = 1;
+const CONSOLE_JSON: u8 = 2;
+
+static CONSOLE: AtomicU8 = AtomicU8::new(CONSOLE_STDOUT);
+
+struct Logger;
+
+static LOGGER: Logger = Logger;
+static LINES: OnceLock<Mutex<VecDeque<LogLine>>> = OnceLock::new();
+
+fn lines() -> &'static Mutex<VecDeque<LogLine>> {
+    LINES.get_or_init(|| Mutex::new(VecDeque::new()))
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        // keep dependency chatter (wgpu, winit, ...) out of the log
+        metadata.target().starts_with(env!("CARGO_CRATE_NAME")) || metadata.level() <= Level::Warn
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+
+        let line = LogLine {
+            time: Local::now().format("%H:%M:%S%.3f").to_string(),
+            level: record.level(),
+            target: record
+                .target()
+                .trim_start_matches(concat!(env!("CARGO_CRATE_NAME"), "::"))
+                .to_string(),
+            message: record.args().to_string(),
+        };
+        match CONSOLE.load(Ordering::Relaxed) {
+            CONSOLE_STDOUT => println!("[{}] {}", line.level, line.message),
+            console => {
+                eprintln!("[{}] {}", line.level, line.message);
+                if console == CONSOLE_JSON && line.level <= Level::Warn {
+                    let warning = ProgressEvent::Warning {
+                        message: line.message.clone(),
+                    };
+                    if let Ok(json) = serde_json::to_string(&warning) {
+                        println!("{json}");
+                    }
+                }
+            }
+        }
+
+        let mut lines = lines().lock().unwrap();
+        if lines.len() >= MAX_LINES {
+            lines.pop_front();
+        }
+        lines.push_back(line);
+    }
+
+    fn flush(&self) {}
+}
+
+pub fn init() {
+    if log::set_logger(&LOGGER).is_ok() {
+        log::set_max_level(LevelFilter::Debug);
+    }
+}
+
+// keeps stdout for the command line's own output
+pub fn headless(json: bool) {
+    let console = if json { CONSOLE_JSON } else { CONSOLE_STDERR };
+    CONSOLE.store(console, Ordering::Relaxed);
+}
+
+pub fn snapshot() -> Vec<LogLine> {
+    lines().lock().unwrap().iter().cloned().collect()
+}
+
+pub fn line_count() -> usize {
+    lines().lock().unwrap().len()
+}
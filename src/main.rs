@@ -1,18 +1,86 @@
 #![windows_subsystem = "windows"]
 
+mod api;
+mod archive;
 mod backup;
+mod bench;
+mod catalog;
+mod cli;
+mod compare;
+mod crypto;
+mod daemon;
+mod diagnostics;
+mod drives;
+mod exclude;
+mod gpg;
 mod helpers;
+mod incremental;
+mod journal;
+mod log_viewer;
+mod logger;
+mod names;
+mod path_table;
+mod priority;
+mod remote;
+mod repo;
+mod report;
 mod restore;
+mod schedule;
+mod secrets;
+mod settings;
+mod signing;
+mod sparse;
+mod stats;
+mod streams;
+mod style;
+mod task_scheduler;
+mod validate;
+mod verify;
+mod vss;
+mod watch;
 
-use backup::backup_gui;
+use archive::ArchiveFormat;
+use backup::{BackupMode, BackupOptions, ErrorPolicy, backup_gui};
+use bench::Benchmark;
+use catalog::{Catalog, CatalogEntry, SearchHit};
+use compare::{Change, DiffTree, Difference, compare_with_disk, diff_archives};
+use crypto::{Key, Protection};
+use daemon::JobState;
+use drives::{Drive, DriveBinding};
+use exclude::{Filters, SymlinkPolicy, parse_cutoff};
 use helpers::Progress;
 use helpers::build_human_tree;
+use helpers::check_matching;
 use helpers::collect_paths;
+use helpers::edit_limit;
+use helpers::edit_patterns;
 use helpers::fix_skip;
+use helpers::format_bytes;
+use helpers::format_count;
+use helpers::format_eta;
+use helpers::icon_size_for;
+use helpers::invert_checked;
 use helpers::load_icon_image;
 use helpers::parse_fingerprint;
-use helpers::render_tree;
-use restore::restore_backup;
+use helpers::render_diff_tree;
+use helpers::selected_total;
+use helpers::set_all_checked;
+use helpers::set_all_open;
+use helpers::{TreeAction, render_tree};
+use log_viewer::LogViewer;
+use path_table::PathTable;
+use repo::{Repo, Snapshot};
+use report::{ErrorLog, LeftOut};
+use restore::{
+    Action, CaseCollision, Existing, Planned, RestoreOptions, RestoreReport, default_workers,
+    restore_backup,
+};
+use schedule::{CatchUp, Schedule};
+use settings::{Density, Settings};
+use signing::Verdict;
+use stats::{TypeStat, type_breakdown};
+use validate::{PathIssue, scan_paths};
+use verify::{VerifyReport, test_restore, verify_backup};
 
 use std::{
     collections::HashMap,
@@ -23,14 +91,151 @@ use std::{
 };
 
 use eframe::egui;
+use log::{debug, warn};
 use rfd::FileDialog;
 use serde::{Deserialize, Serialize};
 
-type RestoreMsg = Result<(FolderTreeNode, PathBuf), String>;
+type RestoreMsg = Result<OpenedTree, String>;
+type StatsMsg = Result<Vec<TypeStat>, String>;
+type BenchMsg = Result<Benchmark, String>;
+type RestoreDoneMsg = Result<RestoreReport, String>;
+type ConflictMsg = Result<ConflictPrompt, String>;
+type PreviewMsg = Result<Vec<Planned>, String>;
+type PeekMsg = Result<(PathBuf, u64, Peeked<egui::ColorImage>), String>;
+type UnlockMsg = Result<Key, String>;
+type GpgMsg = (Result<PathBuf, String>, OpenPurpose);
+type RepoMsg = Result<Vec<Snapshot>, String>;
+type VerifyMsg = Result<VerifyReport, String>;
+// title of the view and what was found
+// name of the picked file and the backups holding it
+type HistoryMsg = Result<(String, Vec<CatalogEntry>), String>;
+type SearchMsg = Result<Vec<SearchHit>, String>;
+type CompareMsg = (String, Result<Vec<Difference>, String>);
+
+// what an archive is being opened for, kept while it waits for a password
+#[derive(Clone, Copy)]
+enum OpenPurpose {
+    Restore,
+    Stats,
+    Verify,
+    TestRestore,
+    // the two sides of a diff, picked one after the other
+    DiffOlder,
+    DiffNewer,
+    // just the file in `restore_one`
+    RestoreFile,
+}
+
+// what a finished backup or restore left out, until it's closed
+struct LeftOutPanel {
+    // "Backup" or "Restore"
+    title: &'static str,
+    skipped: LeftOut,
+    failed: ErrorLog,
+    // the same lists next to the archive
+    file: Option<PathBuf>,
+}
+
+// how much of a file is read to show it from the restore tree
+const PEEK_TEXT: u64 = 256 * 1024;
+const PEEK_IMAGE: u64 = 16 * 1024 * 1024;
+
+// a file read out of the archive. images are decoded off the ui thread
+// and become a texture once they arrive
+enum Peeked<I> {
+    Text(String),
+    Image(I),
+    // why it can't be shown
+    Not(String),
+}
+
+// a file shown over the restore screen
+struct FilePreview {
+    original: PathBuf,
+    // of the whole file, the text may be just its start
+    size: u64,
+    shown: Peeked<egui::TextureHandle>,
+}
+
+// text up to where the read stopped, None for binary data
+fn peek_text(data: &[u8]) -> Option<String> {
+    let text = match std::str::from_utf8(data) {
+        Ok(text) => text,
+        // the limit cut a character in half
+        Err(e) if e.error_len().is_none() => std::str::from_utf8(&data[..e.valid_up_to()]).ok()?,
+        Err(_) => return None,
+    };
+    (!text.contains('\0')).then(|| text.to_string())
+}
+
+fn peek(zip_path: &Path, original: PathBuf, options: &RestoreOptions) -> PeekMsg {
+    let is_image = image::ImageFormat::from_path(&original).is_ok();
+    let limit = if is_image { PEEK_IMAGE } else { PEEK_TEXT };
+    let (data, size) = restore::peek_file(zip_path, &original, limit, options)?;
+    let peeked = if is_image && size > limit {
+        Peeked::Not(format!("{} is too big to preview.", format_bytes(size)))
+    } else if is_image {
+        match image::load_from_memory(&data) {
+            Ok(image) => {
+                let image = image.to_rgba8();
+                Peeked::Image(egui::ColorImage::from_rgba_unmultiplied(
+                    [image.width() as usize, image.height() as usize],
+                    image.as_raw(),
+                ))
+            }
+            Err(e) => Peeked::Not(format!("Couldn't read the image: {e}")),
+        }
+    } else {
+        match peek_text(&data) {
+            Some(text) => Peeked::Text(text),
+            None => Peeked::Not("Not text or an image, nothing to show.".into()),
+        }
+    };
+    Ok((original, size, peeked))
+}
+
+// an archive opened for the restore screen
+struct OpenedTree {
+    tree: FolderTreeNode,
+    zip: PathBuf,
+    also: Vec<PathBuf>,
+    // other users' home folders its paths are in and drives they're on
+    // that aren't here
+    homes: Vec<PathBuf>,
+    drives: Vec<PathBuf>,
+    // not made by Konserve, it can only go into a picked folder
+    foreign: bool,
+}
+
+// a restore waiting for what to do with the files already on disk
+struct ConflictPrompt {
+    zip_path: PathBuf,
+    selected: Vec<String>,
+    options: RestoreOptions,
+    existing: Vec<PathBuf>,
+}
+
+const PATTERN_HINT: &str =
+    "\"*.tmp\" matches names anywhere, \"Cache/*\" paths below a folder, \";\" separates several";
+const CUTOFF_HINT: &str =
+    "Only files changed after a date like 2025-01-31, or in the last days like 7d";
+const HIDDEN_HINT: &str =
+    "Leaves out dotfiles, files marked hidden or system, and Thumbs.db, desktop.ini and .DS_Store";
+const SYMLINK_HINT: &str = "Store links as links and restore them the same way, back up what they point to, or leave them out with a warning";
+const VOLUME_HINT: &str = "Doesn't go into other drives mounted below the backed up folders, or into sync folders like OneDrive";
+const ERROR_HINT: &str = "Whether a file that can't be read stops the backup. By default files other programs keep open are skipped, and unattended runs skip whatever they can't read";
+const IGNORE_HINT: &str = "Leaves out what .gitignore and .konserveignore files in the folders say";
 
 #[derive(Serialize, Deserialize)]
 struct BackupTemplate {
     paths: Vec<PathBuf>,
+    // age public keys, backups from this template are encrypted to them
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    recipients: Vec<String>,
+    #[serde(default, skip_serializing_if = "ErrorPolicy::is_default")]
+    on_error: ErrorPolicy,
+    #[serde(flatten)]
+    filters: Filters,
 }
 
 #[derive(Default)]
@@ -38,6 +243,11 @@ struct FolderTreeNode {
     children: HashMap<String, FolderTreeNode>,
     checked: bool,
     is_file: bool,
+    // expanded in the restore tree
+    open: bool,
+    // bytes of a file, or files and bytes below a folder
+    size: u64,
+    files: usize,
 }
 
 #[allow(dead_code)]
@@ -54,6 +264,9 @@ fn build_tree_from_paths(paths: &[String]) -> FolderTreeNode {
                     children: HashMap::new(),
                     checked: true,
                     is_file: false,
+                    open: false,
+                    size: 0,
+                    files: 0,
                 });
         }
         current.is_file = true;
@@ -76,74 +289,989 @@ fn build_tree_from_paths(paths: &[String]) -> FolderTreeNode {
 // }
 
 fn main() -> Result<(), eframe::Error> {
-    println!("[DEBUG] main: Starting application");
+    logger::init();
+    debug!("main: Starting application");
 
     dotenv::dotenv().ok();
-    println!("[DEBUG] .env loaded (if present)");
+    debug!(".env loaded (if present)");
 
-    let icon = load_icon_image();
-    println!("[DEBUG] Icon loaded");
+    // arguments mean a headless run, macOS adds -psn_ when started from Finder
+    let args: Vec<String> = std::env::args()
+        .skip(1)
+        .filter(|a| !a.starts_with("-psn_"))
+        .collect();
+    if !args.is_empty() {
+        cli::attach_console();
+        std::process::exit(cli::run(&args));
+    }
+
+    // corrected to the monitor's scale once the first frame knows it
+    let icon = load_icon_image(icon_size_for(1.0));
+    debug!("Icon loaded");
+
+    let settings = Settings::load();
+    debug!("Settings loaded, ui_scale = {}", settings.ui_scale);
 
     let options = eframe::NativeOptions {
         viewport: egui::ViewportBuilder::default()
-            .with_inner_size([410.0, 450.0])
+            .with_inner_size(settings.window_size())
             .with_resizable(false)
             .with_icon(icon),
         ..Default::default()
     };
-    println!("[DEBUG] NativeOptions configured");
+    debug!("NativeOptions configured");
 
-    println!("[DEBUG] Launching GUI with run_native");
+    debug!("Launching GUI with run_native");
     eframe::run_native(
         "Konserve",
         options,
-        Box::new(|_cc| {
-            println!("[DEBUG] GUIApp::default() instantiated");
-            Ok(Box::new(GUIApp::default()))
+        Box::new(|cc| {
+            cc.egui_ctx.set_zoom_factor(settings.ui_scale);
+            style::apply(&cc.egui_ctx, &settings);
+            debug!("GUIApp::default() instantiated");
+            let (tx, rx) = mpsc::channel::<Drive>();
+            drives::monitor(tx);
+            let daemon_running = daemon::request(&daemon::Request::Jobs).is_ok();
+            Ok(Box::new(GUIApp {
+                settings,
+                drive_rx: Some(rx),
+                missed: schedule::catch_up(daemon_running),
+                daemon_running,
+                ..GUIApp::default()
+            }))
         }),
     )
 }
 
 struct GUIApp {
     status: Arc<Mutex<String>>,
+    settings: Settings,
+    settings_open: bool,
+    log_viewer: LogViewer,
+    resize_pending: bool,
+    icon_ppp: f32,
     selected_folders: Vec<PathBuf>,
+    backup_format: ArchiveFormat,
+    zstd_level: i32,
+    backup_password: String,
+    backup_password_confirm: String,
+    backup_recipients: Vec<String>,
+    backup_filters: Filters,
+    backup_on_error: ErrorPolicy,
+    backup_mode: BackupMode,
+    // what an incremental or differential builds on
+    backup_parent: Option<PathBuf>,
+    // hand the backup to the daemon so closing the window doesn't end it
+    backup_in_background: bool,
+    // last template loaded or saved, its password can live in the keyring
+    loaded_template: Option<PathBuf>,
+    path_table: PathTable,
     template_editor: bool,
     template_paths: Vec<PathBuf>,
+    template_recipients: Vec<String>,
+    template_filters: Filters,
+    template_on_error: ErrorPolicy,
     restore_editor: bool,
     restore_zip_path: Option<PathBuf>,
+    restore_key: Option<Key>,
+    unlock: Option<(PathBuf, OpenPurpose, Protection)>,
+    unlock_password: String,
+    unlock_rx: Option<mpsc::Receiver<UnlockMsg>>,
+    gpg_rx: Option<mpsc::Receiver<GpgMsg>>,
+    gpg_plain: Option<PathBuf>,
+    signature_rx: Option<mpsc::Receiver<Verdict>>,
+    restore_signature: Option<Verdict>,
     restore_tree: FolderTreeNode,
+    // typed into the restore screen to tick or untick matching entries
+    restore_pattern: String,
+    // old prefix -> new prefix, as typed
+    restore_remap: Vec<(String, String)>,
+    // other users' home folders the open backup was made in, until taken
+    // up as remap rules or dismissed
+    restore_homes: Vec<PathBuf>,
+    // drives the backup's paths are on that this machine lacks, with the
+    // folder typed in for each
+    restore_drives: Vec<(PathBuf, String)>,
+    // restored together with `restore_zip_path`
+    restore_also: Vec<PathBuf>,
+    // narrows the restore tree to names containing it
+    restore_filter: String,
+    // below this folder instead of the original places
+    restore_into: Option<PathBuf>,
+    // the open archive wasn't made by Konserve and needs `restore_into`
+    restore_foreign: bool,
     _saved_path_map: Option<HashMap<String, PathBuf>>,
     backup_progress: Option<Progress>,
     restore_progress: Option<Progress>,
+    verify_progress: Option<Progress>,
+    test_restore_progress: Option<Progress>,
+    verify_rx: Option<mpsc::Receiver<VerifyMsg>>,
+    verify_report: Option<VerifyReport>,
     restore_opening: bool,
+    restore_workers: usize,
+    restore_case: CaseCollision,
+    restore_existing: Existing,
+    // replaced files go to a dated quarantine folder
+    restore_quarantine: bool,
+    restore_staged: bool,
+    restore_sanitize: bool,
+    restore_hard_links: bool,
+    restore_permissions: bool,
+    restore_done_rx: Option<mpsc::Receiver<RestoreDoneMsg>>,
+    restore_report: Option<RestoreReport>,
+    conflicts_rx: Option<mpsc::Receiver<ConflictMsg>>,
+    conflict_prompt: Option<ConflictPrompt>,
+    preview_rx: Option<mpsc::Receiver<PreviewMsg>>,
+    // what restoring the ticked files would write, over the restore screen
+    restore_preview: Option<Vec<Planned>>,
+    peek_rx: Option<mpsc::Receiver<PeekMsg>>,
+    file_preview: Option<FilePreview>,
+    // what the last backup or restore skipped or couldn't do
+    left_out: Option<LeftOutPanel>,
+    left_out_rx: Option<mpsc::Receiver<LeftOutPanel>>,
+    // backups from the catalog, None while the history screen is closed
+    history: Option<Vec<CatalogEntry>>,
+    // set when the list only shows backups holding a picked file
+    history_filter: Option<String>,
+    history_rx: Option<mpsc::Receiver<HistoryMsg>>,
+    history_search: String,
+    search_hits: Option<Vec<SearchHit>>,
+    search_rx: Option<mpsc::Receiver<SearchMsg>>,
+    // original path of the single file a search hit restores
+    restore_one: Option<PathBuf>,
+    compare_rx: Option<mpsc::Receiver<CompareMsg>>,
+    differences: Option<(String, DiffTree)>,
+    // first archive of a diff, with its key and gpg decrypted copy
+    diff_older: Option<(PathBuf, Option<Key>, Option<PathBuf>)>,
+    // decrypted copies the running diff reads
+    diff_plains: Vec<PathBuf>,
     restore_rx: Option<mpsc::Receiver<RestoreMsg>>,
+    type_stats: Option<Vec<TypeStat>>,
+    stats_rx: Option<mpsc::Receiver<StatsMsg>>,
+    // formats tried on a sample of the selected folders
+    benchmark: Option<Benchmark>,
+    bench_rx: Option<mpsc::Receiver<BenchMsg>>,
+    bench_progress: Option<Progress>,
+    path_issues: Option<Vec<PathIssue>>,
+    validation_rx: Option<mpsc::Receiver<Vec<PathIssue>>>,
+    repo_open: bool,
+    repo_path: Option<PathBuf>,
+    repo_snapshots: Vec<Snapshot>,
+    repo_selected: Option<String>,
+    repo_progress: Option<Progress>,
+    repo_rx: Option<mpsc::Receiver<RepoMsg>>,
+    // None while the schedules screen is closed
+    schedules: Option<Vec<Schedule>>,
+    schedule_template: Option<PathBuf>,
+    schedule_out: Option<PathBuf>,
+    schedule_days: [bool; 7],
+    schedule_hour: u32,
+    schedule_minute: u32,
+    schedule_catch_up: CatchUp,
+    // only for tasks in the Windows Task Scheduler
+    schedule_wake: task_scheduler::Wake,
+    // scheduled runs missed before the app started, offered until run or skipped
+    missed: Vec<(Schedule, chrono::NaiveDateTime)>,
+    // schedules only run while the daemon does
+    daemon_running: bool,
+    // registered with the Windows Task Scheduler instead
+    win_tasks: Vec<task_scheduler::Task>,
+    // backs the selection up whenever it changes, until dropped
+    watch: Option<watch::Watch>,
+    // drives plugged in while we run, for the bound templates
+    drive_rx: Option<mpsc::Receiver<Drive>>,
+    // the ones plugged in now, to pick from when binding
+    drives: Vec<Drive>,
+    bind_drive: Option<usize>,
+    // shown in the corner for a few seconds
+    toast: Option<(String, std::time::Instant)>,
 }
 
 impl Default for GUIApp {
     fn default() -> Self {
         Self {
             status: Arc::new(Mutex::new("Waiting...".to_string())),
+            settings: Settings::default(),
+            settings_open: false,
+            log_viewer: LogViewer::default(),
+            resize_pending: false,
+            icon_ppp: 0.0,
             selected_folders: Vec::new(),
+            backup_format: ArchiveFormat::Tar,
+            zstd_level: archive::DEFAULT_ZSTD_LEVEL,
+            backup_password: String::new(),
+            backup_password_confirm: String::new(),
+            backup_recipients: Vec::new(),
+            backup_filters: Filters::default(),
+            backup_on_error: ErrorPolicy::default(),
+            backup_mode: BackupMode::Full,
+            backup_parent: None,
+            backup_in_background: false,
+            loaded_template: None,
+            path_table: PathTable::default(),
             template_editor: false,
             template_paths: Vec::new(),
+            template_recipients: Vec::new(),
+            template_filters: Filters::default(),
+            template_on_error: ErrorPolicy::default(),
             restore_editor: false,
             restore_zip_path: None,
+            restore_key: None,
+            unlock: None,
+            unlock_password: String::new(),
+            unlock_rx: None,
+            gpg_rx: None,
+            gpg_plain: None,
+            signature_rx: None,
+            restore_signature: None,
             restore_tree: FolderTreeNode::default(),
+            restore_pattern: String::new(),
+            restore_remap: Vec::new(),
+            restore_homes: Vec::new(),
+            restore_drives: Vec::new(),
+            restore_also: Vec::new(),
+            restore_filter: String::new(),
+            restore_into: None,
+            restore_foreign: false,
             _saved_path_map: None,
             backup_progress: None,
             restore_progress: None,
+            verify_progress: None,
+            test_restore_progress: None,
+            verify_rx: None,
+            verify_report: None,
             restore_opening: false,
+            restore_workers: default_workers(),
+            restore_case: CaseCollision::Rename,
+            restore_existing: Existing::Ask,
+            restore_quarantine: true,
+            restore_staged: false,
+            restore_sanitize: cfg!(windows),
+            restore_hard_links: true,
+            restore_permissions: true,
+            restore_done_rx: None,
+            restore_report: None,
+            conflicts_rx: None,
+            conflict_prompt: None,
+            preview_rx: None,
+            restore_preview: None,
+            peek_rx: None,
+            file_preview: None,
+            left_out: None,
+            left_out_rx: None,
+            history: None,
+            history_filter: None,
+            history_rx: None,
+            history_search: String::new(),
+            search_hits: None,
+            search_rx: None,
+            restore_one: None,
+            compare_rx: None,
+            differences: None,
+            diff_older: None,
+            diff_plains: Vec::new(),
             restore_rx: None,
+            type_stats: None,
+            stats_rx: None,
+            benchmark: None,
+            bench_rx: None,
+            bench_progress: None,
+            path_issues: None,
+            validation_rx: None,
+            repo_open: false,
+            repo_path: None,
+            repo_snapshots: Vec::new(),
+            repo_selected: None,
+            repo_progress: None,
+            repo_rx: None,
+            schedules: None,
+            schedule_template: None,
+            schedule_out: None,
+            schedule_days: [true; 7],
+            schedule_hour: 2,
+            schedule_minute: 0,
+            schedule_catch_up: CatchUp::default(),
+            schedule_wake: task_scheduler::Wake::default(),
+            missed: Vec::new(),
+            daemon_running: false,
+            win_tasks: Vec::new(),
+            watch: None,
+            drive_rx: None,
+            drives: Vec::new(),
+            bind_drive: None,
+            toast: None,
+        }
+    }
+}
+
+impl GUIApp {
+    fn remember_password(&mut self) {
+        let Some(template) = self.loaded_template.clone() else {
+            return;
+        };
+        if !self.settings.remember_passwords || self.backup_password.is_empty() {
+            return;
+        }
+
+        match secrets::store(&template, &self.backup_password) {
+            Ok(()) if !self.settings.remembered_templates.contains(&template) => {
+                self.settings.remembered_templates.push(template);
+                if let Err(e) = self.settings.save() {
+                    warn!("Couldn't save settings: {e}");
+                }
+            }
+            Ok(()) => {}
+            Err(e) => warn!("Couldn't store password in keyring: {e}"),
+        }
+    }
+
+    fn forget_passwords(&mut self) {
+        for template in std::mem::take(&mut self.settings.remembered_templates) {
+            if let Err(e) = secrets::forget(&template) {
+                warn!("Couldn't remove password for {}: {e}", template.display());
+            }
+        }
+        *self.status.lock().unwrap() = match self.settings.save() {
+            Ok(()) => "✅ Saved passwords cleared.".into(),
+            Err(e) => format!("❌ Couldn't save settings: {e}"),
+        };
+    }
+
+    fn backup_options(&self) -> BackupOptions {
+        BackupOptions {
+            format: self.backup_format,
+            zstd_level: self.zstd_level,
+            password: Some(self.backup_password.clone()).filter(|p| !p.is_empty()),
+            recipients: self.backup_recipients.clone(),
+            gpg_recipient: Some(self.settings.gpg_recipient.trim().to_string())
+                .filter(|r| !r.is_empty()),
+            sign: self.settings.sign_backups,
+            remote: Some(self.settings.remote_folder.trim())
+                .filter(|folder| !folder.is_empty())
+                .map(PathBuf::from),
+            mode: self.backup_mode,
+            parent: self.backup_parent.clone(),
+            workers: self.settings.backup_workers,
+            max_mb_per_sec: Some(self.settings.backup_limit).filter(|limit| *limit > 0),
+            background: self.settings.background_priority,
+            streams: self.settings.keep_streams,
+            shadow_copy: self.settings.shadow_copy,
+            filters: self.backup_filters.clone(),
+            on_error: self.backup_on_error,
+            interactive: true,
+        }
+    }
+
+    fn start_benchmark(&mut self) {
+        *self.status.lock().unwrap() = "Comparing formats…".into();
+        let paths = self.selected_folders.clone();
+        let filters = self.backup_filters.clone();
+        let workers = self.settings.backup_workers;
+        let progress = Progress::default();
+        self.bench_progress = Some(progress.clone());
+        let (tx, rx) = mpsc::channel::<BenchMsg>();
+        self.bench_rx = Some(rx);
+
+        thread::spawn(move || {
+            let _ = tx.send(bench::run(&paths, &filters, workers, &progress));
+            progress.done();
+        });
+    }
+
+    fn start_backup(&mut self) {
+        self.remember_password();
+        let folders = self.selected_folders.clone();
+        let status = self.status.clone();
+        let options = self.backup_options();
+
+        *status.lock().unwrap() = format!("Packing into .{}", options.format.extension());
+
+        let progress = Progress::default();
+        self.backup_progress = Some(progress.clone());
+        let in_background = self.backup_in_background;
+        let (left_out_tx, left_out_rx) = mpsc::channel();
+        self.left_out_rx = Some(left_out_rx);
+
+        thread::spawn(move || {
+            if let Some(out_dir) = FileDialog::new()
+                .set_title("Choose backup destination")
+                .pick_folder()
+            {
+                if in_background {
+                    *status.lock().unwrap() = "Handing the backup to the daemon…".into();
+                    *status.lock().unwrap() =
+                        match daemon::run_and_follow(folders, out_dir, options, &progress) {
+                            Ok(job) if job.state == JobState::Done => {
+                                format!("✅ Backup created:\n{}", job.message)
+                            }
+                            Ok(job) => format!("❌ Backup failed: {}", job.message),
+                            Err(e) => format!("❌ Backup failed: {e}"),
+                        };
+                    return;
+                }
+                match backup_gui(&folders, &out_dir, &progress, &options) {
+                    Ok(summary) => {
+                        *status.lock().unwrap() = format!(
+                            "✅ Backup created:\n{}\n{}",
+                            summary.archive.display(),
+                            summary.report()
+                        );
+                        if summary.report_file.is_some() {
+                            let _ = left_out_tx.send(LeftOutPanel {
+                                title: "Backup",
+                                skipped: summary.skipped,
+                                failed: summary.failed,
+                                file: summary.report_file,
+                            });
+                        }
+                    }
+                    Err(e) => {
+                        // a cancelled or failed run never reaches 100
+                        progress.done();
+                        *status.lock().unwrap() = format!("❌ Backup failed: {}", e);
+                    }
+                }
+            } else {
+                *status.lock().unwrap() = "❌ Cancelled.".into();
+            }
+        });
+    }
+
+    fn start_watch(&mut self) {
+        if self.selected_folders.is_empty() {
+            *self.status.lock().unwrap() = "❌ Nothing selected.".into();
+            return;
+        }
+        let Some(out) = FileDialog::new()
+            .set_title("Choose where the backups go")
+            .pick_folder()
+        else {
+            return;
+        };
+        self.remember_password();
+        match watch::start(
+            self.selected_folders.clone(),
+            out,
+            self.backup_options(),
+            self.status.clone(),
+        ) {
+            Ok(watch) => {
+                *self.status.lock().unwrap() = format!(
+                    "Watching {} paths, backing up to {} after changes.",
+                    watch.paths,
+                    watch.out.display()
+                );
+                self.watch = Some(watch);
+            }
+            Err(e) => *self.status.lock().unwrap() = format!("❌ {e}"),
+        }
+    }
+
+    fn pick_archive(&mut self, purpose: OpenPurpose) {
+        let dialog = FileDialog::new().add_filter("Backups", &archive::EXTENSIONS);
+        let dialog = match purpose {
+            OpenPurpose::DiffOlder => dialog.set_title("Pick the older backup"),
+            OpenPurpose::DiffNewer => dialog.set_title("Pick the newer backup"),
+            _ => dialog,
+        };
+        let Some(zip_file) = dialog.pick_file() else {
+            return;
+        };
+        self.open_path(zip_file, purpose);
+    }
+
+    fn open_path(&mut self, zip_file: PathBuf, purpose: OpenPurpose) {
+        // checked against the file as picked, before any gpg decryption
+        if let OpenPurpose::Restore = purpose {
+            self.restore_signature = None;
+            let archive = zip_file.clone();
+            let trusted = self.settings.trusted_keys.clone();
+            let (tx, rx) = mpsc::channel::<Verdict>();
+            self.signature_rx = Some(rx);
+
+            thread::spawn(move || {
+                let _ = tx.send(signing::verify(&archive, &trusted));
+            });
+        }
+
+        if gpg::is_gpg(&zip_file) {
+            self.restore_opening = true;
+            *self.status.lock().unwrap() = "Decrypting with gpg…".into();
+
+            let (tx, rx) = mpsc::channel::<GpgMsg>();
+            self.gpg_rx = Some(rx);
+
+            thread::spawn(move || {
+                let _ = tx.send((gpg::decrypt(&zip_file, true), purpose));
+            });
+            return;
+        }
+
+        self.check_protection(zip_file, purpose);
+    }
+
+    // encrypted archives stop at the password screen first
+    fn check_protection(&mut self, zip_file: PathBuf, purpose: OpenPurpose) {
+        match crypto::protection(&zip_file) {
+            Ok(Some(protection)) => {
+                debug!("{} is encrypted ({protection:?})", zip_file.display());
+                self.unlock_password.clear();
+                self.unlock = Some((zip_file, purpose, protection));
+            }
+            Ok(None) => self.open_archive(zip_file, purpose, None),
+            Err(e) => *self.status.lock().unwrap() = format!("❌ Couldn't read archive: {e}"),
+        }
+    }
+
+    // the decrypted copy of a .gpg backup stays in the temp dir only while it's open
+    fn drop_gpg_plain(&mut self) {
+        if let Some(plain) = self.gpg_plain.take() {
+            gpg::discard(&plain);
+        }
+    }
+
+    // the tree of `zip_file` and the archives restored with it
+    fn load_restore(&mut self, zip_file: PathBuf, also: Vec<PathBuf>) {
+        // show spinner right away
+        self.restore_opening = true;
+        *self.status.lock().unwrap() = "Opening archive…".into();
+        let key = self.restore_key.clone();
+
+        let (tx, rx) = mpsc::channel::<RestoreMsg>();
+        self.restore_rx = Some(rx);
+
+        thread::spawn(move || {
+            let result: RestoreMsg =
+                parse_fingerprint(&zip_file, &also, key.as_ref()).and_then(|(entries, map, _)| {
+                    let homes = dirs::home_dir()
+                        .map(|home| restore::other_homes(map.values(), &home))
+                        .unwrap_or_default();
+                    let mut foreign = false;
+                    for archive in std::iter::once(&zip_file).chain(&also) {
+                        foreign |= archive::read_fingerprint(archive, key.as_ref())?.is_none();
+                    }
+                    Ok(OpenedTree {
+                        drives: restore::missing_drives(map.values(), &[]),
+                        tree: build_human_tree(entries, map),
+                        zip: zip_file.clone(),
+                        also,
+                        homes,
+                        foreign,
+                    })
+                });
+            let _ = tx.send(result);
+        });
+    }
+
+    // the choices on the restore screen
+    fn restore_options(&self, key: Option<Key>) -> RestoreOptions {
+        RestoreOptions {
+            workers: self.restore_workers,
+            case_collisions: self.restore_case,
+            existing: self.restore_existing,
+            quarantine: self
+                .restore_quarantine
+                .then(restore::quarantine_dir)
+                .flatten(),
+            staged: self.restore_staged,
+            sanitize_names: self.restore_sanitize,
+            key,
+            also: self.restore_also.clone(),
+            into: self.restore_into.clone(),
+            remap: self
+                .restore_remap
+                .iter()
+                .filter(|(from, _)| !from.trim().is_empty())
+                .map(|(from, to)| (from.trim().into(), to.trim().into()))
+                .collect(),
+            hard_links: self.restore_hard_links,
+            permissions: self.restore_permissions,
+            interactive: true,
+        }
+    }
+
+    // with Ask the files in the way are looked up first and the restore
+    // waits in `conflict_prompt` until one of the other choices is made
+    fn start_restore(&mut self, zip_path: PathBuf, selected: Vec<String>, options: RestoreOptions) {
+        let status = self.status.clone();
+        if options.existing == Existing::Ask {
+            *status.lock().unwrap() = "Looking for files already there…".into();
+            let (tx, rx) = mpsc::channel::<ConflictMsg>();
+            self.conflicts_rx = Some(rx);
+
+            thread::spawn(move || {
+                let found = restore::conflicts(&zip_path, Some(selected.clone()), &options);
+                let _ = tx.send(found.map(|existing| ConflictPrompt {
+                    zip_path,
+                    selected,
+                    options,
+                    existing,
+                }));
+            });
+            return;
+        }
+
+        let progress = Progress::default();
+        self.restore_progress = Some(progress.clone());
+        let (tx, rx) = mpsc::channel::<RestoreDoneMsg>();
+        self.restore_done_rx = Some(rx);
+
+        thread::spawn(move || {
+            let result = restore_backup(
+                &zip_path,
+                Some(selected),
+                status.clone(),
+                &progress,
+                &options,
+            );
+            if let Err(e) = &result {
+                *status.lock().unwrap() = format!("❌ Restore failed: {}", e);
+            }
+            let _ = tx.send(result);
+        });
+    }
+
+    fn load_history(&mut self) {
+        self.history_filter = None;
+        self.search_hits = None;
+        match Catalog::open().and_then(|catalog| catalog.backups()) {
+            Ok(entries) => self.history = Some(entries),
+            Err(e) => *self.status.lock().unwrap() = format!("❌ Catalog: {e}"),
+        }
+    }
+
+    fn open_schedules(&mut self) {
+        self.schedules = Some(schedule::load().unwrap_or_else(|e| {
+            *self.status.lock().unwrap() = format!("❌ Schedules: {e}");
+            Vec::new()
+        }));
+        self.daemon_running = daemon::request(&daemon::Request::Jobs).is_ok();
+        if self.schedule_template.is_none() {
+            self.schedule_template = self.loaded_template.clone();
+        }
+        if cfg!(windows) {
+            self.load_win_tasks();
+        }
+        self.drives = drives::removable();
+        self.bind_drive = None;
+    }
+
+    fn bind_template(&mut self) {
+        let (Some(template), Some(drive)) = (
+            self.schedule_template.clone(),
+            self.bind_drive.and_then(|i| self.drives.get(i)),
+        ) else {
+            return;
+        };
+        let binding = DriveBinding::new(template, drive, self.backup_format);
+        self.settings
+            .drive_bindings
+            .retain(|b| b.template != binding.template || !b.matches(drive));
+        self.settings.drive_bindings.push(binding);
+        match self.settings.save() {
+            Ok(()) => {
+                *self.status.lock().unwrap() =
+                    format!("✅ Backs up whenever {} is plugged in.", drive.describe())
+            }
+            Err(e) => *self.status.lock().unwrap() = format!("❌ Couldn't save settings: {e}"),
+        }
+    }
+
+    // a bound drive was plugged in
+    fn backup_to_drive(&mut self, binding: DriveBinding, drive: &Drive) {
+        let name = binding
+            .template
+            .file_stem()
+            .map(|n| n.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        self.toast = Some((
+            format!("💾 {} plugged in, backing up {name}", drive.label),
+            std::time::Instant::now(),
+        ));
+        let out = binding.out(drive);
+        let status = self.status.clone();
+        let progress = Progress::default();
+        self.backup_progress = Some(progress.clone());
+        *status.lock().unwrap() = format!("Backing up {name} to {}", drive.label);
+
+        thread::spawn(move || {
+            let result = cli::load_template(&binding.template).and_then(|template| {
+                let options = BackupOptions {
+                    format: binding.format,
+                    ..cli::headless_options(&binding.template, &template)
+                };
+                backup_gui(&template.paths, &out, &progress, &options)
+            });
+            progress.done();
+            *status.lock().unwrap() = match result {
+                Ok(summary) => format!(
+                    "✅ Backup created:\n{}\n{}",
+                    summary.archive.display(),
+                    summary.report()
+                ),
+                Err(e) => format!("❌ Backup to the drive failed: {e}"),
+            };
+        });
+    }
+
+    // a missed run the user wants now, handed to the daemon when it runs so
+    // closing the app doesn't stop it
+    fn run_missed(&mut self, schedule: Schedule) {
+        let name = schedule
+            .template
+            .file_stem()
+            .map(|n| n.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let run = Some(schedule::now_text());
+        let (paths, options) = match schedule::options(&schedule) {
+            Ok(found) => found,
+            Err(e) => {
+                schedule::record(schedule.id, run, format!("❌ {e}"));
+                *self.status.lock().unwrap() = format!("❌ Couldn't catch up {name}: {e}");
+                return;
+            }
+        };
+
+        if self.daemon_running {
+            let request = daemon::Request::Run {
+                paths: paths.clone(),
+                out: schedule.out.clone(),
+                options: Box::new(options.clone()),
+            };
+            match daemon::request(&request) {
+                Ok(daemon::Reply::Started { id }) => {
+                    schedule::record(schedule.id, run, format!("▶ Caught up as daemon job {id}"));
+                    *self.status.lock().unwrap() = format!("✅ Catching up {name} in the daemon.");
+                    return;
+                }
+                Ok(_) => warn!("Unexpected daemon reply to a missed run"),
+                Err(e) => warn!("The daemon didn't take the missed run: {e}"),
+            }
+        }
+
+        let status = self.status.clone();
+        let progress = Progress::default();
+        self.backup_progress = Some(progress.clone());
+        *status.lock().unwrap() = format!("Catching up {name}…");
+        schedule::record(schedule.id, run, "Running".into());
+
+        thread::spawn(move || {
+            let result = backup_gui(&paths, &schedule.out, &progress, &options);
+            progress.done();
+            let (recorded, shown) = match result {
+                Ok(summary) => (
+                    format!("✅ {}", summary.report()),
+                    format!("✅ Caught up {name}:\n{}", summary.archive.display()),
+                ),
+                Err(e) => (
+                    format!("❌ {e}"),
+                    format!("❌ Catching up {name} failed: {e}"),
+                ),
+            };
+            schedule::record(schedule.id, None, recorded);
+            *status.lock().unwrap() = shown;
+        });
+    }
+
+    fn load_win_tasks(&mut self) {
+        match task_scheduler::list() {
+            Ok(tasks) => self.win_tasks = tasks,
+            Err(e) => *self.status.lock().unwrap() = format!("❌ Task Scheduler: {e}"),
+        }
+    }
+
+    fn start_daemon(&mut self) {
+        let status = self.status.clone();
+        *status.lock().unwrap() = "Starting the daemon…".into();
+        thread::spawn(move || {
+            *status.lock().unwrap() = match daemon::ensure_running() {
+                Ok(()) => "✅ The daemon is running, schedules will start on time.".into(),
+                Err(e) => format!("❌ {e}"),
+            };
+        });
+        self.daemon_running = true;
+    }
+
+    fn drop_diff_plains(&mut self) {
+        let older = self.diff_older.take().and_then(|(_, _, plain)| plain);
+        for plain in self.diff_plains.drain(..).chain(older) {
+            gpg::discard(&plain);
+        }
+    }
+
+    fn open_archive(&mut self, zip_file: PathBuf, purpose: OpenPurpose, key: Option<Key>) {
+        match purpose {
+            OpenPurpose::Restore => {
+                self.restore_key = key;
+                self.restore_into = None;
+                self.restore_filter.clear();
+                self.load_restore(zip_file, Vec::new());
+            }
+            OpenPurpose::Stats => {
+                *self.status.lock().unwrap() = "Scanning archive…".into();
+
+                let (tx, rx) = mpsc::channel::<StatsMsg>();
+                self.stats_rx = Some(rx);
+
+                thread::spawn(move || {
+                    let _ = tx.send(type_breakdown(&zip_file, key.as_ref()));
+                });
+            }
+            OpenPurpose::Verify => {
+                *self.status.lock().unwrap() = "Verifying backup…".into();
+
+                let progress = Progress::default();
+                self.verify_progress = Some(progress.clone());
+                let (tx, rx) = mpsc::channel::<VerifyMsg>();
+                self.verify_rx = Some(rx);
+
+                thread::spawn(move || {
+                    let _ = tx.send(verify_backup(&zip_file, key.as_ref(), &progress));
+                });
+            }
+            OpenPurpose::TestRestore => {
+                *self.status.lock().unwrap() = "Test restoring into a temp folder…".into();
+
+                let progress = Progress::default();
+                self.test_restore_progress = Some(progress.clone());
+                let (tx, rx) = mpsc::channel::<VerifyMsg>();
+                self.verify_rx = Some(rx);
+
+                thread::spawn(move || {
+                    let _ = tx.send(test_restore(&zip_file, key.as_ref(), &progress));
+                });
+            }
+            OpenPurpose::RestoreFile => {
+                let Some(file) = self.restore_one.take() else {
+                    return;
+                };
+                *self.status.lock().unwrap() = format!("Restoring {}…", file.display());
+
+                let options = self.restore_options(key);
+                self.start_restore(zip_file, vec![file.display().to_string()], options);
+            }
+            OpenPurpose::DiffOlder => {
+                self.drop_diff_plains();
+                self.diff_older = Some((zip_file, key, self.gpg_plain.take()));
+                self.pick_archive(OpenPurpose::DiffNewer);
+            }
+            OpenPurpose::DiffNewer => {
+                let Some((older, older_key, older_plain)) = self.diff_older.take() else {
+                    return;
+                };
+                *self.status.lock().unwrap() = "Comparing backups…".into();
+                self.diff_plains = older_plain
+                    .into_iter()
+                    .chain(self.gpg_plain.take())
+                    .collect();
+
+                let title = format!(
+                    "{} → {}",
+                    older.file_name().unwrap_or_default().to_string_lossy(),
+                    zip_file.file_name().unwrap_or_default().to_string_lossy()
+                );
+                let (tx, rx) = mpsc::channel::<CompareMsg>();
+                self.compare_rx = Some(rx);
+
+                thread::spawn(move || {
+                    let result = diff_archives(&older, older_key.as_ref(), &zip_file, key.as_ref());
+                    let _ = tx.send((title, result));
+                });
+            }
         }
     }
+
+    // every repository job reopens it in the background and reports the
+    // snapshot list afterwards, so the screen always shows what's on disk
+    fn repo_task<F>(&mut self, busy: &str, task: F)
+    where
+        F: FnOnce(&mut Repo, &Progress) -> Result<String, String> + Send + 'static,
+    {
+        let Some(root) = self.repo_path.clone() else {
+            return;
+        };
+        *self.status.lock().unwrap() = busy.to_string();
+
+        let status = self.status.clone();
+        let progress = Progress::default();
+        self.repo_progress = Some(progress.clone());
+        let (tx, rx) = mpsc::channel::<RepoMsg>();
+        self.repo_rx = Some(rx);
+
+        thread::spawn(move || {
+            let result = Repo::init(&root).and_then(|mut repo| {
+                let done = task(&mut repo, &progress)?;
+                *status.lock().unwrap() = done;
+                repo.snapshots()
+            });
+            progress.done();
+            if let Err(e) = &result {
+                *status.lock().unwrap() = format!("❌ Repository: {e}");
+            }
+            let _ = tx.send(result);
+        });
+    }
 }
 
 impl eframe::App for GUIApp {
     fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
+        // moving to a monitor with a different scale needs a sharper or smaller icon
+        if let Some(ppp) = ctx.native_pixels_per_point()
+            && ppp != self.icon_ppp
+        {
+            debug!("Native scale changed to {ppp}, reloading icon");
+            self.icon_ppp = ppp;
+            ctx.send_viewport_cmd(egui::ViewportCommand::Icon(Some(load_icon_image(
+                icon_size_for(ppp),
+            ))));
+        }
+
+        // sizes are in points, so wait a frame for the new zoom to take effect
+        if self.resize_pending {
+            self.resize_pending = false;
+            ctx.send_viewport_cmd(egui::ViewportCommand::InnerSize(
+                settings::WINDOW_SIZE.into(),
+            ));
+        }
+
+        self.log_viewer.show(ctx);
+
+        while let Some(drive) = self.drive_rx.as_ref().and_then(|rx| rx.try_recv().ok()) {
+            let bound: Vec<DriveBinding> = self
+                .settings
+                .drive_bindings
+                .iter()
+                .filter(|b| b.matches(&drive))
+                .cloned()
+                .collect();
+            for binding in bound {
+                self.backup_to_drive(binding, &drive);
+            }
+        }
+
+        if let Some((text, since)) = &self.toast {
+            if since.elapsed() > std::time::Duration::from_secs(6) {
+                self.toast = None;
+            } else {
+                egui::Area::new(egui::Id::new("toast"))
+                    .anchor(egui::Align2::RIGHT_BOTTOM, [-8.0, -8.0])
+                    .show(ctx, |ui| {
+                        egui::Frame::popup(ui.style()).show(ui, |ui| ui.label(text.as_str()));
+                    });
+            }
+        }
+
         egui::CentralPanel::default().show(ctx, |ui| {
             if let Some(finished_msg) = self.restore_rx.as_ref().and_then(|rx| rx.try_recv().ok()) {
                 match finished_msg {
-                    Ok((mut tree, zip)) => {
+                    Ok(OpenedTree {
+                        mut tree,
+                        zip,
+                        also,
+                        homes,
+                        drives,
+                        foreign,
+                    }) => {
                         // NEW: mark everything checked
                         fn check_all(n: &mut FolderTreeNode) {
                             n.checked = true;
@@ -155,57 +1283,2189 @@ impl eframe::App for GUIApp {
 
                         self.restore_tree = tree;
                         self.restore_zip_path = Some(zip);
+                        self.restore_also = also;
+                        self.restore_homes = homes
+                            .into_iter()
+                            .filter(|home| {
+                                let home = home.to_string_lossy();
+                                !self.restore_remap.iter().any(|(from, _)| *from == home)
+                            })
+                            .collect();
+                        self.restore_drives = drives
+                            .into_iter()
+                            .filter(|drive| {
+                                let drive = drive.to_string_lossy();
+                                !self.restore_remap.iter().any(|(from, _)| *from == drive)
+                            })
+                            .map(|drive| (drive, String::new()))
+                            .collect();
+                        self.restore_foreign = foreign;
+                        if foreign {
+                            *self.status.lock().unwrap() =
+                                "Not a Konserve backup, its files go into a folder you pick.".into();
+                        }
                         self.restore_editor = true;
                     }
                     Err(e) => {
                         *self.status.lock().unwrap() = format!("Failed: {e}");
+                        // adding to an open restore keeps what was open
+                        if !self.restore_editor {
+                            self.drop_gpg_plain();
+                        }
+                    }
+                }
+                self.restore_opening = false;
+                self.restore_rx = None;
+            }
+
+            if let Some((gpg_msg, purpose)) = self.gpg_rx.as_ref().and_then(|rx| rx.try_recv().ok())
+            {
+                self.gpg_rx = None;
+                self.restore_opening = false;
+                match gpg_msg {
+                    Ok(plain) => {
+                        self.drop_gpg_plain();
+                        self.gpg_plain = Some(plain.clone());
+                        self.check_protection(plain, purpose);
+                    }
+                    Err(e) => {
+                        *self.status.lock().unwrap() = format!("❌ gpg: {e}");
+                    }
+                }
+            }
+
+            if let Some(verdict) = self.signature_rx.as_ref().and_then(|rx| rx.try_recv().ok()) {
+                self.signature_rx = None;
+                self.restore_signature = Some(verdict);
+            }
+
+            if let Some(stats_msg) = self.stats_rx.as_ref().and_then(|rx| rx.try_recv().ok()) {
+                match stats_msg {
+                    Ok(stats) => {
+                        *self.status.lock().unwrap() = "✅ Archive scanned.".into();
+                        self.type_stats = Some(stats);
+                    }
+                    Err(e) => {
+                        *self.status.lock().unwrap() = format!("❌ Couldn't read archive: {e}");
+                    }
+                }
+                self.stats_rx = None;
+                self.drop_gpg_plain();
+            }
+
+            if let Some(bench_msg) = self.bench_rx.as_ref().and_then(|rx| rx.try_recv().ok()) {
+                self.bench_rx = None;
+                match bench_msg {
+                    Ok(benchmark) => {
+                        *self.status.lock().unwrap() = "✅ Formats compared.".into();
+                        self.benchmark = Some(benchmark);
+                    }
+                    Err(e) => {
+                        *self.status.lock().unwrap() = format!("❌ Couldn't compare formats: {e}");
+                    }
+                }
+            }
+
+            if let Some(verify_msg) = self.verify_rx.as_ref().and_then(|rx| rx.try_recv().ok()) {
+                self.verify_rx = None;
+                self.drop_gpg_plain();
+                match verify_msg {
+                    Ok(report) => {
+                        *self.status.lock().unwrap() = if report.passed() {
+                            format!("✅ Backup verified: {}", report.summary())
+                        } else {
+                            format!("❌ Verification failed: {}", report.summary())
+                        };
+                        self.verify_report = Some(report);
+                    }
+                    Err(e) => {
+                        *self.status.lock().unwrap() = format!("❌ Couldn't verify backup: {e}");
+                    }
+                }
+            }
+
+            if let Some(compare_msg) = self.compare_rx.as_ref().and_then(|rx| rx.try_recv().ok()) {
+                self.compare_rx = None;
+                self.drop_diff_plains();
+                match compare_msg {
+                    (title, Ok(differences)) => {
+                        *self.status.lock().unwrap() = if differences.is_empty() {
+                            "✅ No differences.".into()
+                        } else {
+                            format!("{} files differ.", differences.len())
+                        };
+                        self.differences = Some((title, DiffTree::build(&differences)));
+                    }
+                    (_, Err(e)) => {
+                        *self.status.lock().unwrap() = format!("❌ Couldn't compare: {e}");
+                    }
+                }
+            }
+
+            if let Some(history_msg) = self.history_rx.as_ref().and_then(|rx| rx.try_recv().ok()) {
+                self.history_rx = None;
+                match history_msg {
+                    Ok((name, entries)) => {
+                        *self.status.lock().unwrap() =
+                            format!("{} backups hold {name}.", entries.len());
+                        self.history_filter = Some(name);
+                        self.history = Some(entries);
+                        self.search_hits = None;
+                    }
+                    Err(e) => *self.status.lock().unwrap() = format!("❌ {e}"),
+                }
+            }
+
+            if let Some(search_msg) = self.search_rx.as_ref().and_then(|rx| rx.try_recv().ok()) {
+                self.search_rx = None;
+                match search_msg {
+                    Ok(hits) => {
+                        self.status.lock().unwrap().clear();
+                        self.search_hits = Some(hits);
+                    }
+                    Err(e) => *self.status.lock().unwrap() = format!("❌ Catalog: {e}"),
+                }
+            }
+
+            if let Some(repo_msg) = self.repo_rx.as_ref().and_then(|rx| rx.try_recv().ok()) {
+                self.repo_rx = None;
+                if let Ok(snapshots) = repo_msg {
+                    if self
+                        .repo_selected
+                        .as_ref()
+                        .is_some_and(|id| !snapshots.iter().any(|s| &s.id == id))
+                    {
+                        self.repo_selected = None;
+                    }
+                    self.repo_snapshots = snapshots;
+                }
+            }
+
+            if let Some(issues) = self
+                .validation_rx
+                .as_ref()
+                .and_then(|rx| rx.try_recv().ok())
+            {
+                self.validation_rx = None;
+                if issues.is_empty() {
+                    self.start_backup();
+                } else {
+                    *self.status.lock().unwrap() =
+                        format!("⚠ {} paths need attention.", issues.len());
+                    self.path_issues = Some(issues);
+                }
+            }
+
+            if let Some(done_msg) = self
+                .restore_done_rx
+                .as_ref()
+                .and_then(|rx| rx.try_recv().ok())
+            {
+                self.restore_done_rx = None;
+                self.drop_gpg_plain();
+                if let Ok(mut report) = done_msg {
+                    if !report.skipped.is_empty() || !report.failed.is_empty() {
+                        self.left_out = Some(LeftOutPanel {
+                            title: "Restore",
+                            skipped: std::mem::take(&mut report.skipped),
+                            failed: std::mem::take(&mut report.failed),
+                            file: report.report_file.take(),
+                        });
+                    }
+                    if !report.renamed.is_empty() {
+                        self.restore_report = Some(report);
+                    }
+                }
+            }
+
+            if let Some(preview) = self.preview_rx.as_ref().and_then(|rx| rx.try_recv().ok()) {
+                self.preview_rx = None;
+                match preview {
+                    Ok(planned) => {
+                        *self.status.lock().unwrap() = "Preview ready, nothing was written.".into();
+                        self.restore_preview = Some(planned);
+                    }
+                    Err(e) => *self.status.lock().unwrap() = format!("❌ Preview failed: {e}"),
+                }
+            }
+
+            if let Some(peeked) = self.peek_rx.as_ref().and_then(|rx| rx.try_recv().ok()) {
+                self.peek_rx = None;
+                match peeked {
+                    Ok((original, size, peeked)) => {
+                        *self.status.lock().unwrap() = String::new();
+                        let shown = match peeked {
+                            Peeked::Text(text) => Peeked::Text(text),
+                            Peeked::Image(image) => Peeked::Image(ctx.load_texture(
+                                "file_preview",
+                                image,
+                                Default::default(),
+                            )),
+                            Peeked::Not(why) => Peeked::Not(why),
+                        };
+                        self.file_preview = Some(FilePreview {
+                            original,
+                            size,
+                            shown,
+                        });
                     }
+                    Err(e) => *self.status.lock().unwrap() = format!("❌ Preview failed: {e}"),
+                }
+            }
+
+            if let Some(found) = self
+                .conflicts_rx
+                .as_ref()
+                .and_then(|rx| rx.try_recv().ok())
+            {
+                self.conflicts_rx = None;
+                match found {
+                    Ok(mut prompt) if prompt.existing.is_empty() => {
+                        prompt.options.existing = Existing::Overwrite;
+                        self.start_restore(prompt.zip_path, prompt.selected, prompt.options);
+                    }
+                    Ok(prompt) => {
+                        *self.status.lock().unwrap() =
+                            format!("⚠ {} files are already there.", prompt.existing.len());
+                        self.conflict_prompt = Some(prompt);
+                    }
+                    Err(e) => {
+                        self.drop_gpg_plain();
+                        *self.status.lock().unwrap() = format!("❌ Restore failed: {e}");
+                    }
+                }
+            }
+
+            if let Some(panel) = self
+                .left_out_rx
+                .as_ref()
+                .and_then(|rx| rx.try_recv().ok())
+            {
+                self.left_out_rx = None;
+                self.left_out = Some(panel);
+            }
+
+            ui.horizontal(|ui| {
+                ui.heading("Konserve");
+                ui.with_layout(egui::Layout::right_to_left(egui::Align::Center), |ui| {
+                    if ui.button("⚙").on_hover_text("Settings").clicked() {
+                        self.settings_open = !self.settings_open;
+                    }
+                    if ui.button("📜").on_hover_text("Log").clicked() {
+                        self.log_viewer.open = !self.log_viewer.open;
+                    }
+                });
+            });
+            ui.separator();
+
+            if self.settings_open {
+                ui.label("Settings");
+
+                ui.add_space(4.0);
+
+                ui.horizontal(|ui| {
+                    ui.label("UI scale");
+                    let slider = ui.add(
+                        egui::Slider::new(&mut self.settings.ui_scale, 0.75..=2.0)
+                            .step_by(0.05)
+                            .custom_formatter(|v, _| format!("{:.0}%", v * 100.0)),
+                    );
+                    // applying while dragging moves the slider out from under the cursor
+                    if slider.drag_stopped() || (slider.changed() && !slider.dragged()) {
+                        ctx.set_zoom_factor(self.settings.ui_scale);
+                        self.resize_pending = true;
+                    }
+                });
+
+                ui.horizontal(|ui| {
+                    ui.label("Accent color");
+                    if egui::color_picker::color_edit_button_srgb(ui, &mut self.settings.accent)
+                        .changed()
+                    {
+                        style::apply(ctx, &self.settings);
+                    }
+                });
+
+                ui.horizontal(|ui| {
+                    ui.label("Spacing");
+                    egui::ComboBox::from_id_salt("density")
+                        .selected_text(self.settings.density.label())
+                        .show_ui(ui, |ui| {
+                            for density in Density::ALL {
+                                if ui
+                                    .selectable_value(
+                                        &mut self.settings.density,
+                                        density,
+                                        density.label(),
+                                    )
+                                    .changed()
+                                {
+                                    style::apply(ctx, &self.settings);
+                                }
+                            }
+                        });
+                });
+
+                ui.horizontal(|ui| {
+                    ui.label("GPG recipient");
+                    ui.add(
+                        egui::TextEdit::singleline(&mut self.settings.gpg_recipient)
+                            .hint_text("off")
+                            .desired_width(160.0),
+                    )
+                    .on_hover_text(
+                        "Key id or email, new backups are encrypted with the installed gpg",
+                    );
+                });
+
+                ui.horizontal(|ui| {
+                    ui.label("Copy backups to");
+                    ui.add(
+                        egui::TextEdit::singleline(&mut self.settings.remote_folder)
+                            .hint_text("off")
+                            .desired_width(160.0),
+                    )
+                    .on_hover_text(
+                        "A network share, NAS or synced cloud folder. Each new backup is copied there and read back to check it",
+                    );
+                    if ui.button("📁").clicked()
+                        && let Some(dir) = FileDialog::new()
+                            .set_title("Copy backups to")
+                            .pick_folder()
+                    {
+                        self.settings.remote_folder = dir.display().to_string();
+                    }
+                });
+
+                ui.horizontal(|ui| {
+                    ui.label("Backup threads");
+                    ui.add(egui::DragValue::new(&mut self.settings.backup_workers).range(1..=32))
+                        .on_hover_text("Files are read and compressed on this many threads");
+                });
+
+                ui.horizontal(|ui| {
+                    ui.label("Backup speed limit");
+                    ui.add(
+                        egui::DragValue::new(&mut self.settings.backup_limit)
+                            .range(0..=2000)
+                            .suffix(" MB/s"),
+                    )
+                    .on_hover_text("Keeps the disk free for other programs, 0 is no limit");
+                });
+
+                ui.checkbox(
+                    &mut self.settings.background_priority,
+                    "Back up in background priority",
+                )
+                .on_hover_text(
+                    "Lower cpu and disk priority, the computer stays responsive but backups take longer",
+                );
+
+                ui.checkbox(
+                    &mut self.settings.keep_streams,
+                    "Keep alternate data streams and extended attributes",
+                )
+                .on_hover_text(
+                    "Metadata programs attach to files, like where a download came from",
+                );
+
+                if cfg!(windows) {
+                    ui.checkbox(
+                        &mut self.settings.shadow_copy,
+                        "Read from a shadow copy",
+                    )
+                    .on_hover_text(
+                        "Backs up files other programs hold open, like Outlook mailboxes. Needs administrator rights",
+                    );
+                }
+
+                ui.horizontal(|ui| {
+                    ui.checkbox(&mut self.settings.sign_backups, "Sign backups")
+                        .on_hover_text("Writes an ed25519 signature next to every new backup");
+                    if ui.button("Copy public key").clicked() {
+                        match signing::public_key() {
+                            Ok(key) => ctx.copy_text(key),
+                            Err(e) => *self.status.lock().unwrap() = format!("❌ {e}"),
+                        }
+                    }
+                });
+
+                ui.horizontal(|ui| {
+                    let toggle = ui
+                        .checkbox(
+                            &mut self.settings.remember_passwords,
+                            "Remember template passwords",
+                        )
+                        .on_hover_text("Stored in the OS keyring, unticking clears them");
+                    if toggle.changed() && !self.settings.remember_passwords {
+                        self.forget_passwords();
+                    }
+                    let saved = self.settings.remembered_templates.len();
+                    if saved > 0 && ui.button(format!("Forget {saved} saved")).clicked() {
+                        self.forget_passwords();
+                    }
+                });
+
+                ui.label("Trusted signing keys");
+                let mut to_remove = None;
+                for (i, key) in self.settings.trusted_keys.iter_mut().enumerate() {
+                    ui.horizontal(|ui| {
+                        ui.add(
+                            egui::TextEdit::singleline(key)
+                                .hint_text("hex public key")
+                                .desired_width(240.0),
+                        );
+                        if ui.button("Remove").clicked() {
+                            to_remove = Some(i);
+                        }
+                    });
+                }
+                if let Some(i) = to_remove {
+                    self.settings.trusted_keys.remove(i);
+                }
+                if ui.button("Add trusted key").clicked() {
+                    self.settings.trusted_keys.push(String::new());
+                }
+
+                ui.horizontal(|ui| {
+                    ui.label("Troubleshooting");
+                    if ui
+                        .button("Export diagnostics…")
+                        .on_hover_text("Log, settings and system info in one .zip for bug reports")
+                        .clicked()
+                    {
+                        let settings = self.settings.clone();
+                        let status = self.status.clone();
+
+                        thread::spawn(move || {
+                            if let Some(out) = FileDialog::new()
+                                .set_title("Save diagnostics")
+                                .set_file_name(diagnostics::default_file_name())
+                                .save_file()
+                            {
+                                *status.lock().unwrap() =
+                                    match diagnostics::export_bundle(&out, &settings) {
+                                        Ok(()) => {
+                                            format!("✅ Diagnostics saved:\n{}", out.display())
+                                        }
+                                        Err(e) => format!("❌ Diagnostics failed: {e}"),
+                                    };
+                            }
+                        });
+                    }
+                });
+
+                ui.separator();
+
+                ui.horizontal(|ui| {
+                    if ui.button("Save").clicked() {
+                        *self.status.lock().unwrap() = match self.settings.save() {
+                            Ok(()) => "✅ Settings saved.".into(),
+                            Err(e) => format!("❌ Couldn't save settings: {e}"),
+                        };
+                        self.settings_open = false;
+                    }
+                    if ui.button("Close").clicked() {
+                        self.settings_open = false;
+                    }
+                });
+
+                return;
+            }
+
+            if !self.missed.is_empty() {
+                ui.label("Missed Backups");
+                ui.weak("These were due while nothing was running to start them.");
+
+                ui.add_space(4.0);
+
+                let mut run = None;
+                let mut skipped = None;
+                egui::ScrollArea::vertical()
+                    .max_height(300.0)
+                    .show(ui, |ui| {
+                        egui::Grid::new("missed")
+                            .striped(true)
+                            .num_columns(3)
+                            .show(ui, |ui| {
+                                for (index, (schedule, due)) in self.missed.iter().enumerate() {
+                                    let name = schedule
+                                        .template
+                                        .file_stem()
+                                        .map(|n| n.to_string_lossy().into_owned())
+                                        .unwrap_or_default();
+                                    ui.label(name).on_hover_text(format!(
+                                        "{}\n→ {}",
+                                        schedule.template.display(),
+                                        schedule.out.display()
+                                    ));
+                                    ui.label(format!("due {}", due.format("%Y-%m-%d %H:%M")));
+                                    ui.horizontal(|ui| {
+                                        if ui.small_button("Run now").clicked() {
+                                            run = Some(index);
+                                        }
+                                        if ui.small_button("Skip").clicked() {
+                                            skipped = Some(index);
+                                        }
+                                    });
+                                    ui.end_row();
+                                }
+                            });
+                    });
+
+                ui.separator();
+
+                let mut later = false;
+                ui.horizontal(|ui| {
+                    if self.missed.len() > 1 && ui.button("Run all").clicked() {
+                        for (schedule, _) in std::mem::take(&mut self.missed) {
+                            self.run_missed(schedule);
+                        }
+                    }
+                    later = ui
+                        .button("Later")
+                        .on_hover_text("Asks again the next time Konserve starts")
+                        .clicked();
+                });
+                if let Some(index) = run {
+                    let (schedule, _) = self.missed.remove(index);
+                    self.run_missed(schedule);
+                } else if let Some(index) = skipped {
+                    let (schedule, due) = self.missed.remove(index);
+                    schedule::skip(&schedule, due);
+                } else if later {
+                    self.missed.clear();
+                }
+
+                return;
+            }
+
+            if let Some(prompt) = &self.conflict_prompt {
+                ui.label(format!(
+                    "{} files to restore are already there",
+                    prompt.existing.len()
+                ));
+
+                ui.add_space(4.0);
+
+                egui::ScrollArea::vertical()
+                    .max_height(300.0)
+                    .show(ui, |ui| {
+                        ui.set_width(ui.available_width());
+                        for path in &prompt.existing {
+                            ui.label(path.display().to_string());
+                        }
+                    });
+
+                ui.separator();
+
+                let mut choice = None;
+                let mut cancel = false;
+                ui.horizontal(|ui| {
+                    for existing in Existing::ALL {
+                        if existing != Existing::Ask && ui.button(existing.label()).clicked() {
+                            choice = Some(existing);
+                        }
+                    }
+                    cancel = ui.button("Cancel").clicked();
+                });
+                if let Some(existing) = choice
+                    && let Some(mut prompt) = self.conflict_prompt.take()
+                {
+                    prompt.options.existing = existing;
+                    self.start_restore(prompt.zip_path, prompt.selected, prompt.options);
+                } else if cancel {
+                    self.conflict_prompt = None;
+                    self.drop_gpg_plain();
+                    *self.status.lock().unwrap() = "Restore cancelled.".into();
+                }
+
+                return;
+            }
+
+            if let Some(report) = &self.restore_report {
+                ui.label(format!(
+                    "Renamed During Restore: {} of {} files",
+                    report.renamed.len(),
+                    report.restored
+                ));
+
+                ui.add_space(4.0);
+
+                egui::ScrollArea::vertical()
+                    .max_height(300.0)
+                    .show(ui, |ui| {
+                        ui.set_width(ui.available_width());
+                        for (from, to) in &report.renamed {
+                            ui.label(egui::RichText::new(from.display().to_string()).small());
+                            ui.label(format!("→ {}", to.display()));
+                            ui.separator();
+                        }
+                    });
+
+                ui.separator();
+
+                if ui.button("Close").clicked() {
+                    self.restore_report = None;
+                }
+
+                return;
+            }
+
+            if let Some(left_out) = &self.left_out {
+                ui.label(format!(
+                    "{}: {} skipped, {} failed",
+                    left_out.title,
+                    left_out.skipped.len(),
+                    left_out.failed.len()
+                ));
+
+                ui.add_space(4.0);
+
+                report::show(ui, &left_out.skipped, &left_out.failed);
+                if let Some(file) = &left_out.file {
+                    ui.weak(format!("Also saved to {}", file.display()));
+                }
+
+                ui.separator();
+
+                let close = ui.horizontal(|ui| {
+                    if !left_out.failed.is_empty()
+                        && ui
+                            .button("Export error log…")
+                            .on_hover_text("Path, operation, error code and time of every failure")
+                            .clicked()
+                        && let Some(path) = FileDialog::new()
+                            .set_title("Export error log")
+                            .set_file_name("errors.json")
+                            .add_filter("JSON", &["json"])
+                            .add_filter("CSV", &["csv"])
+                            .save_file()
+                    {
+                        *self.status.lock().unwrap() =
+                            match report::export(&path, &left_out.failed) {
+                                Ok(()) => format!("✅ Error log saved:\n{}", path.display()),
+                                Err(e) => format!("❌ Couldn't save the error log: {e}"),
+                            };
+                    }
+                    ui.button("Close").clicked()
+                });
+                if close.inner {
+                    self.left_out = None;
+                }
+
+                return;
+            }
+
+            if let Some(report) = &self.verify_report {
+                if report.passed() {
+                    ui.label(format!("✅ Verification passed: {}", report.summary()));
+                } else {
+                    ui.label(format!("❌ Verification failed: {}", report.summary()));
+                }
+
+                ui.add_space(4.0);
+
+                egui::ScrollArea::vertical()
+                    .max_height(300.0)
+                    .show(ui, |ui| {
+                        ui.set_width(ui.available_width());
+                        for (path, problem) in &report.corrupt {
+                            ui.label(egui::RichText::new(path.display().to_string()).small());
+                            ui.label(format!("⚠ {problem}"));
+                            ui.separator();
+                        }
+                        for path in &report.missing {
+                            ui.label(egui::RichText::new(path.display().to_string()).small());
+                            ui.label("⚠ missing from the backup");
+                            ui.separator();
+                        }
+                    });
+
+                ui.separator();
+
+                if ui.button("Close").clicked() {
+                    self.verify_report = None;
+                }
+
+                return;
+            }
+
+            if let Some(issues) = &self.path_issues {
+                ui.label(format!("Path Check: {} issues", issues.len()));
+
+                ui.add_space(4.0);
+
+                egui::ScrollArea::vertical()
+                    .max_height(300.0)
+                    .show(ui, |ui| {
+                        ui.set_width(ui.available_width());
+                        for issue in issues {
+                            ui.label(egui::RichText::new(issue.path.display().to_string()).small());
+                            ui.label(format!("⚠ {}", issue.problem));
+                            ui.weak(issue.suggestion);
+                            ui.separator();
+                        }
+                    });
+
+                ui.separator();
+
+                ui.horizontal(|ui| {
+                    if ui.button("Back up anyway").clicked() {
+                        self.path_issues = None;
+                        self.start_backup();
+                    }
+                    if ui.button("Cancel").clicked() {
+                        self.path_issues = None;
+                        *self.status.lock().unwrap() = "❌ Cancelled.".into();
+                    }
+                });
+
+                return;
+            }
+
+            if let Some(stats) = &self.type_stats {
+                ui.label("Space by File Type");
+
+                ui.add_space(4.0);
+
+                egui::ScrollArea::vertical()
+                    .max_height(300.0)
+                    .show(ui, |ui| {
+                        let total: u64 = stats.iter().map(|s| s.stored_bytes).sum::<u64>().max(1);
+
+                        egui::Grid::new("type_stats")
+                            .striped(true)
+                            .num_columns(5)
+                            .show(ui, |ui| {
+                                ui.strong("Type");
+                                ui.strong("Files");
+                                ui.strong("Original");
+                                ui.strong("Stored");
+                                ui.strong("Share");
+                                ui.end_row();
+
+                                for stat in stats {
+                                    ui.label(&stat.kind);
+                                    ui.label(stat.files.to_string());
+                                    ui.label(format_bytes(stat.original_bytes));
+                                    ui.label(format_bytes(stat.stored_bytes)).on_hover_text(
+                                        format!("{:.1}% of original", stat.ratio() * 100.0),
+                                    );
+                                    ui.label(format!(
+                                        "{:.1}%",
+                                        stat.stored_bytes as f64 * 100.0 / total as f64
+                                    ));
+                                    ui.end_row();
+                                }
+                            });
+                    });
+
+                ui.separator();
+
+                if ui.button("Close").clicked() {
+                    self.type_stats = None;
+                }
+
+                return;
+            }
+
+            if let Some(benchmark) = &self.benchmark {
+                ui.label("Compression Benchmark");
+                ui.label(format!(
+                    "{} of {} files from the selection",
+                    format_bytes(benchmark.sample),
+                    format_count(benchmark.files)
+                ));
+
+                ui.add_space(4.0);
+
+                let winner = benchmark.winner();
+                let mut picked = None;
+                let mut close = false;
+                egui::ScrollArea::vertical()
+                    .max_height(300.0)
+                    .show(ui, |ui| {
+                        egui::Grid::new("benchmark")
+                            .striped(true)
+                            .num_columns(5)
+                            .show(ui, |ui| {
+                                ui.strong("Format");
+                                ui.strong("Size");
+                                ui.strong("Ratio");
+                                ui.strong("Speed");
+                                ui.label("");
+                                ui.end_row();
+
+                                for trial in &benchmark.trials {
+                                    let best = winner.is_some_and(|w| std::ptr::eq(w, trial));
+                                    match best {
+                                        true => ui.strong(format!("★ {}", trial.label())),
+                                        false => ui.label(trial.label()),
+                                    };
+                                    ui.label(format_bytes(trial.size));
+                                    ui.label(format!(
+                                        "{:.1}%",
+                                        benchmark.ratio(trial) * 100.0
+                                    ));
+                                    ui.label(format!(
+                                        "{}/s",
+                                        format_bytes(benchmark.speed(trial) as u64)
+                                    ));
+                                    if ui.small_button("Use").clicked() {
+                                        picked = Some((trial.format, trial.zstd_level));
+                                    }
+                                    ui.end_row();
+                                }
+                            });
+                    });
+                ui.label("★ the smallest of those at most four times slower than the fastest");
+
+                ui.separator();
+
+                ui.horizontal(|ui| {
+                    if let Some(winner) = winner
+                        && ui.button(format!("Use {}", winner.label())).clicked()
+                    {
+                        picked = Some((winner.format, winner.zstd_level));
+                    }
+                    close = ui.button("Close").clicked();
+                });
+
+                if close {
+                    self.benchmark = None;
+                }
+                if let Some((format, level)) = picked {
+                    self.backup_format = format;
+                    self.zstd_level = level;
+                    self.benchmark = None;
+                    *self.status.lock().unwrap() = match format {
+                        ArchiveFormat::TarZst => {
+                            format!("✅ Backups are now {} at level {level}.", format.label())
+                        }
+                        _ => format!("✅ Backups are now {}.", format.label()),
+                    };
+                }
+
+                return;
+            }
+
+            if let Some(schedules) = &self.schedules {
+                ui.label("Scheduled Backups");
+
+                ui.add_space(4.0);
+
+                let mut removed = None;
+                egui::ScrollArea::vertical()
+                    .max_height(160.0)
+                    .show(ui, |ui| {
+                        egui::Grid::new("schedules")
+                            .striped(true)
+                            .num_columns(4)
+                            .show(ui, |ui| {
+                                ui.strong("Template");
+                                ui.strong("When");
+                                ui.strong("Next / last run");
+                                ui.end_row();
+
+                                for schedule in schedules {
+                                    let name = schedule
+                                        .template
+                                        .file_stem()
+                                        .map(|n| n.to_string_lossy().into_owned())
+                                        .unwrap_or_default();
+                                    ui.label(name).on_hover_text(format!(
+                                        "{}\n→ {}",
+                                        schedule.template.display(),
+                                        schedule.out.display()
+                                    ));
+                                    ui.label(schedule.describe()).on_hover_text(format!(
+                                        "If missed: {}",
+                                        schedule.catch_up.label().to_lowercase()
+                                    ));
+                                    ui.vertical(|ui| {
+                                        ui.label(schedule.upcoming().unwrap_or_else(|| "—".into()));
+                                        let last = ui.weak(
+                                            schedule.last_run.as_deref().unwrap_or("never run"),
+                                        );
+                                        if let Some(result) = &schedule.last_result {
+                                            last.on_hover_text(result);
+                                        }
+                                    });
+                                    if ui.small_button("Remove").clicked() {
+                                        removed = Some(schedule.id);
+                                    }
+                                    ui.end_row();
+                                }
+                            });
+                    });
+                if let Some(id) = removed {
+                    match schedule::remove(id) {
+                        Ok(list) => self.schedules = Some(list),
+                        Err(e) => *self.status.lock().unwrap() = format!("❌ {e}"),
+                    }
+                }
+
+                ui.separator();
+
+                ui.horizontal(|ui| {
+                    let template = self
+                        .schedule_template
+                        .as_ref()
+                        .and_then(|p| p.file_name())
+                        .map(|n| n.to_string_lossy().into_owned());
+                    if ui
+                        .button(template.as_deref().unwrap_or("Template…"))
+                        .clicked()
+                        && let Some(file) = FileDialog::new()
+                            .add_filter("Template", &["json"])
+                            .pick_file()
+                    {
+                        self.schedule_template = Some(file);
+                    }
+                    let out = self.schedule_out.as_ref().map(|p| p.display().to_string());
+                    if ui
+                        .button(out.as_deref().unwrap_or("Destination…"))
+                        .clicked()
+                        && let Some(dir) = FileDialog::new().pick_folder()
+                    {
+                        self.schedule_out = Some(dir);
+                    }
+                });
+                ui.horizontal(|ui| {
+                    for (day, on) in schedule::DAYS.iter().zip(&mut self.schedule_days) {
+                        ui.checkbox(on, *day);
+                    }
+                });
+                ui.horizontal(|ui| {
+                    ui.label("at");
+                    ui.add(egui::DragValue::new(&mut self.schedule_hour).range(0..=23));
+                    ui.label(":");
+                    ui.add(
+                        egui::DragValue::new(&mut self.schedule_minute)
+                            .range(0..=59)
+                            .custom_formatter(|n, _| format!("{n:02}")),
+                    );
+                    ui.label(format!("as .{}", self.backup_format.extension()));
+                });
+                ui.horizontal(|ui| {
+                    ui.label("If missed");
+                    egui::ComboBox::from_id_salt("schedule_catch_up")
+                        .selected_text(self.schedule_catch_up.label())
+                        .show_ui(ui, |ui| {
+                            for catch_up in CatchUp::ALL {
+                                ui.selectable_value(
+                                    &mut self.schedule_catch_up,
+                                    catch_up,
+                                    catch_up.label(),
+                                );
+                            }
+                        })
+                        .response
+                        .on_hover_text(
+                            "What happens when the computer was off or asleep at the time",
+                        );
+                    if cfg!(windows) {
+                        egui::ComboBox::from_id_salt("schedule_wake")
+                            .selected_text(self.schedule_wake.label())
+                            .show_ui(ui, |ui| {
+                                for wake in task_scheduler::Wake::ALL {
+                                    ui.selectable_value(&mut self.schedule_wake, wake, wake.label());
+                                }
+                            })
+                            .response
+                            .on_hover_text(
+                                "Only for Add to Task Scheduler: Windows wakes the computer \
+                                 from sleep for the backup",
+                            );
+                    }
+
+                    let ready = self.schedule_template.is_some()
+                        && self.schedule_out.is_some()
+                        && self.schedule_days.contains(&true);
+                    // the daemon can't wake a sleeping computer, only a task can
+                    let wakes = self.schedule_wake != task_scheduler::Wake::No;
+                    if ui
+                        .add_enabled(ready && !wakes, egui::Button::new("Add"))
+                        .on_disabled_hover_text(if wakes {
+                            "Konserve's own schedules can't wake the computer, \
+                             use Add to Task Scheduler or pick Don't wake"
+                        } else {
+                            "Pick a template, a folder and at least one day"
+                        })
+                        .clicked()
+                        && let (Some(template), Some(out)) =
+                            (self.schedule_template.clone(), self.schedule_out.clone())
+                    {
+                        let schedule = Schedule {
+                            days: self.schedule_days,
+                            hour: self.schedule_hour,
+                            minute: self.schedule_minute,
+                            catch_up: self.schedule_catch_up,
+                            ..Schedule::new(template, out, self.backup_format)
+                        };
+                        match schedule::add(schedule) {
+                            Ok(list) => {
+                                self.schedules = Some(list);
+                                self.schedule_template = None;
+                                if !self.daemon_running {
+                                    self.start_daemon();
+                                }
+                            }
+                            Err(e) => *self.status.lock().unwrap() = format!("❌ {e}"),
+                        }
+                    }
+                    // windows has no one to ask when a task was missed
+                    let asks = self.schedule_catch_up == CatchUp::Ask;
+                    let about = if asks {
+                        "Task Scheduler can't ask about a missed run, \
+                         pick running or skipping it"
+                    } else {
+                        "Windows runs it even when Konserve isn't open, \
+                         one task per template"
+                    };
+                    if cfg!(windows)
+                        && ui
+                            .add_enabled(
+                                ready && !asks,
+                                egui::Button::new("Add to Task Scheduler"),
+                            )
+                            .on_hover_text(about)
+                            .on_disabled_hover_text(about)
+                            .clicked()
+                        && let (Some(template), Some(out)) =
+                            (&self.schedule_template, &self.schedule_out)
+                    {
+                        let schedule = Schedule {
+                            days: self.schedule_days,
+                            hour: self.schedule_hour,
+                            minute: self.schedule_minute,
+                            catch_up: self.schedule_catch_up,
+                            ..Schedule::new(template.clone(), out.clone(), self.backup_format)
+                        };
+                        match task_scheduler::register(&schedule, self.schedule_wake) {
+                            Ok(name) => {
+                                *self.status.lock().unwrap() =
+                                    format!("✅ Registered task {name}.");
+                                self.load_win_tasks();
+                            }
+                            Err(e) => {
+                                *self.status.lock().unwrap() = format!("❌ Task Scheduler: {e}")
+                            }
+                        }
+                    }
+                });
+
+                if !self.win_tasks.is_empty() {
+                    ui.separator();
+                    ui.label("Windows Task Scheduler");
+                    let mut removed = None;
+                    egui::Grid::new("win_tasks")
+                        .striped(true)
+                        .num_columns(4)
+                        .show(ui, |ui| {
+                            ui.strong("Task");
+                            ui.strong("Next run");
+                            ui.strong("Last result");
+                            ui.end_row();
+
+                            for task in &self.win_tasks {
+                                ui.label(&task.name).on_hover_text(&task.command);
+                                ui.label(&task.next_run).on_hover_text(&task.status);
+                                ui.label(&task.last_result)
+                                    .on_hover_text(format!("Last run {}", task.last_run));
+                                if ui.small_button("Remove").clicked() {
+                                    removed = Some(task.name.clone());
+                                }
+                                ui.end_row();
+                            }
+                        });
+                    if let Some(name) = removed {
+                        match task_scheduler::remove(&name) {
+                            Ok(()) => self.load_win_tasks(),
+                            Err(e) => {
+                                *self.status.lock().unwrap() = format!("❌ Task Scheduler: {e}")
+                            }
+                        }
+                    }
+                }
+
+                ui.separator();
+                ui.label("When a drive is plugged in");
+                let mut unbound = None;
+                for (i, binding) in self.settings.drive_bindings.iter().enumerate() {
+                    ui.horizontal(|ui| {
+                        let name = binding
+                            .template
+                            .file_stem()
+                            .map(|n| n.to_string_lossy().into_owned())
+                            .unwrap_or_default();
+                        ui.label(format!("{name} → {}", binding.label))
+                            .on_hover_text(format!(
+                                "{}\ninto {} as .{}",
+                                binding.template.display(),
+                                binding.folder,
+                                binding.format.extension()
+                            ));
+                        if ui.small_button("Remove").clicked() {
+                            unbound = Some(i);
+                        }
+                    });
+                }
+                if let Some(i) = unbound {
+                    self.settings.drive_bindings.remove(i);
+                    if let Err(e) = self.settings.save() {
+                        *self.status.lock().unwrap() = format!("❌ Couldn't save settings: {e}");
+                    }
+                }
+                ui.horizontal(|ui| {
+                    let picked = self
+                        .bind_drive
+                        .and_then(|i| self.drives.get(i))
+                        .map(Drive::describe);
+                    egui::ComboBox::from_id_salt("bind_drive")
+                        .selected_text(picked.as_deref().unwrap_or("Drive…"))
+                        .show_ui(ui, |ui| {
+                            for (i, drive) in self.drives.iter().enumerate() {
+                                ui.selectable_value(
+                                    &mut self.bind_drive,
+                                    Some(i),
+                                    drive.describe(),
+                                );
+                            }
+                        });
+                    if ui
+                        .small_button("⟳")
+                        .on_hover_text("Look for drives again")
+                        .clicked()
+                    {
+                        self.drives = drives::removable();
+                        self.bind_drive = None;
+                    }
+                    let ready = self.schedule_template.is_some() && self.bind_drive.is_some();
+                    if ui
+                        .add_enabled(ready, egui::Button::new("Bind"))
+                        .on_hover_text(
+                            "Back the template up to this drive whenever it's plugged in",
+                        )
+                        .clicked()
+                    {
+                        self.bind_template();
+                    }
+                });
+                if self.drives.is_empty() {
+                    ui.weak("No removable drives plugged in.");
+                }
+
+                ui.separator();
+                ui.horizontal(|ui| {
+                    if self.daemon_running {
+                        ui.weak("The daemon runs these while you're logged in.");
+                    } else {
+                        ui.colored_label(egui::Color32::YELLOW, "The daemon isn't running.");
+                        if ui.small_button("Start it").clicked() {
+                            self.start_daemon();
+                        }
+                    }
+                    if ui.button("Close").clicked() {
+                        self.schedules = None;
+                    }
+                });
+
+                ui.label(self.status.lock().unwrap().as_str());
+                return;
+            }
+
+            if self.repo_open {
+                ui.label("Snapshot Repository");
+
+                ui.add_space(4.0);
+
+                let busy = self.repo_rx.is_some();
+                ui.horizontal(|ui| {
+                    match &self.repo_path {
+                        Some(path) => ui.label(path.display().to_string()),
+                        None => ui.weak("No repository open"),
+                    };
+                    if ui
+                        .add_enabled(!busy, egui::Button::new("Open…"))
+                        .on_hover_text("Pick a repository, or an empty folder to start one")
+                        .clicked()
+                        && let Some(dir) = FileDialog::new().pick_folder()
+                    {
+                        self.repo_path = Some(dir);
+                        self.repo_snapshots.clear();
+                        self.repo_selected = None;
+                        self.repo_task("Opening repository…", |_, _| {
+                            Ok("✅ Repository ready.".into())
+                        });
+                    }
+                });
+
+                ui.add_space(4.0);
+
+                egui::ScrollArea::vertical()
+                    .max_height(250.0)
+                    .show(ui, |ui| {
+                        egui::Grid::new("repo_snapshots")
+                            .striped(true)
+                            .num_columns(3)
+                            .show(ui, |ui| {
+                                ui.strong("Snapshot");
+                                ui.strong("Files");
+                                ui.strong("Size");
+                                ui.end_row();
+
+                                for snapshot in &self.repo_snapshots {
+                                    let selected =
+                                        self.repo_selected.as_ref() == Some(&snapshot.id);
+                                    if ui
+                                        .selectable_label(selected, &snapshot.time)
+                                        .on_hover_text(
+                                            snapshot
+                                                .sources
+                                                .iter()
+                                                .map(|p| p.display().to_string())
+                                                .collect::<Vec<_>>()
+                                                .join("\n"),
+                                        )
+                                        .clicked()
+                                    {
+                                        self.repo_selected = Some(snapshot.id.clone());
+                                    }
+                                    ui.label(snapshot.files().to_string());
+                                    ui.label(format_bytes(snapshot.size()));
+                                    ui.end_row();
+                                }
+                            });
+                    });
+
+                ui.separator();
+
+                let ready = !busy && self.repo_path.is_some();
+                ui.horizontal(|ui| {
+                    if ui
+                        .add_enabled(
+                            ready && !self.selected_folders.is_empty(),
+                            egui::Button::new("Snapshot selected paths"),
+                        )
+                        .clicked()
+                    {
+                        let sources = self.selected_folders.clone();
+                        let filters = self.backup_filters.clone();
+                        self.repo_task("Creating snapshot…", move |repo, progress| {
+                            repo.create_snapshot(&sources, &filters, progress)
+                                .map(|summary| format!("✅ Snapshot saved:\n{}", summary.report()))
+                        });
+                    }
+                    if ui
+                        .add_enabled(
+                            ready && self.repo_selected.is_some(),
+                            egui::Button::new("Restore snapshot"),
+                        )
+                        .on_hover_text("Puts every file back where it was backed up from")
+                        .clicked()
+                        && let Some(id) = self.repo_selected.clone()
+                    {
+                        self.repo_task("Restoring snapshot…", move |repo, progress| {
+                            repo.restore_snapshot(&id, progress)
+                                .map(|files| format!("✅ Restored {files} files."))
+                        });
+                    }
+                    if ui
+                        .add_enabled(ready, egui::Button::new("Push to…"))
+                        .on_hover_text(
+                            "Copies the snapshots to a repository on a share or NAS, sending only the chunks it doesn't have yet",
+                        )
+                        .clicked()
+                    {
+                        let mut dialog = FileDialog::new().set_title("Push snapshots to");
+                        let remote = self.settings.remote_folder.trim();
+                        if !remote.is_empty() {
+                            dialog = dialog.set_directory(remote);
+                        }
+                        if let Some(to) = dialog.pick_folder() {
+                            self.repo_task("Pushing snapshots…", move |repo, progress| {
+                                repo.push(&to, progress)
+                                    .map(|summary| format!("✅ {}", summary.report()))
+                            });
+                        }
+                    }
+                    if ui.add_enabled(!busy, egui::Button::new("Close")).clicked() {
+                        self.repo_open = false;
+                    }
+                });
+
+                if let Some(p) = &self.repo_progress
+                    && busy
+                {
+                    ui.add(
+                        egui::ProgressBar::new((p.get().min(100) as f32) / 100.0)
+                            .fill(style::accent(&self.settings))
+                            .desired_height(6.0)
+                            .animate(true)
+                            .desired_width(ui.available_width()),
+                    );
+                    ctx.request_repaint_after(std::time::Duration::from_millis(30));
+                }
+
+                ui.separator();
+                ui.label(self.status.lock().unwrap().as_str());
+
+                return;
+            }
+
+            if let Some((zip_file, purpose, protection)) = self.unlock.clone() {
+                match protection {
+                    Protection::Password => ui.label("🔒 Password protected backup"),
+                    Protection::PublicKey => ui.label("🔑 Backup encrypted to public keys"),
+                };
+
+                ui.add_space(4.0);
+
+                ui.label(zip_file.display().to_string());
+                let mut entered = false;
+                if protection == Protection::Password {
+                    let field = ui.add(
+                        egui::TextEdit::singleline(&mut self.unlock_password)
+                            .password(true)
+                            .hint_text("Password"),
+                    );
+                    entered = field.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter));
+                }
+
+                ui.separator();
+
+                if self.unlock_rx.is_some() {
+                    ui.horizontal(|ui| {
+                        ui.add(egui::Spinner::new().size(16.0));
+                        ui.label("Unlocking…");
+                    });
+                    ctx.request_repaint_after(std::time::Duration::from_millis(30));
+                } else {
+                    ui.horizontal(|ui| {
+                        let path = zip_file.clone();
+                        match protection {
+                            Protection::Password => {
+                                if (ui.button("Open").clicked() || entered)
+                                    && !self.unlock_password.is_empty()
+                                {
+                                    let password = self.unlock_password.clone();
+                                    let (tx, rx) = mpsc::channel::<UnlockMsg>();
+                                    self.unlock_rx = Some(rx);
+
+                                    // key derivation is deliberately slow
+                                    thread::spawn(move || {
+                                        let _ = tx.send(crypto::unlock(&path, &password));
+                                    });
+                                }
+                            }
+                            Protection::PublicKey => {
+                                if ui.button("Choose identity file…").clicked()
+                                    && let Some(identity) = FileDialog::new()
+                                        .set_title("Choose age identity file")
+                                        .pick_file()
+                                {
+                                    let (tx, rx) = mpsc::channel::<UnlockMsg>();
+                                    self.unlock_rx = Some(rx);
+
+                                    thread::spawn(move || {
+                                        let _ =
+                                            tx.send(crypto::unlock_with_identity(&path, &identity));
+                                    });
+                                }
+                            }
+                        }
+                        if ui.button("Cancel").clicked() {
+                            self.unlock = None;
+                            self.unlock_password.clear();
+                            self.drop_gpg_plain();
+                        }
+                    });
+                }
+
+                if let Some(unlock_msg) = self.unlock_rx.as_ref().and_then(|rx| rx.try_recv().ok())
+                {
+                    self.unlock_rx = None;
+                    match unlock_msg {
+                        Ok(key) => {
+                            self.unlock = None;
+                            self.unlock_password.clear();
+                            self.open_archive(zip_file, purpose, Some(key));
+                        }
+                        Err(e) => {
+                            *self.status.lock().unwrap() = format!("❌ {e}");
+                        }
+                    }
+                }
+
+                ui.separator();
+                ui.label(self.status.lock().unwrap().as_str());
+
+                return;
+            }
+
+            if let Some(history) = &self.history {
+                match &self.history_filter {
+                    Some(name) => ui.label(format!("Backups Holding {name}")),
+                    None => ui.label("Backup History"),
+                };
+
+                ui.horizontal(|ui| {
+                    let field = ui.add(
+                        egui::TextEdit::singleline(&mut self.history_search)
+                            .hint_text("Search file names, e.g. invoice_2023.xlsx"),
+                    );
+                    let entered =
+                        field.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter));
+                    // a big catalog takes a while, the window stays responsive
+                    let idle = self.search_rx.is_none();
+                    let clicked = ui.add_enabled(idle, egui::Button::new("Search")).clicked();
+                    if (clicked || entered) && idle {
+                        if self.history_search.trim().is_empty() {
+                            self.search_hits = None;
+                        } else {
+                            *self.status.lock().unwrap() = "Searching the catalog…".into();
+                            let (tx, rx) = mpsc::channel::<SearchMsg>();
+                            self.search_rx = Some(rx);
+                            let name = self.history_search.clone();
+                            thread::spawn(move || {
+                                let _ = tx.send(Catalog::open().and_then(|c| c.search(&name)));
+                            });
+                        }
+                    }
+                });
+
+                ui.add_space(4.0);
+
+                let mut restore_hit = None;
+                if let Some(hits) = &self.search_hits {
+                    ui.label(format!("{} files found", hits.len()));
+                    egui::ScrollArea::vertical()
+                        .id_salt("search_hits")
+                        .max_height(300.0)
+                        .show(ui, |ui| {
+                            egui::Grid::new("search_hits_grid")
+                                .striped(true)
+                                .num_columns(4)
+                                .show(ui, |ui| {
+                                    ui.strong("File");
+                                    ui.strong("Backup");
+                                    ui.strong("Size");
+                                    ui.strong("");
+                                    ui.end_row();
+
+                                    for hit in hits {
+                                        let name = hit.path.file_name().unwrap_or_default();
+                                        ui.label(name.to_string_lossy())
+                                            .on_hover_text(hit.path.display().to_string());
+                                        let modified =
+                                            chrono::DateTime::from_timestamp(hit.mtime as i64, 0)
+                                                .map(|t| {
+                                                    t.with_timezone(&chrono::Local)
+                                                        .format("%Y-%m-%d %H:%M")
+                                                        .to_string()
+                                                })
+                                                .unwrap_or_default();
+                                        ui.label(&hit.created).on_hover_text(format!(
+                                            "{}\nfile modified {modified}",
+                                            hit.archive.display()
+                                        ));
+                                        ui.label(format_bytes(hit.size));
+                                        if hit.archive.exists() {
+                                            if ui
+                                                .button("Restore")
+                                                .on_hover_text("Put this file back where it was")
+                                                .clicked()
+                                            {
+                                                restore_hit =
+                                                    Some((hit.archive.clone(), hit.path.clone()));
+                                            }
+                                        } else {
+                                            ui.weak("missing");
+                                        }
+                                        ui.end_row();
+                                    }
+                                });
+                        });
+                    ui.separator();
+                }
+
+                let mut open = None;
+                let mut forget = None;
+                // search results take the place of the list
+                if self.search_hits.is_none() {
+                    egui::ScrollArea::vertical()
+                        .max_height(300.0)
+                        .show(ui, |ui| {
+                            egui::Grid::new("history")
+                                .striped(true)
+                                .num_columns(5)
+                                .show(ui, |ui| {
+                                    ui.strong("Created");
+                                    ui.strong("Mode");
+                                    ui.strong("Files");
+                                    ui.strong("Size");
+                                    ui.strong("");
+                                    ui.end_row();
+
+                                    for entry in history {
+                                        let name = entry.path.file_name().unwrap_or_default();
+                                        ui.label(&entry.created)
+                                            .on_hover_text(entry.path.display().to_string());
+                                        ui.label(&entry.mode);
+                                        ui.label(entry.files.to_string());
+                                        let size = ui.label(format_bytes(entry.size));
+                                        if let (Some(original), Some(ratio), Some(elapsed)) =
+                                            (entry.original_size, entry.ratio, entry.elapsed)
+                                        {
+                                            size.on_hover_text(format!(
+                                                "{} → {} ({:.1}% of original) in {:.1}s",
+                                                format_bytes(original),
+                                                format_bytes(entry.size),
+                                                ratio * 100.0,
+                                                elapsed.as_secs_f64()
+                                            ));
+                                        }
+                                        ui.horizontal(|ui| {
+                                            if entry.exists() {
+                                                if ui
+                                                    .button("Restore")
+                                                    .on_hover_text(name.to_string_lossy())
+                                                    .clicked()
+                                                {
+                                                    open = Some(entry.path.clone());
+                                                }
+                                            } else {
+                                                ui.weak("missing").on_hover_text(
+                                                    entry.path.display().to_string(),
+                                                );
+                                            }
+                                            if ui
+                                                .small_button("🗑")
+                                                .on_hover_text("Forget")
+                                                .clicked()
+                                            {
+                                                forget = Some(entry.id);
+                                            }
+                                        });
+                                        ui.end_row();
+                                    }
+                                });
+                        });
+                }
+
+                ui.separator();
+
+                ui.horizontal(|ui| {
+                    if ui
+                        .add_enabled(
+                            self.history_rx.is_none(),
+                            egui::Button::new("Find by file…"),
+                        )
+                        .on_hover_text("Only list backups holding a copy of this file")
+                        .clicked()
+                        && let Some(file) = FileDialog::new().pick_file()
+                    {
+                        *self.status.lock().unwrap() = "Looking through the catalog…".into();
+                        let (tx, rx) = mpsc::channel::<HistoryMsg>();
+                        self.history_rx = Some(rx);
+
+                        thread::spawn(move || {
+                            let name = file
+                                .file_name()
+                                .unwrap_or_default()
+                                .to_string_lossy()
+                                .into_owned();
+                            let result = fs::File::open(&file)
+                                .and_then(|mut f| incremental::hash_reader(&mut f))
+                                .map_err(|e| e.to_string())
+                                .and_then(|hash| Catalog::open()?.containing(&hash))
+                                .map(|entries| (name, entries));
+                            let _ = tx.send(result);
+                        });
+                    }
+                    if self.history_filter.is_some() && ui.button("Show all").clicked() {
+                        self.load_history();
+                    }
+                    if ui.button("Close").clicked() {
+                        self.history = None;
+                        self.history_filter = None;
+                        self.search_hits = None;
+                    }
+                });
+
+                if let Some(id) = forget {
+                    if let Err(e) = Catalog::open().and_then(|catalog| catalog.forget(id)) {
+                        *self.status.lock().unwrap() = format!("❌ Catalog: {e}");
+                    }
+                    if let Some(history) = &mut self.history {
+                        history.retain(|entry| entry.id != id);
+                    }
+                }
+                if let Some(path) = open {
+                    self.history = None;
+                    self.history_filter = None;
+                    self.open_path(path, OpenPurpose::Restore);
+                } else if let Some((archive, file)) = restore_hit {
+                    self.restore_one = Some(file);
+                    self.open_path(archive, OpenPurpose::RestoreFile);
+                }
+
+                return;
+            }
+
+            if let Some((title, tree)) = &self.differences {
+                ui.label(title);
+
+                ui.horizontal(|ui| {
+                    for change in [Change::Added, Change::Removed, Change::Modified] {
+                        ui.label(format!(
+                            "{} {}: {}",
+                            change.symbol(),
+                            change.label(),
+                            tree.count(change)
+                        ));
+                    }
+                });
+
+                ui.add_space(4.0);
+
+                egui::ScrollArea::vertical()
+                    .max_height(300.0)
+                    .show(ui, |ui| {
+                        ui.set_width(ui.available_width());
+                        render_diff_tree(ui, tree);
+                    });
+
+                ui.separator();
+
+                if ui.button("Close").clicked() {
+                    self.differences = None;
+                }
+
+                return;
+            }
+
+            if let Some(planned) = &self.restore_preview {
+                let writes: Vec<&Planned> = planned.iter().filter(|p| p.action.writes()).collect();
+                let count = |action: fn(&Action) -> bool| {
+                    planned.iter().filter(|p| action(&p.action)).count()
+                };
+                ui.label(format!(
+                    "Restore Preview: {} files, {}",
+                    writes.len(),
+                    format_bytes(writes.iter().map(|p| p.size).sum())
+                ));
+                ui.label(format!(
+                    "+ new: {} · ~ replaced: {} · ⧉ kept beside: {} · ? to ask: {} · − left out: {}",
+                    count(|a| *a == Action::New),
+                    count(|a| *a == Action::Replace),
+                    count(|a| *a == Action::Beside),
+                    count(|a| *a == Action::Ask),
+                    count(|a| matches!(a, Action::Skip(_))),
+                ));
+
+                ui.add_space(4.0);
+
+                egui::ScrollArea::vertical()
+                    .max_height(300.0)
+                    .show(ui, |ui| {
+                        ui.set_width(ui.available_width());
+                        for p in planned {
+                            let line = format!(
+                                "{} {}  ({})",
+                                p.action.symbol(),
+                                p.target.display(),
+                                format_bytes(p.size)
+                            );
+                            let label = ui.label(line);
+                            if let Action::Skip(reason) = p.action {
+                                label.on_hover_text(reason);
+                            }
+                        }
+                    });
+
+                ui.separator();
+
+                if ui.button("Close").clicked() {
+                    self.restore_preview = None;
+                }
+
+                return;
+            }
+
+            if let Some(preview) = &self.file_preview {
+                ui.label(format!(
+                    "{}  ({})",
+                    preview.original.display(),
+                    format_bytes(preview.size)
+                ));
+
+                egui::ScrollArea::both()
+                    .max_height(400.0)
+                    .show(ui, |ui| match &preview.shown {
+                        Peeked::Text(text) => {
+                            ui.add(
+                                egui::TextEdit::multiline(&mut text.as_str())
+                                    .font(egui::TextStyle::Monospace)
+                                    .desired_width(f32::INFINITY),
+                            );
+                        }
+                        Peeked::Image(image) => {
+                            ui.add(egui::Image::from_texture(image).shrink_to_fit());
+                        }
+                        Peeked::Not(why) => {
+                            ui.label(why);
+                        }
+                    });
+                if let Peeked::Text(text) = &preview.shown
+                    && (text.len() as u64) < preview.size
+                {
+                    ui.label(format!("Showing the first {}.", format_bytes(text.len() as u64)));
+                }
+
+                ui.separator();
+
+                if ui.button("Close").clicked() {
+                    self.file_preview = None;
                 }
-                self.restore_rx = None;
-            }
 
-            ui.heading("Konserve");
-            ui.separator();
+                return;
+            }
 
             if self.restore_editor {
                 ui.label("Restore Selection");
 
+                match &self.restore_signature {
+                    None => {
+                        ui.horizontal(|ui| {
+                            ui.add(egui::Spinner::new().size(12.0));
+                            ui.label("Checking signature…");
+                        });
+                    }
+                    Some(Verdict::Verified) => {
+                        ui.colored_label(
+                            egui::Color32::from_rgb(80, 190, 100),
+                            "✅ Signature verified",
+                        );
+                    }
+                    Some(Verdict::Unverified(reason)) => {
+                        ui.colored_label(
+                            egui::Color32::from_rgb(230, 180, 60),
+                            "⚠ Signature unverified",
+                        )
+                        .on_hover_text(reason);
+                    }
+                    Some(Verdict::Invalid(reason)) => {
+                        ui.colored_label(
+                            egui::Color32::from_rgb(230, 70, 70),
+                            format!("❌ Signature INVALID: {reason}"),
+                        );
+                    }
+                }
+
+                let name = |path: &Path| {
+                    path.file_name()
+                        .map(|name| name.to_string_lossy().into_owned())
+                        .unwrap_or_default()
+                };
+                let mut reload = None;
+                ui.horizontal(|ui| {
+                    ui.label("Archives");
+                    if let Some(zip) = &self.restore_zip_path {
+                        ui.label(name(zip));
+                    }
+                    if ui
+                        .add_enabled(!self.restore_opening, egui::Button::new("Add…"))
+                        .on_hover_text(
+                            "Incrementals or other backups restored together with this one, \
+                             the newest copy of a file wins",
+                        )
+                        .clicked()
+                        && let Some(picked) = FileDialog::new()
+                            .add_filter("Backups", &archive::EXTENSIONS)
+                            .pick_files()
+                    {
+                        let mut also = self.restore_also.clone();
+                        also.extend(picked);
+                        reload = Some(also);
+                    }
+                });
+                for (i, archive) in self.restore_also.iter().enumerate() {
+                    ui.horizontal(|ui| {
+                        ui.label(format!("+ {}", name(archive)))
+                            .on_hover_text(archive.display().to_string());
+                        if ui
+                            .add_enabled(!self.restore_opening, egui::Button::new("Remove"))
+                            .clicked()
+                        {
+                            let mut also = self.restore_also.clone();
+                            also.remove(i);
+                            reload = Some(also);
+                        }
+                    });
+                }
+                if let Some(also) = reload
+                    && let Some(zip) = self.restore_zip_path.clone()
+                {
+                    self.load_restore(zip, also);
+                }
+
                 ui.add_space(4.0);
 
+                ui.horizontal(|ui| {
+                    ui.add(
+                        egui::TextEdit::singleline(&mut self.restore_pattern)
+                            .hint_text("*.jpg or Documents/**"),
+                    )
+                    .on_hover_text(
+                        "Matches the whole path, names anywhere unless it starts at a root",
+                    );
+                    for (button, checked) in [("Select", true), ("Deselect", false)] {
+                        if ui.button(button).clicked() {
+                            *self.status.lock().unwrap() = match check_matching(
+                                &mut self.restore_tree,
+                                &self.restore_pattern,
+                                checked,
+                            ) {
+                                Ok(0) => format!("Nothing matches {}.", self.restore_pattern),
+                                Ok(n) if checked => format!("{n} entries selected."),
+                                Ok(n) => format!("{n} entries deselected."),
+                                Err(e) => format!("❌ Pattern: {e}"),
+                            };
+                        }
+                    }
+                });
+
+                ui.horizontal(|ui| {
+                    ui.add(
+                        egui::TextEdit::singleline(&mut self.restore_filter)
+                            .hint_text("🔍 Filter by name"),
+                    )
+                    .on_hover_text("Only shows names containing this and the folders they're in");
+                    if !self.restore_filter.is_empty() && ui.button("Clear").clicked() {
+                        self.restore_filter.clear();
+                    }
+                });
+
+                ui.horizontal(|ui| {
+                    if ui.button("Expand all").clicked() {
+                        set_all_open(&mut self.restore_tree, true);
+                    }
+                    if ui.button("Collapse all").clicked() {
+                        set_all_open(&mut self.restore_tree, false);
+                    }
+                    ui.separator();
+                    if ui.button("Select all").clicked() {
+                        set_all_checked(&mut self.restore_tree, true);
+                    }
+                    if ui.button("Select none").clicked() {
+                        set_all_checked(&mut self.restore_tree, false);
+                    }
+                    if ui.button("Invert selection").clicked() {
+                        invert_checked(&mut self.restore_tree);
+                    }
+                });
+
+                let mut action = None;
+                let filter = self.restore_filter.trim().to_lowercase();
                 egui::ScrollArea::vertical()
                     .max_height(300.0)
                     .show(ui, |ui| {
                         let mut current_path = vec![];
-                        render_tree(ui, &mut current_path, &mut self.restore_tree)
+                        render_tree(
+                            ui,
+                            &mut current_path,
+                            &mut self.restore_tree,
+                            &filter,
+                            &mut action,
+                        )
+                    });
+                let (files, bytes) = selected_total(&self.restore_tree);
+                ui.label(format!(
+                    "Selected: {} {}, {}",
+                    format_count(files),
+                    if files == 1 { "file" } else { "files" },
+                    format_bytes(bytes)
+                ));
+                if let Some(TreeAction::Preview(original)) = &action
+                    && let Some(zip_path) = self.restore_zip_path.clone()
+                {
+                    let options = self.restore_options(self.restore_key.clone());
+                    let original = original.clone();
+                    *self.status.lock().unwrap() = format!("Reading {}…", original.display());
+                    let (tx, rx) = mpsc::channel::<PeekMsg>();
+                    self.peek_rx = Some(rx);
+
+                    thread::spawn(move || {
+                        let _ = tx.send(peek(&zip_path, original, &options));
+                    });
+                }
+                if let Some(TreeAction::Extract(original)) = action
+                    && let Some(zip_path) = self.restore_zip_path.clone()
+                    && let Some(folder) = FileDialog::new()
+                        .set_title("Extract this file to")
+                        .pick_folder()
+                {
+                    let options = self.restore_options(self.restore_key.clone());
+                    let status = self.status.clone();
+                    *status.lock().unwrap() = format!("Extracting {}…", original.display());
+                    thread::spawn(move || {
+                        *status.lock().unwrap() =
+                            match restore::extract_file(&zip_path, &original, &folder, &options) {
+                                Ok(to) => format!("✅ Extracted to {}", to.display()),
+                                Err(e) => format!("❌ {e}"),
+                            };
                     });
+                }
 
                 ui.separator();
 
-                if ui.button("Restore selected").clicked() {
-                    if let Some(zip_path) = &self.restore_zip_path.clone() {
-                        let selected = collect_paths(&self.restore_tree);
-                        let zip_path = zip_path.clone();
-                        let status = self.status.clone();
+                ui.horizontal(|ui| {
+                    ui.label("Worker threads");
+                    ui.add(egui::DragValue::new(&mut self.restore_workers).range(1..=32))
+                        .on_hover_text("How many files are written to disk at the same time");
+                });
 
-                        let progress = Progress::default();
-                        self.restore_progress = Some(progress.clone());
-                        self.restore_opening = false;
+                ui.checkbox(&mut self.restore_sanitize, "Make names Windows-safe")
+                    .on_hover_text("Replaces : * ? | and similar, and trailing dots or spaces");
+                ui.checkbox(&mut self.restore_hard_links, "Re-create hard links")
+                    .on_hover_text(
+                        "Off writes a full copy for every name, for drives without hard links",
+                    );
+                ui.checkbox(&mut self.restore_quarantine, "Keep replaced files")
+                    .on_hover_text(
+                        "Files the restore overwrites are moved into a dated Quarantine \
+                         folder first, at their full path, so they can be put back",
+                    );
+                ui.checkbox(&mut self.restore_staged, "All or nothing")
+                    .on_hover_text(
+                        "Everything is unpacked to a staging folder first and only moved \
+                         into place when nothing failed, otherwise nothing changes",
+                    );
+                ui.checkbox(&mut self.restore_permissions, "Restore permissions")
+                    .on_hover_text(
+                        "Read-only, hidden and unix mode bits as they were backed up, \
+                         off gives files the defaults of where they land",
+                    );
 
-                        thread::spawn(move || {
-                            if let Err(e) =
-                                restore_backup(&zip_path, Some(selected), status.clone(), &progress)
-                            {
-                                *status.lock().unwrap() = format!("❌ Restore failed: {}", e);
+                ui.horizontal(|ui| {
+                    ui.label("Names differing only by case");
+                    egui::ComboBox::from_id_salt("restore_case")
+                        .selected_text(self.restore_case.label())
+                        .show_ui(ui, |ui| {
+                            for choice in CaseCollision::ALL {
+                                ui.selectable_value(&mut self.restore_case, choice, choice.label());
                             }
                         });
-
-                        self.restore_editor = false;
+                });
+                ui.horizontal(|ui| {
+                    ui.label("Files already there");
+                    egui::ComboBox::from_id_salt("restore_existing")
+                        .selected_text(self.restore_existing.label())
+                        .show_ui(ui, |ui| {
+                            for choice in Existing::ALL {
+                                ui.selectable_value(
+                                    &mut self.restore_existing,
+                                    choice,
+                                    choice.label(),
+                                );
+                            }
+                        })
+                        .response
+                        .on_hover_text(
+                            "Overwrite if newer compares modification times, \
+                             Keep both restores a numbered copy next to the file, \
+                             Ask first lists them before anything is written",
+                        );
+                });
+                ui.horizontal(|ui| {
+                    ui.label("Restore to");
+                    match &self.restore_into {
+                        Some(folder) => ui.label(folder.display().to_string()),
+                        None if self.restore_foreign => ui.colored_label(
+                            egui::Color32::from_rgb(230, 180, 60),
+                            "pick a folder, this archive has no original locations",
+                        ),
+                        None => ui.label("where everything was backed up from"),
+                    };
+                    if ui.button("Browse…").clicked()
+                        && let Some(folder) = FileDialog::new().pick_folder()
+                    {
+                        self.restore_into = Some(folder);
+                    }
+                    if self.restore_into.is_some()
+                        && !self.restore_foreign
+                        && ui.button("Original locations").clicked()
+                    {
+                        self.restore_into = None;
                     }
+                });
+
+                if !self.restore_homes.is_empty()
+                    && let Some(home) = dirs::home_dir()
+                {
+                    let homes: Vec<String> = self
+                        .restore_homes
+                        .iter()
+                        .map(|h| h.display().to_string())
+                        .collect();
+                    ui.colored_label(
+                        egui::Color32::from_rgb(230, 180, 60),
+                        format!("⚠ Backed up from another user's folder: {}", homes.join(", ")),
+                    );
+                    ui.horizontal(|ui| {
+                        if ui
+                            .button(format!("Restore into {}", home.display()))
+                            .on_hover_text("Adds a rule below for each folder")
+                            .clicked()
+                        {
+                            for old in std::mem::take(&mut self.restore_homes) {
+                                self.restore_remap
+                                    .push((old.display().to_string(), home.display().to_string()));
+                            }
+                        }
+                        if ui.button("Dismiss").clicked() {
+                            self.restore_homes.clear();
+                        }
+                    });
+                }
+
+                let mut mapped = None;
+                for (i, (drive, to)) in self.restore_drives.iter_mut().enumerate() {
+                    ui.colored_label(
+                        egui::Color32::from_rgb(230, 180, 60),
+                        format!("⚠ {} isn't on this computer, restore it to", drive.display()),
+                    );
+                    ui.horizontal(|ui| {
+                        ui.add(
+                            egui::TextEdit::singleline(to)
+                                .hint_text("another drive or folder")
+                                .desired_width(200.0),
+                        );
+                        if ui.button("Browse…").clicked()
+                            && let Some(folder) = FileDialog::new().pick_folder()
+                        {
+                            *to = folder.display().to_string();
+                        }
+                        if ui
+                            .add_enabled(!to.trim().is_empty(), egui::Button::new("Use"))
+                            .clicked()
+                        {
+                            mapped = Some(i);
+                        }
+                    });
+                }
+                if let Some(i) = mapped {
+                    let (drive, to) = self.restore_drives.remove(i);
+                    self.restore_remap
+                        .push((drive.display().to_string(), to.trim().to_string()));
+                }
+
+                ui.label("Move paths")
+                    .on_hover_text("Restores what was below an old folder below a new one, e.g. for another machine");
+                let mut to_remove = None;
+                for (i, (from, to)) in self.restore_remap.iter_mut().enumerate() {
+                    ui.horizontal(|ui| {
+                        ui.add(
+                            egui::TextEdit::singleline(from)
+                                .hint_text("C:\\Users\\olduser")
+                                .desired_width(160.0),
+                        );
+                        ui.label("→");
+                        ui.add(
+                            egui::TextEdit::singleline(to)
+                                .hint_text("D:\\Users\\me")
+                                .desired_width(160.0),
+                        );
+                        if ui.button("Remove").clicked() {
+                            to_remove = Some(i);
+                        }
+                    });
+                }
+                if let Some(i) = to_remove {
+                    self.restore_remap.remove(i);
+                }
+                if ui.button("Add rule").clicked() {
+                    self.restore_remap.push(Default::default());
+                }
+
+                ui.separator();
+
+                let placed = !self.restore_foreign || self.restore_into.is_some();
+                if ui
+                    .add_enabled(placed, egui::Button::new("Restore selected"))
+                    .clicked()
+                    && let Some(zip_path) = &self.restore_zip_path.clone()
+                {
+                    let selected = collect_paths(&self.restore_tree);
+                    self.restore_opening = false;
+
+                    let key = self.restore_key.take();
+                    let options = self.restore_options(key);
+                    self.start_restore(zip_path.clone(), selected, options);
+
+                    self.restore_editor = false;
                 }
 
+                ui.horizontal(|ui| {
+                    if ui
+                        .add_enabled(
+                            placed && self.preview_rx.is_none(),
+                            egui::Button::new("Preview"),
+                        )
+                        .on_hover_text(
+                            "Which files the ticked selection would write and replace, \
+                             without writing anything",
+                        )
+                        .clicked()
+                        && let Some(zip_path) = self.restore_zip_path.clone()
+                    {
+                        *self.status.lock().unwrap() = "Working out the restore…".into();
+                        let selected = collect_paths(&self.restore_tree);
+                        let options = self.restore_options(self.restore_key.clone());
+                        let (tx, rx) = mpsc::channel::<PreviewMsg>();
+                        self.preview_rx = Some(rx);
+
+                        thread::spawn(move || {
+                            let _ = tx.send(restore::preview(&zip_path, Some(selected), &options));
+                        });
+                    }
+                    if self.preview_rx.is_some() {
+                        ui.add(egui::Spinner::new().size(12.0));
+                    }
+                    if ui
+                        .add_enabled(
+                            self.compare_rx.is_none(),
+                            egui::Button::new("Compare with disk"),
+                        )
+                        .on_hover_text("What restoring would bring back or overwrite")
+                        .clicked()
+                        && let Some(zip_path) = self.restore_zip_path.clone()
+                    {
+                        *self.status.lock().unwrap() = "Comparing with disk…".into();
+                        let key = self.restore_key.clone();
+                        let (tx, rx) = mpsc::channel::<CompareMsg>();
+                        self.compare_rx = Some(rx);
+
+                        thread::spawn(move || {
+                            let title = "Backup vs Disk\nAdded: only on disk · Removed: only in \
+                                         the backup · Modified: restoring overwrites it";
+                            let result = compare_with_disk(&zip_path, key.as_ref());
+                            let _ = tx.send((title.to_string(), result));
+                        });
+                    }
+                    if self.compare_rx.is_some() {
+                        ui.add(egui::Spinner::new().size(12.0));
+                    }
+                });
+
                 if ui.button("Cancel").clicked() {
                     self.restore_editor = false;
                     self.restore_zip_path = None;
+                    self.restore_key = None;
+                    self.drop_gpg_plain();
                     self.restore_tree = FolderTreeNode::default();
                 }
 
@@ -223,8 +3483,10 @@ impl eframe::App for GUIApp {
                         ui.set_width(ui.available_width());
                         let mut to_remove = None;
 
+                        let depths = &mut self.template_filters.max_depth;
                         for (i, path) in self.template_paths.iter_mut().enumerate() {
                             let mut path_str = path.display().to_string();
+                            let mut depth = depths.remove(path);
 
                             ui.horizontal(|ui| {
                                 ui.add_sized(
@@ -242,44 +3504,140 @@ impl eframe::App for GUIApp {
                                     ui.label("❌").on_hover_text("This path does not exist");
                                 }
 
-                                if ui.button("Browse").clicked() {
-                                    if let Some(p) = FileDialog::new().pick_folder() {
-                                        *path = p;
-                                    }
+                                if ui.button("Browse").clicked()
+                                    && let Some(p) = FileDialog::new().pick_folder()
+                                {
+                                    *path = p;
                                 }
 
+                                let mut on = depth.is_some();
+                                ui.checkbox(&mut on, "Depth")
+                                    .on_hover_text("Only walk this many levels into the folder");
+                                let mut value = depth.unwrap_or(2);
+                                ui.add_enabled(
+                                    on,
+                                    egui::DragValue::new(&mut value).range(1..=64),
+                                );
+                                depth = on.then_some(value);
+
                                 if ui.button("Remove").clicked() {
                                     to_remove = Some(i);
                                 }
                             });
+                            // keyed by the path, so it follows the path's edits
+                            if let Some(depth) = depth {
+                                depths.insert(path.clone(), depth);
+                            }
                         }
                         if let Some(i) = to_remove {
-                            self.template_paths.remove(i);
+                            depths.remove(&self.template_paths.remove(i));
                         }
                     });
                 ui.separator();
                 if ui.button("Add Path").clicked() {
                     self.template_paths.push(PathBuf::new());
                 }
-                if ui.button("Save Template").clicked() {
-                    if let Some(path) = FileDialog::new().add_filter("JSON", &["json"]).save_file()
-                    {
-                        let tpl = BackupTemplate {
-                            paths: self.template_paths.clone(),
+
+                ui.separator();
+                ui.label("Encrypt to public keys");
+                let mut to_remove = None;
+                for (i, recipient) in self.template_recipients.iter_mut().enumerate() {
+                    ui.horizontal(|ui| {
+                        ui.add_sized(
+                            [240.0, 20.0],
+                            egui::TextEdit::singleline(recipient).hint_text("age1…"),
+                        );
+
+                        match crypto::parse_recipient(recipient) {
+                            Ok(_) => ui.label("✅").on_hover_text("Valid age public key"),
+                            Err(e) => ui.label("❌").on_hover_text(e),
                         };
-                        match serde_json::to_string_pretty(&tpl) {
-                            Ok(json) => {
-                                if fs::write(&path, json).is_ok() {
-                                    *self.status.lock().unwrap() = "✅ Template saved".into();
-                                    self.template_editor = false;
-                                } else {
-                                    *self.status.lock().unwrap() = "❌ Couldn't write file.".into();
-                                }
+
+                        if ui.button("Remove").clicked() {
+                            to_remove = Some(i);
+                        }
+                    });
+                }
+                if let Some(i) = to_remove {
+                    self.template_recipients.remove(i);
+                }
+                if ui.button("Add Key").clicked() {
+                    self.template_recipients.push(String::new());
+                }
+
+                ui.separator();
+                ui.label("Leave out").on_hover_text(PATTERN_HINT);
+                let filters = &mut self.template_filters;
+                edit_patterns(ui, &mut filters.excludes, "**/node_modules/**");
+                ui.label("Only include").on_hover_text(PATTERN_HINT);
+                edit_patterns(ui, &mut filters.includes, "*.docx;*.xlsx");
+                ui.checkbox(&mut filters.ignore_files, "Use ignore files")
+                    .on_hover_text(IGNORE_HINT);
+                ui.checkbox(&mut filters.skip_hidden, "Skip hidden and system files")
+                    .on_hover_text(HIDDEN_HINT);
+                ui.checkbox(&mut filters.stay_on_volume, "Stay on one drive")
+                    .on_hover_text(VOLUME_HINT);
+                ui.horizontal(|ui| {
+                    ui.label("Symlinks").on_hover_text(SYMLINK_HINT);
+                    egui::ComboBox::from_id_salt("template_symlinks")
+                        .selected_text(filters.symlinks.label())
+                        .show_ui(ui, |ui| {
+                            for policy in SymlinkPolicy::ALL {
+                                ui.selectable_value(&mut filters.symlinks, policy, policy.label());
+                            }
+                        });
+                });
+                ui.horizontal(|ui| {
+                    ui.label("Unreadable files").on_hover_text(ERROR_HINT);
+                    egui::ComboBox::from_id_salt("template_on_error")
+                        .selected_text(self.template_on_error.label())
+                        .show_ui(ui, |ui| {
+                            for policy in ErrorPolicy::ALL {
+                                ui.selectable_value(
+                                    &mut self.template_on_error,
+                                    policy,
+                                    policy.label(),
+                                );
                             }
-                            Err(_) => {
-                                *self.status.lock().unwrap() = "❌ Failed to serialize.".into();
+                        });
+                });
+                edit_limit(ui, &mut filters.max_size_mb, "Skip files over", " MB", 4096);
+                edit_limit(ui, &mut filters.min_size_kb, "Skip files under", " KB", 1);
+                ui.horizontal(|ui| {
+                    ui.label("Changed since");
+                    ui.add(
+                        egui::TextEdit::singleline(&mut filters.modified_after)
+                            .hint_text("any time")
+                            .desired_width(90.0),
+                    )
+                    .on_hover_text(CUTOFF_HINT);
+                    if let Err(e) = parse_cutoff(&filters.modified_after) {
+                        ui.label("❌").on_hover_text(e);
+                    }
+                });
+                ui.separator();
+
+                if ui.button("Save Template").clicked()
+                    && let Some(path) = FileDialog::new().add_filter("JSON", &["json"]).save_file()
+                {
+                    let tpl = BackupTemplate {
+                        paths: self.template_paths.clone(),
+                        recipients: self.template_recipients.clone(),
+                        on_error: self.template_on_error,
+                        filters: self.template_filters.clone(),
+                    };
+                    match serde_json::to_string_pretty(&tpl) {
+                        Ok(json) => {
+                            if fs::write(&path, json).is_ok() {
+                                *self.status.lock().unwrap() = "✅ Template saved".into();
+                                self.template_editor = false;
+                            } else {
+                                *self.status.lock().unwrap() = "❌ Couldn't write file.".into();
                             }
                         }
+                        Err(_) => {
+                            *self.status.lock().unwrap() = "❌ Failed to serialize.".into();
+                        }
                     }
                 }
                 if ui.button("Cancel").clicked() {
@@ -292,47 +3650,27 @@ impl eframe::App for GUIApp {
             }
 
             ui.horizontal(|ui| {
-                if ui.button("Add Folders").clicked() {
-                    if let Some(folders) = FileDialog::new().pick_folders() {
-                        self.selected_folders.extend(folders);
-                        self.selected_folders.sort();
-                        self.selected_folders.dedup();
-                    }
+                if ui.button("Add Folders").clicked()
+                    && let Some(folders) = FileDialog::new().pick_folders()
+                {
+                    self.selected_folders.extend(folders);
+                    self.selected_folders.sort();
+                    self.selected_folders.dedup();
                 }
 
-                if ui.button("Add Files").clicked() {
-                    if let Some(files) = FileDialog::new().pick_files() {
-                        self.selected_folders.extend(files);
-                        self.selected_folders.sort();
-                        self.selected_folders.dedup();
-                    }
+                if ui.button("Add Files").clicked()
+                    && let Some(files) = FileDialog::new().pick_files()
+                {
+                    self.selected_folders.extend(files);
+                    self.selected_folders.sort();
+                    self.selected_folders.dedup();
                 }
             });
 
             if !self.selected_folders.is_empty() {
                 ui.add_space(4.0);
 
-                // selected paths
-                let mut to_remove = None;
-                egui::ScrollArea::vertical()
-                    .max_height(240.0)
-                    .show(ui, |ui| {
-                        ui.set_width(ui.available_width());
-                        for (i, path) in self.selected_folders.iter().enumerate() {
-                            if ui.button(path.display().to_string()).clicked() {
-                                to_remove = Some(i);
-                            }
-                        }
-                    });
-                if let Some(i) = to_remove {
-                    self.selected_folders.remove(i);
-                }
-
-                ui.add_space(4.0);
-
-                if ui.button("Clear All").clicked() {
-                    self.selected_folders.clear();
-                }
+                self.path_table.show(ui, &mut self.selected_folders);
             }
 
             ui.separator();
@@ -346,37 +3684,49 @@ impl eframe::App for GUIApp {
                         .then(|| {
                             if let Some(path) =
                                 FileDialog::new().add_filter("JSON", &["json"]).pick_file()
+                                && let Ok(data) = fs::read_to_string(&path)
                             {
-                                if let Ok(data) = fs::read_to_string(&path) {
-                                    if let Ok(template) =
-                                        serde_json::from_str::<BackupTemplate>(&data)
-                                    {
-                                        let mut valid = Vec::new();
-                                        let mut skipped = Vec::new();
+                                if let Ok(template) = serde_json::from_str::<BackupTemplate>(&data)
+                                {
+                                    let mut valid = Vec::new();
+                                    let mut skipped = Vec::new();
 
-                                        for p in template.paths {
-                                            match fix_skip(&p) {
-                                                Some(adjusted) => valid.push(adjusted),
-                                                None => skipped.push(p),
-                                            }
+                                    for p in template.paths {
+                                        match fix_skip(&p) {
+                                            Some(adjusted) => valid.push(adjusted),
+                                            None => skipped.push(p),
                                         }
+                                    }
 
-                                        self.selected_folders = valid;
+                                    self.selected_folders = valid;
+                                    self.backup_recipients = template.recipients;
+                                    self.backup_on_error = template.on_error;
+                                    self.backup_filters = template.filters;
 
-                                        let msg = if skipped.is_empty() {
-                                            "✅ Template loaded".into()
-                                        } else {
-                                            format!(
-                                                "✅ Loaded with {} paths skipped",
-                                                skipped.len()
-                                            )
-                                        };
+                                    let saved = self
+                                        .settings
+                                        .remember_passwords
+                                        .then(|| secrets::load(&path))
+                                        .flatten();
+                                    let from_keyring = saved.is_some();
+                                    if let Some(password) = saved {
+                                        self.backup_password_confirm = password.clone();
+                                        self.backup_password = password;
+                                    }
+                                    self.loaded_template = Some(path);
 
-                                        *self.status.lock().unwrap() = msg;
+                                    let mut msg = if skipped.is_empty() {
+                                        "✅ Template loaded".to_string()
                                     } else {
-                                        *self.status.lock().unwrap() =
-                                            "❌ Bad template format.".into();
+                                        format!("✅ Loaded with {} paths skipped", skipped.len())
+                                    };
+                                    if from_keyring {
+                                        msg.push_str(", password from keyring");
                                     }
+
+                                    *self.status.lock().unwrap() = msg;
+                                } else {
+                                    *self.status.lock().unwrap() = "❌ Bad template format.".into();
                                 }
                             }
                         });
@@ -389,11 +3739,15 @@ impl eframe::App for GUIApp {
                             {
                                 let template = BackupTemplate {
                                     paths: self.selected_folders.clone(),
+                                    recipients: self.backup_recipients.clone(),
+                                    on_error: self.backup_on_error,
+                                    filters: self.backup_filters.clone(),
                                 };
 
                                 if let Ok(json) = serde_json::to_string_pretty(&template) {
                                     if fs::write(&path, json).is_ok() {
                                         *self.status.lock().unwrap() = "✅ Template saved.".into();
+                                        self.loaded_template = Some(path);
                                     } else {
                                         *self.status.lock().unwrap() =
                                             "❌ Failed to write template.".into();
@@ -402,26 +3756,39 @@ impl eframe::App for GUIApp {
                             }
                         });
 
+                    if self.loaded_template.is_some() {
+                        ui.add_sized(btn_size, egui::Button::new("Schedule Backup"))
+                            .on_hover_text("Run the loaded template on a schedule")
+                            .clicked()
+                            .then(|| {
+                                self.schedule_template = self.loaded_template.clone();
+                                self.open_schedules();
+                            });
+                    }
+
                     ui.add_sized(btn_size, egui::Button::new("Edit Template"))
                         .clicked()
                         .then(|| {
                             if let Some(path) =
                                 FileDialog::new().add_filter("JSON", &["json"]).pick_file()
+                                && let Ok(data) = fs::read_to_string(&path)
                             {
-                                if let Ok(data) = fs::read_to_string(&path) {
-                                    if let Ok(template) =
-                                        serde_json::from_str::<BackupTemplate>(&data)
-                                    {
-                                        self.template_paths = template
-                                            .paths
-                                            .into_iter()
-                                            .map(|p| fix_skip(&p).unwrap_or(p))
-                                            .collect();
-                                        self.template_editor = true;
-                                    } else {
-                                        *self.status.lock().unwrap() =
-                                            "❌ Couldn't parse template.".into();
-                                    }
+                                if let Ok(template) = serde_json::from_str::<BackupTemplate>(&data)
+                                {
+                                    self.template_paths = template
+                                        .paths
+                                        .into_iter()
+                                        .map(|p| fix_skip(&p).unwrap_or(p))
+                                        .collect();
+                                    self.template_recipients = template.recipients;
+                                    self.template_on_error = template.on_error;
+                                    self.template_filters = template.filters;
+                                    self.template_filters.max_depth =
+                                        self.template_filters.fixed_depths();
+                                    self.template_editor = true;
+                                } else {
+                                    *self.status.lock().unwrap() =
+                                        "❌ Couldn't parse template.".into();
                                 }
                             }
                         });
@@ -434,65 +3801,244 @@ impl eframe::App for GUIApp {
                         .clicked()
                         .then(|| {
                             let folders = self.selected_folders.clone();
-                            let status = self.status.clone();
 
                             if folders.is_empty() {
-                                *status.lock().unwrap() = "❌ Nothing selected.".into();
+                                *self.status.lock().unwrap() = "❌ Nothing selected.".into();
+                                return;
+                            }
+
+                            if self.backup_password != self.backup_password_confirm
+                                && !self.backup_password.is_empty()
+                                && self.backup_recipients.is_empty()
+                            {
+                                *self.status.lock().unwrap() = "❌ Passwords don't match.".into();
                                 return;
                             }
 
-                            *status.lock().unwrap() = "Packing into .tar".into();
+                            *self.status.lock().unwrap() = "Checking paths…".into();
 
-                            let progress = Progress::default();
-                            self.backup_progress = Some(progress.clone());
+                            let (tx, rx) = mpsc::channel::<Vec<PathIssue>>();
+                            self.validation_rx = Some(rx);
 
                             thread::spawn(move || {
-                                if let Some(out_dir) = FileDialog::new()
-                                    .set_title("Choose backup destination")
-                                    .pick_folder()
-                                {
-                                    match backup_gui(&folders, &out_dir, &progress) {
-                                        Ok(path) => {
-                                            *status.lock().unwrap() =
-                                                format!("✅ Backup created:\n{}", path.display());
-                                        }
-                                        Err(e) => {
-                                            *status.lock().unwrap() =
-                                                format!("❌ Backup failed: {}", e);
-                                        }
-                                    }
-                                } else {
-                                    *status.lock().unwrap() = "❌ Cancelled.".into();
-                                }
+                                let _ = tx.send(scan_paths(&folders));
                             });
                         });
 
+                    if self.watch.is_some() {
+                        ui.add_sized(btn_size, egui::Button::new("Stop Watching"))
+                            .clicked()
+                            .then(|| {
+                                self.watch = None;
+                                *self.status.lock().unwrap() = "Stopped watching.".into();
+                            });
+                    } else {
+                        ui.add_sized(btn_size, egui::Button::new("Watch"))
+                            .on_hover_text(
+                                "Back the selection up again whenever it changes, \
+                                 incremental after the first",
+                            )
+                            .clicked()
+                            .then(|| self.start_watch());
+                    }
+
                     ui.add_sized(btn_size, egui::Button::new("Restore Backup"))
                         .clicked()
-                        .then(|| {
-                            let status = self.status.clone();
+                        .then(|| self.pick_archive(OpenPurpose::Restore));
 
-                            if let Some(zip_file) =
-                                FileDialog::new().add_filter("tar", &["tar"]).pick_file()
-                            {
-                                // show spinner right away
-                                self.restore_opening = true;
-                                *status.lock().unwrap() = "Opening archive…".into();
-
-                                // prepare a one-shot channel
-                                // create a channel of the *new* type
-                                let (tx, rx) = mpsc::channel::<RestoreMsg>();
-                                self.restore_rx = Some(rx);
-
-                                thread::spawn(move || {
-                                    let result: RestoreMsg =
-                                        parse_fingerprint(&zip_file).map(|(entries, map)| {
-                                            (build_human_tree(entries, map), zip_file.clone())
-                                        });
-                                    let _ = tx.send(result);
-                                });
+                    ui.add_sized(btn_size, egui::Button::new("History"))
+                        .clicked()
+                        .then(|| self.load_history());
+
+                    ui.add_sized(btn_size, egui::Button::new("Archive Stats"))
+                        .clicked()
+                        .then(|| self.pick_archive(OpenPurpose::Stats));
+
+                    ui.add_sized(btn_size, egui::Button::new("Verify Backup"))
+                        .clicked()
+                        .then(|| self.pick_archive(OpenPurpose::Verify));
+
+                    ui.add_sized(btn_size, egui::Button::new("Diff Backups"))
+                        .on_hover_text("Pick the older backup, then the newer one")
+                        .clicked()
+                        .then(|| self.pick_archive(OpenPurpose::DiffOlder));
+
+                    ui.add_sized(btn_size, egui::Button::new("Test Restore"))
+                        .on_hover_text("Restore into a temp folder, compare and clean up")
+                        .clicked()
+                        .then(|| self.pick_archive(OpenPurpose::TestRestore));
+
+                    ui.add_sized(btn_size, egui::Button::new("Repository"))
+                        .clicked()
+                        .then(|| self.repo_open = true);
+
+                    ui.add_sized(btn_size, egui::Button::new("Schedules"))
+                        .clicked()
+                        .then(|| self.open_schedules());
+                });
+
+                ui.vertical(|ui| {
+                    ui.label("Backup format");
+                    egui::ComboBox::from_id_salt("backup_format")
+                        .width(80.0)
+                        .selected_text(self.backup_format.label())
+                        .show_ui(ui, |ui| {
+                            for format in ArchiveFormat::ALL {
+                                ui.selectable_value(
+                                    &mut self.backup_format,
+                                    format,
+                                    format.label(),
+                                );
+                            }
+                        });
+
+                    if self.backup_format == ArchiveFormat::TarZst {
+                        ui.label("Level");
+                        ui.add(egui::Slider::new(
+                            &mut self.zstd_level,
+                            archive::ZSTD_LEVELS,
+                        ))
+                        .on_hover_text("Higher is smaller but slower");
+                    }
+
+                    ui.add_enabled_ui(
+                        !self.selected_folders.is_empty() && self.bench_rx.is_none(),
+                        |ui| {
+                            ui.button("Benchmark")
+                                .on_hover_text(
+                                    "Compress a sample of the selected folders with every \
+                                     format and compare size and speed",
+                                )
+                                .clicked()
+                                .then(|| self.start_benchmark());
+                        },
+                    );
+
+                    ui.label("Mode");
+                    let mode_before = self.backup_mode;
+                    egui::ComboBox::from_id_salt("backup_mode")
+                        .width(80.0)
+                        .selected_text(self.backup_mode.label())
+                        .show_ui(ui, |ui| {
+                            for mode in BackupMode::ALL {
+                                ui.selectable_value(&mut self.backup_mode, mode, mode.label());
+                            }
+                        })
+                        .response
+                        .on_hover_text(
+                            "Incremental stores changes since any backup, \
+                             differential changes since a full one",
+                        );
+
+                    if self.backup_mode != BackupMode::Full {
+                        let title = match self.backup_mode {
+                            BackupMode::Differential => "Choose the full backup to compare with",
+                            _ => "Choose the previous backup",
+                        };
+                        let name = self
+                            .backup_parent
+                            .as_ref()
+                            .and_then(|p| p.file_name())
+                            .map(|n| n.to_string_lossy().into_owned());
+                        let changed = self.backup_mode != mode_before;
+                        if ((changed && self.backup_parent.is_none())
+                            || ui
+                                .small_button(name.as_deref().unwrap_or("Choose base…"))
+                                .on_hover_text(title)
+                                .clicked())
+                            && let Some(parent) = FileDialog::new()
+                                .set_title(title)
+                                .add_filter("Backups", &archive::EXTENSIONS)
+                                .pick_file()
+                        {
+                            self.backup_parent = Some(parent);
+                        }
+                    }
+
+                    ui.checkbox(&mut self.backup_in_background, "In background")
+                        .on_hover_text("Keeps running after the window is closed");
+                    ui.checkbox(&mut self.backup_filters.ignore_files, "Use ignore files")
+                        .on_hover_text(IGNORE_HINT);
+                    ui.checkbox(&mut self.backup_filters.skip_hidden, "Skip hidden files")
+                        .on_hover_text(HIDDEN_HINT);
+                    ui.checkbox(&mut self.backup_filters.stay_on_volume, "Stay on one drive")
+                        .on_hover_text(VOLUME_HINT);
+                    ui.label("Symlinks").on_hover_text(SYMLINK_HINT);
+                    egui::ComboBox::from_id_salt("backup_symlinks")
+                        .selected_text(self.backup_filters.symlinks.label())
+                        .show_ui(ui, |ui| {
+                            for policy in SymlinkPolicy::ALL {
+                                ui.selectable_value(
+                                    &mut self.backup_filters.symlinks,
+                                    policy,
+                                    policy.label(),
+                                );
                             }
                         });
+                    ui.label("Unreadable files").on_hover_text(ERROR_HINT);
+                    egui::ComboBox::from_id_salt("backup_on_error")
+                        .selected_text(self.backup_on_error.label())
+                        .show_ui(ui, |ui| {
+                            for policy in ErrorPolicy::ALL {
+                                ui.selectable_value(&mut self.backup_on_error, policy, policy.label());
+                            }
+                        });
+                    ui.label("Changed since");
+                    ui.add(
+                        egui::TextEdit::singleline(&mut self.backup_filters.modified_after)
+                            .hint_text("any time")
+                            .desired_width(70.0),
+                    )
+                    .on_hover_text(CUTOFF_HINT);
+
+                    let filters = &self.backup_filters;
+                    if !filters.excludes.is_empty() {
+                        ui.label(format!("🚫 {} left out", filters.excludes.len()))
+                            .on_hover_text(filters.excludes.join("\n"));
+                    }
+                    if !filters.includes.is_empty() {
+                        ui.label(format!("🔎 only {}", filters.includes.join(";")))
+                            .on_hover_text("Only files matching these are backed up");
+                    }
+                    if !filters.max_depth.is_empty() {
+                        let depths: Vec<String> = filters
+                            .max_depth
+                            .iter()
+                            .map(|(root, depth)| format!("{}: {depth} levels", root.display()))
+                            .collect();
+                        ui.label("📐 depth limits").on_hover_text(depths.join("\n"));
+                    }
+                    if let Some(sizes) = filters.describe_sizes() {
+                        ui.label("📏 size limits")
+                            .on_hover_text(format!("Files {sizes} are skipped"));
+                    }
+
+                    if !self.backup_recipients.is_empty() {
+                        // the template's keys take over from the password
+                        ui.label(format!("🔑 {} keys", self.backup_recipients.len()))
+                            .on_hover_text(self.backup_recipients.join("\n"));
+                        if ui.small_button("Don't encrypt").clicked() {
+                            self.backup_recipients.clear();
+                        }
+                        return;
+                    }
+
+                    ui.label("Password");
+                    ui.add(
+                        egui::TextEdit::singleline(&mut self.backup_password)
+                            .password(true)
+                            .hint_text("optional")
+                            .desired_width(80.0),
+                    )
+                    .on_hover_text("Encrypts the backup with AES-256");
+                    if !self.backup_password.is_empty() {
+                        ui.add(
+                            egui::TextEdit::singleline(&mut self.backup_password_confirm)
+                                .password(true)
+                                .hint_text("confirm")
+                                .desired_width(80.0),
+                        );
+                    }
                 });
             });
 
@@ -504,31 +4050,62 @@ impl eframe::App for GUIApp {
                 ctx.request_repaint_after(std::time::Duration::from_millis(30));
             }
 
-            for opt in [&mut self.backup_progress, &mut self.restore_progress]
-                .into_iter()
-                .enumerate()
-            {
-                let (i, p_opt) = opt;
+            // only a backup can be held and let go again
+            for (p_opt, progress_status, pausable) in [
+                (&mut self.backup_progress, "Backing up...", true),
+                (&mut self.restore_progress, "Restoring...", false),
+                (&mut self.verify_progress, "Verifying...", false),
+                (&mut self.bench_progress, "Comparing formats...", false),
+                (&mut self.test_restore_progress, "Test restoring...", false),
+            ] {
                 if let Some(p) = p_opt {
                     let pct = p.get(); // 0‥101   (101 == done)
                     match p.get() {
                         0..=100 => {
                             ui.add(
                                 egui::ProgressBar::new((p.get() as f32) / 100.0)
-                                    .fill(egui::Color32::from_rgb(80, 160, 240))
+                                    .fill(style::accent(&self.settings))
                                     .desired_height(6.0)
-                                    .animate(true)
+                                    .animate(!p.is_paused())
                                     .desired_width(ui.available_width()),
                             );
                             ui.add_space(1.0);
-                            ui.label(format!("{pct}%"));
+                            match p.files() {
+                                (_, 0) => ui.label(format!("{pct}%")),
+                                (done, total) => {
+                                    ui.label(format!("{pct}% ({done} of {total} files)"))
+                                }
+                            };
+                            if !p.is_paused()
+                                && let (Some(rate), Some(eta)) = (p.rate(), p.eta())
+                            {
+                                ui.label(format!(
+                                    "{}/s — {}",
+                                    format_bytes(rate as u64),
+                                    format_eta(eta)
+                                ));
+                            }
                             ui.add_space(1.0);
-                            let progress_status = if i == 0 {
-                                "Backing up..."
+                            if p.is_cancelled() {
+                                ui.label("Cancelling...");
+                            } else if p.is_paused() {
+                                ui.label("Paused");
                             } else {
-                                "Restoring..."
-                            };
-                            ui.label(progress_status);
+                                ui.label(progress_status);
+                            }
+                            if pausable && !p.is_cancelled() {
+                                ui.horizontal(|ui| {
+                                    if p.is_paused() {
+                                        ui.small_button("Resume").clicked().then(|| p.resume());
+                                    } else {
+                                        ui.small_button("Pause")
+                                            .on_hover_text("Stops before the next file")
+                                            .clicked()
+                                            .then(|| p.pause());
+                                    }
+                                    ui.small_button("Cancel").clicked().then(|| p.cancel());
+                                });
+                            }
                             ctx.request_repaint_after(std::time::Duration::from_millis(4));
                         }
                         _ => {
@@ -537,8 +4114,20 @@ impl eframe::App for GUIApp {
                     }
                 }
             }
+
+            ui.separator();
+            if let Some(watch) = &self.watch {
+                ui.label(format!("👀 watching {} paths", watch.paths))
+                    .on_hover_text(format!("Backups go to {}", watch.out.display()));
+            }
+            ui.label(self.status.lock().unwrap().as_str());
         });
 
         ctx.request_repaint_after(std::time::Duration::from_millis(500));
     }
+
+    fn on_exit(&mut self, _gl: Option<&eframe::glow::Context>) {
+        self.drop_gpg_plain();
+        self.drop_diff_plains();
+    }
 }
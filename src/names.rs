@@ -0,0 +1,149 @@
+use std::ffi::{OsStr, OsString};
+use std::path::{Path, PathBuf};
+
+use crate::helpers::fingerprint_section;
+
+// file names can hold bytes that aren't utf-8 on unix and lone utf-16
+// surrogates on windows, and tabs or line breaks anywhere. the fingerprint
+// and the archive entry names carry them escaped: "%", tabs, line breaks and
+// stray bytes as "%XX", a lone surrogate as "%uXXXX". backups that do this
+// say so in a [Names] section, older ones hold plain text
+const SECTION: &str = "[Names]\nescaped\n";
+
+pub fn format_names() -> &'static str {
+    SECTION
+}
+
+pub fn escaped(txt: &str) -> bool {
+    fingerprint_section(txt, "Names").any(|line| line.trim() == "escaped")
+}
+
+// a path field of a fingerprint written with or without escapes
+pub fn read(text: &str, escaped: bool) -> PathBuf {
+    if escaped {
+        unescape(text)
+    } else {
+        PathBuf::from(text)
+    }
+}
+
+pub fn escape(path: &Path) -> String {
+    escape_os(path.as_os_str())
+}
+
+#[cfg(unix)]
+fn escape_os(name: &OsStr) -> String {
+    use std::os::unix::ffi::OsStrExt;
+
+    let mut out = String::new();
+    for chunk in name.as_bytes().utf8_chunks() {
+        escape_str(chunk.valid(), &mut out);
+        for byte in chunk.invalid() {
+            out.push_str(&format!("%{byte:02X}"));
+        }
+    }
+    out
+}
+
+#[cfg(windows)]
+fn escape_os(name: &OsStr) -> String {
+    use std::os::windows::ffi::OsStrExt;
+
+    let mut out = String::new();
+    for c in char::decode_utf16(name.encode_wide()) {
+        match c {
+            Ok(c) => escape_str(c.encode_utf8(&mut [0; 4]), &mut out),
+            Err(e) => out.push_str(&format!("%u{:04X}", e.unpaired_surrogate())),
+        }
+    }
+    out
+}
+
+fn escape_str(text: &str, out: &mut String) {
+    for c in text.chars() {
+        match c {
+            '%' | '\t' | '\n' | '\r' => out.push_str(&format!("%{:02X}", c as u32)),
+            c => out.push(c),
+        }
+    }
+}
+
+// a surrogate only means something on windows
+#[cfg_attr(unix, allow(dead_code))]
+enum Piece {
+    Char(char),
+    Byte(u8),
+    Surrogate(u16),
+}
+
+// malformed escapes are kept as they are
+fn pieces(text: &str) -> Vec<Piece> {
+    let hex = |digits: &str| {
+        digits
+            .bytes()
+            .all(|b| b.is_ascii_hexdigit())
+            .then(|| u16::from_str_radix(digits, 16).ok())
+            .flatten()
+    };
+    let mut pieces = Vec::new();
+    let mut rest = text;
+    while let Some(c) = rest.chars().next() {
+        if c == '%' {
+            if let Some(unit) = rest.get(2..6).filter(|_| rest[1..].starts_with('u'))
+                && let Some(unit) = hex(unit)
+            {
+                pieces.push(Piece::Surrogate(unit));
+                rest = &rest[6..];
+                continue;
+            }
+            if let Some(byte) = rest.get(1..3).and_then(hex) {
+                pieces.push(Piece::Byte(byte as u8));
+                rest = &rest[3..];
+                continue;
+            }
+        }
+        pieces.push(Piece::Char(c));
+        rest = &rest[c.len_utf8()..];
+    }
+    pieces
+}
+
+// a surrogate has no place in a unix name, it comes back as U+FFFD
+#[cfg(unix)]
+pub fn unescape(text: &str) -> PathBuf {
+    use std::os::unix::ffi::OsStringExt;
+
+    let mut bytes = Vec::new();
+    for piece in pieces(text) {
+        match piece {
+            Piece::Char(c) => bytes.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes()),
+            Piece::Byte(byte) => bytes.push(byte),
+            Piece::Surrogate(_) => bytes.extend_from_slice("\u{FFFD}".as_bytes()),
+        }
+    }
+    PathBuf::from(OsString::from_vec(bytes))
+}
+
+// stray bytes from a unix name are read as utf-8 where they can be
+#[cfg(windows)]
+pub fn unescape(text: &str) -> PathBuf {
+    use std::os::windows::ffi::OsStringExt;
+
+    let mut wide = Vec::new();
+    let mut bytes = Vec::new();
+    for piece in pieces(text) {
+        if let Piece::Byte(byte) = piece {
+            bytes.push(byte);
+            continue;
+        }
+        wide.extend(String::from_utf8_lossy(&bytes).encode_utf16());
+        bytes.clear();
+        match piece {
+            Piece::Char(c) => wide.extend(c.encode_utf16(&mut [0; 2]).iter()),
+            Piece::Surrogate(unit) => wide.push(unit),
+            Piece::Byte(_) => {}
+        }
+    }
+    wide.extend(String::from_utf8_lossy(&bytes).encode_utf16());
+    PathBuf::from(OsString::from_wide(&wide))
+}
@@ -0,0 +1,263 @@
+use std::{
+    collections::{HashMap, HashSet},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    thread,
+};
+
+use eframe::egui;
+use egui_extras::{Column, TableBuilder};
+
+use crate::helpers::{format_bytes, open_in_file_manager, path_size};
+
+enum RowAction {
+    Remove(Vec<PathBuf>),
+    ExcludeChildren(PathBuf),
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Default)]
+enum SortBy {
+    #[default]
+    Path,
+    Type,
+    Size,
+    Status,
+}
+
+struct Row {
+    path: PathBuf,
+    exists: bool,
+    is_dir: bool,
+    size: Option<u64>,
+}
+
+#[derive(Default)]
+pub struct PathTable {
+    selected: HashSet<PathBuf>,
+    // folder sizes are walked in the background and filled in as they finish
+    sizes: Arc<Mutex<HashMap<PathBuf, u64>>>,
+    pending: HashSet<PathBuf>,
+    filter: String,
+    sort_by: SortBy,
+    descending: bool,
+}
+
+impl PathTable {
+    fn size_of(&mut self, path: &Path) -> Option<u64> {
+        if let Some(size) = self.sizes.lock().unwrap().get(path) {
+            return Some(*size);
+        }
+
+        if self.pending.insert(path.to_path_buf()) {
+            let sizes = self.sizes.clone();
+            let path = path.to_path_buf();
+            thread::spawn(move || {
+                let size = path_size(&path);
+                sizes.lock().unwrap().insert(path, size);
+            });
+        }
+        None
+    }
+
+    fn visible_rows(&mut self, paths: &[PathBuf]) -> Vec<Row> {
+        let needle = self.filter.to_lowercase();
+        let mut rows: Vec<Row> = paths
+            .iter()
+            .filter(|p| {
+                needle.is_empty() || p.display().to_string().to_lowercase().contains(&needle)
+            })
+            .map(|p| {
+                let exists = p.exists();
+                Row {
+                    path: p.clone(),
+                    exists,
+                    is_dir: p.is_dir(),
+                    size: if exists { self.size_of(p) } else { None },
+                }
+            })
+            .collect();
+
+        match self.sort_by {
+            SortBy::Path => rows.sort_by(|a, b| a.path.cmp(&b.path)),
+            SortBy::Type => rows.sort_by_key(|r| (!r.exists, !r.is_dir)),
+            SortBy::Size => rows.sort_by_key(|r| r.size),
+            SortBy::Status => rows.sort_by_key(|r| !r.exists),
+        }
+        if self.descending {
+            rows.reverse();
+        }
+        rows
+    }
+
+    fn sort_header(&mut self, ui: &mut egui::Ui, title: &str, column: SortBy) {
+        let arrow = match (self.sort_by == column, self.descending) {
+            (false, _) => "",
+            (true, false) => " ⏶",
+            (true, true) => " ⏷",
+        };
+        if ui
+            .selectable_label(self.sort_by == column, format!("{title}{arrow}"))
+            .clicked()
+        {
+            if self.sort_by == column {
+                self.descending = !self.descending;
+            } else {
+                self.sort_by = column;
+                self.descending = false;
+            }
+        }
+    }
+
+    pub fn show(&mut self, ui: &mut egui::Ui, paths: &mut Vec<PathBuf>) {
+        let mut action = None;
+        let row_height = ui.text_style_height(&egui::TextStyle::Body) + 4.0;
+
+        let rows = self.visible_rows(paths);
+
+        ui.horizontal(|ui| {
+            ui.add(
+                egui::TextEdit::singleline(&mut self.filter)
+                    .hint_text("Filter paths…")
+                    .desired_width(200.0),
+            );
+            if !self.filter.is_empty() {
+                if ui.small_button("✖").clicked() {
+                    self.filter.clear();
+                }
+                ui.label(format!("{} of {}", rows.len(), paths.len()));
+            }
+        });
+
+        TableBuilder::new(ui)
+            .striped(true)
+            .sense(egui::Sense::click())
+            .max_scroll_height(200.0)
+            .column(Column::remainder().clip(true))
+            .column(Column::exact(44.0))
+            .column(Column::exact(62.0))
+            .column(Column::exact(20.0))
+            .header(row_height, |mut header| {
+                header.col(|ui| self.sort_header(ui, "Path", SortBy::Path));
+                header.col(|ui| self.sort_header(ui, "Type", SortBy::Type));
+                header.col(|ui| self.sort_header(ui, "Size", SortBy::Size));
+                header.col(|ui| self.sort_header(ui, "", SortBy::Status));
+            })
+            .body(|mut body| {
+                for row_data in &rows {
+                    let Row {
+                        path,
+                        exists,
+                        is_dir,
+                        size,
+                    } = row_data;
+                    let (exists, is_dir) = (*exists, *is_dir);
+
+                    body.row(row_height, |mut row| {
+                        row.set_selected(self.selected.contains(path));
+
+                        row.col(|ui| {
+                            ui.label(path.display().to_string())
+                                .on_hover_text(path.display().to_string());
+                        });
+                        row.col(|ui| {
+                            ui.label(match (exists, is_dir) {
+                                (false, _) => "?",
+                                (true, true) => "Folder",
+                                (true, false) => "File",
+                            });
+                        });
+                        row.col(|ui| {
+                            ui.label(match (exists, size) {
+                                (false, _) => "-".into(),
+                                (true, Some(size)) => format_bytes(*size),
+                                (true, None) => "…".into(),
+                            });
+                        });
+                        row.col(|ui| {
+                            if exists {
+                                ui.label("✅").on_hover_text("This path exists");
+                            } else {
+                                ui.label("❌").on_hover_text("This path does not exist");
+                            }
+                        });
+
+                        let response = row.response();
+                        if response.clicked() {
+                            // ctrl/cmd-click adds to the selection, plain click replaces it
+                            if response.ctx.input(|i| i.modifiers.command) {
+                                if !self.selected.remove(path) {
+                                    self.selected.insert(path.clone());
+                                }
+                            } else {
+                                self.selected.clear();
+                                self.selected.insert(path.clone());
+                            }
+                        }
+
+                        response.context_menu(|ui| {
+                            // act on the whole selection when right-clicking a selected row
+                            let targets: Vec<PathBuf> = if self.selected.contains(path) {
+                                self.selected.iter().cloned().collect()
+                            } else {
+                                vec![path.clone()]
+                            };
+
+                            if ui.button("Remove").clicked() {
+                                action = Some(RowAction::Remove(targets));
+                                ui.close_menu();
+                            }
+                            if ui
+                                .add_enabled(exists, egui::Button::new("Open in Explorer"))
+                                .clicked()
+                            {
+                                open_in_file_manager(path);
+                                ui.close_menu();
+                            }
+                            if ui
+                                .add_enabled(is_dir, egui::Button::new("Exclude children"))
+                                .on_hover_text("Drop selected paths that are inside this folder")
+                                .clicked()
+                            {
+                                action = Some(RowAction::ExcludeChildren(path.clone()));
+                                ui.close_menu();
+                            }
+                        });
+                    });
+                }
+            });
+
+        match action {
+            Some(RowAction::Remove(targets)) => {
+                paths.retain(|p| !targets.contains(p));
+                for t in &targets {
+                    self.selected.remove(t);
+                }
+            }
+            Some(RowAction::ExcludeChildren(parent)) => {
+                paths.retain(|p| p == &parent || !p.starts_with(&parent));
+                self.selected.retain(|p| paths.contains(p));
+            }
+            None => {}
+        }
+
+        ui.add_space(4.0);
+
+        ui.horizontal(|ui| {
+            if ui
+                .add_enabled(
+                    !self.selected.is_empty(),
+                    egui::Button::new("Remove Selected"),
+                )
+                .clicked()
+            {
+                paths.retain(|p| !self.selected.contains(p));
+                self.selected.clear();
+            }
+
+            if ui.button("Clear All").clicked() {
+                paths.clear();
+                self.selected.clear();
+            }
+        });
+    }
+}
@@ -0,0 +1,55 @@
+use log::{debug, warn};
+
+// the calling thread only gets the cpu and the disk when nothing else wants
+// them. it stays that way, going back up needs privileges we don't have
+#[cfg(target_os = "linux")]
+pub fn background() {
+    // from linux/ioprio.h, the idle class has no levels
+    const IOPRIO_WHO_PROCESS: libc::c_int = 1;
+    const IOPRIO_CLASS_IDLE: libc::c_int = 3;
+    const IOPRIO_CLASS_SHIFT: libc::c_int = 13;
+
+    let tid = unsafe { libc::gettid() };
+    if unsafe { libc::setpriority(libc::PRIO_PROCESS, tid as libc::id_t, 19) } != 0 {
+        warn!("Couldn't lower the cpu priority: {}", last_error());
+    }
+    let io = IOPRIO_CLASS_IDLE << IOPRIO_CLASS_SHIFT;
+    if unsafe { libc::syscall(libc::SYS_ioprio_set, IOPRIO_WHO_PROCESS, tid, io) } != 0 {
+        warn!("Couldn't lower the disk priority: {}", last_error());
+    }
+    debug!("Thread {tid} runs in the background");
+}
+
+// lowers cpu and disk priority together
+#[cfg(target_os = "macos")]
+pub fn background() {
+    if unsafe { libc::setpriority(libc::PRIO_DARWIN_THREAD, 0, libc::PRIO_DARWIN_BG) } != 0 {
+        warn!("Couldn't switch to background priority: {}", last_error());
+        return;
+    }
+    debug!("Thread runs in the background");
+}
+
+// lowers cpu, disk and memory priority together
+#[cfg(windows)]
+pub fn background() {
+    use windows_sys::Win32::System::Threading::{
+        GetCurrentThread, SetThreadPriority, THREAD_MODE_BACKGROUND_BEGIN,
+    };
+
+    if unsafe { SetThreadPriority(GetCurrentThread(), THREAD_MODE_BACKGROUND_BEGIN) } == 0 {
+        warn!("Couldn't switch to background mode: {}", last_error());
+        return;
+    }
+    debug!("Thread runs in background mode");
+}
+
+#[cfg(not(any(windows, target_os = "linux", target_os = "macos")))]
+pub fn background() {
+    debug!("Background priority isn't supported here");
+}
+
+#[cfg(any(windows, target_os = "linux", target_os = "macos"))]
+fn last_error() -> std::io::Error {
+    std::io::Error::last_os_error()
+}
@@ -0,0 +1,427 @@
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    thread,
+    time::Duration,
+};
+
+use aes_gcm::aead::{OsRng, rand_core::RngCore};
+use log::{debug, info, warn};
+use serde::{Deserialize, Serialize};
+
+use crate::helpers::Progress;
+use crate::signing;
+
+// a finished backup can get a second copy in a folder somewhere else: a
+// network share, a NAS or a folder a cloud drive client syncs. the copy is
+// written as "<name>.part" a chunk at a time and only takes the archive's
+// name once what landed there reads back the same as the local archive.
+// a copy that didn't finish stays queued in uploads.json, the next backup
+// continues it from the chunks already there
+const CHUNK: u64 = 8 * 1024 * 1024;
+// tries per chunk, waiting 1, 2, 4 and 8s in between
+const ATTEMPTS: u32 = 5;
+const SAMPLE: u64 = 1024 * 1024;
+// windows read back at random offsets, besides the first and the last
+const RANDOM_SAMPLES: usize = 8;
+
+// a copy that still has to be made
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq, Debug)]
+pub struct Pending {
+    pub archive: PathBuf,
+    pub remote: PathBuf,
+}
+
+fn part_path(target: &Path) -> PathBuf {
+    let mut path = target.as_os_str().to_owned();
+    path.push(".part");
+    PathBuf::from(path)
+}
+
+fn queue_path() -> Option<PathBuf> {
+    Some(dirs::config_dir()?.join("Konserve").join("uploads.json"))
+}
+
+fn load_queue(path: &Path) -> Result<Vec<Pending>, String> {
+    match fs::read_to_string(path) {
+        Ok(data) => serde_json::from_str(&data).map_err(|e| format!("{}: {e}", path.display())),
+        Err(_) => Ok(Vec::new()),
+    }
+}
+
+// locked and renamed into place like schedules.json, the app and the daemon
+// may both finish a backup at once
+fn change_queue(path: &Path, f: impl FnOnce(&mut Vec<Pending>)) -> Result<Vec<Pending>, String> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+    }
+    let lock_path = path.with_extension("lock");
+    let lock = File::create(&lock_path).map_err(|e| format!("{}: {e}", lock_path.display()))?;
+    lock.lock()
+        .map_err(|e| format!("{}: {e}", lock_path.display()))?;
+    let mut queue = load_queue(path)?;
+    f(&mut queue);
+    let json = serde_json::to_string_pretty(&queue).map_err(|e| e.to_string())?;
+    let tmp = path.with_extension(format!("json.{}.tmp", std::process::id()));
+    fs::write(&tmp, json)
+        .and_then(|()| fs::rename(&tmp, path))
+        .map_err(|e| {
+            let _ = fs::remove_file(&tmp);
+            format!("{}: {e}", path.display())
+        })?;
+    Ok(queue)
+}
+
+// copies earlier runs left unfinished go first, then `archive`. whatever
+// fails stays queued for the next backup
+pub fn send(archive: &Path, remote: &Path, progress: &Progress) -> Result<PathBuf, String> {
+    match queue_path() {
+        Some(queue) => send_queued(&queue, archive, remote, progress),
+        None => upload(archive, remote, progress),
+    }
+}
+
+fn send_queued(
+    queue: &Path,
+    archive: &Path,
+    remote: &Path,
+    progress: &Progress,
+) -> Result<PathBuf, String> {
+    let this = Pending {
+        archive: archive.to_path_buf(),
+        remote: remote.to_path_buf(),
+    };
+    // without the queue the copy is still made, it just can't be continued
+    let pending = change_queue(queue, |pending| {
+        if !pending.contains(&this) {
+            pending.push(this.clone());
+        }
+    })
+    .unwrap_or_else(|e| {
+        warn!("Couldn't queue the copy: {e}");
+        vec![this.clone()]
+    });
+    let forget = |done: &Pending| {
+        if let Err(e) = change_queue(queue, |pending| pending.retain(|p| p != done)) {
+            warn!("Couldn't update the copy queue: {e}");
+        }
+    };
+
+    for earlier in pending.iter().filter(|p| **p != this) {
+        if progress.is_cancelled() {
+            break;
+        }
+        if !earlier.archive.exists() {
+            info!(
+                "Dropping the copy of {}, it's gone",
+                earlier.archive.display()
+            );
+            forget(earlier);
+            continue;
+        }
+        match upload(&earlier.archive, &earlier.remote, progress) {
+            Ok(_) => forget(earlier),
+            Err(e) => warn!("Copy of {} stays queued: {e}", earlier.archive.display()),
+        }
+    }
+
+    let uploaded = upload(archive, remote, progress);
+    if uploaded.is_ok() {
+        forget(&this);
+    }
+    uploaded
+}
+
+// a share that drops out for a moment shouldn't end the copy. errors that
+// waiting can't fix end it right away
+pub fn retrying(what: &Path, mut attempt: impl FnMut() -> io::Result<()>) -> Result<(), String> {
+    use io::ErrorKind::*;
+
+    let mut tries = 1;
+    loop {
+        match attempt() {
+            Ok(()) => return Ok(()),
+            Err(e)
+                if tries < ATTEMPTS
+                    && !matches!(
+                        e.kind(),
+                        AlreadyExists
+                            | NotADirectory
+                            | IsADirectory
+                            | InvalidInput
+                            | InvalidFilename
+                            | ReadOnlyFilesystem
+                            | StorageFull
+                            | FileTooLarge
+                    ) =>
+            {
+                let wait = Duration::from_secs(1 << (tries - 1));
+                warn!(
+                    "{}: {e}, trying again in {}s",
+                    what.display(),
+                    wait.as_secs()
+                );
+                thread::sleep(wait);
+                tries += 1;
+            }
+            Err(e) => return Err(format!("{}: {e}", what.display())),
+        }
+    }
+}
+
+// opened again for every chunk, a handle a dropped connection broke isn't reused
+fn write_at(part: &Path, offset: u64, data: &[u8]) -> io::Result<()> {
+    let mut file = OpenOptions::new().write(true).open(part)?;
+    file.seek(SeekFrom::Start(offset))?;
+    file.write_all(data)?;
+    file.sync_data()
+}
+
+// how much of an earlier "<name>.part" can stay: its whole chunks, as long
+// as the last of them still matches the archive
+fn resume_point(local: &mut File, part: &Path, len: u64) -> u64 {
+    let have = fs::metadata(part).map(|m| m.len()).unwrap_or(0);
+    let kept = have.min(len) / CHUNK * CHUNK;
+    if kept == 0 {
+        return 0;
+    }
+    let last = kept - CHUNK;
+    let matches = File::open(part)
+        .and_then(|mut file| Ok(read_at(&mut file, last, CHUNK)? == read_at(local, last, CHUNK)?))
+        .unwrap_or(false);
+    if matches { kept } else { 0 }
+}
+
+// copies the archive and its signature into `remote`, where it ended up
+pub fn upload(archive: &Path, remote: &Path, progress: &Progress) -> Result<PathBuf, String> {
+    let name = archive
+        .file_name()
+        .ok_or_else(|| format!("{} has no file name", archive.display()))?;
+    retrying(remote, || fs::create_dir_all(remote))?;
+    let target = remote.join(name);
+    let part = part_path(&target);
+
+    let mut local = File::open(archive).map_err(|e| format!("{}: {e}", archive.display()))?;
+    let len = local
+        .metadata()
+        .map_err(|e| format!("{}: {e}", archive.display()))?
+        .len();
+    let mut offset = resume_point(&mut local, &part, len);
+    if offset > 0 {
+        info!("Continuing {} from byte {offset}", part.display());
+    } else {
+        info!("Copying {} to {}", archive.display(), remote.display());
+    }
+    retrying(&part, || {
+        OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(&part)?
+            .set_len(offset)
+    })?;
+
+    progress.set_total_bytes(len);
+    progress.add_bytes(offset);
+    progress.file_started(&target);
+    while offset < len {
+        progress.wait_if_paused();
+        if progress.is_cancelled() {
+            return Err("Cancelled, the copy continues with the next backup.".into());
+        }
+        let chunk = read_at(&mut local, offset, CHUNK)
+            .map_err(|e| format!("{}: {e}", archive.display()))?;
+        retrying(&part, || write_at(&part, offset, &chunk))?;
+        offset += chunk.len() as u64;
+        progress.add_bytes(chunk.len() as u64);
+    }
+
+    // a copy that doesn't match starts over next time
+    if let Err(e) = verify(archive, &part) {
+        let _ = fs::remove_file(&part);
+        return Err(e);
+    }
+    retrying(&target, || fs::rename(&part, &target))?;
+
+    let sig = signing::sig_path(archive);
+    if sig.exists() {
+        let remote_sig = signing::sig_path(&target);
+        retrying(&remote_sig, || fs::copy(&sig, &remote_sig).map(drop))?;
+    }
+    info!("Copied and checked {}", target.display());
+    Ok(target)
+}
+
+// the same length and the same bytes in the sampled windows, read back from
+// `remote` rather than trusted from the write
+pub fn verify(local: &Path, remote: &Path) -> Result<(), String> {
+    let open = |path: &Path| File::open(path).map_err(|e| format!("{}: {e}", path.display()));
+    let (mut ours, mut theirs) = (open(local)?, open(remote)?);
+    let len = ours.metadata().map_err(|e| e.to_string())?.len();
+    let remote_len = theirs.metadata().map_err(|e| e.to_string())?.len();
+    if remote_len != len {
+        return Err(format!(
+            "{} is {remote_len} bytes, the backup is {len}.",
+            remote.display()
+        ));
+    }
+
+    for offset in sample_offsets(len) {
+        let expected =
+            read_at(&mut ours, offset, SAMPLE).map_err(|e| format!("{}: {e}", local.display()))?;
+        let found = read_at(&mut theirs, offset, SAMPLE)
+            .map_err(|e| format!("{}: {e}", remote.display()))?;
+        if found != expected {
+            return Err(format!(
+                "{} doesn't match the backup near byte {offset}.",
+                remote.display()
+            ));
+        }
+    }
+    debug!("verify: {} matches {}", remote.display(), local.display());
+    Ok(())
+}
+
+fn sample_offsets(len: u64) -> Vec<u64> {
+    let last = len.saturating_sub(SAMPLE);
+    let mut offsets = vec![0, last];
+    if last > 0 {
+        offsets.extend((0..RANDOM_SAMPLES).map(|_| OsRng.next_u64() % last));
+    }
+    offsets.sort_unstable();
+    offsets.dedup();
+    offsets
+}
+
+fn read_at(file: &mut File, offset: u64, len: u64) -> io::Result<Vec<u8>> {
+    let mut data = Vec::new();
+    file.seek(SeekFrom::Start(offset))?;
+    Read::by_ref(file).take(len).read_to_end(&mut data)?;
+    Ok(data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::helpers::test_dir;
+
+    fn archive(base: &Path, len: usize) -> PathBuf {
+        let path = base.join("backup.tar");
+        fs::write(&path, (0..len).map(|i| (i % 251) as u8).collect::<Vec<_>>()).unwrap();
+        path
+    }
+
+    #[test]
+    fn uploads_are_checked_before_they_get_their_name() {
+        let base = test_dir("remote_upload");
+        let local = archive(&base, 3 * SAMPLE as usize + 10);
+        fs::write(signing::sig_path(&local), b"sig").unwrap();
+        let remote = base.join("nas/backups");
+
+        let copy = upload(&local, &remote, &Progress::default()).unwrap();
+        assert_eq!(copy, remote.join("backup.tar"));
+        assert_eq!(fs::read(&copy).unwrap(), fs::read(&local).unwrap());
+        assert_eq!(fs::read(signing::sig_path(&copy)).unwrap(), b"sig");
+        assert!(!part_path(&copy).exists());
+        let _ = fs::remove_dir_all(&base);
+    }
+
+    #[test]
+    fn verify_catches_short_and_changed_copies() {
+        let base = test_dir("remote_verify");
+        let local = archive(&base, 2 * SAMPLE as usize);
+        let copy = base.join("copy.tar");
+        let data = fs::read(&local).unwrap();
+
+        fs::write(&copy, &data).unwrap();
+        assert!(verify(&local, &copy).is_ok());
+
+        fs::write(&copy, &data[..data.len() - 1]).unwrap();
+        assert!(verify(&local, &copy).unwrap_err().contains("bytes"));
+
+        // the last byte is in a window every check reads
+        let mut changed = data.clone();
+        *changed.last_mut().unwrap() ^= 1;
+        fs::write(&copy, &changed).unwrap();
+        assert!(verify(&local, &copy).unwrap_err().contains("doesn't match"));
+
+        // an empty archive has nothing to sample but still has to match
+        fs::write(&local, b"").unwrap();
+        fs::write(&copy, b"").unwrap();
+        assert!(verify(&local, &copy).is_ok());
+        let _ = fs::remove_dir_all(&base);
+    }
+
+    #[test]
+    fn interrupted_copies_continue_from_whole_chunks() {
+        let base = test_dir("remote_resume");
+        let local = archive(&base, (2 * CHUNK + CHUNK / 2) as usize);
+        let data = fs::read(&local).unwrap();
+        let remote = base.join("nas");
+        fs::create_dir_all(&remote).unwrap();
+        let part = part_path(&remote.join("backup.tar"));
+        let mut from = File::open(&local).unwrap();
+
+        // two chunks and a bit of the third made it before the drop
+        fs::write(&part, &data[..2 * CHUNK as usize + 100]).unwrap();
+        assert_eq!(resume_point(&mut from, &part, data.len() as u64), 2 * CHUNK);
+
+        // a last chunk that doesn't match means the part is something else
+        let mut other = data[..2 * CHUNK as usize].to_vec();
+        other[CHUNK as usize + 5] ^= 1;
+        fs::write(&part, &other).unwrap();
+        assert_eq!(resume_point(&mut from, &part, data.len() as u64), 0);
+
+        fs::write(&part, &data[..CHUNK as usize - 1]).unwrap();
+        assert_eq!(resume_point(&mut from, &part, data.len() as u64), 0);
+
+        fs::write(&part, &data[..2 * CHUNK as usize + 100]).unwrap();
+        let copy = upload(&local, &remote, &Progress::default()).unwrap();
+        assert_eq!(fs::read(&copy).unwrap(), data);
+        assert!(!part.exists());
+        let _ = fs::remove_dir_all(&base);
+    }
+
+    #[test]
+    fn failed_copies_wait_for_the_next_backup() {
+        let base = test_dir("remote_queue");
+        let queue = base.join("uploads.json");
+        let first = archive(&base, 1000);
+        // a file where the folder should be
+        let remote = base.join("nas");
+        fs::write(&remote, b"").unwrap();
+
+        assert!(send_queued(&queue, &first, &remote, &Progress::default()).is_err());
+        let pending = load_queue(&queue).unwrap();
+        assert_eq!(pending.len(), 1);
+        assert_eq!(pending[0].archive, first);
+
+        // the share is back, the next backup brings the earlier one along
+        fs::remove_file(&remote).unwrap();
+        let second = base.join("second.tar");
+        fs::write(&second, b"second").unwrap();
+        let copy = send_queued(&queue, &second, &remote, &Progress::default()).unwrap();
+        assert_eq!(copy, remote.join("second.tar"));
+        assert_eq!(
+            fs::read(remote.join("backup.tar")).unwrap(),
+            fs::read(&first).unwrap()
+        );
+        assert!(load_queue(&queue).unwrap().is_empty());
+        let _ = fs::remove_dir_all(&base);
+    }
+
+    #[test]
+    fn chunks_are_tried_again() {
+        let mut failures = 1;
+        let written = retrying(Path::new("part"), || {
+            if failures > 0 {
+                failures -= 1;
+                return Err(io::Error::new(io::ErrorKind::TimedOut, "gone"));
+            }
+            Ok(())
+        });
+        assert!(written.is_ok());
+        assert_eq!(failures, 0);
+    }
+}
@@ -0,0 +1,804 @@
+use std::{
+    collections::HashSet,
+    fs::{self, File, OpenOptions},
+    io::{self, BufRead, BufReader, Write},
+    path::{Path, PathBuf},
+    time::{Duration, Instant, UNIX_EPOCH},
+};
+
+use chrono::Local;
+use fastcdc::v2020::StreamCDC;
+use log::{debug, info, warn};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+use walkdir::WalkDir;
+
+use crate::archive::{self, DEFAULT_ZSTD_LEVEL};
+use crate::exclude::{Excludes, Filters, SymlinkPolicy};
+use crate::helpers::{Progress, adjust_path, format_bytes};
+use crate::remote::retrying;
+use crate::report::{ErrorLog, Problem};
+use crate::restore::{finish_dirs, finish_file, make_link, make_writable};
+
+// a repository is a folder of content-defined chunks shared by every snapshot:
+//   config            marks the folder as a repository
+//   index             hash of every stored chunk, one per line
+//   chunks/ab/abcd…   zstd compressed chunk, named by the sha256 of its data
+//   snapshots/*.json  what was backed up and which chunks make up each file
+// chunk borders follow the content, so an edit in the middle of a big file
+// only produces new chunks around the edit and the rest is stored once
+const CONFIG: &str = "konserve-repo-v1";
+const MIN_CHUNK: usize = 256 * 1024;
+const AVG_CHUNK: usize = 1024 * 1024;
+const MAX_CHUNK: usize = 4 * 1024 * 1024;
+
+#[derive(Serialize, Deserialize)]
+pub struct Snapshot {
+    pub id: String,
+    pub time: String,
+    pub sources: Vec<PathBuf>,
+    pub entries: Vec<SnapshotEntry>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct SnapshotEntry {
+    pub path: PathBuf,
+    pub dir: bool,
+    pub size: u64,
+    pub mtime: u64,
+    pub mode: Option<u32>,
+    // sha256 of each chunk in file order, empty for folders
+    pub chunks: Vec<String>,
+    // where a stored symlink points
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub link: Option<PathBuf>,
+}
+
+impl Snapshot {
+    pub fn files(&self) -> usize {
+        self.entries.iter().filter(|e| !e.dir).count()
+    }
+
+    pub fn size(&self) -> u64 {
+        self.entries.iter().map(|e| e.size).sum()
+    }
+}
+
+pub struct SnapshotSummary {
+    pub id: String,
+    pub files: u32,
+    // data that had to be written as new chunks
+    pub new_bytes: u64,
+    // data already in the repository
+    pub reused_bytes: u64,
+    pub skipped: u32,
+    // couldn't be read and were left out
+    pub failed: ErrorLog,
+    pub elapsed: Duration,
+}
+
+impl SnapshotSummary {
+    pub fn report(&self) -> String {
+        let mut report = format!(
+            "{}: {} files, {} new, {} already stored, in {:.1}s",
+            self.id,
+            self.files,
+            format_bytes(self.new_bytes),
+            format_bytes(self.reused_bytes),
+            self.elapsed.as_secs_f64()
+        );
+        if self.skipped > 0 {
+            report.push_str(&format!(
+                "\n{} special files and links skipped",
+                self.skipped
+            ));
+        }
+        if !self.failed.is_empty() {
+            report.push_str(&format!(
+                "\n{} files couldn't be read and were left out",
+                self.failed.len()
+            ));
+        }
+        report
+    }
+}
+
+pub struct PushSummary {
+    pub snapshots: u32,
+    // chunks the other repository didn't have, and their size as stored
+    pub sent: u32,
+    pub sent_bytes: u64,
+    // chunks it already had
+    pub present: u32,
+    pub elapsed: Duration,
+}
+
+impl PushSummary {
+    pub fn report(&self) -> String {
+        if self.snapshots == 0 {
+            return "Nothing to push, the other repository has every snapshot.".into();
+        }
+        format!(
+            "{} snapshots pushed: {} new chunks ({}), {} already there, in {:.1}s",
+            self.snapshots,
+            self.sent,
+            format_bytes(self.sent_bytes),
+            self.present,
+            self.elapsed.as_secs_f64()
+        )
+    }
+}
+
+pub struct Repo {
+    root: PathBuf,
+    index: HashSet<String>,
+}
+
+fn mtime_secs(meta: &fs::Metadata) -> u64 {
+    meta.modified()
+        .ok()
+        .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+        .map_or(0, |d| d.as_secs())
+}
+
+fn hex_sha256(data: &[u8]) -> String {
+    hex::encode(Sha256::digest(data))
+}
+
+// chunk names end up in paths, anything else could point outside chunks/
+fn is_hash(name: &str) -> bool {
+    name.len() == 64 && name.bytes().all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f'))
+}
+
+fn load_snapshot(path: &Path) -> Result<Snapshot, String> {
+    let data = fs::read_to_string(path).map_err(|e| format!("{}: {e}", path.display()))?;
+    let snapshot: Snapshot =
+        serde_json::from_str(&data).map_err(|e| format!("{}: {e}", path.display()))?;
+    if let Some(bad) = snapshot
+        .entries
+        .iter()
+        .flat_map(|e| &e.chunks)
+        .find(|hash| !is_hash(hash))
+    {
+        return Err(format!("{}: bad chunk name {bad:?}", path.display()));
+    }
+    Ok(snapshot)
+}
+
+impl Repo {
+    // an empty or missing folder becomes a repository, an existing one is opened
+    pub fn init(root: &Path) -> Result<Self, String> {
+        if root.join("config").exists() {
+            return Self::open(root);
+        }
+        if fs::read_dir(root).is_ok_and(|mut d| d.next().is_some()) {
+            return Err(format!(
+                "{} isn't empty and isn't a repository.",
+                root.display()
+            ));
+        }
+
+        info!("Creating repository at {}", root.display());
+        fs::create_dir_all(root.join("chunks")).map_err(|e| e.to_string())?;
+        fs::create_dir_all(root.join("snapshots")).map_err(|e| e.to_string())?;
+        fs::write(root.join("index"), "").map_err(|e| e.to_string())?;
+        fs::write(root.join("config"), format!("{CONFIG}\n")).map_err(|e| e.to_string())?;
+        Self::open(root)
+    }
+
+    pub fn open(root: &Path) -> Result<Self, String> {
+        let config = fs::read_to_string(root.join("config"))
+            .map_err(|_| format!("{} is not a repository.", root.display()))?;
+        if config.trim() != CONFIG {
+            return Err(format!("Unknown repository version: {}", config.trim()));
+        }
+
+        let mut repo = Self {
+            root: root.to_path_buf(),
+            index: HashSet::new(),
+        };
+        match File::open(root.join("index")) {
+            Ok(file) => {
+                for line in BufReader::new(file).lines() {
+                    let line = line.map_err(|e| e.to_string())?;
+                    if !line.trim().is_empty() {
+                        repo.index.insert(line.trim().to_string());
+                    }
+                }
+            }
+            Err(_) => repo.rebuild_index()?,
+        }
+        debug!("Repository {}: {} chunks", root.display(), repo.index.len());
+        Ok(repo)
+    }
+
+    // the index only saves a directory walk, it can always be recreated
+    fn rebuild_index(&mut self) -> Result<(), String> {
+        info!("Rebuilding index of {}", self.root.display());
+        self.index = WalkDir::new(self.root.join("chunks"))
+            .into_iter()
+            .filter_map(Result::ok)
+            .filter(|e| e.file_type().is_file())
+            .map(|e| e.file_name().to_string_lossy().into_owned())
+            .filter(|name| is_hash(name))
+            .collect();
+        let listing: String = self.index.iter().map(|h| format!("{h}\n")).collect();
+        fs::write(self.root.join("index"), listing).map_err(|e| e.to_string())
+    }
+
+    fn chunk_path(&self, hash: &str) -> PathBuf {
+        self.root.join("chunks").join(&hash[..2]).join(hash)
+    }
+
+    // newest first
+    pub fn snapshots(&self) -> Result<Vec<Snapshot>, String> {
+        let mut snapshots = Vec::new();
+        for entry in fs::read_dir(self.root.join("snapshots")).map_err(|e| e.to_string())? {
+            let path = entry.map_err(|e| e.to_string())?.path();
+            if path.extension().is_none_or(|e| e != "json") {
+                continue;
+            }
+            snapshots.push(load_snapshot(&path)?);
+        }
+        snapshots.sort_by(|a, b| b.id.cmp(&a.id));
+        Ok(snapshots)
+    }
+
+    // true when the chunk wasn't stored yet
+    fn store_chunk(&mut self, hash: &str, data: &[u8], index: &mut File) -> Result<bool, String> {
+        if self.index.contains(hash) {
+            return Ok(false);
+        }
+
+        let path = self.chunk_path(hash);
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+        }
+        let packed = zstd::encode_all(data, DEFAULT_ZSTD_LEVEL).map_err(|e| e.to_string())?;
+        // renamed into place so a crash never leaves half a chunk under its name
+        let tmp = path.with_extension("tmp");
+        fs::write(&tmp, packed).map_err(|e| e.to_string())?;
+        fs::rename(&tmp, &path).map_err(|e| e.to_string())?;
+
+        writeln!(index, "{hash}").map_err(|e| e.to_string())?;
+        self.index.insert(hash.to_string());
+        Ok(true)
+    }
+
+    fn read_chunk(&self, hash: &str) -> Result<Vec<u8>, String> {
+        let path = self.chunk_path(hash);
+        let packed = fs::read(&path).map_err(|e| format!("chunk {hash}: {e}"))?;
+        let data = zstd::decode_all(packed.as_slice()).map_err(|e| format!("chunk {hash}: {e}"))?;
+        if hex_sha256(&data) != hash {
+            return Err(format!("chunk {hash} is damaged"));
+        }
+        Ok(data)
+    }
+
+    // a chunk another repository sent, still compressed. read back before
+    // the index lists it, so a bad write is never taken for a stored chunk
+    fn receive_chunk(&mut self, hash: &str, packed: &[u8], index: &mut File) -> Result<(), String> {
+        let path = self.chunk_path(hash);
+        let tmp = path.with_extension("tmp");
+        retrying(&path, || {
+            if let Some(dir) = path.parent() {
+                fs::create_dir_all(dir)?;
+            }
+            fs::write(&tmp, packed)?;
+            fs::rename(&tmp, &path)
+        })?;
+        if let Err(e) = self.read_chunk(hash) {
+            let _ = fs::remove_file(&path);
+            return Err(e);
+        }
+        writeln!(index, "{hash}").map_err(|e| e.to_string())?;
+        self.index.insert(hash.to_string());
+        Ok(())
+    }
+
+    // copies the snapshots the repository at `to` doesn't have yet, sending
+    // only chunks it doesn't hold already. a snapshot goes last, once every
+    // chunk it needs is there, so a push that stops halfway leaves nothing
+    // pointing at missing chunks and the next one skips what was sent
+    pub fn push(&self, to: &Path, progress: &Progress) -> Result<PushSummary, String> {
+        let started = Instant::now();
+        if fs::canonicalize(to).ok() == fs::canonicalize(&self.root).ok() {
+            return Err("Pick another folder than this repository.".into());
+        }
+        let mut remote = Repo::init(to)?;
+        info!("Pushing {} to {}", self.root.display(), to.display());
+
+        let theirs: HashSet<String> = remote.snapshots()?.into_iter().map(|s| s.id).collect();
+        let mut missing: Vec<Snapshot> = self
+            .snapshots()?
+            .into_iter()
+            .filter(|s| !theirs.contains(&s.id))
+            .collect();
+        // oldest first, so the newest is the one missing if this stops
+        missing.reverse();
+
+        let mut summary = PushSummary {
+            snapshots: 0,
+            sent: 0,
+            sent_bytes: 0,
+            present: 0,
+            elapsed: Duration::ZERO,
+        };
+        let mut seen = HashSet::new();
+        let mut needed = Vec::new();
+        for hash in missing
+            .iter()
+            .flat_map(|s| &s.entries)
+            .flat_map(|e| &e.chunks)
+        {
+            if !seen.insert(hash) {
+                continue;
+            }
+            if remote.index.contains(hash) {
+                summary.present += 1;
+            } else {
+                needed.push(hash);
+            }
+        }
+        let total: u64 = needed
+            .iter()
+            .filter_map(|hash| fs::metadata(self.chunk_path(hash)).ok())
+            .map(|meta| meta.len())
+            .sum();
+        debug!(
+            "push: {} snapshots, {} of {} chunks to send",
+            missing.len(),
+            needed.len(),
+            seen.len()
+        );
+        progress.set_total_bytes(total.max(1));
+
+        let mut index = OpenOptions::new()
+            .append(true)
+            .create(true)
+            .open(to.join("index"))
+            .map_err(|e| e.to_string())?;
+        for hash in needed {
+            progress.wait_if_paused();
+            if progress.is_cancelled() {
+                return Err("Cancelled, pushing again continues where this stopped.".into());
+            }
+            let path = self.chunk_path(hash);
+            let packed = fs::read(&path).map_err(|e| format!("chunk {hash}: {e}"))?;
+            remote.receive_chunk(hash, &packed, &mut index)?;
+            summary.sent += 1;
+            summary.sent_bytes += packed.len() as u64;
+            progress.add_bytes(packed.len() as u64);
+        }
+        index.sync_all().map_err(|e| e.to_string())?;
+
+        for snapshot in &missing {
+            let name = format!("{}.json", snapshot.id);
+            let from = self.root.join("snapshots").join(&name);
+            let target = to.join("snapshots").join(&name);
+            let tmp = target.with_extension("tmp");
+            retrying(&target, || {
+                fs::copy(&from, &tmp)?;
+                fs::rename(&tmp, &target)
+            })?;
+            summary.snapshots += 1;
+        }
+
+        summary.elapsed = started.elapsed();
+        info!("Push to {}: {}", to.display(), summary.report());
+        progress.done();
+        Ok(summary)
+    }
+
+    fn write_chunks(&self, chunks: &[String], to: &Path) -> Result<(), String> {
+        let mut out = File::create(to).map_err(|e| format!("{}: {e}", to.display()))?;
+        for hash in chunks {
+            io::copy(&mut self.read_chunk(hash)?.as_slice(), &mut out)
+                .map_err(|e| format!("{}: {e}", to.display()))?;
+        }
+        Ok(())
+    }
+
+    pub fn create_snapshot(
+        &mut self,
+        sources: &[PathBuf],
+        filters: &Filters,
+        progress: &Progress,
+    ) -> Result<SnapshotSummary, String> {
+        let started = Instant::now();
+        let id = format!(
+            "{}-{}",
+            Local::now().format("%Y%m%d-%H%M%S"),
+            &Uuid::new_v4().simple().to_string()[..8]
+        );
+        info!("Creating snapshot {id} in {}", self.root.display());
+
+        let mut index = OpenOptions::new()
+            .append(true)
+            .create(true)
+            .open(self.root.join("index"))
+            .map_err(|e| e.to_string())?;
+
+        // walked like a backup, so the same files are left out and links are
+        // followed only when the filters say so
+        let excludes = Excludes::new(filters)?;
+        let found: Vec<_> = sources
+            .iter()
+            .flat_map(|p| excludes.walk(p))
+            .filter_map(|e| e.metadata().ok().map(|meta| (e, meta)))
+            .collect();
+        let total_bytes: u64 = found
+            .iter()
+            .filter(|(_, m)| m.is_file())
+            .map(|(_, m)| m.len())
+            .sum::<u64>()
+            .max(1);
+        // with include patterns only the folders leading to a file go in
+        let holding_files: HashSet<&Path> = found
+            .iter()
+            .filter(|(_, m)| excludes.has_includes() && m.is_file())
+            .flat_map(|(e, _)| e.path().ancestors().skip(1))
+            .collect();
+
+        let mut summary = SnapshotSummary {
+            id: id.clone(),
+            files: 0,
+            new_bytes: 0,
+            reused_bytes: 0,
+            skipped: 0,
+            failed: ErrorLog::new(),
+            elapsed: Duration::ZERO,
+        };
+        let mut entries = Vec::new();
+        let mut done_bytes = 0u64;
+
+        for (entry, meta) in &found {
+            let mut record = SnapshotEntry {
+                path: entry.path().to_path_buf(),
+                dir: meta.is_dir(),
+                size: 0,
+                mtime: mtime_secs(meta),
+                mode: Some(archive::file_mode(meta)),
+                chunks: Vec::new(),
+                link: None,
+            };
+
+            if meta.is_file() {
+                debug!("Chunking {}", entry.path().display());
+                // a file that can't be read is left out like in a backup,
+                // chunks it already stored are simply not used
+                let file = match File::open(entry.path()) {
+                    Ok(file) => file,
+                    Err(e) => {
+                        let error = format!("{}: {e}", entry.path().display());
+                        warn!("Leaving out {error}");
+                        summary
+                            .failed
+                            .push(Problem::io(entry.path(), "read", &e, error));
+                        done_bytes += meta.len();
+                        continue;
+                    }
+                };
+                let started_at = done_bytes;
+                let mut unreadable = None;
+                for chunk in StreamCDC::new(file, MIN_CHUNK, AVG_CHUNK, MAX_CHUNK) {
+                    let chunk = match chunk {
+                        Ok(chunk) => chunk,
+                        Err(e) => {
+                            unreadable = Some(format!("{}: {e}", entry.path().display()));
+                            break;
+                        }
+                    };
+                    let hash = hex_sha256(&chunk.data);
+                    if self.store_chunk(&hash, &chunk.data, &mut index)? {
+                        summary.new_bytes += chunk.length as u64;
+                    } else {
+                        summary.reused_bytes += chunk.length as u64;
+                    }
+                    record.size += chunk.length as u64;
+                    record.chunks.push(hash);
+
+                    done_bytes += chunk.length as u64;
+                    progress.set((done_bytes * 100 / total_bytes).min(100) as u32);
+                }
+                if let Some(error) = unreadable {
+                    warn!("Leaving out {error}");
+                    summary
+                        .failed
+                        .push(Problem::new(entry.path(), "read", error));
+                    done_bytes = started_at + meta.len();
+                    continue;
+                }
+                summary.files += 1;
+            } else if meta.is_symlink() {
+                // only seen when links aren't followed
+                if filters.symlinks == SymlinkPolicy::Skip {
+                    warn!("Skipping symlink: {}", entry.path().display());
+                    summary.skipped += 1;
+                    continue;
+                }
+                let target = fs::read_link(entry.path())
+                    .map_err(|e| format!("{}: {e}", entry.path().display()))?;
+                debug!(
+                    "Adding symlink: {} -> {}",
+                    entry.path().display(),
+                    target.display()
+                );
+                record.link = Some(target);
+            } else if meta.is_dir()
+                && excludes.has_includes()
+                && !holding_files.contains(entry.path())
+            {
+                debug!("No included files in: {}", entry.path().display());
+                continue;
+            } else if !meta.is_dir() {
+                debug!("Skipping special file {}", entry.path().display());
+                summary.skipped += 1;
+                continue;
+            }
+            entries.push(record);
+        }
+
+        // the snapshot goes last, chunks it points at are all on disk by now
+        index.sync_all().map_err(|e| e.to_string())?;
+        let snapshot = Snapshot {
+            id: id.clone(),
+            time: Local::now().format("%Y-%m-%d %H:%M:%S").to_string(),
+            sources: sources.to_vec(),
+            entries,
+        };
+        let json = serde_json::to_string(&snapshot).map_err(|e| e.to_string())?;
+        fs::write(self.root.join("snapshots").join(format!("{id}.json")), json)
+            .map_err(|e| e.to_string())?;
+
+        summary.elapsed = started.elapsed();
+        info!("Snapshot {}", summary.report());
+        progress.done();
+        Ok(summary)
+    }
+
+    // back to the original locations, like an archive restore
+    pub fn restore_snapshot(&self, id: &str, progress: &Progress) -> Result<u32, String> {
+        let path = self.root.join("snapshots").join(format!("{id}.json"));
+        let snapshot = load_snapshot(&path)?;
+        info!("Restoring snapshot {id}");
+
+        let current_home = dirs::home_dir().unwrap_or_else(|| PathBuf::from("C:\\"));
+        let total = snapshot.entries.len().max(1) as u64;
+        let mut dir_times = Vec::new();
+        let mut restored = 0u32;
+
+        for (i, entry) in snapshot.entries.iter().enumerate() {
+            let target = adjust_path(&entry.path, &current_home);
+            if entry.dir {
+                fs::create_dir_all(&target).map_err(|e| e.to_string())?;
+                dir_times.push((target, entry.mtime, entry.mode));
+            } else if let Some(link) = &entry.link {
+                if let Some(dir) = target.parent() {
+                    fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+                }
+                make_link(link, &target, entry.mtime)?;
+                restored += 1;
+            } else {
+                if let Some(dir) = target.parent() {
+                    fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+                }
+                // written next to the target and renamed over it once every
+                // chunk was read, a missing or damaged one leaves the file as it was
+                let name = target.file_name().unwrap_or_default().to_string_lossy();
+                let tmp = target.with_file_name(format!(".{name}.konserve-tmp"));
+                let written = self.write_chunks(&entry.chunks, &tmp).and_then(|()| {
+                    finish_file(&tmp, entry.mtime, entry.mode)?;
+                    make_writable(&target)?;
+                    fs::rename(&tmp, &target).map_err(|e| format!("{}: {e}", target.display()))
+                });
+                if let Err(e) = written {
+                    let _ = fs::remove_file(&tmp);
+                    return Err(e);
+                }
+                restored += 1;
+            }
+            progress.set(((i as u64 + 1) * 100 / total) as u32);
+        }
+
+        finish_dirs(dir_times);
+        info!("Snapshot {id}: restored {restored} files");
+        progress.done();
+        Ok(restored)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::helpers::test_dir;
+
+    // incompressible and the same on every run
+    fn noise(len: usize) -> Vec<u8> {
+        let mut x = 88172645463325252u64;
+        (0..len)
+            .map(|_| {
+                x ^= x << 13;
+                x ^= x >> 7;
+                x ^= x << 17;
+                x as u8
+            })
+            .collect()
+    }
+
+    fn snapshot(repo: &mut Repo, src: &Path) -> SnapshotSummary {
+        repo.create_snapshot(
+            std::slice::from_ref(&src.to_path_buf()),
+            &Filters::default(),
+            &Progress::default(),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn snapshots_restore_what_was_there() {
+        let base = test_dir("repo_round_trip");
+        let src = base.join("src");
+        fs::create_dir_all(src.join("sub/empty")).unwrap();
+        fs::write(src.join("big.bin"), noise(3 * MAX_CHUNK)).unwrap();
+        fs::write(src.join("sub/small.txt"), b"small").unwrap();
+        fs::write(src.join("zero"), b"").unwrap();
+        filetime::set_file_mtime(
+            src.join("sub/small.txt"),
+            filetime::FileTime::from_unix_time(1_500_000_000, 0),
+        )
+        .unwrap();
+
+        let mut repo = Repo::init(&base.join("repo")).unwrap();
+        let summary = snapshot(&mut repo, &src);
+        assert_eq!(summary.files, 3);
+        assert!(summary.failed.is_empty());
+        let snapshots = repo.snapshots().unwrap();
+        assert_eq!(snapshots.len(), 1);
+        assert_eq!(snapshots[0].id, summary.id);
+        assert_eq!(snapshots[0].files(), 3);
+
+        fs::remove_dir_all(&src).unwrap();
+        // a lost index is rebuilt from the chunk folder
+        fs::remove_file(base.join("repo/index")).unwrap();
+        let repo = Repo::open(&base.join("repo")).unwrap();
+        assert_eq!(
+            repo.restore_snapshot(&summary.id, &Progress::default())
+                .unwrap(),
+            3
+        );
+        assert_eq!(fs::read(src.join("big.bin")).unwrap(), noise(3 * MAX_CHUNK));
+        assert_eq!(fs::read(src.join("sub/small.txt")).unwrap(), b"small");
+        assert_eq!(fs::read(src.join("zero")).unwrap(), b"");
+        assert!(src.join("sub/empty").is_dir());
+        assert_eq!(
+            mtime_secs(&fs::metadata(src.join("sub/small.txt")).unwrap()),
+            1_500_000_000
+        );
+        let _ = fs::remove_dir_all(&base);
+    }
+
+    #[test]
+    fn unchanged_data_is_stored_once() {
+        let base = test_dir("repo_dedup");
+        let src = base.join("src");
+        fs::create_dir_all(&src).unwrap();
+        let mut big = noise(8 * AVG_CHUNK);
+        fs::write(src.join("big.bin"), &big).unwrap();
+        fs::write(src.join("copy.bin"), &big).unwrap();
+
+        let mut repo = Repo::init(&base.join("repo")).unwrap();
+        let first = snapshot(&mut repo, &src);
+        // the copy is all chunks the original already stored
+        assert_eq!(first.new_bytes, big.len() as u64);
+        assert_eq!(first.reused_bytes, big.len() as u64);
+
+        let again = snapshot(&mut repo, &src);
+        assert_eq!(again.new_bytes, 0);
+        assert_eq!(again.reused_bytes, 2 * big.len() as u64);
+
+        // an edit in the middle only brings new chunks around it
+        let middle = big.len() / 2;
+        big[middle] ^= 0xff;
+        fs::write(src.join("big.bin"), &big).unwrap();
+        let edited = snapshot(&mut repo, &src);
+        assert!(edited.new_bytes > 0);
+        assert!(
+            edited.new_bytes <= 2 * MAX_CHUNK as u64,
+            "{}",
+            edited.report()
+        );
+        assert_eq!(edited.new_bytes + edited.reused_bytes, 2 * big.len() as u64);
+        assert_eq!(repo.snapshots().unwrap().len(), 3);
+        let _ = fs::remove_dir_all(&base);
+    }
+
+    #[test]
+    fn a_damaged_chunk_leaves_the_file_alone() {
+        let base = test_dir("repo_damaged");
+        let src = base.join("src");
+        fs::create_dir_all(&src).unwrap();
+        fs::write(src.join("a.txt"), "archived").unwrap();
+        let mut repo = Repo::init(&base.join("repo")).unwrap();
+        let summary = snapshot(&mut repo, &src);
+
+        fs::write(src.join("a.txt"), "current work").unwrap();
+        let chunk = repo.chunk_path(&hex_sha256(b"archived"));
+        fs::write(chunk, zstd::encode_all(&b"garbage"[..], 3).unwrap()).unwrap();
+        let err = repo
+            .restore_snapshot(&summary.id, &Progress::default())
+            .unwrap_err();
+        assert!(err.contains("damaged"), "{err}");
+        assert_eq!(
+            fs::read_to_string(src.join("a.txt")).unwrap(),
+            "current work"
+        );
+        // no temp file left next to it
+        assert_eq!(fs::read_dir(&src).unwrap().count(), 1);
+        let _ = fs::remove_dir_all(&base);
+    }
+
+    #[test]
+    fn pushes_send_only_missing_chunks() {
+        let base = test_dir("repo_push");
+        let src = base.join("src");
+        fs::create_dir_all(&src).unwrap();
+        let mut big = noise(8 * AVG_CHUNK);
+        fs::write(src.join("big.bin"), &big).unwrap();
+        fs::write(src.join("small.txt"), b"small").unwrap();
+        let mut repo = Repo::init(&base.join("repo")).unwrap();
+        let first = snapshot(&mut repo, &src);
+
+        let offsite = base.join("offsite");
+        let pushed = repo.push(&offsite, &Progress::default()).unwrap();
+        assert_eq!(pushed.snapshots, 1);
+        assert_eq!(pushed.present, 0);
+        assert_eq!(pushed.sent as usize, repo.index.len());
+
+        // a middle edit only sends the chunks around it
+        let middle = big.len() / 2;
+        big[middle] ^= 0xff;
+        fs::write(src.join("big.bin"), &big).unwrap();
+        snapshot(&mut repo, &src);
+        let pushed = repo.push(&offsite, &Progress::default()).unwrap();
+        assert_eq!(pushed.snapshots, 1);
+        assert!(pushed.sent >= 1 && pushed.sent <= 3, "{}", pushed.report());
+        assert!(pushed.present > 0);
+
+        let pushed = repo.push(&offsite, &Progress::default()).unwrap();
+        assert_eq!((pushed.snapshots, pushed.sent), (0, 0));
+
+        // the pushed copy restores on its own, with its index rebuilt
+        fs::remove_dir_all(&src).unwrap();
+        fs::remove_file(offsite.join("index")).unwrap();
+        let offsite = Repo::open(&offsite).unwrap();
+        assert_eq!(offsite.snapshots().unwrap().len(), 2);
+        offsite
+            .restore_snapshot(&first.id, &Progress::default())
+            .unwrap();
+        assert_eq!(fs::read(src.join("big.bin")).unwrap(), noise(8 * AVG_CHUNK));
+        assert!(repo.push(&base.join("repo"), &Progress::default()).is_err());
+        let _ = fs::remove_dir_all(&base);
+    }
+
+    #[test]
+    fn a_push_with_a_bad_chunk_leaves_the_snapshot_out() {
+        let base = test_dir("repo_push_damaged");
+        let src = base.join("src");
+        fs::create_dir_all(&src).unwrap();
+        fs::write(src.join("a.txt"), "archived").unwrap();
+        let mut repo = Repo::init(&base.join("repo")).unwrap();
+        snapshot(&mut repo, &src);
+        let chunk = repo.chunk_path(&hex_sha256(b"archived"));
+        fs::write(&chunk, zstd::encode_all(&b"garbage"[..], 3).unwrap()).unwrap();
+
+        let offsite = base.join("offsite");
+        let err = repo.push(&offsite, &Progress::default()).err().unwrap();
+        assert!(err.contains("damaged"), "{err}");
+        let offsite = Repo::open(&offsite).unwrap();
+        assert!(offsite.snapshots().unwrap().is_empty());
+        assert!(offsite.index.is_empty());
+        let _ = fs::remove_dir_all(&base);
+    }
+}
@@ -0,0 +1,149 @@
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use chrono::Local;
+use eframe::egui;
+use log::{info, warn};
+use serde::Serialize;
+
+// what a backup or restore left out, each path with a "<path>: <reason>"
+// line
+pub type LeftOut = Vec<(PathBuf, String)>;
+
+// a file a run couldn't read or write, the error log is a list of them
+#[derive(Serialize, Clone, Debug)]
+pub struct Problem {
+    pub path: PathBuf,
+    // "read", "write" or "link"
+    pub operation: &'static str,
+    // the system's error number, when the error came from it
+    pub code: Option<i32>,
+    // "<path>: <reason>"
+    pub error: String,
+    pub time: String,
+}
+
+pub type ErrorLog = Vec<Problem>;
+
+impl Problem {
+    pub fn new(path: &Path, operation: &'static str, error: String) -> Self {
+        Self {
+            path: path.to_path_buf(),
+            operation,
+            code: None,
+            error,
+            time: Local::now().to_rfc3339(),
+        }
+    }
+
+    pub fn io(path: &Path, operation: &'static str, e: &io::Error, error: String) -> Self {
+        Self {
+            code: e.raw_os_error(),
+            ..Self::new(path, operation, error)
+        }
+    }
+}
+
+// the log as csv when `path` ends in .csv, json otherwise
+pub fn export(path: &Path, log: &[Problem]) -> Result<(), String> {
+    let csv = path
+        .extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("csv"));
+    let text = if csv {
+        let mut text = String::from("path,operation,code,error,time\n");
+        for problem in log {
+            let code = problem.code.map(|c| c.to_string()).unwrap_or_default();
+            let fields = [
+                problem.path.display().to_string(),
+                problem.operation.to_string(),
+                code,
+                problem.error.clone(),
+                problem.time.clone(),
+            ];
+            let quoted: Vec<String> = fields.iter().map(|f| csv_field(f)).collect();
+            text.push_str(&quoted.join(","));
+            text.push('\n');
+        }
+        text
+    } else {
+        serde_json::to_string_pretty(log).map_err(|e| e.to_string())?
+    };
+    fs::write(path, text).map_err(|e| e.to_string())?;
+    info!("Error log written to {}", path.display());
+    Ok(())
+}
+
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+// "<archive>.<what>-report.txt" listing what a run skipped and what failed,
+// nothing is written when both are empty
+pub fn write_sidecar(
+    archive: &Path,
+    what: &str,
+    skipped: &LeftOut,
+    failed: &[Problem],
+) -> Option<PathBuf> {
+    if skipped.is_empty() && failed.is_empty() {
+        return None;
+    }
+    let mut path = archive.as_os_str().to_owned();
+    path.push(format!(".{what}-report.txt"));
+    let path = PathBuf::from(path);
+
+    let mut text = format!(
+        "Konserve {what} of {}\n{}\n",
+        archive.display(),
+        Local::now().format("%Y-%m-%d %H:%M:%S")
+    );
+    let skipped: Vec<&String> = skipped.iter().map(|(_, reason)| reason).collect();
+    let failed: Vec<&String> = failed.iter().map(|problem| &problem.error).collect();
+    for (title, list) in [("Skipped", &skipped), ("Failed", &failed)] {
+        if list.is_empty() {
+            continue;
+        }
+        text.push_str(&format!("\n{title} ({}):\n", list.len()));
+        for reason in list {
+            text.push_str(&format!("  {reason}\n"));
+        }
+    }
+
+    match fs::write(&path, text) {
+        Ok(()) => {
+            info!("Report written to {}", path.display());
+            Some(path)
+        }
+        Err(e) => {
+            warn!("Couldn't write {}: {e}", path.display());
+            None
+        }
+    }
+}
+
+// both lists for the panel shown after a run
+pub fn show(ui: &mut egui::Ui, skipped: &LeftOut, failed: &[Problem]) {
+    let failed: Vec<&String> = failed.iter().map(|problem| &problem.error).collect();
+    let skipped: Vec<&String> = skipped.iter().map(|(_, reason)| reason).collect();
+    egui::ScrollArea::vertical()
+        .max_height(300.0)
+        .show(ui, |ui| {
+            ui.set_width(ui.available_width());
+            for (title, list) in [("Failed", &failed), ("Skipped", &skipped)] {
+                if list.is_empty() {
+                    continue;
+                }
+                ui.strong(format!("{title} ({})", list.len()));
+                for reason in list {
+                    ui.label(egui::RichText::new(reason.as_str()).small());
+                }
+                ui.separator();
+            }
+        });
+}
@@ -1,176 +1,1904 @@
-use crate::helpers::{Progress, adjust_path, get_fingered};
+use crate::archive::{self, EntryKind};
+use crate::backup::mtime_secs;
+use crate::crypto::Key;
+use crate::helpers::{Progress, adjust_path, get_fingered, original_path};
+use crate::incremental;
+use crate::report::{self, ErrorLog, LeftOut, Problem};
+use crate::sparse::{self, Layout};
+use crate::streams::{self, Stream};
+use crate::validate::sanitize_name;
+use chrono::Local;
+use log::{debug, info, warn};
 use std::{
-    collections::{HashMap, HashSet},
+    collections::{BTreeSet, HashMap, HashSet},
     fs::{self, File},
-    io::Read,
-    path::{Path, PathBuf},
-    sync::{Arc, Mutex},
+    io::{self, Read},
+    ops::ControlFlow,
+    path::{Component, Path, PathBuf},
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicU32, Ordering},
+        mpsc,
+    },
+    thread,
 };
-use tar::Archive;
+
+#[derive(Clone)]
+pub struct RestoreOptions {
+    // number of threads writing extracted files
+    pub workers: usize,
+    pub case_collisions: CaseCollision,
+    // files already on disk where something is restored
+    pub existing: Existing,
+    // files about to be replaced are moved below this folder first, at
+    // their full path, so a restore can be undone by hand
+    pub quarantine: Option<PathBuf>,
+    // write everything to a staging folder first and move it into place
+    // once nothing failed, so a failed restore changes nothing
+    pub staged: bool,
+    // rewrite names Windows can't store, on by default there
+    pub sanitize_names: bool,
+    // needed for password protected backups
+    pub key: Option<Key>,
+    // more archives restored together with the one opened, the newest copy
+    // of a file wins
+    pub also: Vec<PathBuf>,
+    // recreate the original layout below this folder instead of in place
+    pub into: Option<PathBuf>,
+    // old prefix -> new prefix for backups from another machine or layout.
+    // the first rule that fits applies, ahead of `into`
+    pub remap: Vec<(PathBuf, PathBuf)>,
+    // hard links in the backup become hard links again, otherwise each
+    // name gets a copy. a copy is made anyway where the disk can't link
+    pub hard_links: bool,
+    // put back permissions and hidden or system attributes, off leaves the
+    // defaults the restored files get. times are restored either way
+    pub permissions: bool,
+    // false for command line runs, which note files they can't write and go on
+    pub interactive: bool,
+}
+
+pub struct RestoreReport {
+    pub restored: u32,
+    // archived target -> where it actually went
+    pub renamed: Vec<(PathBuf, PathBuf)>,
+    // left alone on purpose, with the reason
+    pub skipped: LeftOut,
+    // couldn't be written during a headless run, with the reason
+    pub failed: ErrorLog,
+    // both lists, next to the archive
+    pub report_file: Option<PathBuf>,
+}
+
+impl Default for RestoreOptions {
+    fn default() -> Self {
+        Self {
+            workers: default_workers(),
+            case_collisions: CaseCollision::Rename,
+            existing: Existing::Overwrite,
+            quarantine: None,
+            staged: false,
+            also: Vec::new(),
+            sanitize_names: cfg!(windows),
+            key: None,
+            into: None,
+            remap: Vec::new(),
+            hard_links: true,
+            permissions: true,
+            interactive: true,
+        }
+    }
+}
+
+// what to do with files whose names only differ by case on a filesystem that
+// can't tell them apart
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum CaseCollision {
+    Rename,
+    Skip,
+    Overwrite,
+}
+
+impl CaseCollision {
+    pub const ALL: [CaseCollision; 3] = [Self::Rename, Self::Skip, Self::Overwrite];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            Self::Rename => "Rename",
+            Self::Skip => "Skip",
+            Self::Overwrite => "Overwrite",
+        }
+    }
+}
+
+// what to do when a restored file would land on one that's already there.
+// Ask is settled by the app before the restore starts, one reaching
+// restore_backup leaves the file alone
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum Existing {
+    #[default]
+    Overwrite,
+    // only where the archived copy was modified later than the one on disk
+    Newer,
+    Skip,
+    KeepBoth,
+    Ask,
+}
+
+impl Existing {
+    pub const ALL: [Existing; 5] = [
+        Self::Overwrite,
+        Self::Newer,
+        Self::Skip,
+        Self::KeepBoth,
+        Self::Ask,
+    ];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            Self::Overwrite => "Overwrite",
+            Self::Newer => "Overwrite if newer",
+            Self::Skip => "Skip",
+            Self::KeepBoth => "Keep both",
+            Self::Ask => "Ask first",
+        }
+    }
+}
+
+// a dated folder for what the restore started now replaces
+pub fn quarantine_dir() -> Option<PathBuf> {
+    let stamp = Local::now().format("%Y-%m-%d_%H-%M-%S").to_string();
+    Some(
+        dirs::data_local_dir()?
+            .join("Konserve")
+            .join("Quarantine")
+            .join(stamp),
+    )
+}
+
+// moves `path` to the same place below `root`
+fn quarantine(path: &Path, root: &Path) -> Result<(), String> {
+    move_path(path, &rebase(path, root))
+        .map_err(|e| format!("{}: couldn't move it aside: {e}", path.display()))
+}
+
+// a rename, or a copy where a rename can't cross drives
+fn move_path(from: &Path, to: &Path) -> io::Result<()> {
+    if let Some(dir) = to.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    fs::rename(from, to).or_else(|e| match fs::symlink_metadata(from) {
+        Ok(meta) if meta.is_file() => fs::copy(from, to)
+            .and_then(|_| make_writable(from).map_err(io::Error::other))
+            .and_then(|()| fs::remove_file(from)),
+        _ => Err(e),
+    })
+}
+
+// a staged restore writes everything below `root` and only moves it into
+// place once nothing failed. dropping it clears the staging folder, which
+// is all a failure before the move has to undo
+struct Stage {
+    root: PathBuf,
+    // staged path, where it belongs, and whether it's a folder. folders
+    // are only created there
+    moves: Vec<(PathBuf, PathBuf, bool)>,
+}
+
+impl Stage {
+    fn new(base: &Path) -> Self {
+        Self {
+            root: base.join(format!(".konserve-staging-{}", std::process::id())),
+            moves: Vec::new(),
+        }
+    }
+
+    // where `target` is written for now
+    fn place(&mut self, target: &Path, dir: bool) -> PathBuf {
+        let staged = rebase(target, &self.root.join("files"));
+        self.moves.push((staged.clone(), target.to_path_buf(), dir));
+        staged
+    }
+
+    // moves the staged files into place. what they replace goes below
+    // `quarantine`, or is held in the staging folder until the end. on an
+    // error everything moved so far is taken back and the replaced files
+    // return. gives the number of files replaced
+    fn commit(&self, quarantine: Option<&Path>) -> Result<u32, String> {
+        let held_root = quarantine.map_or_else(|| self.root.join("replaced"), Path::to_path_buf);
+        let mut created: Vec<PathBuf> = Vec::new();
+        let mut done: Vec<(&Path, Option<PathBuf>)> = Vec::new();
+        for (staged, target, dir) in &self.moves {
+            let swapped = if *dir {
+                make_dirs(target, &mut created).map(|()| None)
+            } else {
+                swap_in(staged, target, &held_root, &mut created)
+            };
+            match swapped {
+                Ok(held) if !dir => done.push((target, held)),
+                Ok(_) => {}
+                Err(e) => {
+                    roll_back(done, created);
+                    return Err(format!("{e}, nothing was restored"));
+                }
+            }
+        }
+        Ok(done.iter().filter(|(_, held)| held.is_some()).count() as u32)
+    }
+
+    // folder times are set on the folders in place
+    fn in_place(
+        &self,
+        dir_times: Vec<(PathBuf, u64, Option<u32>)>,
+    ) -> Vec<(PathBuf, u64, Option<u32>)> {
+        let targets: HashMap<&Path, &Path> = self
+            .moves
+            .iter()
+            .filter(|(_, _, dir)| *dir)
+            .map(|(staged, target, _)| (staged.as_path(), target.as_path()))
+            .collect();
+        dir_times
+            .into_iter()
+            .filter_map(|(dir, mtime, mode)| {
+                Some((targets.get(dir.as_path())?.to_path_buf(), mtime, mode))
+            })
+            .collect()
+    }
+}
+
+impl Drop for Stage {
+    fn drop(&mut self) {
+        if let Err(e) = fs::remove_dir_all(&self.root)
+            && e.kind() != io::ErrorKind::NotFound
+        {
+            warn!("   couldn't clear {}: {e}", self.root.display());
+        }
+    }
+}
+
+// create_dir_all that notes the folders it made, outermost first
+fn make_dirs(path: &Path, created: &mut Vec<PathBuf>) -> Result<(), String> {
+    let missing: Vec<PathBuf> = path
+        .ancestors()
+        .take_while(|dir| !dir.as_os_str().is_empty() && fs::symlink_metadata(dir).is_err())
+        .map(Path::to_path_buf)
+        .collect();
+    fs::create_dir_all(path).map_err(|e| format!("{}: {e}", path.display()))?;
+    created.extend(missing.into_iter().rev());
+    Ok(())
+}
+
+// puts one staged file in place, giving where the one it replaced went
+fn swap_in(
+    staged: &Path,
+    target: &Path,
+    held_root: &Path,
+    created: &mut Vec<PathBuf>,
+) -> Result<Option<PathBuf>, String> {
+    if let Some(dir) = target.parent() {
+        make_dirs(dir, created)?;
+    }
+    let held = match fs::symlink_metadata(target) {
+        Ok(_) => {
+            let held = rebase(target, held_root);
+            move_path(target, &held)
+                .map_err(|e| format!("{}: couldn't move it aside: {e}", target.display()))?;
+            Some(held)
+        }
+        Err(_) => None,
+    };
+    if let Err(e) = move_path(staged, target) {
+        if let Some(held) = &held
+            && let Err(e) = move_path(held, target)
+        {
+            warn!("   couldn't put back {}: {e}", target.display());
+        }
+        return Err(format!("{}: {e}", target.display()));
+    }
+    Ok(held)
+}
+
+// undoes the files moved in so far, newest first, and the folders made for them
+fn roll_back(done: Vec<(&Path, Option<PathBuf>)>, created: Vec<PathBuf>) {
+    for (target, held) in done.into_iter().rev() {
+        if let Err(e) = fs::remove_file(target) {
+            warn!("   couldn't take back {}: {e}", target.display());
+            continue;
+        }
+        if let Some(held) = held
+            && let Err(e) = move_path(&held, target)
+        {
+            warn!("   couldn't put back {}: {e}", target.display());
+        }
+    }
+    for dir in created.into_iter().rev() {
+        let _ = fs::remove_dir(dir);
+    }
+}
+
+pub const CASE_INSENSITIVE_FS: bool = cfg!(any(windows, target_os = "macos"));
+
+// "/home/me/a.txt" -> "<root>/home/me/a.txt", C:\x -> <root>\C\x
+pub fn rebase(original: &Path, root: &Path) -> PathBuf {
+    let mut path = root.to_path_buf();
+    for c in original.components() {
+        match c {
+            Component::Prefix(prefix) => path.push(
+                prefix
+                    .as_os_str()
+                    .to_string_lossy()
+                    .replace([':', '\\', '?'], ""),
+            ),
+            Component::Normal(name) => path.push(name),
+            _ => {}
+        }
+    }
+    path
+}
+
+// "notes.txt" -> "notes (2).txt"
+fn sanitize_path(path: &Path) -> PathBuf {
+    path.components()
+        .map(|c| match c {
+            Component::Normal(name) => match name.to_str() {
+                Some(name) => sanitize_name(name).into(),
+                None => name.to_os_string(),
+            },
+            other => other.as_os_str().to_os_string(),
+        })
+        .collect()
+}
+
+fn numbered_name(path: &Path, n: u32) -> PathBuf {
+    let stem = path
+        .file_stem()
+        .map(|s| s.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let name = match path.extension() {
+        Some(ext) => format!("{stem} ({n}).{}", ext.to_string_lossy()),
+        None => format!("{stem} ({n})"),
+    };
+    path.with_file_name(name)
+}
+
+pub fn default_workers() -> usize {
+    thread::available_parallelism()
+        .map(|n| n.get())
+        .unwrap_or(4)
+        .min(8)
+}
 
 // Return the path rendered with `/` separators
 fn canon<S: AsRef<str>>(s: S) -> String {
     s.as_ref().replace('\\', "/")
 }
 
+// "uuid/a/b/file" -> ["uuid/a/b", "uuid/a", "uuid"]
+fn tar_ancestors(path: &str) -> Vec<String> {
+    let mut out = Vec::new();
+    let mut rest = path;
+    while let Some((parent, _)) = rest.rsplit_once('/') {
+        out.push(parent.to_string());
+        rest = parent;
+    }
+    out
+}
+
+// `path` moved by the first rule whose old prefix it starts with. paths
+// from another system are compared with their separators evened out
+pub fn remap(path: &Path, rules: &[(PathBuf, PathBuf)]) -> Option<PathBuf> {
+    let slashed = |p: &Path| canon(p.to_string_lossy()).trim_end_matches('/').to_string();
+    rules.iter().find_map(|(from, to)| {
+        if let Ok(rest) = path.strip_prefix(from) {
+            return Some(to.join(rest));
+        }
+        let (path, from) = (slashed(path), slashed(from));
+        let rest = match path.get(..from.len()) {
+            Some(head) if head.eq_ignore_ascii_case(&from) => &path[from.len()..],
+            _ => return None,
+        };
+        if !rest.is_empty() && !rest.starts_with('/') {
+            return None;
+        }
+        Some(
+            rest.split('/')
+                .filter(|part| !part.is_empty())
+                .fold(to.clone(), |out, part| out.join(part)),
+        )
+    })
+}
+
+// profile folders the paths were backed up from that aren't `home`, like
+// another user's C:\Users\name or /home/name
+pub fn other_homes<'a>(paths: impl IntoIterator<Item = &'a PathBuf>, home: &Path) -> Vec<PathBuf> {
+    let current = canon(home.to_string_lossy()).to_lowercase();
+    paths
+        .into_iter()
+        .filter_map(|path| profile_of(path))
+        .filter(|profile| canon(profile.to_string_lossy()).to_lowercase() != current)
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .collect()
+}
+
+// drives like E:\ the paths are on that this machine doesn't have, left
+// out where a remap rule already moves the paths
+pub fn missing_drives<'a>(
+    paths: impl IntoIterator<Item = &'a PathBuf>,
+    rules: &[(PathBuf, PathBuf)],
+) -> Vec<PathBuf> {
+    paths
+        .into_iter()
+        .filter(|path| remap(path, rules).is_none())
+        .filter_map(|path| drive_of(path))
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .filter(|drive| !drive.exists())
+        .collect()
+}
+
+// "e:\x" -> "E:\"
+fn drive_of(path: &Path) -> Option<PathBuf> {
+    let text = path.to_string_lossy();
+    match text.as_bytes() {
+        [letter, b':', ..] if letter.is_ascii_alphabetic() => Some(PathBuf::from(format!(
+            "{}:\\",
+            letter.to_ascii_uppercase() as char
+        ))),
+        _ => None,
+    }
+}
+
+// the user folder a path lies in, as it was written
+fn profile_of(path: &Path) -> Option<PathBuf> {
+    let text = path.to_string_lossy();
+    let parts: Vec<&str> = text.split(['/', '\\']).take(3).collect();
+    let name = match parts.as_slice() {
+        [drive, users, name]
+            if drive.len() == 2 && drive.ends_with(':') && users.eq_ignore_ascii_case("users") =>
+        {
+            name
+        }
+        ["", "home" | "Users", name] => name,
+        _ => return None,
+    };
+    let shared = [
+        "",
+        "public",
+        "default",
+        "default user",
+        "all users",
+        "shared",
+    ];
+    if shared.contains(&name.to_lowercase().as_str()) {
+        return None;
+    }
+    let len = parts.iter().map(|part| part.len()).sum::<usize>() + 2;
+    Some(PathBuf::from(&text[..len]))
+}
+
+// where an original path belongs before names are sanitized. a remap rule
+// is taken as it is, without moving the old home to the current one
+fn archived_target(original: &Path, options: &RestoreOptions, home: &Path) -> PathBuf {
+    let remapped = remap(original, &options.remap);
+    match (&options.into, remapped) {
+        (Some(root), remapped) => rebase(remapped.as_deref().unwrap_or(original), root),
+        (None, Some(remapped)) => remapped,
+        (None, None) => adjust_path(original, home),
+    }
+}
+
+// where an original path is restored to, before case collisions
+fn target_path(original: &Path, options: &RestoreOptions, home: &Path) -> PathBuf {
+    let target = archived_target(original, options, home);
+    if options.sanitize_names {
+        sanitize_path(&target)
+    } else {
+        target
+    }
+}
+
+// an archive Konserve didn't make has no original places to go back to
+fn needs_folder(chain: &[incremental::Link], options: &RestoreOptions) -> Result<(), String> {
+    if chain.iter().any(|link| link.foreign) && options.into.is_none() {
+        return Err(
+            "This archive wasn't made by Konserve, pick a folder to restore it into.".into(),
+        );
+    }
+    Ok(())
+}
+
+// the ticked paths plus the files hard links among them share data with
+fn with_link_sources(
+    selected: Option<Vec<String>>,
+    hard_links: &incremental::HardLinks,
+) -> Option<Vec<String>> {
+    selected.map(|mut human_sel| {
+        let picked = |path: &Path| {
+            let path = canon(path.display().to_string());
+            human_sel
+                .iter()
+                .map(canon)
+                .any(|h| path == h || path.starts_with(&format!("{h}/")))
+        };
+        let sources: Vec<String> = hard_links
+            .iter()
+            .filter(|(link, first)| picked(link) && !picked(first))
+            .map(|(_, first)| first.display().to_string())
+            .collect();
+        human_sel.extend(sources);
+        human_sel
+    })
+}
+
+// what becomes of an entry whose target is already there: where it's
+// written, or why it's left out
+fn settle(
+    existing: Existing,
+    target: PathBuf,
+    mtime: u64,
+    there: &fs::Metadata,
+) -> Result<PathBuf, &'static str> {
+    match existing {
+        Existing::Overwrite => Ok(target),
+        Existing::KeepBoth => {
+            let mut n = 2;
+            let mut renamed = numbered_name(&target, n);
+            while fs::symlink_metadata(&renamed).is_ok() {
+                n += 1;
+                renamed = numbered_name(&target, n);
+            }
+            Ok(renamed)
+        }
+        // archived times are whole seconds, an untouched file on disk has
+        // the same one
+        Existing::Newer if mtime > mtime_secs(there) => Ok(target),
+        Existing::Newer => Err("the copy there is as new or newer"),
+        Existing::Skip | Existing::Ask => Err("already exists"),
+    }
+}
+
+// what a restore would do with one file, for the preview
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Action {
+    New,
+    Replace,
+    // a numbered copy next to the file there
+    Beside,
+    // already there, the app asks before restoring
+    Ask,
+    Skip(&'static str),
+}
+
+impl Action {
+    pub fn symbol(self) -> &'static str {
+        match self {
+            Self::New => "+",
+            Self::Replace => "~",
+            Self::Beside => "⧉",
+            Self::Ask => "?",
+            Self::Skip(_) => "−",
+        }
+    }
+
+    pub fn writes(self) -> bool {
+        matches!(self, Self::New | Self::Replace | Self::Beside)
+    }
+}
+
+pub struct Planned {
+    // where it's written, the numbered name for Beside
+    pub target: PathBuf,
+    pub size: u64,
+    pub action: Action,
+}
+
+// the files and links a restore with `options` would write, without
+// touching the disk. case collisions aren't settled here
+pub fn preview(
+    zip_path: &Path,
+    selected: Option<Vec<String>>,
+    options: &RestoreOptions,
+) -> Result<Vec<Planned>, String> {
+    let key = options.key.as_ref();
+    let chain = incremental::session(zip_path, &options.also, key)?;
+    needs_folder(&chain, options)?;
+    let owners = incremental::owners(&chain);
+    let hard_links = incremental::head_hard_links(&chain);
+    let selected = with_link_sources(selected, &hard_links);
+    let current_home = dirs::home_dir().unwrap_or_else(|| PathBuf::from("C:\\"));
+
+    let mut planned = Vec::new();
+    for (index, link) in chain.iter().enumerate() {
+        let to_extract = selected
+            .as_ref()
+            .map(|human_sel| selected_entries(&link.path_map, human_sel));
+        archive::visit_entries(&link.path, key, |entry| {
+            if matches!(entry.kind, EntryKind::File | EntryKind::Link)
+                && !streams::is_stream_entry(&entry.name)
+                && to_extract
+                    .as_ref()
+                    .is_none_or(|sel| sel.contains(&entry.name))
+                && incremental::keeps(owners.as_ref(), &chain, index, &entry.name, entry.kind)
+                && let Some(original) = original_path(&link.path_map, &entry.name, link.escaped)
+            {
+                let target = target_path(&original, options, &current_home);
+                let size = match link.sparse.get(&original) {
+                    Some(layout) => layout.size,
+                    None => entry.size,
+                };
+                let (target, action) = match fs::symlink_metadata(&target) {
+                    Err(_) => (target, Action::New),
+                    Ok(_) if options.existing == Existing::Ask => (target, Action::Ask),
+                    Ok(there) => {
+                        match settle(options.existing, target.clone(), entry.mtime, &there) {
+                            Ok(to) if to == target => (to, Action::Replace),
+                            Ok(to) => (to, Action::Beside),
+                            Err(reason) => (target, Action::Skip(reason)),
+                        }
+                    }
+                };
+                planned.push(Planned {
+                    target,
+                    size,
+                    action,
+                });
+            }
+            Ok(ControlFlow::Continue(()))
+        })?;
+    }
+    planned.sort_by(|a, b| a.target.cmp(&b.target));
+    Ok(planned)
+}
+
+// files and links a restore would put where something already is, so the
+// app can ask about them before anything is written
+pub fn conflicts(
+    zip_path: &Path,
+    selected: Option<Vec<String>>,
+    options: &RestoreOptions,
+) -> Result<Vec<PathBuf>, String> {
+    let options = RestoreOptions {
+        existing: Existing::Ask,
+        ..options.clone()
+    };
+    Ok(preview(zip_path, selected, &options)?
+        .into_iter()
+        .filter(|planned| planned.action == Action::Ask)
+        .map(|planned| planned.target)
+        .collect())
+}
+
+// archive entries behind the ticked human paths, folder entries above them included
+fn selected_entries(
+    path_map: &HashMap<String, PathBuf>,
+    human_sel_raw: &[String],
+) -> BTreeSet<String> {
+    let mut to_extract: BTreeSet<String> = BTreeSet::new();
+    let human_sel: Vec<String> = human_sel_raw.iter().map(canon).collect();
+
+    for (uuid, orig) in path_map {
+        let parent_c = canon(orig.parent().unwrap_or(orig).display().to_string());
+        let item_name = orig.file_name().unwrap().to_string_lossy();
+        let base = format!("{}/{}", parent_c, item_name);
+
+        if human_sel.contains(&base) {
+            to_extract.insert(uuid.clone());
+
+            if let Some(ext) = orig.extension().and_then(|e| e.to_str()) {
+                to_extract.insert(format!("{uuid}.{ext}"));
+            }
+        }
+
+        for h in &human_sel {
+            let base_slash = format!("{}/", base);
+            if let Some(rest) = h.strip_prefix(&base_slash) {
+                to_extract.insert(format!("{uuid}/{}", rest));
+            }
+        }
+    }
+
+    // pull in the directory entries above every selected item so they get
+    // created with their own metadata even when they weren't ticked
+    let ancestors: Vec<String> = to_extract.iter().flat_map(|p| tar_ancestors(p)).collect();
+    to_extract.extend(ancestors);
+    to_extract
+}
+
 pub fn restore_backup(
-    zip_path: &PathBuf,
+    zip_path: &Path,
     selected: Option<Vec<String>>,
     status: Arc<Mutex<String>>,
     progress: &Progress,
-) -> Result<(), String> {
+    options: &RestoreOptions,
+) -> Result<RestoreReport, String> {
     *status.lock().unwrap() = "Restoring backup…".into();
 
-    let mut archive = Archive::new(File::open(zip_path).map_err(|e| e.to_string())?);
-    let mut path_map: HashMap<String, PathBuf> = HashMap::new();
-    let mut valid_fingerprint = false;
+    let key = options.key.as_ref();
+    // an incremental brings its parents along, oldest first
+    let chain = incremental::session(zip_path, &options.also, key)?;
+    if chain
+        .iter()
+        .any(|link| !link.foreign && !link.fingerprint.contains(get_fingered()))
+    {
+        return Err("Invalid backup fingerprint.".into());
+    }
+    needs_folder(&chain, options)?;
+    let owners = incremental::owners(&chain);
+    let hard_links = incremental::head_hard_links(&chain);
+    let attributes = if options.permissions {
+        incremental::head_attributes(&chain)
+    } else {
+        Default::default()
+    };
+    let named_streams: HashMap<String, Stream> = chain
+        .iter()
+        .filter(|link| link.head)
+        .flat_map(|link| streams::parse_streams(&link.fingerprint))
+        .map(|stream| (stream.entry.clone(), stream))
+        .collect();
+    let with_streams: HashSet<&Path> = named_streams
+        .values()
+        .map(|stream| stream.path.as_path())
+        .collect();
+    // a hard link needs the file it shares data with, even when only the
+    // link was picked
+    let selected = with_link_sources(selected, &hard_links);
 
-    for entry_res in archive.entries().map_err(|e| e.to_string())? {
-        let mut entry = entry_res.map_err(|e| e.to_string())?;
-        let header_path = entry.path().map_err(|e| e.to_string())?;
-        let entry_name = header_path.to_string_lossy();
+    info!(
+        "[fingerprint] loaded, {} archives, {} uuids",
+        chain.len(),
+        chain.iter().map(|l| l.path_map.len()).sum::<usize>()
+    );
 
-        if entry_name == "fingerprint.txt" {
-            let mut txt = String::new();
-            entry.read_to_string(&mut txt).map_err(|e| e.to_string())?;
+    let to_extract: Vec<BTreeSet<String>> = chain
+        .iter()
+        .map(|link| match &selected {
+            Some(human_sel) => selected_entries(&link.path_map, human_sel),
+            None => BTreeSet::new(),
+        })
+        .collect();
+    let wanted = |index: usize, name: &str, kind: EntryKind| {
+        kind != EntryKind::Other
+            && (selected.is_none() || to_extract[index].contains(name))
+            && incremental::keeps(owners.as_ref(), &chain, index, name, kind)
+    };
 
-            if txt.contains(get_fingered()) {
-                valid_fingerprint = true;
-                for line in txt.lines().filter(|l| l.contains(": ")) {
-                    let (uuid, p) = line.split_once(": ").unwrap();
-                    path_map.insert(uuid.to_string(), PathBuf::from(p.trim()));
-                }
+    // files are counted by size so a big one doesn't hold the bar still
+    let mut total_bytes = 0u64;
+    for (index, link) in chain.iter().enumerate() {
+        archive::visit_entries(&link.path, key, |entry| {
+            if entry.kind == EntryKind::File && wanted(index, &entry.name, entry.kind) {
+                total_bytes += entry.size;
             }
-            break;
-        }
-    }
-    if !valid_fingerprint {
-        return Err("Invalid backup fingerprint.".into());
+            Ok(ControlFlow::Continue(()))
+        })?;
     }
+    progress.set_total_bytes(total_bytes);
 
-    println!("[fingerprint] loaded, {} uuids", path_map.len());
+    debug!("[select]  to_extract = {to_extract:?}");
 
-    let mut to_extract: HashSet<String> = HashSet::new();
+    let current_home = dirs::home_dir().unwrap_or_else(|| PathBuf::from("C:\\"));
+    let failed: Failed = Arc::default();
+    // before the pool, whose workers are done with it when it's dropped
+    let mut stage = options
+        .staged
+        .then(|| Stage::new(options.into.as_deref().unwrap_or(&current_home)));
+    let pool = ExtractPool::new(
+        options.workers,
+        progress.clone(),
+        (!options.interactive).then(|| failed.clone()),
+    );
+    // the app stops at the first file it can't write
+    let tolerate = |target: PathBuf, e: String| {
+        if options.interactive {
+            return Err(e);
+        }
+        warn!("[failed]  {e}");
+        failed
+            .lock()
+            .unwrap()
+            .push(Problem::new(&target, "write", e));
+        pool.tick(0);
+        Ok(ControlFlow::Continue(()))
+    };
+    let mut dir_times: Vec<(PathBuf, u64, Option<u32>)> = Vec::new();
+    // links and big files written on this thread, the pool counts its own
+    let mut written_here = 0u32;
+    // files already there with the archived content, set to its mtime and
+    // mode once everything else is in place
+    let mut touched: Vec<(PathBuf, u64, Option<u32>)> = Vec::new();
+    let mut seen_targets: HashMap<String, PathBuf> = HashMap::new();
+    let mut collisions = 0u32;
+    let mut quarantined = 0u32;
+    let mut renamed: Vec<(PathBuf, PathBuf)> = Vec::new();
+    let mut skipped = LeftOut::new();
+    // nothing is written through a restored link, wherever it points
+    let mut links: Vec<PathBuf> = Vec::new();
+    // hard links are made once the files they point at are written
+    let mut linked: Vec<(PathBuf, PathBuf)> = Vec::new();
+    let mut placed: HashMap<PathBuf, PathBuf> = HashMap::new();
+    // originals left alone because something was already in their place
+    let mut kept: HashSet<PathBuf> = HashSet::new();
+    // targets and their hidden or system flags
+    let mut flagged: Vec<(PathBuf, String)> = Vec::new();
+    // streams are kept until their files are written, the originals with
+    // streams map to where they went and their mtime
+    let mut stream_data: Vec<(&Stream, Vec<u8>)> = Vec::new();
+    let mut stream_targets: HashMap<PathBuf, (PathBuf, u64)> = HashMap::new();
 
-    if let Some(human_sel_raw) = &selected {
-        let human_sel: Vec<String> = human_sel_raw.iter().map(canon).collect();
+    info!(
+        "[extract] scanning archive with {} workers…",
+        options.workers
+    );
 
-        for (uuid, orig) in &path_map {
-            let parent_c = canon(orig.parent().unwrap_or(orig).display().to_string());
-            let item_name = orig.file_name().unwrap().to_string_lossy();
-            let base = format!("{}/{}", parent_c, item_name);
+    for (index, link) in chain.iter().enumerate() {
+        info!("[extract] {}", link.path.display());
+        archive::visit_entries(&link.path, key, |entry| {
+            let path_in_tar = entry.name.as_str();
 
-            if human_sel.contains(&base) {
-                to_extract.insert(uuid.clone());
+            if path_in_tar == "fingerprint.txt" {
+                return Ok(ControlFlow::Continue(()));
+            }
+            if streams::is_stream_entry(path_in_tar) {
+                if chain[index].head
+                    && let Some(stream) = named_streams.get(path_in_tar)
+                {
+                    let mut data = Vec::with_capacity(entry.size as usize);
+                    entry
+                        .data
+                        .read_to_end(&mut data)
+                        .map_err(|e| e.to_string())?;
+                    stream_data.push((stream, data));
+                }
+                return Ok(ControlFlow::Continue(()));
+            }
+            if entry.kind == EntryKind::Other {
+                info!("[skip]    {path_in_tar}  (special file)");
+                skipped.push((
+                    PathBuf::from(path_in_tar),
+                    format!("{path_in_tar}: special file"),
+                ));
+                return Ok(ControlFlow::Continue(()));
+            }
+            if !wanted(index, path_in_tar, entry.kind) {
+                info!("[skip]    {path_in_tar}  (not selected)");
+                return Ok(ControlFlow::Continue(()));
+            }
 
-                if let Some(ext) = orig.extension().and_then(|e| e.to_str()) {
-                    to_extract.insert(format!("{uuid}.{ext}"));
+            let Some(original) = original_path(&link.path_map, path_in_tar, link.escaped) else {
+                info!("[skip]    {path_in_tar}  (not below a backed up folder)");
+                return Ok(ControlFlow::Continue(()));
+            };
+            let archived_target = archived_target(&original, options, &current_home);
+            let mut unpack_to = target_path(&original, options, &current_home);
+
+            if CASE_INSENSITIVE_FS && entry.kind != EntryKind::Dir {
+                let key = unpack_to.to_string_lossy().to_lowercase();
+                match seen_targets.get(&key) {
+                    Some(first) if *first != unpack_to => {
+                        collisions += 1;
+                        warn!(
+                            "   {} collides with {} (case only), {}",
+                            unpack_to.display(),
+                            first.display(),
+                            options.case_collisions.label().to_lowercase()
+                        );
+                        match options.case_collisions {
+                            CaseCollision::Skip => {
+                                skipped.push((
+                                    unpack_to.clone(),
+                                    format!(
+                                        "{}: only differs by case from {}",
+                                        unpack_to.display(),
+                                        first.display()
+                                    ),
+                                ));
+                                pool.tick(entry.size);
+                                return Ok(ControlFlow::Continue(()));
+                            }
+                            CaseCollision::Overwrite => {}
+                            CaseCollision::Rename => {
+                                let mut n = 2;
+                                let mut renamed = numbered_name(&unpack_to, n);
+                                while seen_targets
+                                    .contains_key(&renamed.to_string_lossy().to_lowercase())
+                                {
+                                    n += 1;
+                                    renamed = numbered_name(&unpack_to, n);
+                                }
+                                unpack_to = renamed;
+                                seen_targets.insert(
+                                    unpack_to.to_string_lossy().to_lowercase(),
+                                    unpack_to.clone(),
+                                );
+                            }
+                        }
+                    }
+                    Some(_) => {}
+                    None => {
+                        seen_targets.insert(key, unpack_to.clone());
+                    }
                 }
             }
 
-            for h in &human_sel {
-                let base_slash = format!("{}/", base);
-                if let Some(rest) = h.strip_prefix(&base_slash) {
-                    to_extract.insert(format!("{uuid}/{}", rest));
+            if entry.kind != EntryKind::Dir
+                && options.existing != Existing::Overwrite
+                && let Ok(there) = fs::symlink_metadata(&unpack_to)
+            {
+                match settle(options.existing, unpack_to.clone(), entry.mtime, &there) {
+                    Ok(target) => unpack_to = target,
+                    // the archived content is already there, only its time
+                    // or mode are brought back
+                    Err(_)
+                        if entry.kind == EntryKind::File
+                            && there.is_file()
+                            && there.len() == entry.size
+                            && !link.sparse.contains_key(&original)
+                            && !hard_links.contains_key(&original)
+                            && metadata_differs(
+                                &there,
+                                entry.mtime,
+                                entry.mode.filter(|_| options.permissions),
+                            )
+                            && same_content(&unpack_to, entry.data) =>
+                    {
+                        info!("[meta]    {path_in_tar}  (content unchanged)");
+                        if hard_links.values().any(|first| *first == original) {
+                            placed.insert(original, unpack_to.clone());
+                        }
+                        touched.push((
+                            unpack_to,
+                            entry.mtime,
+                            entry.mode.filter(|_| options.permissions),
+                        ));
+                        pool.tick(entry.size);
+                        return Ok(ControlFlow::Continue(()));
+                    }
+                    Err(reason) => {
+                        info!("[skip]    {path_in_tar}  ({reason})");
+                        kept.insert(original);
+                        skipped.push((
+                            unpack_to.clone(),
+                            format!("{}: {reason}", unpack_to.display()),
+                        ));
+                        pool.tick(entry.size);
+                        return Ok(ControlFlow::Continue(()));
+                    }
                 }
             }
-        }
-    }
 
-    let total_files: u32 = {
-        let mut arc = Archive::new(File::open(zip_path).map_err(|e| e.to_string())?);
-        arc.entries()
-            .map_err(|e| e.to_string())?
-            .filter_map(Result::ok)
-            .filter(|e| {
-                let ty = e.header().entry_type();
-                ty.is_file() || ty.is_dir()
-            })
-            .filter(|e| {
-                if selected.is_some() {
-                    let p = e
-                        .path()
-                        .ok()
-                        .map(|x| x.to_string_lossy().into_owned())
-                        .unwrap_or_default();
-                    to_extract.contains(&p)
-                } else {
-                    true
+            if let Some(root) = options.quarantine.as_ref().filter(|_| stage.is_none())
+                && entry.kind != EntryKind::Dir
+                && fs::symlink_metadata(&unpack_to).is_ok()
+            {
+                if let Err(e) = quarantine(&unpack_to, root) {
+                    return tolerate(unpack_to, e);
                 }
-            })
-            .count()
-            .max(1) as u32
-    };
+                debug!("[aside]   {}", unpack_to.display());
+                quarantined += 1;
+            }
 
-    let mut done: u32 = 0;
+            if unpack_to != archived_target {
+                info!(
+                    "[rename]  {}  →  {}",
+                    archived_target.display(),
+                    unpack_to.display()
+                );
+                renamed.push((archived_target, unpack_to.clone()));
+            }
 
-    println!("[select]  to_extract = {to_extract:?}");
+            if links
+                .iter()
+                .any(|link| unpack_to.starts_with(link) && unpack_to != *link)
+            {
+                warn!("[skip]    {path_in_tar}  (below a link)");
+                skipped.push((
+                    unpack_to.clone(),
+                    format!("{}: inside a restored link", unpack_to.display()),
+                ));
+                pool.tick(entry.size);
+                return Ok(ControlFlow::Continue(()));
+            }
 
-    let current_home = dirs::home_dir().unwrap_or_else(|| PathBuf::from("C:\\"));
-    let mut archive = Archive::new(File::open(zip_path).map_err(|e| e.to_string())?);
+            let in_place = unpack_to.clone();
+            if let Some(stage) = &mut stage {
+                unpack_to = stage.place(&unpack_to, entry.kind == EntryKind::Dir);
+            }
 
-    println!("[extract] scanning archive…");
-    let mut restored_count = 0;
+            if let Some(flags) = attributes.get(&original) {
+                flagged.push((unpack_to.clone(), flags.clone()));
+            }
+            if entry.kind != EntryKind::Link && with_streams.contains(original.as_path()) {
+                stream_targets.insert(original.clone(), (unpack_to.clone(), entry.mtime));
+            }
+            let mode = entry.mode.filter(|_| options.permissions);
+            let layout = link.sparse.get(&original).cloned();
 
-    for entry_res in archive.entries().map_err(|e| e.to_string())? {
-        let mut entry = entry_res.map_err(|e| e.to_string())?;
-        let tar_path_ref = entry.path().map_err(|e| e.to_string())?;
-        let path_in_tar = tar_path_ref.to_string_lossy().into_owned();
+            if let Some(first) = hard_links.get(&original) {
+                debug!("[link]    {path_in_tar}  →  {}", first.display());
+                linked.push((unpack_to, first.clone()));
+                return Ok(ControlFlow::Continue(()));
+            }
+            if entry.kind == EntryKind::File && hard_links.values().any(|first| *first == original)
+            {
+                placed.insert(original, unpack_to.clone());
+            }
 
-        if path_in_tar == "fingerprint.txt" {
-            continue;
-        }
-        if selected.is_some() && !to_extract.contains(&path_in_tar) {
-            println!("[skip]    {path_in_tar}  (not selected)");
-            continue;
-        }
+            debug!("[write]   {path_in_tar}  →  {}", unpack_to.display());
 
-        let tar_path = Path::new(&path_in_tar);
-        let root_component = tar_path
-            .components()
-            .next()
-            .unwrap()
-            .as_os_str()
-            .to_string_lossy();
-
-        if let Some(orig_base) = path_map.get(&root_component.to_string()) {
-            let adjusted_base = adjust_path(orig_base, &current_home);
-            let rel = tar_path
-                .strip_prefix(Path::new(&root_component as &str))
-                .unwrap_or_else(|_| Path::new(""));
-
-            let unpack_to = adjusted_base.join(rel);
-            println!("[write] dir {path_in_tar}  →  {}", unpack_to.display());
-
-            if let Some(dir) = unpack_to.parent() {
-                fs::create_dir_all(dir).map_err(|e| e.to_string())?;
-            }
-            entry.unpack(&unpack_to).map_err(|e| e.to_string())?;
-            restored_count += 1;
-            done += 1;
-            progress.set((done * 100) / total_files);
-        } else if let Some((uuid_part, _ext)) = root_component.split_once('.') {
-            if let Some(orig_file) = path_map.get(uuid_part) {
-                let unpack_to = adjust_path(orig_file, &current_home);
-                println!("[write] file {path_in_tar}  →  {}", unpack_to.display());
-
-                if let Some(dir) = unpack_to.parent() {
-                    fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+            if let Some(dir) = unpack_to.parent()
+                && let Err(e) = fs::create_dir_all(dir)
+            {
+                let e = format!("{}: {e}", dir.display());
+                return tolerate(unpack_to, e);
+            }
+
+            // small files are handed to the pool, everything else is written here
+            if entry.kind == EntryKind::Dir {
+                if let Err(e) = fs::create_dir_all(&unpack_to) {
+                    let e = format!("{}: {e}", unpack_to.display());
+                    return tolerate(unpack_to, e);
                 }
-                entry.unpack(&unpack_to).map_err(|e| e.to_string())?;
-                restored_count += 1;
-                done += 1;
-                progress.set((done * 100) / total_files);
+                dir_times.push((unpack_to, entry.mtime, mode));
+                pool.tick(0);
+            } else if entry.kind == EntryKind::Link {
+                let target = entry.link.as_deref().unwrap_or(Path::new(""));
+                if let Err(e) = make_link(target, &unpack_to, entry.mtime) {
+                    return tolerate(unpack_to, e);
+                }
+                links.push(in_place);
+                written_here += 1;
+                pool.tick(0);
+            } else if entry.size <= PARALLEL_MAX_SIZE {
+                let job = WriteJob {
+                    mtime: entry.mtime,
+                    mode,
+                    layout,
+                    target: unpack_to,
+                    data: {
+                        let mut data = Vec::with_capacity(entry.size as usize);
+                        entry
+                            .data
+                            .read_to_end(&mut data)
+                            .map_err(|e| e.to_string())?;
+                        data
+                    },
+                };
+                pool.submit(job)?;
             } else {
-                println!("[skip]    {path_in_tar}  (uuid not in map)");
+                let written = make_writable(&unpack_to).and_then(|()| {
+                    let mut out = File::create(&unpack_to)
+                        .map_err(|e| format!("{}: {}", unpack_to.display(), e))?;
+                    let mut data = pool.progress.reading(&mut *entry.data);
+                    match &layout {
+                        Some(layout) => sparse::write(&mut out, layout, &mut data),
+                        None => io::copy(&mut data, &mut out).map(|_| ()),
+                    }
+                    .map_err(|e| format!("{}: {}", unpack_to.display(), e))?;
+                    drop(out);
+                    finish_file(&unpack_to, entry.mtime, mode)
+                });
+                if let Err(e) = written {
+                    return tolerate(unpack_to, e);
+                }
+                written_here += 1;
+                pool.tick(0);
+            }
+            Ok(ControlFlow::Continue(()))
+        })?;
+    }
+
+    let written = pool.finish()? + written_here;
+    let mut linked_count = 0u32;
+    for (target, first) in linked {
+        let made = match placed.get(&first) {
+            Some(source) => link_or_copy(source, &target, options.hard_links),
+            None if kept.contains(&first) => {
+                skipped.push((
+                    target.clone(),
+                    format!(
+                        "{}: {} it is a hard link to already existed",
+                        target.display(),
+                        first.display()
+                    ),
+                ));
+                continue;
             }
-        } else {
-            println!("[skip]    {path_in_tar}  (no handler)");
+            None => Err(format!(
+                "{}: {} it is a hard link to wasn't restored",
+                target.display(),
+                first.display()
+            )),
+        };
+        match made {
+            Ok(()) => linked_count += 1,
+            Err(e) if options.interactive => return Err(e),
+            Err(e) => {
+                warn!("[failed]  {e}");
+                failed
+                    .lock()
+                    .unwrap()
+                    .push(Problem::new(&target, "link", e));
+            }
+        }
+    }
+    for (stream, data) in stream_data {
+        let Some((target, mtime)) = stream_targets.get(&stream.path) else {
+            continue;
+        };
+        if let Err(e) = write_stream(target, *mtime, stream, &data) {
+            warn!("   couldn't restore a stream on {e}");
+        }
+    }
+    // last, windows won't open a hidden file for overwriting
+    for (target, flags) in flagged {
+        if let Err(e) = apply_flags(&target, &flags) {
+            warn!("   couldn't set attributes on {e}");
+        }
+    }
+    let failed = std::mem::take(&mut *failed.lock().unwrap());
+    let restored_count = written + linked_count + touched.len() as u32;
+
+    if let Some(stage) = &stage {
+        if !failed.is_empty() {
+            return Err(format!(
+                "{} files couldn't be written, nothing was restored.",
+                failed.len()
+            ));
+        }
+        *status.lock().unwrap() = "Moving restored files into place…".into();
+        quarantined += stage.commit(options.quarantine.as_deref())?;
+        dir_times = stage.in_place(dir_times);
+    }
+    for (target, mtime, mode) in &touched {
+        if let Err(e) = touch_file(target, *mtime, *mode) {
+            warn!("   couldn't update {e}");
         }
     }
+    finish_dirs(dir_times);
 
-    println!("[done]   restored {restored_count} entries");
-    *status.lock().unwrap() = "✅ Restore complete.".into();
+    info!("[done]   restored {restored_count} files");
+    let mut done = String::from("✅ Restore complete.");
+    if collisions > 0 {
+        done.push_str(&format!(
+            "\n{collisions} names only differed by case ({}).",
+            options.case_collisions.label().to_lowercase()
+        ));
+    }
+    if let Some(root) = options.quarantine.as_ref().filter(|_| quarantined > 0) {
+        done.push_str(&format!(
+            "\n{quarantined} replaced files were moved to {}.",
+            root.display()
+        ));
+    }
+    *status.lock().unwrap() = done;
     progress.done();
+    Ok(RestoreReport {
+        restored: restored_count,
+        renamed,
+        report_file: report::write_sidecar(zip_path, "restore", &skipped, &failed),
+        skipped,
+        failed,
+    })
+}
+
+// runs `found` on the entry holding the data of `original`, through the
+// incrementals and hard links, and stops reading there
+fn with_file<T>(
+    zip_path: &Path,
+    original: &Path,
+    options: &RestoreOptions,
+    mut found: impl FnMut(archive::Entry, Option<&Layout>) -> Result<T, String>,
+) -> Result<T, String> {
+    let key = options.key.as_ref();
+    let chain = incremental::session(zip_path, &options.also, key)?;
+    let owners = incremental::owners(&chain);
+    // a hard link has its data in the file it was linked to
+    let source = incremental::head_hard_links(&chain)
+        .get(original)
+        .cloned()
+        .unwrap_or_else(|| original.to_path_buf());
+
+    let mut result = None;
+    for (index, link) in chain.iter().enumerate() {
+        archive::visit_entries(&link.path, key, |entry| {
+            if entry.kind != EntryKind::File
+                || !incremental::keeps(owners.as_ref(), &chain, index, &entry.name, entry.kind)
+                || original_path(&link.path_map, &entry.name, link.escaped).as_ref()
+                    != Some(&source)
+            {
+                return Ok(ControlFlow::Continue(()));
+            }
+            result = Some(found(entry, link.sparse.get(&source)));
+            Ok(ControlFlow::Break(()))
+        })?;
+        if let Some(result) = result {
+            return result;
+        }
+    }
+    Err(format!("{} isn't in the archive.", original.display()))
+}
+
+// one file straight out of the archive into `folder` under its own name,
+// numbered when that's taken. gives where it went
+pub fn extract_file(
+    zip_path: &Path,
+    original: &Path,
+    folder: &Path,
+    options: &RestoreOptions,
+) -> Result<PathBuf, String> {
+    let name = original
+        .file_name()
+        .ok_or_else(|| format!("{} isn't a file", original.display()))?;
+    let mut target = folder.join(name);
+    let mut n = 2;
+    while fs::symlink_metadata(&target).is_ok() {
+        target = numbered_name(&folder.join(name), n);
+        n += 1;
+    }
+
+    with_file(zip_path, original, options, |entry, layout| {
+        debug!("[extract] {}  →  {}", entry.name, target.display());
+        let mut out = File::create(&target).map_err(|e| format!("{}: {e}", target.display()))?;
+        match layout {
+            Some(layout) => sparse::write(&mut out, layout, entry.data),
+            None => io::copy(entry.data, &mut out).map(|_| ()),
+        }
+        .map_err(|e| format!("{}: {e}", target.display()))?;
+        drop(out);
+        finish_file(
+            &target,
+            entry.mtime,
+            entry.mode.filter(|_| options.permissions),
+        )
+    })?;
+    Ok(target)
+}
+
+// up to `limit` bytes from the start of `original` and its full size, for
+// looking into a file without restoring it
+pub fn peek_file(
+    zip_path: &Path,
+    original: &Path,
+    limit: u64,
+    options: &RestoreOptions,
+) -> Result<(Vec<u8>, u64), String> {
+    with_file(zip_path, original, options, |entry, layout| {
+        let mut data = Vec::new();
+        let size = match layout {
+            Some(layout) => {
+                sparse::Expand::new(entry.data, layout)
+                    .take(limit)
+                    .read_to_end(&mut data)
+                    .map_err(|e| e.to_string())?;
+                layout.size
+            }
+            None => {
+                entry
+                    .data
+                    .take(limit)
+                    .read_to_end(&mut data)
+                    .map_err(|e| e.to_string())?;
+                entry.size
+            }
+        };
+        Ok((data, size))
+    })
+}
+
+// writing files into a directory bumps its mtime, so these go last and
+// deepest first so a parent isn't touched again after it was set
+pub fn finish_dirs(mut dir_times: Vec<(PathBuf, u64, Option<u32>)>) {
+    dir_times.sort_by_key(|(dir, _, _)| std::cmp::Reverse(dir.components().count()));
+    for (dir, mtime, mode) in &dir_times {
+        if let Err(e) =
+            filetime::set_file_mtime(dir, filetime::FileTime::from_unix_time(*mtime as i64, 0))
+        {
+            warn!("   couldn't set mtime on {}: {e}", dir.display());
+        }
+        if let Some(mode) = mode
+            && let Err(e) = apply_mode(dir, *mode)
+        {
+            warn!("   couldn't set permissions on {e}");
+        }
+    }
+}
+
+// files above this size are unpacked on the reading thread instead of buffered
+const PARALLEL_MAX_SIZE: u64 = 8 * 1024 * 1024;
+
+// files a headless restore couldn't write, with the reason
+type Failed = Arc<Mutex<ErrorLog>>;
+
+struct WriteJob {
+    target: PathBuf,
+    data: Vec<u8>,
+    mtime: u64,
+    mode: Option<u32>,
+    // `data` is just the ranges of a sparse file
+    layout: Option<Layout>,
+}
+
+// the owner write bit on unix, the read-only attribute on windows
+fn writable(mut perms: fs::Permissions) -> fs::Permissions {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        perms.set_mode(perms.mode() | 0o200);
+    }
+    #[cfg(not(unix))]
+    #[allow(clippy::permissions_set_readonly_false)]
+    perms.set_readonly(false);
+    perms
+}
+
+// an existing read-only file can't be truncated, so drop the flag first
+pub fn make_writable(path: &Path) -> Result<(), String> {
+    let Ok(meta) = fs::symlink_metadata(path) else {
+        return Ok(());
+    };
+    if meta.is_file() && meta.permissions().readonly() {
+        debug!("[write]   clearing read-only flag on {}", path.display());
+        fs::set_permissions(path, writable(meta.permissions()))
+            .map_err(|e| format!("{}: {}", path.display(), e))?;
+    }
+    // nor one that is hidden or system
+    if meta.is_file() && !archive::file_flags(&meta).is_empty() {
+        apply_flags(path, "")?;
+    }
+    Ok(())
+}
+
+// sets hidden and system to what `flags` says, other attributes stay
+#[cfg(windows)]
+fn apply_flags(path: &Path, flags: &str) -> Result<(), String> {
+    use windows_sys::Win32::Storage::FileSystem::{
+        FILE_ATTRIBUTE_HIDDEN, FILE_ATTRIBUTE_SYSTEM, GetFileAttributesW, INVALID_FILE_ATTRIBUTES,
+        SetFileAttributesW,
+    };
+
+    let wide = crate::helpers::wide_path(path);
+    let mut attributes = unsafe { GetFileAttributesW(wide.as_ptr()) };
+    if attributes == INVALID_FILE_ATTRIBUTES {
+        return Err(format!(
+            "{}: {}",
+            path.display(),
+            io::Error::last_os_error()
+        ));
+    }
+    attributes &= !(FILE_ATTRIBUTE_HIDDEN | FILE_ATTRIBUTE_SYSTEM);
+    if flags.contains('H') {
+        attributes |= FILE_ATTRIBUTE_HIDDEN;
+    }
+    if flags.contains('S') {
+        attributes |= FILE_ATTRIBUTE_SYSTEM;
+    }
+    if unsafe { SetFileAttributesW(wide.as_ptr(), attributes) } == 0 {
+        return Err(format!(
+            "{}: {}",
+            path.display(),
+            io::Error::last_os_error()
+        ));
+    }
     Ok(())
 }
+
+// only windows backups have any
+#[cfg(not(windows))]
+fn apply_flags(path: &Path, flags: &str) -> Result<(), String> {
+    debug!("[attrs]   ignoring {flags:?} on {}", path.display());
+    Ok(())
+}
+
+fn apply_mode(path: &Path, mode: u32) -> Result<(), String> {
+    #[cfg(unix)]
+    let perms = {
+        use std::os::unix::fs::PermissionsExt;
+        fs::Permissions::from_mode(mode)
+    };
+    // windows only knows the read-only attribute
+    #[cfg(not(unix))]
+    let perms = {
+        let mut perms = fs::metadata(path)
+            .map_err(|e| format!("{}: {}", path.display(), e))?
+            .permissions();
+        perms.set_readonly(mode & 0o200 == 0);
+        perms
+    };
+    fs::set_permissions(path, perms).map_err(|e| format!("{}: {}", path.display(), e))
+}
+
+// mtime before mode, setting times on a read-only file fails on windows
+pub fn finish_file(target: &Path, mtime: u64, mode: Option<u32>) -> Result<(), String> {
+    filetime::set_file_mtime(target, filetime::FileTime::from_unix_time(mtime as i64, 0))
+        .map_err(|e| format!("{}: {}", target.display(), e))?;
+
+    if let Some(mode) = mode {
+        apply_mode(target, mode)?;
+    }
+    Ok(())
+}
+
+// whether the file at `path` holds exactly what `data` reads, the caller
+// has already compared sizes
+fn same_content(path: &Path, data: &mut dyn Read) -> bool {
+    let Ok(mut file) = File::open(path) else {
+        return false;
+    };
+    let mut ours = vec![0u8; 64 * 1024];
+    let mut theirs = vec![0u8; 64 * 1024];
+    loop {
+        let n = match data.read(&mut ours) {
+            Ok(0) => return file.read(&mut theirs).is_ok_and(|n| n == 0),
+            Ok(n) => n,
+            Err(_) => return false,
+        };
+        if file.read_exact(&mut theirs[..n]).is_err() || ours[..n] != theirs[..n] {
+            return false;
+        }
+    }
+}
+
+// whether a file on disk has another mtime or mode than the archived one
+fn metadata_differs(there: &fs::Metadata, mtime: u64, mode: Option<u32>) -> bool {
+    if mtime_secs(there) != mtime {
+        return true;
+    }
+    let Some(mode) = mode else {
+        return false;
+    };
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        there.permissions().mode() & 0o7777 != mode & 0o7777
+    }
+    #[cfg(not(unix))]
+    {
+        there.permissions().readonly() != (mode & 0o200 == 0)
+    }
+}
+
+// the archived mtime and mode on a file left in place. windows won't set
+// times on a read-only file, so that flag is off in between
+fn touch_file(target: &Path, mtime: u64, mode: Option<u32>) -> Result<(), String> {
+    let perms = fs::metadata(target)
+        .map_err(|e| format!("{}: {e}", target.display()))?
+        .permissions();
+    if perms.readonly() {
+        fs::set_permissions(target, writable(perms.clone()))
+            .map_err(|e| format!("{}: {e}", target.display()))?;
+    }
+    filetime::set_file_mtime(target, filetime::FileTime::from_unix_time(mtime as i64, 0))
+        .map_err(|e| format!("{}: {e}", target.display()))?;
+    match mode {
+        Some(mode) => apply_mode(target, mode),
+        None => {
+            fs::set_permissions(target, perms).map_err(|e| format!("{}: {e}", target.display()))
+        }
+    }
+}
+
+// a read-only file takes no new attributes either, and writing a stream on
+// windows counts as changing the file
+fn write_stream(target: &Path, mtime: u64, stream: &Stream, data: &[u8]) -> Result<(), String> {
+    let perms = fs::metadata(target)
+        .map_err(|e| format!("{}: {e}", target.display()))?
+        .permissions();
+    make_writable(target)?;
+    let written = streams::write(target, stream, data);
+    filetime::set_file_mtime(target, filetime::FileTime::from_unix_time(mtime as i64, 0))
+        .map_err(|e| format!("{}: {e}", target.display()))?;
+    fs::set_permissions(target, perms).map_err(|e| format!("{}: {e}", target.display()))?;
+    written
+}
+
+// `target` as another name for `source`, or a copy of it when `link` is off
+// or the disk has no hard links
+fn link_or_copy(source: &Path, target: &Path, link: bool) -> Result<(), String> {
+    if let Some(dir) = target.parent() {
+        fs::create_dir_all(dir).map_err(|e| format!("{}: {e}", dir.display()))?;
+    }
+    if fs::symlink_metadata(target).is_ok() {
+        make_writable(target)?;
+        fs::remove_file(target).map_err(|e| format!("{}: {e}", target.display()))?;
+    }
+    if link {
+        match fs::hard_link(source, target) {
+            Ok(()) => return Ok(()),
+            Err(e) => warn!("[copy]    {} can't be a hard link: {e}", target.display()),
+        }
+    }
+    // copies permissions but not the time
+    fs::copy(source, target).map_err(|e| format!("{}: {e}", target.display()))?;
+    let mtime = fs::metadata(source)
+        .and_then(|meta| meta.modified())
+        .map_err(|e| format!("{}: {e}", source.display()))?;
+    filetime::set_file_mtime(target, filetime::FileTime::from_system_time(mtime))
+        .map_err(|e| format!("{}: {e}", target.display()))
+}
+
+// replaces whatever file or link is at `link`, a folder there stays and fails
+pub fn make_link(target: &Path, link: &Path, mtime: u64) -> Result<(), String> {
+    if target.as_os_str().is_empty() {
+        return Err(format!("{}: the link has no target", link.display()));
+    }
+    if fs::symlink_metadata(link).is_ok_and(|meta| !meta.is_dir()) {
+        fs::remove_file(link).map_err(|e| format!("{}: {e}", link.display()))?;
+    }
+    #[cfg(unix)]
+    let made = std::os::unix::fs::symlink(target, link);
+    // windows wants to know which kind it links to, a dangling one is a file
+    #[cfg(windows)]
+    let made = match link.parent().unwrap_or(link).join(target).is_dir() {
+        true => std::os::windows::fs::symlink_dir(target, link),
+        false => std::os::windows::fs::symlink_file(target, link),
+    };
+    #[cfg(not(any(unix, windows)))]
+    let made: io::Result<()> = Err(io::ErrorKind::Unsupported.into());
+    made.map_err(|e| format!("{}: {e}", link.display()))?;
+
+    // a link's permissions can't be set apart from its target's
+    let mtime = filetime::FileTime::from_unix_time(mtime as i64, 0);
+    if let Err(e) = filetime::set_symlink_file_times(link, mtime, mtime) {
+        warn!("   couldn't set mtime on {}: {e}", link.display());
+    }
+    Ok(())
+}
+
+fn write_job(job: &WriteJob) -> Result<(), String> {
+    make_writable(&job.target)?;
+    match &job.layout {
+        Some(layout) => File::create(&job.target)
+            .and_then(|mut out| sparse::write(&mut out, layout, &mut job.data.as_slice())),
+        None => fs::write(&job.target, &job.data),
+    }
+    .map_err(|e| format!("{}: {}", job.target.display(), e))?;
+    finish_file(&job.target, job.mtime, job.mode)
+}
+
+struct ExtractPool {
+    tx: Option<mpsc::SyncSender<WriteJob>>,
+    handles: Vec<thread::JoinHandle<Result<(), String>>>,
+    // jobs that made it to disk
+    written: Arc<AtomicU32>,
+    progress: Progress,
+}
+
+impl ExtractPool {
+    // with `failed` a job that can't be written is noted there instead of
+    // stopping its worker
+    fn new(workers: usize, progress: Progress, failed: Option<Failed>) -> Self {
+        let workers = workers.max(1);
+        let (tx, rx) = mpsc::sync_channel::<WriteJob>(workers * 4);
+        let rx = Arc::new(Mutex::new(rx));
+        let written = Arc::new(AtomicU32::new(0));
+
+        let handles = (0..workers)
+            .map(|_| {
+                let rx = rx.clone();
+                let written = written.clone();
+                let progress = progress.clone();
+                let failed = failed.clone();
+                thread::spawn(move || {
+                    loop {
+                        let job = match rx.lock().unwrap().recv() {
+                            Ok(job) => job,
+                            Err(_) => return Ok(()),
+                        };
+                        match write_job(&job) {
+                            Ok(()) => {
+                                written.fetch_add(1, Ordering::Relaxed);
+                            }
+                            Err(e) => {
+                                let Some(failed) = &failed else {
+                                    return Err(e);
+                                };
+                                warn!("[failed]  {e}");
+                                failed
+                                    .lock()
+                                    .unwrap()
+                                    .push(Problem::new(&job.target, "write", e));
+                            }
+                        }
+                        progress.add_bytes(job.data.len() as u64);
+                    }
+                })
+            })
+            .collect();
+
+        Self {
+            tx: Some(tx),
+            handles,
+            written,
+            progress,
+        }
+    }
+
+    fn submit(&self, job: WriteJob) -> Result<(), String> {
+        // a closed channel means every worker bailed out, finish() has the reason
+        if self.tx.as_ref().unwrap().send(job).is_err() {
+            return Err("Restore workers stopped unexpectedly.".into());
+        }
+        Ok(())
+    }
+
+    // an entry finished on the reading thread, `bytes` that never went
+    // through `progress.reading`
+    fn tick(&self, bytes: u64) {
+        self.progress.add_bytes(bytes);
+    }
+
+    // how many jobs were written
+    fn finish(mut self) -> Result<u32, String> {
+        drop(self.tx.take());
+        let mut first_err = None;
+        for handle in self.handles.drain(..) {
+            let res = handle
+                .join()
+                .unwrap_or_else(|_| Err("Restore worker panicked.".into()));
+            if let Err(e) = res {
+                first_err.get_or_insert(e);
+            }
+        }
+        match first_err {
+            Some(e) => Err(e),
+            None => Ok(self.written.load(Ordering::Relaxed)),
+        }
+    }
+}
+
+// a restore that stops early still waits for the queued writes, so nothing
+// lands after it returned
+impl Drop for ExtractPool {
+    fn drop(&mut self) {
+        drop(self.tx.take());
+        for handle in self.handles.drain(..) {
+            let _ = handle.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backup::{BackupOptions, backup_gui};
+    use crate::helpers::test_dir;
+
+    // a backup of `files` in a fresh folder named after the test, with the
+    // archive and where the files land when restored below base/into
+    fn backed_up(test: &str, files: &[(&str, &[u8])]) -> (PathBuf, PathBuf, PathBuf) {
+        let base = test_dir(test);
+        let src = base.join("src");
+        let out = base.join("out");
+        fs::create_dir_all(&src).unwrap();
+        fs::create_dir_all(&out).unwrap();
+        for (name, data) in files {
+            fs::write(src.join(name), data).unwrap();
+            filetime::set_file_mtime(
+                src.join(name),
+                filetime::FileTime::from_unix_time(1_500_000_000, 0),
+            )
+            .unwrap();
+        }
+        let summary = backup_gui(
+            std::slice::from_ref(&src),
+            &out,
+            &Progress::default(),
+            &BackupOptions::default(),
+        )
+        .unwrap();
+        let back = rebase(&src, &base.join("into"));
+        (base, summary.archive, back)
+    }
+
+    fn restore(base: &Path, archive: &Path, existing: Existing) -> RestoreReport {
+        let options = RestoreOptions {
+            into: Some(base.join("into")),
+            existing,
+            ..Default::default()
+        };
+        restore_backup(
+            archive,
+            None,
+            Arc::default(),
+            &Progress::default(),
+            &options,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn zero_byte_files() {
+        let (base, archive, back) = backed_up("zero_byte", &[("empty", b""), ("full", b"data")]);
+        let report = restore(&base, &archive, Existing::Overwrite);
+        assert!(report.failed.is_empty());
+        assert_eq!(report.restored, 2);
+        assert_eq!(fs::read(back.join("empty")).unwrap(), b"");
+        assert_eq!(fs::read(back.join("full")).unwrap(), b"data");
+
+        // an empty file already there is the same content, only the time moves
+        filetime::set_file_mtime(
+            back.join("empty"),
+            filetime::FileTime::from_unix_time(1_600_000_000, 0),
+        )
+        .unwrap();
+        let report = restore(&base, &archive, Existing::Skip);
+        // "full" is left alone, "empty" only gets its time back
+        assert_eq!(report.restored, 1);
+        assert_eq!(
+            mtime_secs(&fs::metadata(back.join("empty")).unwrap()),
+            1_500_000_000
+        );
+        assert!(
+            !report
+                .skipped
+                .iter()
+                .any(|(path, _)| path.ends_with("empty"))
+        );
+        let _ = fs::remove_dir_all(base);
+    }
+
+    #[test]
+    fn read_only_files() {
+        let (base, archive, back) = backed_up("read_only", &[("a.txt", b"archived")]);
+        fs::create_dir_all(&back).unwrap();
+        fs::write(back.join("a.txt"), b"on disk").unwrap();
+        let mut perms = fs::metadata(back.join("a.txt")).unwrap().permissions();
+        perms.set_readonly(true);
+        fs::set_permissions(back.join("a.txt"), perms).unwrap();
+
+        let report = restore(&base, &archive, Existing::Overwrite);
+        assert!(report.failed.is_empty(), "{:?}", report.failed);
+        assert_eq!(fs::read(back.join("a.txt")).unwrap(), b"archived");
+        assert!(
+            !fs::metadata(back.join("a.txt"))
+                .unwrap()
+                .permissions()
+                .readonly()
+        );
+        let _ = fs::remove_dir_all(base);
+    }
+
+    #[test]
+    fn metadata_only_changes() {
+        let (base, archive, back) = backed_up(
+            "metadata_only",
+            &[("same.txt", b"same"), ("other.txt", b"archived")],
+        );
+        assert_eq!(restore(&base, &archive, Existing::Overwrite).restored, 2);
+        let later = filetime::FileTime::from_unix_time(1_600_000_000, 0);
+        filetime::set_file_mtime(back.join("same.txt"), later).unwrap();
+        let mut perms = fs::metadata(back.join("same.txt")).unwrap().permissions();
+        perms.set_readonly(true);
+        fs::set_permissions(back.join("same.txt"), perms).unwrap();
+        fs::write(back.join("other.txt"), b"changed!").unwrap();
+        filetime::set_file_mtime(back.join("other.txt"), later).unwrap();
+
+        // the changed file stays as it is, the unchanged one gets its time
+        // and mode back
+        let report = restore(&base, &archive, Existing::Newer);
+        // touched once, the skipped file isn't counted
+        assert_eq!(report.restored, 1);
+        let meta = fs::metadata(back.join("same.txt")).unwrap();
+        assert_eq!(mtime_secs(&meta), 1_500_000_000);
+        assert!(!meta.permissions().readonly());
+        assert_eq!(fs::read(back.join("same.txt")).unwrap(), b"same");
+        assert_eq!(fs::read(back.join("other.txt")).unwrap(), b"changed!");
+        assert_eq!(report.skipped.len(), 1, "{:?}", report.skipped);
+        assert!(report.skipped[0].0.ends_with("other.txt"));
+        let _ = fs::remove_dir_all(base);
+    }
+
+    #[test]
+    fn remap_rules_move_prefixes() {
+        let rules = vec![
+            (PathBuf::from(r"C:\Users\old"), PathBuf::from("/home/me")),
+            (PathBuf::from("/mnt/photos"), PathBuf::from("/data/photos")),
+        ];
+        assert_eq!(
+            remap(Path::new(r"C:\Users\old\Docs\a.txt"), &rules),
+            Some(PathBuf::from("/home/me/Docs/a.txt"))
+        );
+        // windows paths match whatever the case and separators
+        assert_eq!(
+            remap(Path::new("c:/users/OLD/"), &rules),
+            Some(PathBuf::from("/home/me"))
+        );
+        assert_eq!(
+            remap(Path::new("/mnt/photos/2020/x.jpg"), &rules),
+            Some(PathBuf::from("/data/photos/2020/x.jpg"))
+        );
+        // a prefix of the folder name isn't the folder
+        assert_eq!(remap(Path::new(r"C:\Users\older\a"), &rules), None);
+        assert_eq!(remap(Path::new("/mnt/photos2/x.jpg"), &rules), None);
+        assert_eq!(remap(Path::new("/etc/hosts"), &[]), None);
+    }
+
+    #[test]
+    fn other_users_homes() {
+        let paths: Vec<PathBuf> = [
+            r"C:\Users\olduser\Documents\a.txt",
+            r"C:\Users\olduser\b.txt",
+            r"C:\Users\Public\x",
+            r"c:\users\ME\y",
+            "/home/me/notes",
+            "/home/bob",
+            "/Users/ann/Desktop",
+            "/etc/hosts",
+            r"D:\Data\y",
+        ]
+        .iter()
+        .map(PathBuf::from)
+        .collect();
+        assert_eq!(
+            other_homes(&paths, Path::new("/home/me")),
+            [
+                PathBuf::from("/Users/ann"),
+                PathBuf::from("/home/bob"),
+                PathBuf::from(r"C:\Users\olduser"),
+                PathBuf::from(r"c:\users\ME"),
+            ]
+        );
+        assert_eq!(
+            other_homes(&paths, Path::new(r"C:\Users\me")).len(),
+            4,
+            "the same profile in another case is this user's"
+        );
+    }
+
+    #[test]
+    fn old_homes_move_to_this_user() {
+        let home = Path::new(r"C:\Users\new");
+        assert_eq!(
+            adjust_path(Path::new(r"C:\Users\old\Documents\a.txt"), home),
+            PathBuf::from(r"C:\Users\new\Documents\a.txt")
+        );
+        assert_eq!(
+            adjust_path(Path::new(r"C:\Users\old"), home),
+            PathBuf::from(r"C:\Users\new")
+        );
+        assert_eq!(
+            adjust_path(Path::new(r"D:\Data\a.txt"), home),
+            PathBuf::from(r"D:\Data\a.txt")
+        );
+    }
+
+    #[test]
+    fn restores_follow_remap_rules() {
+        let (base, archive, _) = backed_up("remap", &[("a.txt", b"a")]);
+        let src = base.join("src");
+        let moved = base.join("moved");
+        let options = RestoreOptions {
+            remap: vec![(src.clone(), moved.clone())],
+            ..Default::default()
+        };
+        let report = restore_backup(
+            &archive,
+            None,
+            Arc::default(),
+            &Progress::default(),
+            &options,
+        )
+        .unwrap();
+        assert_eq!(report.restored, 1);
+        assert_eq!(fs::read(moved.join("a.txt")).unwrap(), b"a");
+
+        // below a restore folder the remapped path is rebased like any other
+        let options = RestoreOptions {
+            into: Some(base.join("into")),
+            remap: vec![(src, PathBuf::from("/elsewhere"))],
+            ..Default::default()
+        };
+        restore_backup(
+            &archive,
+            None,
+            Arc::default(),
+            &Progress::default(),
+            &options,
+        )
+        .unwrap();
+        assert_eq!(fs::read(base.join("into/elsewhere/a.txt")).unwrap(), b"a");
+        let _ = fs::remove_dir_all(base);
+    }
+}
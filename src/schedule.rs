@@ -0,0 +1,348 @@
+use std::{
+    collections::HashMap,
+    fs::{self, File},
+    path::PathBuf,
+    sync::Arc,
+    thread,
+    time::Duration,
+};
+
+use chrono::{Datelike, Local, NaiveDateTime, NaiveTime, TimeDelta};
+use log::{debug, info, warn};
+use serde::{Deserialize, Serialize};
+
+use crate::archive::ArchiveFormat;
+use crate::backup::BackupOptions;
+use crate::cli::{headless_options, load_template};
+use crate::daemon::{JobState, Jobs};
+
+pub const DAYS: [&str; 7] = ["Mon", "Tue", "Wed", "Thu", "Fri", "Sat", "Sun"];
+const TIME_FORMAT: &str = "%Y-%m-%d %H:%M";
+// how often the daemon looks for due schedules
+const TICK: Duration = Duration::from_secs(30);
+// a run started this long after its time was missed, not just noticed late
+const LATE: TimeDelta = TimeDelta::minutes(10);
+
+// what happens to a run that should have started while the machine was off
+// or nothing was running to start it
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum CatchUp {
+    // as soon as the daemon or the app notices
+    #[default]
+    Run,
+    // the app offers it when it starts
+    Ask,
+    // wait for the next time it's due
+    Skip,
+}
+
+impl CatchUp {
+    pub const ALL: [CatchUp; 3] = [Self::Run, Self::Ask, Self::Skip];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            Self::Run => "Run it right away",
+            Self::Ask => "Ask me",
+            Self::Skip => "Skip it",
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Schedule {
+    pub id: u64,
+    pub template: PathBuf,
+    pub out: PathBuf,
+    pub format: ArchiveFormat,
+    // monday first, all of them is daily
+    pub days: [bool; 7],
+    pub hour: u32,
+    pub minute: u32,
+    // runs are counted from here until the first one happened
+    pub added: String,
+    pub last_run: Option<String>,
+    pub last_result: Option<String>,
+    #[serde(default)]
+    pub catch_up: CatchUp,
+}
+
+impl Schedule {
+    pub fn new(template: PathBuf, out: PathBuf, format: ArchiveFormat) -> Self {
+        Self {
+            id: 0,
+            template,
+            out,
+            format,
+            days: [true; 7],
+            hour: 2,
+            minute: 0,
+            added: Local::now().naive_local().format(TIME_FORMAT).to_string(),
+            last_run: None,
+            last_result: None,
+            catch_up: CatchUp::default(),
+        }
+    }
+
+    // "Daily at 02:00", "Fri at 02:00", "Mon, Thu at 18:30"
+    pub fn describe(&self) -> String {
+        let time = format!("{:02}:{:02}", self.hour, self.minute);
+        if self.days.iter().all(|d| *d) {
+            return format!("Daily at {time}");
+        }
+        let days: Vec<&str> = DAYS
+            .iter()
+            .zip(self.days)
+            .filter(|(_, on)| *on)
+            .map(|(day, _)| *day)
+            .collect();
+        if days.is_empty() {
+            return "Never".into();
+        }
+        format!("{} at {time}", days.join(", "))
+    }
+
+    // the first run strictly after `after`
+    pub fn next_run(&self, after: NaiveDateTime) -> Option<NaiveDateTime> {
+        let time = NaiveTime::from_hms_opt(self.hour, self.minute, 0)?;
+        (0..=7)
+            .map(|offset| after.date() + TimeDelta::days(offset))
+            .filter(|date| self.days[date.weekday().num_days_from_monday() as usize])
+            .map(|date| date.and_time(time))
+            .find(|run| *run > after)
+    }
+
+    fn anchor(&self) -> Option<NaiveDateTime> {
+        let text = self.last_run.as_deref().unwrap_or(&self.added);
+        NaiveDateTime::parse_from_str(text, TIME_FORMAT).ok()
+    }
+
+    // the latest run that should have happened by `now` and hasn't. runs
+    // missed one after another are caught up once
+    pub fn pending(&self, now: NaiveDateTime) -> Option<NaiveDateTime> {
+        let anchor = self.anchor()?;
+        let time = NaiveTime::from_hms_opt(self.hour, self.minute, 0)?;
+        (0..=7)
+            .map(|offset| now.date() - TimeDelta::days(offset))
+            .filter(|date| self.days[date.weekday().num_days_from_monday() as usize])
+            .map(|date| date.and_time(time))
+            .find(|run| *run <= now)
+            .filter(|run| *run > anchor)
+    }
+
+    pub fn due(&self, now: NaiveDateTime) -> bool {
+        self.pending(now).is_some()
+    }
+
+    // a pending run whose time is long gone, what `catch_up` is for
+    pub fn missed(&self, now: NaiveDateTime) -> Option<NaiveDateTime> {
+        self.pending(now).filter(|run| now - *run > LATE)
+    }
+
+    pub fn upcoming(&self) -> Option<String> {
+        let now = Local::now().naive_local();
+        if let Some(run) = self.missed(now).filter(|_| self.catch_up != CatchUp::Run) {
+            return Some(format!("missed {}", run.format(TIME_FORMAT)));
+        }
+        if self.due(now) {
+            return Some("now".into());
+        }
+        Some(self.next_run(now)?.format(TIME_FORMAT).to_string())
+    }
+}
+
+fn schedules_path() -> Option<PathBuf> {
+    Some(dirs::config_dir()?.join("Konserve").join("schedules.json"))
+}
+
+// a file that doesn't parse is an error rather than no schedules, so a
+// change never saves an empty list over it
+pub fn load() -> Result<Vec<Schedule>, String> {
+    let path = schedules_path().ok_or("No config directory")?;
+    match fs::read_to_string(&path) {
+        Ok(data) => serde_json::from_str(&data).map_err(|e| format!("{}: {e}", path.display())),
+        Err(_) => Ok(Vec::new()),
+    }
+}
+
+// written beside the file and renamed over it, so a crash leaves either
+// the old list or the new one
+fn save(schedules: &[Schedule]) -> Result<(), String> {
+    let path = schedules_path().ok_or("No config directory")?;
+    let json = serde_json::to_string_pretty(schedules).map_err(|e| e.to_string())?;
+    let tmp = path.with_extension(format!("json.{}.tmp", std::process::id()));
+    fs::write(&tmp, json)
+        .and_then(|()| fs::rename(&tmp, &path))
+        .map_err(|e| {
+            let _ = fs::remove_file(&tmp);
+            format!("{}: {e}", path.display())
+        })?;
+    debug!("Schedules saved to {}", path.display());
+    Ok(())
+}
+
+// the app and the daemon both write the file. a change holds a lock on
+// schedules.lock while it reads, edits and saves, so neither loses the
+// other's update. the lock goes away with the file handle, even on a crash
+fn change(f: impl FnOnce(&mut Vec<Schedule>)) -> Result<Vec<Schedule>, String> {
+    let path = schedules_path().ok_or("No config directory")?;
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+    }
+    let lock_path = path.with_extension("lock");
+    let lock = File::create(&lock_path).map_err(|e| format!("{}: {e}", lock_path.display()))?;
+    lock.lock()
+        .map_err(|e| format!("{}: {e}", lock_path.display()))?;
+    let mut schedules = load()?;
+    f(&mut schedules);
+    save(&schedules)?;
+    Ok(schedules)
+}
+
+pub fn add(mut schedule: Schedule) -> Result<Vec<Schedule>, String> {
+    change(|schedules| {
+        schedule.id = schedules.iter().map(|s| s.id).max().unwrap_or(0) + 1;
+        info!(
+            "Scheduled {}: {}",
+            schedule.template.display(),
+            schedule.describe()
+        );
+        schedules.push(schedule);
+    })
+}
+
+pub fn remove(id: u64) -> Result<Vec<Schedule>, String> {
+    change(|schedules| schedules.retain(|s| s.id != id))
+}
+
+pub fn record(id: u64, run: Option<String>, result: String) {
+    let saved = change(|schedules| {
+        if let Some(schedule) = schedules.iter_mut().find(|s| s.id == id) {
+            if run.is_some() {
+                schedule.last_run = run;
+            }
+            schedule.last_result = Some(result);
+        }
+    });
+    if let Err(e) = saved {
+        warn!("Schedules: couldn't save: {e}");
+    }
+}
+
+pub fn now_text() -> String {
+    Local::now().naive_local().format(TIME_FORMAT).to_string()
+}
+
+// moves past a missed run without running it
+pub fn skip(schedule: &Schedule, run: NaiveDateTime) {
+    info!(
+        "Schedule {} skips the run missed at {}",
+        schedule.id,
+        run.format(TIME_FORMAT)
+    );
+    record(
+        schedule.id,
+        Some(now_text()),
+        format!("⏭ Skipped the run due {}", run.format(TIME_FORMAT)),
+    );
+}
+
+// at start-up, the missed runs the app should offer: those set to ask, and
+// those set to run when there's no daemon to run them. the ones set to skip
+// are moved past here
+pub fn catch_up(daemon_running: bool) -> Vec<(Schedule, NaiveDateTime)> {
+    let now = Local::now().naive_local();
+    let mut offered = Vec::new();
+    let schedules = load().unwrap_or_else(|e| {
+        warn!("Schedules: {e}");
+        Vec::new()
+    });
+    for schedule in schedules {
+        let Some(run) = schedule.missed(now) else {
+            continue;
+        };
+        match schedule.catch_up {
+            CatchUp::Skip => skip(&schedule, run),
+            CatchUp::Run if daemon_running => {}
+            CatchUp::Run | CatchUp::Ask => offered.push((schedule, run)),
+        }
+    }
+    offered
+}
+
+pub fn options(schedule: &Schedule) -> Result<(Vec<PathBuf>, BackupOptions), String> {
+    let template = load_template(&schedule.template)?;
+    if template.paths.is_empty() {
+        return Err(format!("{} has no paths.", schedule.template.display()));
+    }
+    let options = BackupOptions {
+        format: schedule.format,
+        ..headless_options(&schedule.template, &template)
+    };
+    Ok((template.paths, options))
+}
+
+// runs inside the daemon, due schedules become ordinary jobs
+pub fn start(jobs: Arc<Jobs>) {
+    thread::spawn(move || {
+        // job id -> schedule id, until the job finishes
+        let mut running: HashMap<u64, u64> = HashMap::new();
+        loop {
+            tick(&jobs, &mut running);
+            thread::sleep(TICK);
+        }
+    });
+}
+
+fn tick(jobs: &Arc<Jobs>, running: &mut HashMap<u64, u64>) {
+    running.retain(|job_id, schedule_id| {
+        let Some(status) = jobs.status(*job_id) else {
+            return false;
+        };
+        let result = match status.state {
+            JobState::Running => return true,
+            JobState::Done => format!("✅ {}", status.message),
+            JobState::Failed => format!("❌ {}", status.message),
+        };
+        record(*schedule_id, None, result);
+        false
+    });
+
+    let now = Local::now().naive_local();
+    let schedules = match load() {
+        Ok(schedules) => schedules,
+        Err(e) => {
+            warn!("Schedules: {e}");
+            return;
+        }
+    };
+    for schedule in schedules {
+        if !schedule.due(now) || running.values().any(|id| *id == schedule.id) {
+            continue;
+        }
+        if let Some(missed) = schedule.missed(now) {
+            match schedule.catch_up {
+                CatchUp::Run => {}
+                // the app asks the next time it starts
+                CatchUp::Ask => continue,
+                CatchUp::Skip => {
+                    skip(&schedule, missed);
+                    continue;
+                }
+            }
+        }
+        info!("Schedule {} is due: {}", schedule.id, schedule.describe());
+        let run = Some(now.format(TIME_FORMAT).to_string());
+        match options(&schedule) {
+            Ok((paths, options)) => {
+                let job = jobs.start(paths, schedule.out.clone(), options);
+                running.insert(job, schedule.id);
+                record(schedule.id, run, "Running".into());
+            }
+            Err(e) => {
+                warn!("Schedule {} can't run: {e}", schedule.id);
+                record(schedule.id, run, format!("❌ {e}"));
+            }
+        }
+    }
+}
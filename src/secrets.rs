@@ -0,0 +1,41 @@
+use std::path::Path;
+
+use keyring::{Entry, Error};
+use log::{debug, warn};
+
+// backup passwords remembered per template in the OS keyring (Credential
+// Manager, Keychain, Secret Service), keyed by the template's path
+const SERVICE: &str = "Konserve";
+
+fn entry(template: &Path) -> Result<Entry, String> {
+    Entry::new(SERVICE, &format!("template:{}", template.display())).map_err(|e| e.to_string())
+}
+
+pub fn load(template: &Path) -> Option<String> {
+    match entry(template).ok()?.get_password() {
+        Ok(password) => Some(password),
+        Err(Error::NoEntry) => None,
+        Err(e) => {
+            warn!(
+                "Keyring: couldn't read password for {}: {e}",
+                template.display()
+            );
+            None
+        }
+    }
+}
+
+pub fn store(template: &Path, password: &str) -> Result<(), String> {
+    debug!("Keyring: storing password for {}", template.display());
+    entry(template)?
+        .set_password(password)
+        .map_err(|e| e.to_string())
+}
+
+pub fn forget(template: &Path) -> Result<(), String> {
+    debug!("Keyring: forgetting password for {}", template.display());
+    match entry(template)?.delete_credential() {
+        Ok(()) | Err(Error::NoEntry) => Ok(()),
+        Err(e) => Err(e.to_string()),
+    }
+}
@@ -0,0 +1,121 @@
+use std::{fs, path::PathBuf};
+
+use log::{debug, warn};
+use serde::{Deserialize, Serialize};
+
+use crate::drives::DriveBinding;
+use crate::restore::default_workers;
+
+// base window size at 100% scale
+pub const WINDOW_SIZE: [f32; 2] = [410.0, 450.0];
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct Settings {
+    pub ui_scale: f32,
+    // sRGB accent used for progress bars, selections and active buttons
+    pub accent: [u8; 3],
+    pub density: Density,
+    // finished backups go through gpg for this key when set
+    pub gpg_recipient: String,
+    // finished backups are also copied into this folder when set
+    pub remote_folder: String,
+    pub sign_backups: bool,
+    // hex ed25519 keys whose signatures count as verified besides our own
+    pub trusted_keys: Vec<String>,
+    // keep backup passwords in the OS keyring, per template
+    pub remember_passwords: bool,
+    // templates that have a password in the keyring, so they can be cleared
+    pub remembered_templates: Vec<PathBuf>,
+    // templates backed up whenever their drive is plugged in
+    pub drive_bindings: Vec<DriveBinding>,
+    // threads reading and compressing during a backup
+    pub backup_workers: usize,
+    // MB/s a backup reads at most, 0 for no limit
+    pub backup_limit: u32,
+    // backup threads yield the cpu and the disk to everything else
+    pub background_priority: bool,
+    // ntfs alternate data streams and extended attributes go in too
+    pub keep_streams: bool,
+    // read files from a volume shadow copy so locked ones can be stored
+    pub shadow_copy: bool,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            ui_scale: 1.0,
+            accent: [80, 160, 240],
+            density: Density::Normal,
+            gpg_recipient: String::new(),
+            remote_folder: String::new(),
+            sign_backups: true,
+            trusted_keys: Vec::new(),
+            remember_passwords: false,
+            remembered_templates: Vec::new(),
+            drive_bindings: Vec::new(),
+            backup_workers: default_workers(),
+            backup_limit: 0,
+            background_priority: false,
+            keep_streams: false,
+            shadow_copy: false,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Density {
+    Compact,
+    Normal,
+    Comfortable,
+}
+
+impl Density {
+    pub const ALL: [Density; 3] = [Self::Compact, Self::Normal, Self::Comfortable];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            Self::Compact => "Compact",
+            Self::Normal => "Normal",
+            Self::Comfortable => "Comfortable",
+        }
+    }
+}
+
+fn settings_path() -> Option<PathBuf> {
+    Some(dirs::config_dir()?.join("Konserve").join("settings.json"))
+}
+
+impl Settings {
+    pub fn load() -> Self {
+        let Some(path) = settings_path() else {
+            return Self::default();
+        };
+        match fs::read_to_string(&path) {
+            Ok(data) => serde_json::from_str(&data).unwrap_or_else(|e| {
+                warn!("Settings: couldn't parse {}: {e}", path.display());
+                Self::default()
+            }),
+            Err(_) => Self::default(),
+        }
+    }
+
+    pub fn save(&self) -> Result<(), String> {
+        let path = settings_path().ok_or("No config directory")?;
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+        }
+        let json = serde_json::to_string_pretty(self).map_err(|e| e.to_string())?;
+        fs::write(&path, json).map_err(|e| e.to_string())?;
+        debug!("Settings saved to {}", path.display());
+        Ok(())
+    }
+
+    // the window is created before the zoom factor is applied
+    pub fn window_size(&self) -> [f32; 2] {
+        [
+            WINDOW_SIZE[0] * self.ui_scale,
+            WINDOW_SIZE[1] * self.ui_scale,
+        ]
+    }
+}
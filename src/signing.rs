@@ -0,0 +1,146 @@
+use std::{
+    fs::{self, File},
+    io,
+    path::{Path, PathBuf},
+};
+
+use aes_gcm::aead::{OsRng, rand_core::RngCore};
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+use log::{debug, info};
+use sha2::{Digest, Sha256};
+
+use crate::helpers::create_private;
+
+// every backup gets "<archive>.sig" next to it: an ed25519 signature over the
+// sha256 of the archive as it sits on disk, made with a key kept in the
+// config dir. archives signed by our own key or a trusted one count as verified.
+const SIG_HEADER: &str = "konserve-signature-v1";
+// keeps these signatures from ever meaning anything else
+const CONTEXT: &[u8] = b"konserve archive sha256:";
+
+pub enum Verdict {
+    Verified,
+    Unverified(String),
+    Invalid(String),
+}
+
+fn key_path() -> Option<PathBuf> {
+    Some(dirs::config_dir()?.join("Konserve").join("signing.key"))
+}
+
+fn read_key(path: &Path) -> Result<SigningKey, String> {
+    let text = fs::read_to_string(path).map_err(|e| format!("{}: {e}", path.display()))?;
+    let bytes: [u8; 32] = hex::decode(text.trim())
+        .ok()
+        .and_then(|b| b.try_into().ok())
+        .ok_or_else(|| format!("{} is damaged", path.display()))?;
+    Ok(SigningKey::from_bytes(&bytes))
+}
+
+// created on first use
+fn signing_key() -> Result<SigningKey, String> {
+    let path = key_path().ok_or("No config directory")?;
+    if path.exists() {
+        return read_key(&path);
+    }
+
+    info!("Creating signing key at {}", path.display());
+    let mut bytes = [0u8; 32];
+    OsRng.fill_bytes(&mut bytes);
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+    }
+    match create_private(&path, hex::encode(bytes).as_bytes()) {
+        Ok(()) => Ok(SigningKey::from_bytes(&bytes)),
+        // made by another run in the meantime
+        Err(e) if e.kind() == io::ErrorKind::AlreadyExists => read_key(&path),
+        Err(e) => Err(format!("{}: {e}", path.display())),
+    }
+}
+
+// what to hand to others so they can trust our backups
+pub fn public_key() -> Result<String, String> {
+    Ok(hex::encode(signing_key()?.verifying_key().as_bytes()))
+}
+
+pub fn sig_path(archive: &Path) -> PathBuf {
+    let mut path = archive.as_os_str().to_owned();
+    path.push(".sig");
+    PathBuf::from(path)
+}
+
+fn digest(archive: &Path) -> Result<Vec<u8>, String> {
+    let mut file = File::open(archive).map_err(|e| e.to_string())?;
+    let mut hasher = Sha256::new();
+    io::copy(&mut file, &mut hasher).map_err(|e| e.to_string())?;
+    Ok([CONTEXT, &hasher.finalize()].concat())
+}
+
+pub fn sign(archive: &Path) -> Result<PathBuf, String> {
+    let key = signing_key()?;
+    let message = digest(archive)?;
+    let signature = key.sign(&message);
+
+    let out = sig_path(archive);
+    fs::write(
+        &out,
+        format!(
+            "{SIG_HEADER}\nkey: {}\nsignature: {}\n",
+            hex::encode(key.verifying_key().as_bytes()),
+            hex::encode(signature.to_bytes())
+        ),
+    )
+    .map_err(|e| e.to_string())?;
+    debug!("Signed {}", archive.display());
+    Ok(out)
+}
+
+fn field<'a>(text: &'a str, name: &str) -> Option<&'a str> {
+    text.lines()
+        .find_map(|l| l.strip_prefix(name)?.strip_prefix(':'))
+        .map(str::trim)
+}
+
+fn parse_sig(text: &str) -> Option<(VerifyingKey, Signature)> {
+    if text.lines().next()? != SIG_HEADER {
+        return None;
+    }
+    let key: [u8; 32] = hex::decode(field(text, "key")?).ok()?.try_into().ok()?;
+    let signature: [u8; 64] = hex::decode(field(text, "signature")?)
+        .ok()?
+        .try_into()
+        .ok()?;
+    Some((
+        VerifyingKey::from_bytes(&key).ok()?,
+        Signature::from_bytes(&signature),
+    ))
+}
+
+// `trusted` are hex public keys from settings, our own key is always trusted
+pub fn verify(archive: &Path, trusted: &[String]) -> Verdict {
+    let Ok(text) = fs::read_to_string(sig_path(archive)) else {
+        return Verdict::Unverified("No signature file".into());
+    };
+    let Some((key, signature)) = parse_sig(&text) else {
+        return Verdict::Invalid("Signature file is damaged".into());
+    };
+    let message = match digest(archive) {
+        Ok(message) => message,
+        Err(e) => return Verdict::Unverified(e),
+    };
+    if key.verify(&message, &signature).is_err() {
+        return Verdict::Invalid("Archive doesn't match its signature".into());
+    }
+
+    let key_hex = hex::encode(key.as_bytes());
+    let ours = public_key().is_ok_and(|own| own == key_hex);
+    if ours
+        || trusted
+            .iter()
+            .any(|t| t.trim().eq_ignore_ascii_case(&key_hex))
+    {
+        Verdict::Verified
+    } else {
+        Verdict::Unverified(format!("Signed by unknown key {key_hex}"))
+    }
+}
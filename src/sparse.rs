@@ -0,0 +1,333 @@
+use std::{
+    collections::BTreeMap,
+    fs::{self, File},
+    io::{self, Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
+};
+
+use log::debug;
+
+use crate::helpers::fingerprint_section;
+use crate::names;
+
+// sparse files like vm disks are stored with just the ranges that hold data,
+// one after the other. the [Sparse] section of the fingerprint lists them as
+// "<size>\t<offset>+<length>,…\t<path>", a restore writes each range back
+// where it was and leaves the holes between them unallocated
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Layout {
+    pub size: u64,
+    // (offset, length), in order
+    pub ranges: Vec<(u64, u64)>,
+}
+
+impl Layout {
+    // bytes the archive entry holds
+    pub fn packed_len(&self) -> u64 {
+        self.ranges.iter().map(|(_, len)| len).sum()
+    }
+}
+
+pub type Sparse = BTreeMap<PathBuf, Layout>;
+
+// the layout of a file with holes in it, None for everything else. only
+// asks the file system when the metadata hints at holes
+pub fn detect(path: &Path, meta: &fs::Metadata) -> Option<Layout> {
+    if !maybe_sparse(meta) {
+        return None;
+    }
+    let ranges = match File::open(path).and_then(|file| allocated(&file, meta.len())) {
+        Ok(ranges) => ranges,
+        Err(e) => {
+            debug!("Couldn't map the holes of {}: {e}", path.display());
+            return None;
+        }
+    };
+    let layout = Layout {
+        size: meta.len(),
+        ranges,
+    };
+    (layout.packed_len() < layout.size).then_some(layout)
+}
+
+pub fn format_sparse(sparse: &Sparse) -> String {
+    if sparse.is_empty() {
+        return String::new();
+    }
+    let mut out = String::from("[Sparse]\n");
+    for (path, layout) in sparse {
+        let ranges: Vec<String> = layout
+            .ranges
+            .iter()
+            .map(|(offset, len)| format!("{offset}+{len}"))
+            .collect();
+        out.push_str(&format!(
+            "{}\t{}\t{}\n",
+            layout.size,
+            ranges.join(","),
+            names::escape(path)
+        ));
+    }
+    out
+}
+
+pub fn parse_sparse(txt: &str) -> Sparse {
+    let escaped = names::escaped(txt);
+    fingerprint_section(txt, "Sparse")
+        .filter_map(|line| {
+            let mut fields = line.splitn(3, '\t');
+            let size = fields.next()?.parse().ok()?;
+            let ranges = fields
+                .next()?
+                .split(',')
+                .filter(|range| !range.is_empty())
+                .map(|range| {
+                    let (offset, len) = range.split_once('+')?;
+                    Some((offset.parse().ok()?, len.parse().ok()?))
+                })
+                .collect::<Option<Vec<_>>>()?;
+            let path = names::read(fields.next()?, escaped);
+            Some((path, Layout { size, ranges }))
+        })
+        .collect()
+}
+
+// the ranges of `file` one after the other, what the archive stores
+pub struct Packed<R> {
+    inner: R,
+    ranges: std::vec::IntoIter<(u64, u64)>,
+    // left of the range being read
+    left: u64,
+}
+
+impl<R: Read + Seek> Packed<R> {
+    pub fn new(inner: R, layout: &Layout) -> Self {
+        Self {
+            inner,
+            ranges: layout.ranges.clone().into_iter(),
+            left: 0,
+        }
+    }
+}
+
+impl<R: Read + Seek> Read for Packed<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.left == 0 {
+            let Some((offset, len)) = self.ranges.next() else {
+                return Ok(0);
+            };
+            self.inner.seek(SeekFrom::Start(offset))?;
+            self.left = len;
+        }
+        let want = buf.len().min(self.left as usize);
+        let read = self.inner.read(&mut buf[..want])?;
+        if read == 0 {
+            // the file shrank since it was mapped
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        self.left -= read as u64;
+        Ok(read)
+    }
+}
+
+// a packed entry read back as the whole file, zeros where the holes are
+pub struct Expand<R> {
+    inner: R,
+    layout: Layout,
+    pos: u64,
+}
+
+impl<R: Read> Expand<R> {
+    pub fn new(inner: R, layout: &Layout) -> Self {
+        Self {
+            inner,
+            layout: layout.clone(),
+            pos: 0,
+        }
+    }
+}
+
+impl<R: Read> Read for Expand<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.pos >= self.layout.size || buf.is_empty() {
+            return Ok(0);
+        }
+        let pos = self.pos;
+        let next = self
+            .layout
+            .ranges
+            .iter()
+            .find(|(offset, len)| offset + len > pos);
+        let read = match next {
+            Some(&(offset, len)) if offset <= pos => {
+                let want = buf.len().min((offset + len - pos) as usize);
+                let read = self.inner.read(&mut buf[..want])?;
+                if read == 0 {
+                    return Err(io::ErrorKind::UnexpectedEof.into());
+                }
+                read
+            }
+            // a hole, up to the next range or the end
+            next => {
+                let end = next.map_or(self.layout.size, |(offset, _)| *offset);
+                let want = buf.len().min((end - pos) as usize);
+                buf[..want].fill(0);
+                want
+            }
+        };
+        self.pos += read as u64;
+        Ok(read)
+    }
+}
+
+// writes a packed entry into `out`, which is empty
+pub fn write(out: &mut File, layout: &Layout, data: &mut dyn Read) -> io::Result<()> {
+    mark_sparse(out)?;
+    for &(offset, len) in &layout.ranges {
+        out.seek(SeekFrom::Start(offset))?;
+        if io::copy(&mut data.take(len), out)? < len {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+    }
+    // also makes the trailing hole
+    out.set_len(layout.size)
+}
+
+#[cfg(unix)]
+fn maybe_sparse(meta: &fs::Metadata) -> bool {
+    use std::os::unix::fs::MetadataExt;
+    meta.is_file() && meta.blocks() * 512 < meta.len()
+}
+
+#[cfg(windows)]
+fn maybe_sparse(meta: &fs::Metadata) -> bool {
+    use std::os::windows::fs::MetadataExt;
+    use windows_sys::Win32::Storage::FileSystem::FILE_ATTRIBUTE_SPARSE_FILE;
+    meta.is_file() && meta.file_attributes() & FILE_ATTRIBUTE_SPARSE_FILE != 0
+}
+
+#[cfg(not(any(unix, windows)))]
+fn maybe_sparse(_meta: &fs::Metadata) -> bool {
+    false
+}
+
+// walks the data and holes with SEEK_DATA and SEEK_HOLE
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+fn allocated(file: &File, size: u64) -> io::Result<Vec<(u64, u64)>> {
+    use std::os::fd::AsRawFd;
+
+    let seek = |offset: u64, whence| {
+        let to = unsafe { libc::lseek(file.as_raw_fd(), offset as libc::off_t, whence) };
+        match to {
+            // ENXIO: no data past `offset`
+            -1 => match io::Error::last_os_error() {
+                e if e.raw_os_error() == Some(libc::ENXIO) => Ok(None),
+                e => Err(e),
+            },
+            to => Ok(Some(to as u64)),
+        }
+    };
+    let mut ranges = Vec::new();
+    let mut pos = 0;
+    while pos < size {
+        let Some(start) = seek(pos, libc::SEEK_DATA)? else {
+            break;
+        };
+        let end = seek(start, libc::SEEK_HOLE)?.unwrap_or(size).min(size);
+        if end > start {
+            ranges.push((start, end - start));
+        }
+        pos = end;
+    }
+    Ok(ranges)
+}
+
+#[cfg(windows)]
+fn allocated(file: &File, size: u64) -> io::Result<Vec<(u64, u64)>> {
+    use std::os::windows::io::AsRawHandle;
+    use windows_sys::Win32::System::IO::DeviceIoControl;
+    use windows_sys::Win32::System::Ioctl::{
+        FILE_ALLOCATED_RANGE_BUFFER, FSCTL_QUERY_ALLOCATED_RANGES,
+    };
+    const ERROR_MORE_DATA: i32 = 234;
+
+    let mut ranges = Vec::new();
+    let mut query = FILE_ALLOCATED_RANGE_BUFFER {
+        FileOffset: 0,
+        Length: size as i64,
+    };
+    let mut found = [FILE_ALLOCATED_RANGE_BUFFER::default(); 64];
+    loop {
+        let mut returned = 0u32;
+        let ok = unsafe {
+            DeviceIoControl(
+                file.as_raw_handle() as _,
+                FSCTL_QUERY_ALLOCATED_RANGES,
+                (&raw const query).cast(),
+                size_of::<FILE_ALLOCATED_RANGE_BUFFER>() as u32,
+                found.as_mut_ptr().cast(),
+                size_of_val(&found) as u32,
+                &mut returned,
+                std::ptr::null_mut(),
+            )
+        };
+        let more = match ok {
+            0 => match io::Error::last_os_error() {
+                e if e.raw_os_error() == Some(ERROR_MORE_DATA) => true,
+                e => return Err(e),
+            },
+            _ => false,
+        };
+        let count = returned as usize / size_of::<FILE_ALLOCATED_RANGE_BUFFER>();
+        ranges.extend(
+            found[..count]
+                .iter()
+                .map(|r| (r.FileOffset as u64, r.Length as u64)),
+        );
+        let Some(last) = ranges.last().filter(|_| more && count > 0) else {
+            return Ok(ranges);
+        };
+        let next = last.0 + last.1;
+        query = FILE_ALLOCATED_RANGE_BUFFER {
+            FileOffset: next as i64,
+            Length: size.saturating_sub(next) as i64,
+        };
+    }
+}
+
+#[cfg(not(any(windows, target_os = "linux", target_os = "macos")))]
+fn allocated(_file: &File, size: u64) -> io::Result<Vec<(u64, u64)>> {
+    Ok(vec![(0, size)])
+}
+
+// without the flag windows fills the holes in with zeros
+#[cfg(windows)]
+fn mark_sparse(file: &File) -> io::Result<()> {
+    use std::os::windows::io::AsRawHandle;
+    use windows_sys::Win32::System::IO::DeviceIoControl;
+    use windows_sys::Win32::System::Ioctl::FSCTL_SET_SPARSE;
+
+    let mut returned = 0u32;
+    let ok = unsafe {
+        DeviceIoControl(
+            file.as_raw_handle() as _,
+            FSCTL_SET_SPARSE,
+            std::ptr::null(),
+            0,
+            std::ptr::null_mut(),
+            0,
+            &mut returned,
+            std::ptr::null_mut(),
+        )
+    };
+    if ok == 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+// seeking past the end leaves a hole on its own
+#[cfg(not(windows))]
+fn mark_sparse(_file: &File) -> io::Result<()> {
+    Ok(())
+}
@@ -0,0 +1,73 @@
+use std::{collections::HashMap, fs, ops::ControlFlow, path::Path};
+
+use log::debug;
+
+use crate::archive::{self, ArchiveFormat, EntryKind};
+use crate::crypto::Key;
+use crate::streams;
+
+pub struct TypeStat {
+    pub kind: String,
+    pub files: u64,
+    pub original_bytes: u64,
+    pub stored_bytes: u64,
+}
+
+impl TypeStat {
+    pub fn ratio(&self) -> f64 {
+        if self.original_bytes == 0 {
+            return 1.0;
+        }
+        self.stored_bytes as f64 / self.original_bytes as f64
+    }
+}
+
+fn kind_of(name: &str) -> String {
+    Path::new(name)
+        .extension()
+        .map(|e| e.to_string_lossy().to_lowercase())
+        .unwrap_or_else(|| "(no extension)".into())
+}
+
+pub fn type_breakdown(zip_path: &Path, key: Option<&Key>) -> Result<Vec<TypeStat>, String> {
+    debug!("type_breakdown: Scanning archive {}", zip_path.display());
+
+    let mut by_kind: HashMap<String, TypeStat> = HashMap::new();
+
+    archive::visit_entries(zip_path, key, |entry| {
+        if entry.kind != EntryKind::File
+            || entry.name == "fingerprint.txt"
+            || streams::is_stream_entry(&entry.name)
+        {
+            return Ok(ControlFlow::Continue(()));
+        }
+
+        let kind = kind_of(&entry.name);
+        let stat = by_kind.entry(kind.clone()).or_insert_with(|| TypeStat {
+            kind,
+            files: 0,
+            original_bytes: 0,
+            stored_bytes: 0,
+        });
+        stat.files += 1;
+        stat.original_bytes += entry.size;
+        stat.stored_bytes += entry.stored_size;
+        Ok(ControlFlow::Continue(()))
+    })?;
+
+    let mut stats: Vec<TypeStat> = by_kind.into_values().collect();
+
+    // compressed tars only know their total size, so share it out by tar size
+    if ArchiveFormat::detect(zip_path, key)?.compresses_whole_stream() {
+        let archive_bytes = fs::metadata(zip_path).map_err(|e| e.to_string())?.len();
+        let stream_bytes: u64 = stats.iter().map(|s| s.stored_bytes).sum::<u64>().max(1);
+        for stat in &mut stats {
+            stat.stored_bytes =
+                (stat.stored_bytes as u128 * archive_bytes as u128 / stream_bytes as u128) as u64;
+        }
+    }
+    stats.sort_by_key(|s| std::cmp::Reverse(s.stored_bytes));
+
+    debug!("type_breakdown: {} file types", stats.len());
+    Ok(stats)
+}
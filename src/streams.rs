@@ -0,0 +1,318 @@
+use std::io;
+use std::path::{Path, PathBuf};
+
+use log::debug;
+
+use crate::helpers::fingerprint_section;
+use crate::names;
+
+// named data a file carries besides its contents, alternate data streams on
+// ntfs and extended attributes elsewhere. each one is an archive entry of
+// its own below "streams/", the [Streams] section of the fingerprint lists
+// them as "<entry>\t<kind>\t<name>\t<path>"
+const PREFIX: &str = "streams/";
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum StreamKind {
+    Ads,
+    Xattr,
+}
+
+impl StreamKind {
+    fn tag(self) -> &'static str {
+        match self {
+            Self::Ads => "ads",
+            Self::Xattr => "xattr",
+        }
+    }
+
+    fn from_tag(tag: &str) -> Option<Self> {
+        match tag {
+            "ads" => Some(Self::Ads),
+            "xattr" => Some(Self::Xattr),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Stream {
+    pub entry: String,
+    pub kind: StreamKind,
+    pub name: String,
+    pub path: PathBuf,
+}
+
+pub fn is_stream_entry(name: &str) -> bool {
+    name.starts_with(PREFIX)
+}
+
+// every stream `path` has, numbered on from `next`
+pub fn list(path: &Path, next: usize) -> Vec<Stream> {
+    let found = match names(path) {
+        Ok(found) => found,
+        Err(e) => {
+            debug!("No streams read from {}: {e}", path.display());
+            return Vec::new();
+        }
+    };
+    found
+        .into_iter()
+        .enumerate()
+        .map(|(i, (kind, name))| Stream {
+            entry: format!("{PREFIX}{}", next + i),
+            kind,
+            name,
+            path: path.to_path_buf(),
+        })
+        .collect()
+}
+
+pub fn format_streams(streams: &[Stream]) -> String {
+    if streams.is_empty() {
+        return String::new();
+    }
+    let mut out = String::from("[Streams]\n");
+    for stream in streams {
+        out.push_str(&format!(
+            "{}\t{}\t{}\t{}\n",
+            stream.entry,
+            stream.kind.tag(),
+            stream.name,
+            names::escape(&stream.path)
+        ));
+    }
+    out
+}
+
+pub fn parse_streams(txt: &str) -> Vec<Stream> {
+    let escaped = names::escaped(txt);
+    fingerprint_section(txt, "Streams")
+        .filter_map(|line| {
+            let mut fields = line.splitn(4, '\t');
+            Some(Stream {
+                entry: fields.next()?.to_string(),
+                kind: StreamKind::from_tag(fields.next()?)?,
+                name: fields.next()?.to_string(),
+                path: names::read(fields.next()?, escaped),
+            })
+        })
+        .collect()
+}
+
+pub fn read(stream: &Stream) -> Result<Vec<u8>, String> {
+    let read = match stream.kind {
+        StreamKind::Ads => read_ads(&stream.path, &stream.name),
+        StreamKind::Xattr => read_xattr(&stream.path, &stream.name),
+    };
+    read.map_err(|e| format!("{} ({}): {e}", stream.path.display(), stream.name))
+}
+
+pub fn write(path: &Path, stream: &Stream, data: &[u8]) -> Result<(), String> {
+    let written = match stream.kind {
+        StreamKind::Ads => write_ads(path, &stream.name, data),
+        StreamKind::Xattr => write_xattr(path, &stream.name, data),
+    };
+    written.map_err(|e| format!("{} ({}): {e}", path.display(), stream.name))
+}
+
+fn names(path: &Path) -> io::Result<Vec<(StreamKind, String)>> {
+    #[cfg(windows)]
+    return Ok(ads_names(path)?
+        .into_iter()
+        .map(|name| (StreamKind::Ads, name))
+        .collect());
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    return Ok(xattr_names(path)?
+        .into_iter()
+        .map(|name| (StreamKind::Xattr, name))
+        .collect());
+    #[cfg(not(any(windows, target_os = "linux", target_os = "macos")))]
+    {
+        let _ = path;
+        Ok(Vec::new())
+    }
+}
+
+fn unsupported(what: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::Unsupported,
+        format!("{what} can't be kept on this system"),
+    )
+}
+
+// the streams besides the unnamed one holding the contents, without the
+// ":$DATA" the api adds
+#[cfg(windows)]
+fn ads_names(path: &Path) -> io::Result<Vec<String>> {
+    use windows_sys::Win32::Storage::FileSystem::{
+        FindClose, FindFirstStreamW, FindNextStreamW, FindStreamInfoStandard,
+        WIN32_FIND_STREAM_DATA,
+    };
+
+    let wide = crate::helpers::wide_path(path);
+    let mut data: WIN32_FIND_STREAM_DATA = unsafe { std::mem::zeroed() };
+    let find = unsafe {
+        FindFirstStreamW(
+            wide.as_ptr(),
+            FindStreamInfoStandard,
+            (&raw mut data).cast(),
+            0,
+        )
+    };
+    // folders mostly have none at all
+    if find as isize == -1 {
+        return Ok(Vec::new());
+    }
+    let mut names = Vec::new();
+    loop {
+        let len = data
+            .cStreamName
+            .iter()
+            .position(|c| *c == 0)
+            .unwrap_or(data.cStreamName.len());
+        let name = String::from_utf16_lossy(&data.cStreamName[..len]);
+        if let Some(name) = name
+            .strip_prefix(':')
+            .and_then(|n| n.strip_suffix(":$DATA"))
+            .filter(|n| !n.is_empty())
+        {
+            names.push(name.to_string());
+        }
+        if unsafe { FindNextStreamW(find, (&raw mut data).cast()) } == 0 {
+            break;
+        }
+    }
+    unsafe { FindClose(find) };
+    Ok(names)
+}
+
+#[cfg(windows)]
+fn stream_path(path: &Path, name: &str) -> PathBuf {
+    let mut joined = path.as_os_str().to_owned();
+    joined.push(format!(":{name}"));
+    PathBuf::from(joined)
+}
+
+#[cfg(windows)]
+fn read_ads(path: &Path, name: &str) -> io::Result<Vec<u8>> {
+    std::fs::read(stream_path(path, name))
+}
+
+#[cfg(windows)]
+fn write_ads(path: &Path, name: &str, data: &[u8]) -> io::Result<()> {
+    std::fs::write(stream_path(path, name), data)
+}
+
+#[cfg(not(windows))]
+fn read_ads(_path: &Path, _name: &str) -> io::Result<Vec<u8>> {
+    Err(unsupported("Alternate data streams"))
+}
+
+#[cfg(not(windows))]
+fn write_ads(_path: &Path, _name: &str, _data: &[u8]) -> io::Result<()> {
+    Err(unsupported("Alternate data streams"))
+}
+
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+fn c_string(text: &[u8]) -> io::Result<std::ffi::CString> {
+    std::ffi::CString::new(text).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))
+}
+
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+fn c_path(path: &Path) -> io::Result<std::ffi::CString> {
+    use std::os::unix::ffi::OsStrExt;
+    c_string(path.as_os_str().as_bytes())
+}
+
+// a size of -1 is an error, anything else how many bytes there were
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+fn checked(size: libc::ssize_t) -> io::Result<usize> {
+    usize::try_from(size).map_err(|_| io::Error::last_os_error())
+}
+
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+fn xattr_names(path: &Path) -> io::Result<Vec<String>> {
+    let path = c_path(path)?;
+    #[cfg(target_os = "linux")]
+    let list = |buf: *mut libc::c_char, size| unsafe { libc::listxattr(path.as_ptr(), buf, size) };
+    #[cfg(target_os = "macos")]
+    let list =
+        |buf: *mut libc::c_char, size| unsafe { libc::listxattr(path.as_ptr(), buf, size, 0) };
+
+    let mut buf = vec![0u8; checked(list(std::ptr::null_mut(), 0))?];
+    if buf.is_empty() {
+        return Ok(Vec::new());
+    }
+    let len = checked(list(buf.as_mut_ptr().cast(), buf.len()))?;
+    buf.truncate(len);
+    Ok(buf
+        .split(|b| *b == 0)
+        .filter(|name| !name.is_empty())
+        .map(|name| String::from_utf8_lossy(name).into_owned())
+        // security and system ones belong to the machine, and setting them
+        // back needs root
+        .filter(|name| cfg!(not(target_os = "linux")) || name.starts_with("user."))
+        .collect())
+}
+
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+fn read_xattr(path: &Path, name: &str) -> io::Result<Vec<u8>> {
+    let (path, name) = (c_path(path)?, c_string(name.as_bytes())?);
+    #[cfg(target_os = "linux")]
+    let get = |buf: *mut libc::c_void, size| unsafe {
+        libc::getxattr(path.as_ptr(), name.as_ptr(), buf, size)
+    };
+    #[cfg(target_os = "macos")]
+    let get = |buf: *mut libc::c_void, size| unsafe {
+        libc::getxattr(path.as_ptr(), name.as_ptr(), buf, size, 0, 0)
+    };
+
+    let mut buf = vec![0u8; checked(get(std::ptr::null_mut(), 0))?];
+    if buf.is_empty() {
+        return Ok(buf);
+    }
+    let len = checked(get(buf.as_mut_ptr().cast(), buf.len()))?;
+    buf.truncate(len);
+    Ok(buf)
+}
+
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+fn write_xattr(path: &Path, name: &str, data: &[u8]) -> io::Result<()> {
+    let (path, name) = (c_path(path)?, c_string(name.as_bytes())?);
+    #[cfg(target_os = "linux")]
+    let set = unsafe {
+        libc::setxattr(
+            path.as_ptr(),
+            name.as_ptr(),
+            data.as_ptr().cast(),
+            data.len(),
+            0,
+        )
+    };
+    #[cfg(target_os = "macos")]
+    let set = unsafe {
+        libc::setxattr(
+            path.as_ptr(),
+            name.as_ptr(),
+            data.as_ptr().cast(),
+            data.len(),
+            0,
+            0,
+        )
+    };
+    if set != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+fn read_xattr(_path: &Path, _name: &str) -> io::Result<Vec<u8>> {
+    Err(unsupported("Extended attributes"))
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+fn write_xattr(_path: &Path, _name: &str, _data: &[u8]) -> io::Result<()> {
+    Err(unsupported("Extended attributes"))
+}
@@ -0,0 +1,43 @@
+use eframe::egui::{self, Color32, Stroke};
+use log::debug;
+
+use crate::settings::{Density, Settings};
+
+pub fn accent(settings: &Settings) -> Color32 {
+    let [r, g, b] = settings.accent;
+    Color32::from_rgb(r, g, b)
+}
+
+fn spacing_factor(density: Density) -> f32 {
+    match density {
+        Density::Compact => 0.6,
+        Density::Normal => 1.0,
+        Density::Comfortable => 1.5,
+    }
+}
+
+// rebuilds the style from egui's defaults every time so repeated calls don't compound
+pub fn apply(ctx: &egui::Context, settings: &Settings) {
+    let accent = accent(settings);
+    let factor = spacing_factor(settings.density);
+
+    ctx.style_mut(|style| {
+        style.spacing = egui::style::Spacing::default();
+        style.spacing.item_spacing *= factor;
+        style.spacing.button_padding *= factor;
+        style.spacing.interact_size.y *= factor.max(0.8);
+
+        let visuals = &mut style.visuals;
+        visuals.selection.bg_fill = accent;
+        visuals.selection.stroke = Stroke::new(1.0, accent.gamma_multiply(1.4));
+        visuals.hyperlink_color = accent;
+        visuals.widgets.hovered.bg_stroke = Stroke::new(1.0, accent);
+        visuals.widgets.active.weak_bg_fill = accent.gamma_multiply(0.8);
+        visuals.widgets.active.bg_fill = accent.gamma_multiply(0.8);
+    });
+
+    debug!(
+        "Style applied: accent = {:?}, density = {:?}",
+        settings.accent, settings.density
+    );
+}
@@ -0,0 +1,319 @@
+use std::{env, fs, path::Path, process::Command};
+
+use aes_gcm::aead::{OsRng, rand_core::RngCore};
+use chrono::Local;
+use log::{debug, info};
+use sha2::{Digest, Sha256};
+
+use crate::helpers::create_private;
+use crate::schedule::{CatchUp, DAYS, Schedule};
+
+// everything we register lives in this Task Scheduler folder, so listing
+// never shows anyone else's tasks
+const FOLDER: &str = "Konserve";
+// the longest command schtasks /TR takes
+const MAX_COMMAND: usize = 261;
+// the names task xml gives the days, monday first like DAYS
+const WEEKDAYS: [&str; 7] = [
+    "Monday",
+    "Tuesday",
+    "Wednesday",
+    "Thursday",
+    "Friday",
+    "Saturday",
+    "Sunday",
+];
+
+// whether a task wakes the computer for its run, and sends it back to sleep
+// once the backup is done
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum Wake {
+    #[default]
+    No,
+    Yes,
+    ThenSleep,
+}
+
+impl Wake {
+    pub const ALL: [Wake; 3] = [Self::No, Self::Yes, Self::ThenSleep];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            Self::No => "Don't wake",
+            Self::Yes => "Wake the computer",
+            Self::ThenSleep => "Wake, then sleep again",
+        }
+    }
+}
+
+pub struct Task {
+    // without the folder
+    pub name: String,
+    pub next_run: String,
+    pub status: String,
+    pub last_run: String,
+    // the exit code of the last run, see `konserve --help`
+    pub last_result: String,
+    pub command: String,
+}
+
+fn schtasks(args: &[&str]) -> Result<String, String> {
+    let output = Command::new("schtasks")
+        .args(args)
+        .output()
+        .map_err(|e| format!("Couldn't run schtasks: {e}"))?;
+    if output.status.success() {
+        return Ok(String::from_utf8_lossy(&output.stdout).into_owned());
+    }
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    debug!("schtasks failed: {stderr}");
+    Err(stderr.trim().trim_start_matches("ERROR: ").to_string())
+}
+
+// one task per template, registering it again replaces it. the hash of the
+// whole path keeps two templates with the same file name apart
+pub fn task_name(template: &Path) -> String {
+    let stem = template
+        .file_stem()
+        .map(|s| s.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "backup".into());
+    let hash = Sha256::digest(template.to_string_lossy().as_bytes());
+    format!(
+        "{}-{}",
+        stem.replace(['\\', '/'], "_"),
+        &hex::encode(hash)[..8]
+    )
+}
+
+// one argument the way CommandLineToArgvW reads it back. backslashes only
+// count before a quote, so those are doubled: a quoted D:\ would otherwise
+// end in an escaped quote and swallow the arguments after it
+fn quote(arg: &str) -> String {
+    let mut quoted = String::from("\"");
+    let mut backslashes = 0;
+    for c in arg.chars() {
+        match c {
+            '\\' => backslashes += 1,
+            '"' => {
+                quoted.extend(std::iter::repeat_n('\\', backslashes * 2 + 1));
+                quoted.push('"');
+                backslashes = 0;
+            }
+            c => {
+                quoted.extend(std::iter::repeat_n('\\', backslashes));
+                quoted.push(c);
+                backslashes = 0;
+            }
+        }
+    }
+    quoted.extend(std::iter::repeat_n('\\', backslashes * 2));
+    quoted.push('"');
+    quoted
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+// the task as Task Scheduler xml, for what schtasks' own switches can't
+// say: waking the computer and starting a missed run once it's back
+pub fn task_xml(exe: &Path, arguments: &str, schedule: &Schedule, wake: bool) -> String {
+    let (days, hour, minute) = (schedule.days, schedule.hour, schedule.minute);
+    let catch_up = schedule.catch_up == CatchUp::Run;
+    let trigger = if days.iter().all(|d| *d) {
+        "<ScheduleByDay><DaysInterval>1</DaysInterval></ScheduleByDay>".to_string()
+    } else {
+        let picked: String = WEEKDAYS
+            .iter()
+            .zip(days)
+            .filter(|(_, on)| *on)
+            .map(|(day, _)| format!("<{day} />"))
+            .collect();
+        format!(
+            "<ScheduleByWeek><DaysOfWeek>{picked}</DaysOfWeek><WeeksInterval>1</WeeksInterval></ScheduleByWeek>"
+        )
+    };
+    let start = format!(
+        "{}T{hour:02}:{minute:02}:00",
+        Local::now().date_naive().format("%Y-%m-%d")
+    );
+    format!(
+        r#"<?xml version="1.0" encoding="UTF-16"?>
+<Task version="1.2" xmlns="http://schemas.microsoft.com/windows/2004/02/mit/task">
+  <Triggers>
+    <CalendarTrigger>
+      <StartBoundary>{start}</StartBoundary>
+      <Enabled>true</Enabled>
+      {trigger}
+    </CalendarTrigger>
+  </Triggers>
+  <Principals>
+    <Principal id="Author">
+      <LogonType>InteractiveToken</LogonType>
+      <RunLevel>LeastPrivilege</RunLevel>
+    </Principal>
+  </Principals>
+  <Settings>
+    <WakeToRun>{wake}</WakeToRun>
+    <StartWhenAvailable>{catch_up}</StartWhenAvailable>
+    <DisallowStartIfOnBatteries>false</DisallowStartIfOnBatteries>
+    <StopIfGoingOnBatteries>false</StopIfGoingOnBatteries>
+    <MultipleInstancesPolicy>IgnoreNew</MultipleInstancesPolicy>
+  </Settings>
+  <Actions Context="Author">
+    <Exec>
+      <Command>{}</Command>
+      <Arguments>{}</Arguments>
+    </Exec>
+  </Actions>
+</Task>
+"#,
+        escape(&exe.display().to_string()),
+        escape(arguments)
+    )
+}
+
+// schtasks wants the file as utf-16 with a byte order mark
+fn create_from_xml(full_name: &str, xml: &str) -> Result<(), String> {
+    let mut bytes = [0u8; 8];
+    OsRng.fill_bytes(&mut bytes);
+    let path = env::temp_dir().join(format!("konserve-task-{}.xml", hex::encode(bytes)));
+    let mut utf16 = vec![0xFF, 0xFE];
+    utf16.extend(xml.encode_utf16().flat_map(u16::to_le_bytes));
+    create_private(&path, &utf16).map_err(|e| format!("{}: {e}", path.display()))?;
+    let created = schtasks(&[
+        "/Create",
+        "/F",
+        "/TN",
+        full_name,
+        "/XML",
+        &path.to_string_lossy(),
+    ]);
+    let _ = fs::remove_file(&path);
+    created.map(|_| ())
+}
+
+// runs the command line backup of the schedule's template into its folder
+pub fn register(schedule: &Schedule, wake: Wake) -> Result<String, String> {
+    let (template, out, format) = (&schedule.template, &schedule.out, schedule.format);
+    let (days, hour, minute) = (schedule.days, schedule.hour, schedule.minute);
+    if schedule.catch_up == CatchUp::Ask {
+        return Err(
+            "Task Scheduler can't ask about a missed run, pick running or skipping it.".into(),
+        );
+    }
+    let exe = std::env::current_exe().map_err(|e| e.to_string())?;
+    let mut arguments = format!(
+        "--template {} --out {} --format {}",
+        quote(&template.to_string_lossy()),
+        quote(&out.to_string_lossy()),
+        format.extension()
+    );
+    if wake == Wake::ThenSleep {
+        arguments.push_str(" --sleep-after");
+    }
+    let command = format!("\"{}\" {arguments}", exe.display());
+    let name = task_name(template);
+    let full_name = format!("{FOLDER}\\{name}");
+    let time = format!("{hour:02}:{minute:02}");
+    let picked: Vec<String> = DAYS
+        .iter()
+        .zip(days)
+        .filter(|(_, on)| *on)
+        .map(|(day, _)| day.to_uppercase())
+        .collect();
+    if picked.is_empty() {
+        return Err("Pick at least one day.".into());
+    }
+    let picked = picked.join(",");
+
+    if wake != Wake::No || schedule.catch_up == CatchUp::Run {
+        info!("Registering task {full_name} from xml ({wake:?}): {command}");
+        let xml = task_xml(&exe, &arguments, schedule, wake != Wake::No);
+        create_from_xml(&full_name, &xml)?;
+        return Ok(name);
+    }
+
+    // the xml above has no such limit
+    let length = command.chars().count();
+    if length > MAX_COMMAND {
+        return Err(format!(
+            "The backup command is {length} characters, Task Scheduler takes at most \
+             {MAX_COMMAND}. Move the template or the output folder to a shorter path."
+        ));
+    }
+    let mut args = vec![
+        "/Create", "/F", "/TN", &full_name, "/TR", &command, "/ST", &time,
+    ];
+    if days.iter().all(|d| *d) {
+        args.extend(["/SC", "DAILY"]);
+    } else {
+        args.extend(["/SC", "WEEKLY", "/D", &picked]);
+    }
+    info!("Registering task {full_name}: {command}");
+    schtasks(&args)?;
+    Ok(name)
+}
+
+pub fn remove(name: &str) -> Result<(), String> {
+    info!("Removing task {FOLDER}\\{name}");
+    schtasks(&["/Delete", "/F", "/TN", &format!("{FOLDER}\\{name}")]).map(|_| ())
+}
+
+// a quoted csv line the way schtasks writes them
+fn fields(line: &str) -> Vec<String> {
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                field.push('"');
+                chars.next();
+            }
+            '"' => quoted = !quoted,
+            ',' if !quoted => fields.push(std::mem::take(&mut field)),
+            c => field.push(c),
+        }
+    }
+    fields.push(field);
+    fields
+}
+
+pub fn parse_tasks(csv: &str) -> Vec<Task> {
+    let prefix = format!("\\{FOLDER}\\");
+    let mut tasks: Vec<Task> = Vec::new();
+    for line in csv.lines() {
+        let f = fields(line);
+        // HostName, TaskName, Next Run Time, Status, Logon Mode, Last Run Time,
+        // Last Result, Author, Task To Run, ...
+        if f.len() < 9 {
+            continue;
+        }
+        let Some(name) = f[1].strip_prefix(&prefix) else {
+            continue;
+        };
+        // a task with several triggers comes once per trigger
+        if tasks.iter().any(|t| t.name == name) {
+            continue;
+        }
+        tasks.push(Task {
+            name: name.to_string(),
+            next_run: f[2].clone(),
+            status: f[3].clone(),
+            last_run: f[5].clone(),
+            last_result: f[6].clone(),
+            command: f[8].clone(),
+        });
+    }
+    tasks
+}
+
+pub fn list() -> Result<Vec<Task>, String> {
+    let csv = schtasks(&["/Query", "/V", "/FO", "CSV", "/NH"])?;
+    Ok(parse_tasks(&csv))
+}
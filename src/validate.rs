@@ -0,0 +1,121 @@
+use std::path::{Path, PathBuf};
+
+use log::debug;
+use walkdir::WalkDir;
+
+// classic MAX_PATH, the limit Explorer and most tools still enforce
+pub const MAX_PATH_LEN: usize = 260;
+pub const MAX_NAME_LEN: usize = 255;
+
+const INVALID_WINDOWS_CHARS: [char; 7] = ['<', '>', ':', '"', '|', '?', '*'];
+const RESERVED_WINDOWS_NAMES: [&str; 22] = [
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+pub struct PathIssue {
+    pub path: PathBuf,
+    pub problem: String,
+    pub suggestion: &'static str,
+}
+
+// what Windows would refuse about a single file or folder name
+pub fn windows_name_problem(name: &str) -> Option<String> {
+    if let Some(c) = name
+        .chars()
+        .find(|c| INVALID_WINDOWS_CHARS.contains(c) || c.is_control())
+    {
+        return Some(format!("Name contains '{}'", c.escape_default()));
+    }
+    if name.ends_with('.') || name.ends_with(' ') {
+        return Some("Name ends with a dot or space".into());
+    }
+    let stem = name.split('.').next().unwrap_or(name);
+    if RESERVED_WINDOWS_NAMES
+        .iter()
+        .any(|r| r.eq_ignore_ascii_case(stem))
+    {
+        return Some(format!("\"{stem}\" is a reserved device name"));
+    }
+    None
+}
+
+// closest name Windows will accept, returns the input unchanged if it's fine
+pub fn sanitize_name(name: &str) -> String {
+    let mut clean: String = name
+        .chars()
+        .map(|c| {
+            if INVALID_WINDOWS_CHARS.contains(&c) || c.is_control() {
+                '_'
+            } else {
+                c
+            }
+        })
+        .collect();
+
+    if clean.ends_with(['.', ' ']) {
+        clean.pop();
+        clean.push('_');
+    }
+
+    let stem_len = clean.split('.').next().unwrap_or(&clean).len();
+    if RESERVED_WINDOWS_NAMES
+        .iter()
+        .any(|r| r.eq_ignore_ascii_case(&clean[..stem_len]))
+    {
+        clean.insert(stem_len, '_');
+    }
+    clean
+}
+
+fn check_path(path: &Path, issues: &mut Vec<PathIssue>) {
+    let len = path.as_os_str().len();
+    if len > MAX_PATH_LEN {
+        issues.push(PathIssue {
+            path: path.to_path_buf(),
+            problem: format!("Path is {len} characters, over the {MAX_PATH_LEN} limit"),
+            suggestion: "Backups and restores cope, but Explorer and older tools may not open it",
+        });
+    }
+
+    let Some(name) = path.file_name() else {
+        return;
+    };
+    let Some(name) = name.to_str() else {
+        issues.push(PathIssue {
+            path: path.to_path_buf(),
+            problem: "Name isn't valid Unicode".into(),
+            suggestion: "Rename it, the stored name may not match the original",
+        });
+        return;
+    };
+
+    if name.len() > MAX_NAME_LEN {
+        issues.push(PathIssue {
+            path: path.to_path_buf(),
+            problem: format!("Name is {} bytes long", name.len()),
+            suggestion: "Rename it, most filesystems stop at 255",
+        });
+    }
+    if let Some(problem) = windows_name_problem(name) {
+        issues.push(PathIssue {
+            path: path.to_path_buf(),
+            problem,
+            suggestion: "Rename it, restoring onto Windows will fail",
+        });
+    }
+}
+
+pub fn scan_paths(folders: &[PathBuf]) -> Vec<PathIssue> {
+    debug!("scan_paths: Checking {} roots", folders.len());
+    let mut issues = Vec::new();
+
+    for root in folders {
+        for entry in WalkDir::new(root).into_iter().filter_map(Result::ok) {
+            check_path(entry.path(), &mut issues);
+        }
+    }
+
+    debug!("scan_paths: {} issues found", issues.len());
+    issues
+}
@@ -0,0 +1,270 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fs::{self, File},
+    ops::ControlFlow,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    thread,
+    time::Duration,
+};
+
+use log::{debug, info, warn};
+use uuid::Uuid;
+
+use crate::archive::{self, EntryKind};
+use crate::crypto::Key;
+use crate::helpers::{FileHashes, Progress, get_fingered, original_path};
+use crate::incremental::{self, hash_reader};
+use crate::restore::{RestoreOptions, rebase, restore_backup};
+use crate::sparse;
+
+pub struct VerifyReport {
+    pub checked: u32,
+    // original path and what's wrong with it
+    pub corrupt: Vec<(PathBuf, String)>,
+    // in the file list but in none of the archives
+    pub missing: Vec<PathBuf>,
+    // listed without a hash, nothing to compare against
+    pub unhashed: u32,
+}
+
+impl VerifyReport {
+    pub fn passed(&self) -> bool {
+        self.corrupt.is_empty() && self.missing.is_empty()
+    }
+
+    pub fn summary(&self) -> String {
+        let mut summary = format!("{} files checked", self.checked);
+        if !self.corrupt.is_empty() {
+            summary.push_str(&format!(", {} corrupt", self.corrupt.len()));
+        }
+        if !self.missing.is_empty() {
+            summary.push_str(&format!(", {} missing", self.missing.len()));
+        }
+        if self.unhashed > 0 {
+            summary.push_str(&format!(", {} without a hash", self.unhashed));
+        }
+        summary
+    }
+}
+
+// what the file list of the newest archive promises, or why it can't be checked
+fn expected_hashes(chain: &[incremental::Link]) -> Result<(FileHashes, u32), String> {
+    if chain
+        .iter()
+        .any(|link| !link.fingerprint.contains(get_fingered()))
+    {
+        return Err("Invalid backup fingerprint.".into());
+    }
+    let manifest = chain
+        .last()
+        .and_then(|link| link.manifest.as_ref())
+        .ok_or("This backup has no file list to verify against.")?;
+    let hashes: FileHashes = manifest
+        .iter()
+        .filter_map(|(path, state)| Some((path.clone(), state.hash.clone()?)))
+        .collect();
+    if hashes.is_empty() && !manifest.is_empty() {
+        return Err("This backup was made before file hashes were recorded.".into());
+    }
+    let unhashed = (manifest.len() - hashes.len()) as u32;
+    Ok((hashes, unhashed))
+}
+
+// reads back every file the backup restores, through the whole chain of an
+// incremental, and compares it with the hash taken when it was backed up
+pub fn verify_backup(
+    zip_path: &Path,
+    key: Option<&Key>,
+    progress: &Progress,
+) -> Result<VerifyReport, String> {
+    info!("Verifying {}", zip_path.display());
+
+    let chain = incremental::chain(zip_path, key)?;
+    let (hashes, unhashed) = expected_hashes(&chain)?;
+    let owners = incremental::owners(&chain);
+    let hard_links = &chain.last().ok_or("Empty backup chain.")?.hard_links;
+
+    let mut report = VerifyReport {
+        checked: 0,
+        corrupt: Vec::new(),
+        missing: Vec::new(),
+        unhashed,
+    };
+    let total = (hashes.len() as u32).max(1);
+    let mut seen: HashSet<PathBuf> = HashSet::new();
+
+    for (index, link) in chain.iter().enumerate() {
+        debug!("Verifying entries of {}", link.path.display());
+        let visited = archive::visit_entries(&link.path, key, |entry| {
+            if entry.kind != EntryKind::File
+                || entry.name == "fingerprint.txt"
+                || !incremental::keeps(owners.as_ref(), &chain, index, &entry.name, entry.kind)
+            {
+                return Ok(ControlFlow::Continue(()));
+            }
+            let Some(original) = original_path(&link.path_map, &entry.name, link.escaped) else {
+                debug!("  {} has no uuid in the map", entry.name);
+                return Ok(ControlFlow::Continue(()));
+            };
+            // no data of its own, the file it shares it with is checked
+            if hard_links.contains_key(&original) {
+                seen.insert(original);
+                return Ok(ControlFlow::Continue(()));
+            }
+            let Some(expected) = hashes.get(&original) else {
+                return Ok(ControlFlow::Continue(()));
+            };
+
+            let read = match link.sparse.get(&original) {
+                Some(layout) => hash_reader(&mut sparse::Expand::new(entry.data, layout)),
+                None => hash_reader(entry.data),
+            };
+            match read {
+                Ok(actual) if &actual == expected => {}
+                Ok(_) => {
+                    warn!("Hash mismatch: {}", original.display());
+                    report
+                        .corrupt
+                        .push((original.clone(), "contents changed".into()));
+                }
+                Err(e) => {
+                    warn!("Unreadable: {}: {e}", original.display());
+                    report.corrupt.push((original.clone(), e.to_string()));
+                }
+            }
+            seen.insert(original);
+            report.checked += 1;
+            progress.set(report.checked * 100 / total);
+            Ok(ControlFlow::Continue(()))
+        });
+        // a damaged stream ends the walk, whatever it didn't reach counts as missing
+        if let Err(e) = visited {
+            warn!("Couldn't read {}: {e}", link.path.display());
+            report.corrupt.push((link.path.clone(), e));
+        }
+    }
+
+    report.missing = hashes
+        .into_keys()
+        .filter(|path| !seen.contains(path))
+        .collect();
+    report.missing.sort();
+    report.corrupt.sort();
+
+    info!("Verified {}: {}", zip_path.display(), report.summary());
+    progress.done();
+    Ok(report)
+}
+
+// a full restore rehearsal: everything is extracted below a temp folder with
+// the normal restore code, each file is compared with the file list and the
+// folder is removed again, whatever the outcome
+pub fn test_restore(
+    zip_path: &Path,
+    key: Option<&Key>,
+    progress: &Progress,
+) -> Result<VerifyReport, String> {
+    info!("Test restore of {}", zip_path.display());
+
+    let chain = incremental::chain(zip_path, key)?;
+    let (hashes, unhashed) = expected_hashes(&chain)?;
+    drop(chain);
+
+    let scratch = std::env::temp_dir().join(format!(
+        "konserve-test-{}",
+        &Uuid::new_v4().simple().to_string()[..8]
+    ));
+    let result = rehearse(zip_path, key, progress, &scratch, hashes, unhashed);
+
+    debug!("Removing {}", scratch.display());
+    if let Err(e) = fs::remove_dir_all(&scratch)
+        && scratch.exists()
+    {
+        warn!("Couldn't remove {}: {e}", scratch.display());
+    }
+    progress.done();
+    result
+}
+
+fn rehearse(
+    zip_path: &Path,
+    key: Option<&Key>,
+    progress: &Progress,
+    scratch: &Path,
+    hashes: FileHashes,
+    unhashed: u32,
+) -> Result<VerifyReport, String> {
+    let options = RestoreOptions {
+        key: key.cloned(),
+        into: Some(scratch.to_path_buf()),
+        ..RestoreOptions::default()
+    };
+
+    // extracting is most of the work, it fills the bar up to 90%
+    let extracting = Progress::default();
+    let restored = thread::scope(|s| {
+        s.spawn(|| {
+            while extracting.get() <= 100 {
+                progress.set(extracting.get() * 9 / 10);
+                thread::sleep(Duration::from_millis(50));
+            }
+        });
+        let restored = restore_backup(
+            zip_path,
+            None,
+            Arc::new(Mutex::new(String::new())),
+            &extracting,
+            &options,
+        );
+        extracting.done();
+        restored
+    })?;
+
+    // names the restore had to change, by where they would have gone
+    let moved: HashMap<PathBuf, PathBuf> = restored.renamed.into_iter().collect();
+
+    let mut report = VerifyReport {
+        checked: 0,
+        corrupt: Vec::new(),
+        missing: Vec::new(),
+        unhashed,
+    };
+    let total = (hashes.len() as u32).max(1);
+
+    for (original, expected) in hashes {
+        let mut target = rebase(&original, scratch);
+        if let Some(renamed) = moved.get(&target) {
+            target = renamed.clone();
+        }
+
+        match File::open(&target).and_then(|mut f| hash_reader(&mut f)) {
+            Ok(actual) if actual == expected => {}
+            Ok(_) => {
+                warn!("Restored copy differs: {}", original.display());
+                report
+                    .corrupt
+                    .push((original, "restored copy differs".into()));
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                warn!("Not restored: {}", original.display());
+                report.missing.push(original);
+            }
+            Err(e) => {
+                warn!("Unreadable restored copy: {}: {e}", original.display());
+                report.corrupt.push((original, e.to_string()));
+            }
+        }
+        report.checked += 1;
+        progress.set(90 + report.checked * 10 / total);
+    }
+
+    report.missing.sort();
+    report.corrupt.sort();
+    info!(
+        "Test restore of {}: {}",
+        zip_path.display(),
+        report.summary()
+    );
+    Ok(report)
+}
@@ -0,0 +1,146 @@
+use std::path::{Component, Path, PathBuf, Prefix};
+
+use log::{debug, info, warn};
+
+// a volume shadow copy, a frozen view of a drive that files other programs
+// hold open can still be read from. it's removed again when dropped
+#[cfg_attr(not(windows), allow(dead_code))]
+struct Snapshot {
+    id: String,
+    // "C:\"
+    volume: PathBuf,
+    // \\?\GLOBALROOT\Device\HarddiskVolumeShadowCopyN
+    device: PathBuf,
+}
+
+// snapshots of the drives a backup reads from, files are opened through
+// `source`. empty when shadow copies are off or couldn't be made
+#[derive(Default)]
+pub struct Shadows {
+    snapshots: Vec<Snapshot>,
+}
+
+impl Shadows {
+    // one snapshot per drive under `paths`, a drive that can't get one is
+    // read as it is
+    pub fn create(paths: &[PathBuf]) -> Self {
+        if !cfg!(windows) {
+            warn!("Shadow copies only exist on Windows, files are read as they are");
+            return Self::default();
+        }
+        let mut volumes: Vec<PathBuf> = paths.iter().filter_map(|p| volume_of(p)).collect();
+        volumes.sort();
+        volumes.dedup();
+
+        let mut snapshots = Vec::new();
+        for volume in volumes {
+            match Snapshot::create(&volume) {
+                Ok(snapshot) => {
+                    info!(
+                        "Reading {} from {}",
+                        volume.display(),
+                        snapshot.device.display()
+                    );
+                    snapshots.push(snapshot);
+                }
+                Err(e) => warn!(
+                    "No shadow copy of {}, locked files there are left out: {e}",
+                    volume.display()
+                ),
+            }
+        }
+        Self { snapshots }
+    }
+
+    // where to read `path` from
+    pub fn source(&self, path: &Path) -> PathBuf {
+        let found = volume_of(path).and_then(|volume| {
+            let snapshot = self.snapshots.iter().find(|s| s.volume == volume)?;
+            let rest = path
+                .components()
+                .skip_while(|c| matches!(c, Component::Prefix(_) | Component::RootDir));
+            Some(snapshot.device.join(rest.collect::<PathBuf>()))
+        });
+        found.unwrap_or_else(|| path.to_path_buf())
+    }
+}
+
+// "C:\" for anything on drive c, None for network shares
+fn volume_of(path: &Path) -> Option<PathBuf> {
+    let Some(Component::Prefix(prefix)) = path.components().next() else {
+        return None;
+    };
+    match prefix.kind() {
+        Prefix::Disk(letter) | Prefix::VerbatimDisk(letter) => Some(PathBuf::from(format!(
+            "{}:\\",
+            letter.to_ascii_uppercase() as char
+        ))),
+        _ => None,
+    }
+}
+
+#[cfg(windows)]
+fn powershell(script: &str) -> Result<String, String> {
+    let output = std::process::Command::new("powershell")
+        .args(["-NoProfile", "-NonInteractive", "-Command", script])
+        .output()
+        .map_err(|e| format!("Couldn't run powershell: {e}"))?;
+    if output.status.success() {
+        return Ok(String::from_utf8_lossy(&output.stdout).trim().to_string());
+    }
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    debug!("powershell failed: {stderr}");
+    Err(stderr.lines().next().unwrap_or("").trim().to_string())
+}
+
+impl Snapshot {
+    // needs administrator rights
+    #[cfg(windows)]
+    fn create(volume: &Path) -> Result<Self, String> {
+        let script = format!(
+            "$r = Invoke-CimMethod -ClassName Win32_ShadowCopy -MethodName Create \
+             -Arguments @{{Volume='{}'; Context='ClientAccessible'}}; \
+             if ($r.ReturnValue -ne 0) {{ [Console]::Error.WriteLine($r.ReturnValue); exit 1 }}; \
+             $c = Get-CimInstance Win32_ShadowCopy -Filter \"ID='$($r.ShadowID)'\"; \
+             \"$($r.ShadowID)`t$($c.DeviceObject)\"",
+            volume.display()
+        );
+        let out = powershell(&script).map_err(|code| match code.as_str() {
+            "2" => "Shadow copies need administrator rights.".to_string(),
+            "5" | "12" => "The drive doesn't support shadow copies.".to_string(),
+            _ => format!("Shadow copy failed ({code})."),
+        })?;
+        let (id, device) = out
+            .split_once('\t')
+            .ok_or_else(|| format!("Unexpected answer about the shadow copy: {out}"))?;
+        debug!("Shadow copy {id} of {}", volume.display());
+        Ok(Self {
+            id: id.to_string(),
+            volume: volume.to_path_buf(),
+            device: PathBuf::from(device),
+        })
+    }
+
+    #[cfg(not(windows))]
+    fn create(_volume: &Path) -> Result<Self, String> {
+        Err("Shadow copies only exist on Windows.".into())
+    }
+}
+
+impl Drop for Snapshot {
+    fn drop(&mut self) {
+        #[cfg(windows)]
+        {
+            let script = format!(
+                "Get-CimInstance Win32_ShadowCopy -Filter \"ID='{}'\" | Remove-CimInstance",
+                self.id
+            );
+            match powershell(&script) {
+                Ok(_) => debug!("Removed shadow copy {}", self.id),
+                Err(e) => warn!("Couldn't remove shadow copy {}: {e}", self.id),
+            }
+        }
+        #[cfg(not(windows))]
+        debug!("Nothing to remove for {}", self.id);
+    }
+}
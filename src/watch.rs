@@ -0,0 +1,116 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::{
+        Arc, Mutex,
+        mpsc::{self, RecvTimeoutError},
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use log::{debug, info, warn};
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+
+use crate::backup::{BackupMode, BackupOptions, backup_gui};
+use crate::helpers::Progress;
+
+// quiet time after the last change before backing up, so saving a batch of
+// files makes one backup instead of one per file
+const SETTLE: Duration = Duration::from_secs(10);
+const POLL: Duration = Duration::from_millis(500);
+
+// dropping it stops watching, a backup already running still finishes
+pub struct Watch {
+    _watcher: RecommendedWatcher,
+    pub paths: usize,
+    pub out: PathBuf,
+}
+
+// the first backup after a change is full unless `options` names a base,
+// every later one is incremental on top of the one before
+pub fn start(
+    paths: Vec<PathBuf>,
+    out: PathBuf,
+    options: BackupOptions,
+    status: Arc<Mutex<String>>,
+) -> Result<Watch, String> {
+    let (tx, rx) = mpsc::channel::<notify::Result<Event>>();
+    let mut watcher = notify::recommended_watcher(tx).map_err(|e| e.to_string())?;
+    for path in &paths {
+        let mode = if path.is_dir() {
+            RecursiveMode::Recursive
+        } else {
+            RecursiveMode::NonRecursive
+        };
+        watcher
+            .watch(path, mode)
+            .map_err(|e| format!("Couldn't watch {}: {e}", path.display()))?;
+    }
+    info!(
+        "Watching {} paths, backups go to {}",
+        paths.len(),
+        out.display()
+    );
+
+    let count = paths.len();
+    let watch_out = out.clone();
+    thread::spawn(move || {
+        let mut parent = options
+            .parent
+            .clone()
+            .filter(|_| options.mode != BackupMode::Full);
+        let mut changed_at: Option<Instant> = None;
+        loop {
+            match rx.recv_timeout(POLL) {
+                Ok(Ok(event)) if relevant(&event, &out) => {
+                    debug!("Watch: {:?} {:?}", event.kind, event.paths);
+                    changed_at = Some(Instant::now());
+                }
+                Ok(Ok(_)) | Err(RecvTimeoutError::Timeout) => {}
+                Ok(Err(e)) => warn!("Watch: {e}"),
+                Err(RecvTimeoutError::Disconnected) => break,
+            }
+            if changed_at.is_none_or(|at| at.elapsed() < SETTLE) {
+                continue;
+            }
+            changed_at = None;
+
+            let options = BackupOptions {
+                mode: match parent {
+                    Some(_) => BackupMode::Incremental,
+                    None => BackupMode::Full,
+                },
+                parent: parent.clone(),
+                // nobody is there to answer a prompt
+                interactive: false,
+                ..options.clone()
+            };
+            info!("Watch: changes settled, {} backup", options.mode.label());
+            *status.lock().unwrap() = "Changes settled, backing up…".into();
+            match backup_gui(&paths, &out, &Progress::default(), &options) {
+                Ok(summary) => {
+                    *status.lock().unwrap() = format!(
+                        "✅ Backup created after changes:\n{}\n{}",
+                        summary.archive.display(),
+                        summary.report()
+                    );
+                    parent = Some(summary.archive);
+                }
+                Err(e) => *status.lock().unwrap() = format!("❌ Backup after changes failed: {e}"),
+            }
+        }
+        info!("Stopped watching {count} paths");
+    });
+
+    Ok(Watch {
+        _watcher: watcher,
+        paths: count,
+        out: watch_out,
+    })
+}
+
+// reads (our own backups included) and the archives we write don't count
+fn relevant(event: &Event, out: &Path) -> bool {
+    !matches!(event.kind, EventKind::Access(_))
+        && event.paths.iter().any(|path| !path.starts_with(out))
+}